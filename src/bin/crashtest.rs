@@ -0,0 +1,43 @@
+use simdisk::{crash_test, logger};
+use std::env;
+use std::process::ExitCode;
+
+// crashes listed for each operation, unless -v
+const SHOWN: usize = 5;
+
+// -v: list every crash leaving the image broken
+fn main() -> ExitCode {
+    let verbose = env::args().skip(1).any(|a| a == "-v");
+    logger::mute(true);
+    let reports = match crash_test() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("crashtest: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut broken = 0;
+    for r in &reports {
+        println!(
+            "{}: {} writes, {} crashes, {} consistent, {} leaking, {} broken",
+            r.op, r.writes, r.crashes,
+            r.crashes - r.leaking - r.broken.len(), r.leaking, r.broken.len()
+        );
+        let shown = if verbose { r.broken.len() } else { SHOWN };
+        for (crash, problems) in r.broken.iter().take(shown) {
+            println!("    {crash}:");
+            for p in problems.iter().filter(|p| !p.is_leak()) {
+                println!("        {p}");
+            }
+        }
+        if r.broken.len() > shown {
+            println!("    ... {} more", r.broken.len() - shown);
+        }
+        broken += r.broken.len();
+    }
+    match broken {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE
+    }
+}
@@ -1,8 +1,21 @@
-use simdisk::{PORT, SdReq, SdRes};
-use std::io::{self, BufRead, BufReader, Write};
-use std::net::{SocketAddr, TcpStream};
+use simdisk::{PORT, SdReq, SdRes, HostFile, Body, BodyWriter};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc::{self, Receiver}, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
 use serde_json;
 
+// how often to look for Enter while a command streams
+const POLL: Duration = Duration::from_millis(200);
+
+// "cp host:a b" copies from the host, "cp a host:b" to the host
+const HOST_PREFIX: &str = "host:";
+const PUT_USAGE: &str = "Usage: put [-r] [-v] HOST_SOURCE... DEST\n";
+const GET_USAGE: &str = "Usage: get [-r] [-v] SOURCE... HOST_DEST\n";
+
 #[derive(Debug)]
 struct Context {
     user: u8,
@@ -70,10 +83,29 @@ fn main() {
             break;
         }
 
+        // cp with host paths is put or get
+        if cmd == "cp" && input.0.iter().any(|a| a.starts_with(HOST_PREFIX)) {
+            match host_cp(input.0) {
+                Ok((c, args)) => {
+                    cmd = c;
+                    input.0 = args;
+                },
+                Err(msg) => {
+                    print(&msg);
+                    continue;
+                }
+            }
+        }
+
         // send request to simdisk: ctx + args + redirects
         // and receive response
         // output the result
-        print(&send(&mut ctx, cmd, input.0, input.1));
+        match cmd.as_str() {
+            "put" => print(&put(&mut ctx, input.0, input.1)),
+            "get" => print(&get(&mut ctx, input.0, input.1)),
+            "tar" => print(&tar(&mut ctx, input.0, input.1)),
+            _ => print(&send(&mut ctx, cmd, input.0, input.1)),
+        }
     }
 }
 
@@ -145,10 +177,26 @@ fn read() -> (Vec<String>, String) {
     (args, redirect)
 }
 
+// lines of stdin, read by a thread so that a stream can be interrupted
+fn lines() -> &'static Mutex<Receiver<String>> {
+    static LINES: OnceLock<Mutex<Receiver<String>>> = OnceLock::new();
+    LINES.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut buf = String::new();
+            match io::stdin().lock().read_line(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => if tx.send(buf).is_err() {
+                    break
+                }
+            }
+        });
+        Mutex::new(rx)
+    })
+}
+
 fn read_raw() -> String {
-    let mut buf = String::new();
-    let mut stdin = io::stdin().lock();
-    stdin.read_line(&mut buf).unwrap();
+    let mut buf = lines().lock().unwrap().recv().unwrap_or_default();
     if buf.ends_with('\n') {
         buf.pop();
         if buf.ends_with('\r') {
@@ -175,33 +223,102 @@ fn send(
     cmd: String,
     args: Vec<String>,
     redirect: String
+) -> String {
+    send_files(ctx, cmd, args, redirect, Vec::new(), &mut |_, _| ())
+}
+
+// write the request, then the body of each file read from its host path
+fn write_request(writer: &mut impl Write, msg: &SdReq, paths: &[PathBuf], out: &mut String) -> io::Result<()> {
+    let s_msg = serde_json::to_string(msg).unwrap() + "\n";
+    writer.write_all(s_msg.as_bytes())?;
+    for (file, path) in msg.files.iter().zip(paths) {
+        let body = BodyWriter::new(writer);
+        if file.is_dir {
+            body.finish()?;
+            continue;
+        }
+        let r = match File::open(path) {
+            Ok(mut f) => body.send_from(&mut f)?,
+            Err(e) => body.cut().map(|_| Err(e))?,
+        };
+        if let Err(e) = r {
+            *out += &format!("{}: Cannot read '{}': {e}\n", msg.cmd, path.display());
+        }
+    }
+    writer.flush()
+}
+
+// send `files` along with the command, their bodies read from the host paths
+// paired with them, and hand the files sent back to `receive` with theirs
+fn send_files(
+    ctx: &mut Context,
+    cmd: String,
+    args: Vec<String>,
+    redirect: String,
+    files: Vec<(HostFile, PathBuf)>,
+    receive: &mut dyn FnMut(HostFile, &mut Body)
 ) -> String {
     let mut conn = connect();
+    let (files, paths): (Vec<_>, Vec<_>) = files.into_iter().unzip();
     let msg = SdReq {
         uid: ctx.user,
         wd: ctx.wd.clone(),
-        cmd, args, redirect
+        cmd, args, redirect, files
     };
-    let mut s_msg = serde_json::to_string(&msg).unwrap();
-    s_msg = s_msg + "\n";
 
-    // send request
-    if let Err(e) = conn.write_all(s_msg.as_bytes()) {
-        return format!("{e}\n");
-    }
-    if let Err(e) = conn.flush() {
-        return format!("{e}\n");
+    // send request, then the files
+    let mut out = String::new();
+    if let Err(e) = write_request(&mut BufWriter::new(&conn), &msg, &paths, &mut out) {
+        return out + &format!("{e}\n");
     }
 
     // read response
-    let mut res = String::new();
+    // a streaming command sends more until interrupted by Enter,
+    // files are sent back ahead of the result
     let mut reader = BufReader::new(&conn);
-    if let Err(e) = reader.read_line(&mut res) {
-        return format!("{e}\n");
+    let mut streaming = false;
+    let mut buf = Vec::new();
+    if let Err(e) = conn.set_read_timeout(Some(POLL)) {
+        return out + &format!("{e}\n");
     }
-    let res: SdRes = match serde_json::from_str(&res) {
-        Ok(obj) => obj,
-        Err(e) => return format!("{e}\n")
+    let res = loop {
+        match reader.read_until(b'\n', &mut buf) {
+            // interrupted
+            Ok(0) => return out,
+            Ok(_) => (),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                if streaming && lines().lock().unwrap().try_recv().is_ok() {
+                    let _ = conn.shutdown(Shutdown::Both);
+                    return out;
+                }
+                continue;
+            },
+            Err(e) => return out + &format!("{e}\n")
+        }
+        let res: SdRes = match serde_json::from_slice(&buf) {
+            Ok(obj) => obj,
+            Err(e) => return out + &format!("{e}\n")
+        };
+        buf.clear();
+        if !res.more {
+            break res;
+        }
+        print(&res.result);
+        if res.files.is_empty() {
+            streaming = true;
+            continue;
+        }
+
+        // the bodies follow, with no need to poll
+        if let Err(e) = conn.set_read_timeout(None) {
+            return out + &format!("{e}\n");
+        }
+        for file in res.files {
+            receive(file, &mut Body::new(&mut reader));
+        }
+        if let Err(e) = conn.set_read_timeout(Some(POLL)) {
+            return out + &format!("{e}\n");
+        }
     };
 
     // workding directory may change
@@ -209,5 +326,229 @@ fn send(
         ctx.move_to(&res.wd);
     }
 
-    res.result
-}
\ No newline at end of file
+    out + &res.result
+}
+
+// ====== HOST FILES ======
+
+// turn cp with host paths into put or get, host prefixes removed
+fn host_cp(args: Vec<String>) -> Result<(String, Vec<String>), String> {
+    let on_host = |a: &String| a.starts_with(HOST_PREFIX);
+    let paths: Vec<&String> = args.iter().filter(|a| !a.starts_with('-')).collect();
+    let cmd = match paths.split_last() {
+        Some((dest, sources)) if !sources.is_empty() && on_host(dest) && !sources.iter().any(|a| on_host(a)) => "get",
+        Some((dest, sources)) if !sources.is_empty() && !on_host(dest) && sources.iter().all(|a| on_host(a)) => "put",
+        _ => return Err(String::from("cp: Either every source or the destination must be a host path\n")),
+    };
+    let args = args.iter()
+        .map(|a| a.strip_prefix(HOST_PREFIX).unwrap_or(a).to_string())
+        .collect();
+    Ok((String::from(cmd), args))
+}
+
+// options and paths in args
+fn split_args(args: Vec<String>) -> (Vec<String>, Vec<String>) {
+    args.into_iter().partition(|a| a.starts_with('-'))
+}
+
+// unix permission bits of a host file
+fn host_mode(meta: &fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        meta.permissions().mode() & 0o777
+    }
+    #[cfg(not(unix))]
+    match (meta.is_dir(), meta.permissions().readonly()) {
+        (true, false) => 0o755,
+        (true, true) => 0o555,
+        (false, false) => 0o644,
+        (false, true) => 0o444,
+    }
+}
+
+// add host `path` to `files` as `name`, and what is in it if `recursive`
+fn pack(path: &Path, name: &str, recursive: bool, files: &mut Vec<(HostFile, PathBuf)>, out: &mut String) {
+    let meta = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(e) => {
+            *out += &format!("put: Cannot access '{}': {e}\n", path.display());
+            return;
+        }
+    };
+    let file = HostFile {
+        path: String::from(name),
+        is_dir: meta.is_dir(),
+        mode: host_mode(&meta),
+        mtime: meta.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs() as u32),
+    };
+
+    if !meta.is_dir() {
+        files.push((file, path.to_path_buf()));
+        return;
+    }
+    if !recursive {
+        *out += &format!("put: -r not specified; omitting directory '{}'\n", path.display());
+        return;
+    }
+    files.push((file, path.to_path_buf()));
+
+    let mut entries: Vec<_> = match fs::read_dir(path) {
+        Ok(rd) => rd.filter_map(|e| e.ok()).collect(),
+        Err(e) => {
+            *out += &format!("put: Cannot read directory '{}': {e}\n", path.display());
+            return;
+        }
+    };
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let sub_name = format!("{name}/{}", entry.file_name().to_string_lossy());
+        pack(&entry.path(), &sub_name, recursive, files, out);
+    }
+}
+
+// files sent back written under a host dir, or as a host file
+struct Unpack<'a> {
+    dest: &'a Path,
+    into_dir: bool,
+    // files written, and paths of dirs not created
+    written: Vec<(PathBuf, HostFile)>,
+    failed: Vec<String>,
+    out: String,
+}
+
+impl Unpack<'_> {
+    // write `file` sent back, copying its body
+    fn file(&mut self, file: HostFile, body: &mut Body) {
+        if self.failed.iter().any(|f| file.path.strip_prefix(f).is_some_and(|rest| rest.starts_with('/'))) {
+            return;
+        }
+        if file.path.split('/').any(|s| s.is_empty() || s == "." || s == "..") {
+            self.out += &format!("get: Invalid name '{}'\n", file.path);
+            self.failed.push(file.path);
+            return;
+        }
+        let target = match self.into_dir {
+            true => self.dest.join(&file.path),
+            false => match file.path.split_once('/') {
+                Some((_, rest)) => self.dest.join(rest),
+                None => self.dest.to_path_buf(),
+            },
+        };
+        let r = match file.is_dir {
+            true => fs::create_dir(&target),
+            false => File::create_new(&target).and_then(|mut f| {
+                // not read whole by simdisk, which tells why
+                io::copy(body, &mut f).map(|_| ()).inspect_err(|_| {
+                    let _ = fs::remove_file(&target);
+                })
+            }),
+        };
+        match r {
+            Ok(_) => self.written.push((target, file)),
+            Err(_) if body.is_cut() => self.failed.push(file.path),
+            Err(e) => {
+                self.out += &format!("get: Cannot create '{}': {e}\n", target.display());
+                self.failed.push(file.path);
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        // writing into a dir changes its mtime: dirs go after what is in them
+        for (target, file) in self.written.iter().rev() {
+            let mtime = UNIX_EPOCH + Duration::from_secs(file.mtime as u64);
+            let r = File::open(target).and_then(|f| f.set_modified(mtime));
+            #[cfg(unix)]
+            let r = r.and_then(|_| {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(target, fs::Permissions::from_mode(file.mode))
+            });
+            if let Err(e) = r {
+                self.out += &format!("get: Cannot set mode and time of '{}': {e}\n", target.display());
+            }
+        }
+        self.out
+    }
+}
+
+// put [-r] [-v] HOST_SOURCE... DEST
+fn put(ctx: &mut Context, args: Vec<String>, redirect: String) -> String {
+    let (mut args, mut paths) = split_args(args);
+    let dest = match paths.pop() {
+        Some(d) if !paths.is_empty() => d,
+        _ => return String::from(PUT_USAGE),
+    };
+    let recursive = args.iter().any(|a| !a.starts_with("--") && a.contains('r'));
+
+    let mut out = String::new();
+    let mut files = Vec::<(HostFile, PathBuf)>::new();
+    for path in &paths {
+        let path = Path::new(path);
+        let name = match fs::canonicalize(path).ok().and_then(|p| p.file_name().map(|n| n.to_owned())) {
+            Some(n) => n.to_string_lossy().to_string(),
+            None => {
+                out += &format!("put: Cannot copy '{}'\n", path.display());
+                continue;
+            }
+        };
+        pack(path, &name, recursive, &mut files, &mut out);
+    }
+    if files.is_empty() {
+        return out;
+    }
+
+    args.push(dest);
+    out + &send_files(ctx, String::from("put"), args, redirect, files, &mut |_, _| ())
+}
+
+// get [-r] [-v] SOURCE... HOST_DEST
+fn get(ctx: &mut Context, args: Vec<String>, redirect: String) -> String {
+    let (mut args, mut paths) = split_args(args);
+    let dest = match paths.pop() {
+        Some(d) if !paths.is_empty() => d,
+        _ => return String::from(GET_USAGE),
+    };
+    let dest = Path::new(&dest);
+    let into_dir = dest.is_dir();
+    if !into_dir && paths.len() > 1 {
+        return format!("get: Target '{}' is not a directory\n", dest.display());
+    }
+
+    args.append(&mut paths);
+    let mut unpack = Unpack { dest, into_dir, written: Vec::new(), failed: Vec::new(), out: String::new() };
+    let out = send_files(ctx, String::from("get"), args, redirect, Vec::new(), &mut |file, body| unpack.file(file, body));
+    out + &unpack.finish()
+}
+
+// tar -c|-x|-t [-v] -f ARCHIVE [-C DIR] [PATH]...
+// with a "host:" archive written or read here
+fn tar(ctx: &mut Context, args: Vec<String>, redirect: String) -> String {
+    let host_path = match args.iter().find_map(|a| a.strip_prefix(HOST_PREFIX)) {
+        Some(p) => String::from(p),
+        None => return send(ctx, String::from("tar"), args, redirect),
+    };
+    let create = args.iter().any(|a| a.starts_with('-') && !a.starts_with("--") && a.contains('c'));
+
+    if create {
+        let mut written = String::new();
+        let out = send_files(ctx, String::from("tar"), args, redirect, Vec::new(), &mut |_, body| {
+            let r = File::create(&host_path).and_then(|mut f| io::copy(body, &mut f));
+            if let Err(e) = r {
+                written = format!("tar: Cannot write 'host:{host_path}': {e}\n");
+            }
+        });
+        return out + &written;
+    }
+
+    if let Err(e) = File::open(&host_path) {
+        return format!("tar: Cannot read 'host:{host_path}': {e}\n");
+    }
+    let file = HostFile {
+        path: host_path.clone(),
+        ..Default::default()
+    };
+    send_files(ctx, String::from("tar"), args, redirect, vec![(file, PathBuf::from(host_path))], &mut |_, _| ())
+}
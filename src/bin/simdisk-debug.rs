@@ -0,0 +1,207 @@
+/* Offline Image Inspection
+ * Opens an image read-only, without a server, and prints what is stored:
+ *
+ * super: the superblock
+ * inode INO: fields of an inode and its block map
+ * ls INO: every slot of a directory, empty ones included
+ * bitmap inode|data START [COUNT]: used and free runs of a bitmap
+ * cat INO [HOST_FILE]: content of an inode, to stdout or a host file
+ */
+
+use chrono::DateTime;
+use getopts::Options;
+use simdisk::{logger, open_image, inode_info, raw_entries, bitmap_range, file_content};
+use simdisk::{BitmapKind, FileDevice, InodeInfo, Superblock, DISK_PATH};
+use simdisk::passphrase::passphrase;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: simdisk-debug [-f IMAGE] [-p] COMMAND [ARG]...
+
+Commands:
+    super                           print the superblock
+    inode INO                       print an inode and its block map
+    ls [INO]                        list raw entries of a directory (default: root)
+    bitmap inode|data START [COUNT] print used and free runs of a bitmap
+    cat INO [HOST_FILE]             extract content of an inode
+";
+// inodes or blocks shown by bitmap without COUNT
+const BITMAP_COUNT: u32 = 64;
+
+fn parse_num(s: Option<&String>, what: &str) -> Result<u32, String> {
+    match s {
+        Some(s) => s.parse::<u32>().map_err(|_| format!("Invalid {what} '{s}'")),
+        None => Err(format!("Missing {what}\n{USAGE}")),
+    }
+}
+
+// "rwxr-x" of owner and others, after 'd' or '-'
+fn mode_str(mode: u8) -> String {
+    let mut s = String::from(if mode & (1 << 6) > 0 { "d" } else { "-" });
+    for (i, c) in "rwxrwx".chars().enumerate() {
+        s.push(if mode & (1 << (5 - i)) > 0 { c } else { '-' });
+    }
+    s
+}
+
+// "3-7, 10" for [3, 4, 5, 6, 7, 10], "-" for none
+fn ranges(addrs: &[u32]) -> String {
+    if addrs.is_empty() {
+        return String::from("-");
+    }
+    let mut v = Vec::<String>::new();
+    let mut i = 0;
+    while i < addrs.len() {
+        let mut j = i;
+        while j + 1 < addrs.len() && addrs[j + 1] == addrs[j] + 1 {
+            j += 1;
+        }
+        v.push(match i == j {
+            true => addrs[i].to_string(),
+            false => format!("{}-{}", addrs[i], addrs[j]),
+        });
+        i = j + 1;
+    }
+    v.join(", ")
+}
+
+fn inode_str(info: &InodeInfo) -> String {
+    let time = DateTime::from_timestamp(info.timestamp as i64, 0).unwrap_or_default();
+    let mut out = String::new();
+    out += &format!("inode {} ({})\n", info.ino, if info.used { "used" } else { "free" });
+    out += &format!("  mode:    {} ({:#04x})\n", mode_str(info.mode), info.mode);
+    out += &format!("  uid:     {}\n", info.uid);
+    out += &format!("  size:    {} (stored: {})\n", info.size, info.stored_size);
+    out += &format!("  flags:   {:#04x}\n", info.flags);
+    out += &format!("  mtime:   {} ({})\n", time.format("%Y-%m-%d %H:%M:%S"), info.timestamp);
+    out += &format!("  direct:  {:?}\n", info.direct);
+    out += &format!("  indirect: {}  double: {}\n", info.indirect_block, info.double_block);
+    out += &format!("  blocks ({}): {}\n", info.blocks.len(), ranges(&info.blocks));
+    out += &format!("  index blocks ({}): {}\n", info.index_blocks.len(), ranges(&info.index_blocks));
+    out
+}
+
+fn run(sb: &Superblock, cmd: &str, args: &[String]) -> Result<Vec<u8>, String> {
+    let mut out = String::new();
+    match cmd {
+        "super" => {
+            out += &format!("block size:        {}\n", sb.block_size);
+            out += &format!("block count:       {}\n", sb.block_count);
+            out += &format!("inode count:       {}\n", sb.inode_count);
+            out += &format!("inode bitmap:      {}\n", sb.inode_bitmap_offset);
+            out += &format!("inode table:       {}\n", sb.inode_offset);
+            out += &format!("data bitmap:       {} (extension: {})\n", sb.data_bitmap_offset, sb.data_bitmap_ext);
+            out += &format!("data blocks:       {}\n", sb.data_offset);
+            out += &format!("max file size:     {}\n", sb.max_file_size);
+            out += &format!("magic:             {}\n", sb.magic);
+            out += &format!("encrypted:         {}\n", sb.encrypted != 0);
+            out += &format!("snapshot table:    {}\n", sb.snapshot_table);
+            out += &format!("commit log:        {} blocks\n", sb.log_blocks);
+        },
+        "inode" => {
+            let ino = parse_num(args.first(), "inode")?;
+            let info = inode_info(ino).map_err(|e| format!("Cannot read inode {ino}: {e}"))?;
+            out += &inode_str(&info);
+        },
+        "ls" => {
+            let ino = match args.first() {
+                Some(_) => parse_num(args.first(), "inode")?,
+                None => 0,
+            };
+            let ents = raw_entries(ino).map_err(|e| format!("Cannot read directory {ino}: {e}"))?;
+            out += &format!("{:>6} {:>6}  name\n", "slot", "inode");
+            for ent in ents {
+                match ent.name.is_empty() {
+                    true => out += &format!("{:>6} {:>6}  (empty)\n", ent.slot, ent.inode),
+                    false => out += &format!("{:>6} {:>6}  {}\n", ent.slot, ent.inode, ent.name),
+                }
+            }
+        },
+        "bitmap" => {
+            let kind = match args.first().map(|s| s.as_str()) {
+                Some("inode") => BitmapKind::Inode,
+                Some("data") => BitmapKind::Data,
+                _ => return Err(format!("Missing bitmap: inode or data\n{USAGE}")),
+            };
+            let start = parse_num(args.get(1), "start")?;
+            let count = match args.get(2) {
+                Some(_) => parse_num(args.get(2), "count")?,
+                None => BITMAP_COUNT,
+            };
+            let bits = bitmap_range(kind, start, count).map_err(|e| format!("Cannot read bitmap: {e}"))?;
+            // runs of the same state
+            let mut i = 0;
+            while i < bits.len() {
+                let mut j = i;
+                while j + 1 < bits.len() && bits[j + 1] == bits[i] {
+                    j += 1;
+                }
+                let state = if bits[i] { "used" } else { "free" };
+                out += &format!("{:>8}-{:<8} {}\n", start + i as u32, start + j as u32, state);
+                i = j + 1;
+            }
+        },
+        "cat" => {
+            let ino = parse_num(args.first(), "inode")?;
+            let data = file_content(ino).map_err(|e| format!("Cannot read inode {ino}: {e}"))?;
+            match args.get(1) {
+                Some(path) => fs::write(path, &data).map_err(|e| format!("Cannot write '{path}': {e}"))?,
+                None => return Ok(data),
+            }
+        },
+        _ => return Err(format!("Unknown command '{cmd}'\n{USAGE}")),
+    }
+    Ok(out.into_bytes())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "Help");
+    opts.optflag("p", "", "Prompt for the passphrase");
+    opts.optopt("f", "", "Image file", "IMAGE");
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("simdisk-debug: {f}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+
+    logger::mute(true);
+    let path = matches.opt_str("f").unwrap_or(String::from(DISK_PATH));
+    let device = match FileDevice::open_read_only(&path) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("simdisk-debug: Cannot open '{path}': {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let sb = match open_image(Box::new(device), passphrase(matches.opt_present("p"))) {
+        Ok(sb) => sb,
+        Err(e) => {
+            eprintln!("simdisk-debug: Cannot mount '{path}': {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (cmd, rest) = (&matches.free[0], &matches.free[1..]);
+    let r = run(&sb, cmd, rest).and_then(|out| match io::stdout().write_all(&out) {
+        // output cut short on purpose, e.g. by head
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e.to_string()),
+        _ => Ok(()),
+    });
+    match r {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(msg) => {
+            eprintln!("simdisk-debug: {msg}");
+            ExitCode::FAILURE
+        }
+    }
+}
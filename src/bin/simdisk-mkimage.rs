@@ -0,0 +1,199 @@
+/* Image Building from a Host Directory
+ * Formats a new image and copies a host directory tree into it, the
+ * directory itself becoming "/". Owners and modes come from the host:
+ *
+ * uid: the host uid, or 255 (not the admin) if it is over 255
+ * mode: owner and others bits of the host mode; group bits are not kept
+ * mtime: the host mtime
+ *
+ * Symbolic links and special files are skipped. A manifest overrides owners
+ * and modes, one entry per line (`#` starts a comment, `-` keeps a value):
+ *
+ *     /home/1     1   0750
+ *     /etc/motd   -   0644
+ */
+
+use getopts::Options;
+use simdisk::{logger, make_image, FileDevice, ImageEntry, ImageError};
+use simdisk::passphrase::passphrase;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: simdisk-mkimage [-m MANIFEST] [-p] HOST_DIR IMAGE\n";
+// owner of what has a host uid over 255, unless the manifest names it
+const FALLBACK_UID: u8 = u8::MAX;
+
+// uid and mode to set, None to keep the host value
+type Manifest = HashMap<String, (Option<u8>, Option<u32>)>;
+
+fn read_manifest(path: &str) -> Result<Manifest, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Cannot read '{path}': {e}"))?;
+    let mut manifest = Manifest::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |msg: &str| format!("{}:{}: {}", path, i + 1, msg);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [entry, uid, mode] = fields[..] else {
+            return Err(err("Expected PATH UID MODE"));
+        };
+        if !entry.starts_with('/') {
+            return Err(err("Path must be absolute"));
+        }
+        let uid = match uid {
+            "-" => None,
+            s => Some(s.parse::<u8>().map_err(|_| err(&format!("Invalid uid '{s}'")))?),
+        };
+        let mode = match mode {
+            "-" => None,
+            s => match u32::from_str_radix(s, 8) {
+                Ok(m) if m <= 0o777 => Some(m),
+                _ => return Err(err(&format!("Invalid mode '{s}'"))),
+            },
+        };
+        let entry = match entry.trim_end_matches('/') {
+            "" => "/",
+            p => p,
+        };
+        manifest.insert(String::from(entry), (uid, mode));
+    }
+    Ok(manifest)
+}
+
+// add `host_path` as `path` to `entries`, and what is in it
+fn walk(host_path: &Path, path: &str, entries: &mut Vec<ImageEntry>) -> Result<(), String> {
+    let meta = fs::symlink_metadata(host_path)
+        .map_err(|e| format!("Cannot access '{}': {e}", host_path.display()))?;
+    if !meta.is_dir() && !meta.is_file() {
+        eprintln!("simdisk-mkimage: Skipping '{}': Not a regular file", host_path.display());
+        return Ok(());
+    }
+    let uid = match u8::try_from(meta.uid()) {
+        Ok(u) => u,
+        Err(_) => {
+            eprintln!(
+                "simdisk-mkimage: '{}': uid {} over 255, using {} unless the manifest names it",
+                host_path.display(), meta.uid(), FALLBACK_UID
+            );
+            FALLBACK_UID
+        }
+    };
+    let mut entry = ImageEntry {
+        path: String::from(path),
+        is_dir: meta.is_dir(),
+        uid,
+        mode: meta.mode() & 0o777,
+        mtime: u32::try_from(meta.mtime()).unwrap_or(0),
+        data: Vec::new(),
+    };
+    if !meta.is_dir() {
+        entry.data = fs::read(host_path).map_err(|e| format!("Cannot read '{}': {e}", host_path.display()))?;
+        entries.push(entry);
+        return Ok(());
+    }
+    entries.push(entry);
+
+    // sorted, for the same image from the same tree
+    let mut names = Vec::new();
+    let dir = fs::read_dir(host_path).map_err(|e| format!("Cannot read '{}': {e}", host_path.display()))?;
+    for ent in dir {
+        let ent = ent.map_err(|e| format!("Cannot read '{}': {e}", host_path.display()))?;
+        names.push(ent.file_name());
+    }
+    names.sort();
+    for name in names {
+        let name_str = name.to_str()
+            .ok_or(format!("Invalid name '{}'", host_path.join(&name).display()))?;
+        let sub_path = match path {
+            "/" => format!("/{name_str}"),
+            p => format!("{p}/{name_str}"),
+        };
+        walk(&host_path.join(&name), &sub_path, entries)?;
+    }
+    Ok(())
+}
+
+fn build(host_dir: &str, image: &str, manifest: Option<Manifest>, passphrase: Option<String>) -> Result<usize, String> {
+    if !Path::new(host_dir).is_dir() {
+        return Err(format!("'{host_dir}' is not a directory"));
+    }
+    let mut entries = Vec::<ImageEntry>::new();
+    walk(Path::new(host_dir), "/", &mut entries)?;
+
+    if let Some(mut manifest) = manifest {
+        for entry in entries.iter_mut() {
+            if let Some((uid, mode)) = manifest.remove(&entry.path) {
+                entry.uid = uid.unwrap_or(entry.uid);
+                entry.mode = mode.unwrap_or(entry.mode);
+            }
+        }
+        if let Some(path) = manifest.keys().next() {
+            return Err(format!("Manifest names '{path}', which is not in '{host_dir}'"));
+        }
+    }
+
+    File::create_new(image).map_err(|e| format!("Cannot create '{image}': {e}"))?;
+    let r = FileDevice::open(image)
+        .map_err(|e| format!("Cannot open '{image}': {e}"))
+        .and_then(|device| match make_image(Box::new(device), &entries, passphrase) {
+            Ok(_) => Ok(()),
+            Err(ImageError::EntryErr(path, e)) => Err(format!("Cannot create '{}': {}", path, e.kind())),
+            Err(e) => Err(format!("Cannot build '{image}': {e}")),
+        });
+    if let Err(msg) = r {
+        // an image half filled is of no use
+        let _ = fs::remove_file(image);
+        return Err(msg);
+    }
+    Ok(entries.len())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "Help");
+    opts.optflag("p", "", "Prompt for a passphrase to encrypt the image");
+    opts.optopt("m", "", "Owners and modes to override", "MANIFEST");
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("simdisk-mkimage: {f}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if matches.opt_present("h") {
+        print!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let [host_dir, image] = &matches.free[..] else {
+        eprint!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let manifest = match matches.opt_str("m").map(|m| read_manifest(&m)) {
+        Some(Ok(m)) => Some(m),
+        Some(Err(msg)) => {
+            eprintln!("simdisk-mkimage: {msg}");
+            return ExitCode::FAILURE;
+        },
+        None => None,
+    };
+
+    logger::mute(true);
+    match build(host_dir, image, manifest, passphrase(matches.opt_present("p"))) {
+        Ok(n) => {
+            println!("{image}: {n} entries from '{host_dir}'");
+            ExitCode::SUCCESS
+        },
+        Err(msg) => {
+            eprintln!("simdisk-mkimage: {msg}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,37 +1,72 @@
 mod bitmap;
 mod utils;
 mod disk;
+mod device;
+#[cfg(any(test, feature = "fault-injection"))]
+mod fault;
+mod crypt;
 
 mod superblock;
 mod inode;
 mod data;
+mod dedup;
 
 mod metadata;
 mod file;
 mod dir;
+mod dentry;
+mod compress;
+
+mod resize;
+mod defrag;
+mod check;
+mod inspect;
+mod mkimage;
+mod snapshot;
+mod trash;
+mod lock;
+mod sync;
+mod watch;
 
 mod error {
     pub use super::bitmap::BitmapError;
     pub use super::disk::DiskError;
+    pub use super::crypt::CryptError;
     pub use super::superblock::SuperblockError;
     pub use super::inode::InodeError;
     pub use super::data::DataError;
     pub use super::metadata::MetadataError;
     pub use super::file::FdError;
     pub use super::dir::DdError;
+    pub use super::snapshot::SnapshotError;
     pub use super::super::sedes::SedesError;
 }
 
 use error::*;
 
-pub use superblock::Superblock;
-pub use metadata::{Metadata, MetadataError, Rwx};
-pub use file::{Fd, FdError};
+pub use superblock::{Superblock, StatFs};
+pub use device::{BlockDevice, FileDevice, MemDevice, MmapDevice};
+pub use disk::DISK_PATH;
+pub use metadata::{Metadata, MetadataError, Rwx, mode_to_rwx, rwx_to_mode};
+pub use file::{Fd, FdError, OpenFlags};
 pub use dir::{Dd, DdError, Entry as DirEntry};
+pub use resize::ResizeError;
+pub use defrag::{DefragError, DefragReport};
+pub use check::{CheckError, Problem};
+pub use inspect::{InspectError, InodeInfo, RawEntry, BitmapKind, open_image, inode_info, raw_entries, bitmap_range, file_content};
+pub use mkimage::{ImageError, ImageEntry, make_image};
+#[cfg(feature = "fault-injection")]
+pub use fault::{FaultDevice, Recording, Crash, CrashImage, CrashReport, crash_test};
+pub use dedup::DedupStats;
+pub use snapshot::{SnapshotError, SnapshotInfo};
+pub use trash::{TrashError, TrashEntry};
+pub use lock::{LockKind, LockRange, FileLock, LockWait};
+pub use watch::{Watcher, EventKind};
+pub use utils::canonical_path;
 
 // ====== ERROR ======
 
-use std::{fmt, result, sync::mpsc};
+use std::{fmt, io, result, sync::mpsc};
 
 #[derive(Debug)]
 pub enum FsError {
@@ -44,10 +79,55 @@ pub enum FsError {
     MetadataErr(MetadataError),
     FileErr(FdError),
     DirErr(DdError),
+    ResizeErr(ResizeError),
+    DefragErr(DefragError),
+    CheckErr(CheckError),
+    SnapshotErr(SnapshotError),
+    TrashErr(TrashError),
+    ReadOnly,
+    Locked,
+    InTransaction,
+    NoTransaction,
+    IoErr(io::Error),
     SendErr(String),
     RecvErr(String),
 }
 
+/// Cause of an [FsError], for services to report and act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsErrorKind {
+    InvalidPath,
+    NotFound,
+    Exists,
+    IsDir,
+    NotDir,
+    ReadOnly,
+    Busy,
+    NoSpace,
+    Corrupted,
+    Io,
+    Other,
+}
+
+impl fmt::Display for FsErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::InvalidPath => "Invalid path",
+            Self::NotFound => "No such file or directory",
+            Self::Exists => "File exists",
+            Self::IsDir => "Is a directory",
+            Self::NotDir => "Not a directory",
+            Self::ReadOnly => "Read-only file system",
+            Self::Busy => "Resource busy",
+            Self::NoSpace => "No space left on disk",
+            Self::Corrupted => "File system corrupted",
+            Self::Io => "I/O error",
+            Self::Other => "Inner error",
+        };
+        write!(f, "{s}")
+    }
+}
+
 impl std::error::Error for FsError {}
 
 impl fmt::Display for FsError {
@@ -57,15 +137,90 @@ impl fmt::Display for FsError {
 }
 
 impl From<MetadataError> for FsError {
-    fn from(e: MetadataError) -> Self { Self::MetadataErr(e) }
+    fn from(e: MetadataError) -> Self {
+        match e {
+            MetadataError::InvalidPath => Self::InvalidPath,
+            MetadataError::NotFound => Self::NotFound,
+            MetadataError::ReadOnly => Self::ReadOnly,
+            MetadataError::DiskErr(e) => Self::DiskErr(e),
+            _ => Self::MetadataErr(e)
+        }
+    }
 }
 
 impl From<FdError> for FsError {
-    fn from(e: FdError) -> Self { Self::FileErr(e) }
+    fn from(e: FdError) -> Self {
+        match e {
+            FdError::InvalidPath => Self::InvalidPath,
+            FdError::NotFound => Self::NotFound,
+            FdError::NotFile => Self::NotFileButDir,
+            FdError::FileExists => Self::Exists,
+            FdError::ReadOnly => Self::ReadOnly,
+            FdError::Locked => Self::Locked,
+            FdError::IoErr(e) => Self::IoErr(e),
+            _ => Self::FileErr(e)
+        }
+    }
+}
+
+impl From<io::Error> for FsError {
+    fn from(e: io::Error) -> Self { FdError::from(e).into() }
 }
 
 impl From<DdError> for FsError {
-    fn from(e: DdError) -> Self { Self::DirErr(e) }
+    fn from(e: DdError) -> Self {
+        match e {
+            DdError::InvalidPath => Self::InvalidPath,
+            DdError::NotFound => Self::NotFound,
+            DdError::NotDir => Self::NotDirButFile,
+            DdError::DirExists => Self::Exists,
+            DdError::ReadOnly => Self::ReadOnly,
+            DdError::IoErr(e) => Self::IoErr(e),
+            _ => Self::DirErr(e)
+        }
+    }
+}
+
+impl From<SuperblockError> for FsError {
+    fn from(e: SuperblockError) -> Self {
+        match e {
+            SuperblockError::IoErr(e) => Self::IoErr(e),
+            SuperblockError::DiskErr(e) => Self::DiskErr(e),
+            SuperblockError::NotInitialized => Self::InnerError,
+            SuperblockError::NoEnoughSpace => Self::MetadataErr(MetadataError::NoEnoughSpace),
+            SuperblockError::Corrupted => Self::MetadataErr(MetadataError::Corrupted)
+        }
+    }
+}
+
+impl From<InodeError> for FsError {
+    fn from(e: InodeError) -> Self {
+        match e {
+            InodeError::InvalidAddr => Self::NotFound,
+            InodeError::NoUsableBlock | InodeError::DataTooBig => Self::FileErr(FdError::NoEnoughSpace),
+            InodeError::DiskErr(e) => Self::DiskErr(e)
+        }
+    }
+}
+
+impl From<ResizeError> for FsError {
+    fn from(e: ResizeError) -> Self { Self::ResizeErr(e) }
+}
+
+impl From<DefragError> for FsError {
+    fn from(e: DefragError) -> Self { Self::DefragErr(e) }
+}
+
+impl From<CheckError> for FsError {
+    fn from(e: CheckError) -> Self { Self::CheckErr(e) }
+}
+
+impl From<SnapshotError> for FsError {
+    fn from(e: SnapshotError) -> Self { Self::SnapshotErr(e) }
+}
+
+impl From<TrashError> for FsError {
+    fn from(e: TrashError) -> Self { Self::TrashErr(e) }
 }
 
 impl From<mpsc::SendError<FsReq>> for FsError {
@@ -80,12 +235,96 @@ impl FsError {
     fn DiskErr(e: DiskError) -> Self {
         match e {
             DiskError::InvalidAddr => Self::InvalidPath,
+            DiskError::NoPassphrase => Self::InnerError,
+            DiskError::CryptErr(e) => {
+                logger::log(&format!("[ERR][FS] CryptErr: {e:?}"));
+                Self::InnerError
+            },
             DiskError::IoErr(e) => {
                 logger::log(&format!("[ERR][FS] IoErr: {e:?}"));
-                Self::InnerError
+                Self::IoErr(e)
             }
         }
     }
+
+    /// Return the cause of the error.
+    pub fn kind(&self) -> FsErrorKind {
+        use FsErrorKind as K;
+        match self {
+            Self::InvalidPath => K::InvalidPath,
+            Self::NotFound => K::NotFound,
+            Self::NotFileButDir => K::IsDir,
+            Self::NotDirButFile => K::NotDir,
+            Self::Exists => K::Exists,
+            Self::ReadOnly => K::ReadOnly,
+            Self::Locked => K::Busy,
+            Self::IoErr(_) => K::Io,
+            Self::MetadataErr(MetadataError::ReadOnly) => K::ReadOnly,
+            Self::MetadataErr(MetadataError::NoEnoughSpace) => K::NoSpace,
+            Self::MetadataErr(MetadataError::Corrupted) => K::Corrupted,
+            Self::MetadataErr(MetadataError::DiskErr(e)) => disk_kind(e),
+            Self::FileErr(e) => match e {
+                FdError::InvalidPath => K::InvalidPath,
+                FdError::NotFound | FdError::ParentNotFound => K::NotFound,
+                FdError::NotFile => K::IsDir,
+                FdError::ParentNotDir => K::NotDir,
+                FdError::FileExists => K::Exists,
+                FdError::ReadOnly | FdError::NotWritable => K::ReadOnly,
+                FdError::FileOccupied | FdError::Locked | FdError::TimedOut => K::Busy,
+                FdError::NoEnoughSpace => K::NoSpace,
+                FdError::FileIncorrupted => K::Corrupted,
+                FdError::IoErr(_) => K::Io,
+                _ => K::Other
+            },
+            Self::DirErr(e) => match e {
+                DdError::InvalidPath => K::InvalidPath,
+                DdError::NotFound | DdError::ParentNotFound => K::NotFound,
+                DdError::NotDir | DdError::ParentNotDir => K::NotDir,
+                DdError::DirExists | DdError::EntryExists => K::Exists,
+                DdError::ReadOnly => K::ReadOnly,
+                DdError::DirOccupied => K::Busy,
+                DdError::NoEnoughSpace => K::NoSpace,
+                DdError::DirIncorrupted => K::Corrupted,
+                DdError::IoErr(_) => K::Io,
+                _ => K::Other
+            },
+            Self::ResizeErr(ResizeError::NoEnoughSpace) => K::NoSpace,
+            Self::ResizeErr(ResizeError::DiskErr(e)) => disk_kind(e),
+            Self::DefragErr(DefragError::NoEnoughSpace) => K::NoSpace,
+            Self::DefragErr(DefragError::DirIncorrupted | DefragError::Corrupted) => K::Corrupted,
+            Self::DefragErr(DefragError::DiskErr(e)) => disk_kind(e),
+            Self::CheckErr(CheckError::DiskErr(e)) => disk_kind(e),
+            Self::SnapshotErr(e) => match e {
+                SnapshotError::NotFound => K::NotFound,
+                SnapshotError::Exists => K::Exists,
+                SnapshotError::Busy => K::Busy,
+                SnapshotError::NoEnoughSpace => K::NoSpace,
+                SnapshotError::Corrupted => K::Corrupted,
+                SnapshotError::DiskErr(e) => disk_kind(e),
+                _ => K::Other
+            },
+            Self::TrashErr(e) => match e {
+                TrashError::InvalidPath => K::InvalidPath,
+                TrashError::NotFound | TrashError::ParentNotFound => K::NotFound,
+                TrashError::ParentNotDir => K::NotDir,
+                TrashError::Exists => K::Exists,
+                TrashError::Occupied => K::Busy,
+                TrashError::NoEnoughSpace => K::NoSpace,
+                TrashError::Incorrupted => K::Corrupted,
+                TrashError::DiskErr(e) => disk_kind(e),
+                _ => K::Other
+            },
+            _ => K::Other
+        }
+    }
+}
+
+fn disk_kind(e: &DiskError) -> FsErrorKind {
+    match e {
+        DiskError::InvalidAddr | DiskError::CryptErr(_) => FsErrorKind::Corrupted,
+        DiskError::IoErr(_) => FsErrorKind::Io,
+        DiskError::NoPassphrase => FsErrorKind::Other
+    }
 }
 
 type Result<T> = result::Result<T, FsError>;
@@ -100,6 +339,9 @@ pub enum FsReq {
     /// `tx`: send back result
     Superblock(Sender<Result<superblock::Superblock>>),
 
+    /// `tx`: send back result
+    StatFs(Sender<Result<StatFs>>),
+
     /// `tx`: send back result
     /// 
     /// `path`: file/directory path
@@ -108,7 +350,11 @@ pub enum FsReq {
     /// `tx`: send back result
     /// 
     /// `path`: file path
-    OpenFile(Sender<Result<Fd>>, String),
+    /// 
+    /// `flags`: how to open it
+    /// 
+    /// `uid`: owner of the file if it gets created
+    OpenFile(Sender<Result<Fd>>, String, OpenFlags, u8),
 
     /// `tx`: send back result
     /// 
@@ -139,28 +385,167 @@ pub enum FsReq {
     /// `path`: file path
     RemoveDir(Sender<Result<()>>, String),
 
+    /// `tx`: send back result
+    /// 
+    /// `block_count`: new size of the disk in blocks
+    Resize(Sender<Result<Superblock>>, u32),
+
+    /// `tx`: send back result
+    /// 
+    /// `analyze_only`: only measure fragmentation, move nothing
+    Defrag(Sender<Result<DefragReport>>, bool),
+
+    /// `tx`: send back result
+    DedupStats(Sender<Result<DedupStats>>),
+
+    /// `tx`: send back result
+    Check(Sender<Result<Vec<Problem>>>),
+
+    /// `tx`: send back result
+    /// 
+    /// `name`: snapshot name
+    CreateSnapshot(Sender<Result<SnapshotInfo>>, String),
+
+    /// `tx`: send back result
+    ListSnapshots(Sender<Result<Vec<SnapshotInfo>>>),
+
+    /// `tx`: send back result
+    /// 
+    /// `name`: snapshot name
+    DeleteSnapshot(Sender<Result<()>>, String),
+
+    /// `tx`: send back result
+    /// 
+    /// `name`: snapshot name
+    RollbackSnapshot(Sender<Result<()>>, String),
+
+    /// `tx`: send back result
+    /// 
+    /// `path`: absolute path of file or directory
+    /// 
+    /// `uid`: user whose trash it goes to
+    Trash(Sender<Result<TrashEntry>>, String, u8),
+
+    /// `tx`: send back result
+    /// 
+    /// `uid`: user whose trash is listed
+    ListTrash(Sender<Result<Vec<TrashEntry>>>, u8),
+
+    /// `tx`: send back result
+    /// 
+    /// `uid`: user whose trash it is in
+    /// 
+    /// `key`: id or original path of the entry
+    /// 
+    /// `to`: absolute path to restore to, instead of the original one
+    RestoreTrash(Sender<Result<TrashEntry>>, u8, String, Option<String>),
+
+    /// `tx`: send back result
+    /// 
+    /// `uid`: user whose trash is emptied
+    EmptyTrash(Sender<Result<Vec<TrashEntry>>>, u8),
+
+    /// `tx`: send back result
+    /// 
+    /// `path`: absolute path of file or directory to watch
+    /// 
+    /// `recursive`: also watch everything below the directory
+    Watch(Sender<Result<Watcher>>, String, bool),
+
+    /// `tx`: send back the sender of the transaction
+    Begin(Sender<Result<Sender<FsReq>>>),
+
+    /// `tx`: send back result
+    Commit(Sender<Result<()>>),
+
+    /// `tx`: send back result
+    Abort(Sender<Result<()>>),
+
     // Metadata request
 
     /// `tx`: send back result
     /// 
     /// `addr`: virtual address of inode
     /// 
-    /// `inode`: the inode to write
-    UpdateInode(Sender<Result<()>>, u32, inode::Inode),
+    /// `attr`: attributes to change
+    UpdateInode(Sender<Result<()>>, u32, metadata::InodeAttr),
+
+    /// `tx`: send back result
+    /// 
+    /// `addr`: virtual address of inode
+    AllocatedBlocks(Sender<Result<u32>>, u32),
 
     // Fd request
 
     /// `tx`: send back result
     /// 
     /// `file_inode`: inode virtual address of the file to read
-    ReadFile(Sender<Result<Vec<u8>>>, u32),
+    /// 
+    /// `handle`: handle of the [Fd] reading
+    ReadFile(Sender<Result<Vec<u8>>>, u32, u64),
+
+    /// `tx`: send back result
+    /// 
+    /// `file_inode`: inode virtual address of the file to read
+    /// 
+    /// `handle`: handle of the [Fd] reading
+    /// 
+    /// `offset`: first byte to read
+    /// 
+    /// `len`: most bytes to read
+    ReadFileAt(Sender<Result<Vec<u8>>>, u32, u64, u32, u32),
+
+    /// `tx`: send back result
+    /// 
+    /// `file_inode`: inode virtual address of the file to write
+    /// 
+    /// `handle`: handle of the [Fd] writing
+    /// 
+    /// `append`: add to the end instead of replacing the content
+    /// 
+    /// `data`: write content as u8 vector
+    WriteFile(Sender<Result<()>>, u32, u64, bool, Vec<u8>),
 
     /// `tx`: send back result
     /// 
     /// `file_inode`: inode virtual address of the file to write
     /// 
+    /// `handle`: handle of the [Fd] writing
+    /// 
+    /// `offset`: where to start writing, at most the length of the file
+    /// 
     /// `data`: write content as u8 vector
-    WriteFile(Sender<Result<()>>, u32, Vec<u8>),
+    WriteFileAt(Sender<Result<()>>, u32, u64, u32, Vec<u8>),
+
+    /// `tx`: send back result
+    /// 
+    /// `file_inode`: inode virtual address of the file
+    FileLen(Sender<Result<u32>>, u32),
+
+    /// `tx`: send back result, Locked if another handle holds a conflicting lock
+    /// 
+    /// `file_inode`: inode virtual address of the file to lock
+    /// 
+    /// `handle`: handle of the [Fd] taking the lock
+    /// 
+    /// `lock`: kind and range of the lock
+    Lock(Sender<Result<()>>, u32, u64, FileLock),
+
+    /// `tx`: send back result
+    /// 
+    /// `file_inode`: inode virtual address of the locked file
+    /// 
+    /// `handle`: handle of the [Fd] holding the locks
+    /// 
+    /// `range`: locks overlapping it are dropped, all of them if None
+    Unlock(Sender<Result<()>>, u32, u64, Option<LockRange>),
+
+    /// `tx`: send back result
+    /// 
+    /// `inode`: inode virtual address of the file or directory
+    /// 
+    /// `compressed`: turn compression on or off
+    SetCompression(Sender<Result<()>>, u32, bool),
 
     // Dd request
 
@@ -195,16 +580,27 @@ use std::collections::HashMap;
 pub struct FdTableEntry {
     pub count: u32,
     pub is_dir: bool,
+    pub readers: u32,
+    pub writers: u32,
 }
 
 #[derive(Debug)]
 pub struct FdTable {
     data: HashMap<u32, FdTableEntry>,
+    locks: lock::LockTable,
+    next_handle: u64,
 }
 
 impl FdTable {
+    /// Handle of no [Fd], e.g. of a file being opened: handles start at 1.
+    pub const NO_HANDLE: u64 = 0;
+
     pub fn new() -> Self {
-        Self { data: HashMap::<u32, FdTableEntry>::new() }
+        Self {
+            data: HashMap::<u32, FdTableEntry>::new(),
+            locks: lock::LockTable::default(),
+            next_handle: 1,
+        }
     }
 
     pub fn try_drop(&mut self, inode: u32) {
@@ -217,12 +613,30 @@ impl FdTable {
         }
     }
 
-    /// ## Error
-    /// 
-    /// - NotFileButDir
+    /// Drop one [Fd] opened with `flags`.
+    pub fn close_file(&mut self, inode: u32, flags: OpenFlags) {
+        if let Some(e) = self.data.get_mut(&inode) {
+            if flags.readable() {
+                e.readers = e.readers.saturating_sub(1);
+            }
+            if flags.writable() {
+                e.writers = e.writers.saturating_sub(1);
+            }
+        }
+        self.try_drop(inode);
+    }
+
+    /// Return how many open descriptors of the file may read and write.
+    pub fn usage(&self, inode_addr: u32) -> (u32, u32) {
+        match self.data.get(&inode_addr) {
+            Some(e) => (e.readers, e.writers),
+            None => (0, 0)
+        }
+    }
+
     fn add_file(&mut self, inode_addr: u32) {
         self.data.insert(inode_addr, FdTableEntry {
-            count: 1, is_dir: false
+            count: 1, is_dir: false, readers: 0, writers: 0
         });
     }
 
@@ -231,10 +645,34 @@ impl FdTable {
     /// - NotDirButFile
     fn add_dir(&mut self, inode_addr: u32) {
         self.data.insert(inode_addr, FdTableEntry{
-            count: 1, is_dir: true
+            count: 1, is_dir: true, readers: 0, writers: 0
         });
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Take `lock` on the file for `handle`. Return `false` on conflict.
+    pub fn lock(&mut self, inode_addr: u32, handle: u64, lock: FileLock) -> bool {
+        self.locks.try_lock(inode_addr, handle, lock)
+    }
+
+    pub fn unlock(&mut self, inode_addr: u32, handle: u64, range: Option<LockRange>) {
+        self.locks.unlock(inode_addr, handle, range)
+    }
+
+    /// Drop every lock of a handle, e.g. when its [Fd] is dropped.
+    pub fn release_locks(&mut self, handle: u64) {
+        self.locks.release(handle)
+    }
+
+    /// Return `true` if mandatory locks of other handles allow `handle` to
+    /// read, or write, `range` of the file.
+    pub fn allows(&self, inode_addr: u32, handle: u64, range: LockRange, write: bool) -> bool {
+        self.locks.allows(inode_addr, handle, range, write)
+    }
+
     pub fn check(&self, inode_addr: u32) -> Option<()> {
         match self.data.get(&inode_addr) {
             Some(_) => Some(()),
@@ -245,13 +683,15 @@ impl FdTable {
     /// ## Error
     /// 
     /// - NotFileButDir
-    pub fn get_file(&mut self, tx: Sender<FsReq>, inode_addr: u32, table_arc: Arc<Mutex<Self>>) -> Result<Fd> {
+    pub fn get_file(
+        &mut self, tx: Sender<FsReq>, inode_addr: u32, flags: OpenFlags, table_arc: Arc<Mutex<Self>>
+    ) -> Result<Fd> {
         let inode = match inode::load_inode(inode_addr) {
             Ok(i) => i,
             Err(e) => match e {
                 error::InodeError::InvalidAddr => return Err(FsError::NotFound),
                 error::InodeError::DiskErr(e) => return Err(FsError::DiskErr(e)),
-                _ => panic!("{e:?}")
+                _ => return Err(e.into())
             }
         };
         let metadata = Metadata::new(inode_addr, inode, tx.clone());
@@ -261,14 +701,24 @@ impl FdTable {
 
         match self.data.get_mut(&inode_addr) {
             Some(ent) => {
-                if !ent.is_dir {
+                if ent.is_dir {
                     return Err(FsError::NotFileButDir);
                 }
                 ent.count += 1;
             }
             None => self.add_file(inode_addr)
         }
-        Ok(Fd::new(inode_addr, metadata, tx, table_arc))
+        if let Some(ent) = self.data.get_mut(&inode_addr) {
+            if flags.readable() {
+                ent.readers += 1;
+            }
+            if flags.writable() {
+                ent.writers += 1;
+            }
+        }
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        Ok(Fd::new(inode_addr, handle, flags, metadata, tx, table_arc))
     }
 
     /// ## Error
@@ -280,7 +730,7 @@ impl FdTable {
             Err(e) => match e {
                 error::InodeError::InvalidAddr => return Err(FsError::NotFound),
                 error::InodeError::DiskErr(e) => return Err(FsError::DiskErr(e)),
-                _ => panic!("{e:?}")
+                _ => return Err(e.into())
             }
         };
         let metadata = Metadata::new(inode_addr, inode, tx.clone());
@@ -305,14 +755,56 @@ impl FdTable {
 
 use crate::logger;
 use std::sync::mpsc::{Sender, Receiver};
+use std::{panic, thread};
+use std::time::Duration;
+use threadpool::ThreadPool;
+use zeroize::Zeroizing;
+
+/// Requests served at the same time.
+const WORKERS: usize = 8;
 
+/// Mount the disk and serve [FsReq] from `rx` until every sender is gone.
+/// Requests not changing the tree are served in parallel.
+/// 
+/// `passphrase` unlocks an encrypted disk, or encrypts a newly created one.
 pub fn start_fs(
     started: Sender<result::Result<(), & 'static str>>,
     self_tx: Sender<FsReq>,
     rx: Receiver<FsReq>,
+    passphrase: Option<String>,
+) {
+    let device = match FileDevice::open(DISK_PATH) {
+        Ok(d) => d,
+        Err(e) => {
+            logger::log(&format!("[ERR][FS] Failed to open disk file. Msg: {e}"));
+            let _ = started.send(Err("[ERR][FS] Failed to open disk file."));
+            return
+        }
+    };
+    start_fs_on(Box::new(device), started, self_tx, rx, passphrase)
+}
+
+/// Like [start_fs], with the image on `device` instead of the disk file.
+pub fn start_fs_on(
+    device: Box<dyn BlockDevice>,
+    started: Sender<result::Result<(), & 'static str>>,
+    self_tx: Sender<FsReq>,
+    rx: Receiver<FsReq>,
+    passphrase: Option<String>,
 ) {
-    if let Err(e) = disk::init_disk() {
+    if let Err(e) = disk::init_disk(device, passphrase.map(Zeroizing::new)) {
         logger::log(&format!("[ERR][FS] Failed to initialize disk. Msg: {e}"));
+        let msg = match e {
+            DiskError::NoPassphrase => "[ERR][FS] Disk is encrypted. Passphrase required.",
+            DiskError::CryptErr(CryptError::WrongPassphrase) => "[ERR][FS] Wrong passphrase.",
+            _ => "[ERR][FS] Failed to initialize disk."
+        };
+        let _ = started.send(Err(msg));
+        return
+    }
+    if let Err(e) = dedup::rebuild() {
+        logger::log(&format!("[ERR][FS] Failed to index data blocks. Msg: {e}"));
+        let _ = started.send(Err("[ERR][FS] Failed to index data blocks."));
         return
     }
     let fd_table = Arc::new(Mutex::new(FdTable::new()));
@@ -323,102 +815,453 @@ pub fn start_fs(
         return
     }
 
+    let pool = ThreadPool::new(WORKERS);
     for received in rx {
-        let ds = format!("{:?}", &received);
+        let fd_table = fd_table.clone();
         match received {
-            FsReq::Superblock(tx) => {
-                match superblock::superblock() {
-                    Ok(sb) => tx_send(tx, Ok(sb), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
-            },
-            FsReq::Metadata(tx, path) => {
-                match metadata::metadata(self_tx.clone(), &path) {
-                    Ok(m) => tx_send(tx, Ok(m), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
+            // a transaction may outlive every worker's request
+            FsReq::Begin(tx) => {
+                thread::spawn(move || transaction(tx, fd_table));
             },
-            FsReq::OpenFile(tx, path) => {
-                match file::open_file(self_tx.clone(), fd_table.clone(), &path) {
-                    Ok(f) => tx_send(tx, Ok(f), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
-            },
-            FsReq::CreateFile(tx, path, uid) => {
-                match file::create_file(self_tx.clone(), fd_table.clone(), &path, uid) {
-                    Ok(f) => tx_send(tx, Ok(f), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
-            },
-            FsReq::RemoveFile(tx, path) => {
-                match file::remove_file(self_tx.clone(), fd_table.clone(), &path) {
-                    Ok(_) => tx_send(tx, Ok(()), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
-            },
-            FsReq::OpenDir(tx, path) => {
-                match dir::open_dir(self_tx.clone(), fd_table.clone(), &path) {
-                    Ok(d) => tx_send(tx, Ok(d), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
+            _ => {
+                let self_tx = self_tx.clone();
+                pool.execute(move || serve(received, self_tx, fd_table));
+            }
+        }
+    }
+}
+
+/// How long a transaction waits for its next request before it is aborted.
+const TRANSACTION_IDLE: Duration = Duration::from_secs(30);
+
+// serve the requests of one transaction alone, journaling every block they
+// write, until it is committed, aborted or left idle
+fn transaction(reply: Sender<Result<Sender<FsReq>>>, fd_table: Arc<Mutex<FdTable>>) {
+    let _tree = sync::exclusive();
+    disk::begin_journal();
+    watch::hold();
+    let (self_tx, rx) = mpsc::channel();
+    let mut commit = false;
+    let mut ended = None;
+    if reply.send(Ok(self_tx.clone())).is_ok() {
+        loop {
+            let received = match rx.recv_timeout(TRANSACTION_IDLE) {
+                Ok(r) => r,
+                Err(_) => {
+                    logger::log("[ERR][FS] Transaction left idle. Aborting.");
+                    break
                 }
-            },
-            FsReq::CreateDir(tx, path, uid) => {
-                match dir::create_dir(self_tx.clone(), fd_table.clone(), &path, uid) {
-                    Ok(d) => tx_send(tx, Ok(d), &ds),
-                    Err(e) => match e {
-                        DdError::InvalidPath => tx_send(tx, Err(FsError::InvalidPath), &ds),
-                        DdError::DirExists => tx_send(tx, Err(FsError::Exists), &ds),
-                        _ => tx_send(tx, Err(FsError::InnerError), &ds)
+            };
+            match received {
+                FsReq::Commit(tx) => {
+                    commit = true;
+                    ended = Some(tx);
+                    break
+                },
+                FsReq::Abort(tx) => {
+                    ended = Some(tx);
+                    break
+                },
+                // the journal cannot follow the image resizing
+                FsReq::Resize(tx, _) => tx_send(tx, Err(FsError::InTransaction), "Resize"),
+                _ => {
+                    // undo whatever the failed request left behind
+                    let ds = format!("{:?}", &received);
+                    let served = supervised(&ds, || handle(received, self_tx.clone(), fd_table.clone()));
+                    commit_writes(&ds);
+                    if !served {
+                        logger::log("[ERR][FS] Transaction failed. Aborting.");
+                        break
                     }
                 }
-            },
-            FsReq::RemoveDir(tx, path) => {
-                match dir::remove_dir(self_tx.clone(), fd_table.clone(), &path) {
-                    Ok(_) => tx_send(tx, Ok(()), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
-            },
-            FsReq::UpdateInode(tx, addr, inode) => {
-                match inode::save_inode(addr, &inode) {
-                    Ok(_) => tx_send(tx, Ok(()), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
             }
-            FsReq::ReadFile(tx, inode) => {
-                match file::read_file(inode) {
-                    Ok(v) => tx_send(tx, Ok(v), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
-            },
-            FsReq::WriteFile(tx, inode, mut data ) => {
-                match file::write_file(inode, &mut data) {
-                    Ok(_) => tx_send(tx, Ok(()), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
-            },
-            FsReq::ReadDir(tx, inode) => {
-                match dir::read_dir(inode) {
-                    Ok(v) => tx_send(tx, Ok(v), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
-            },
-            FsReq::DirAddEntry(tx, dir_inode, entry_inode, name) => {
-                match dir::dir_add_entry(dir_inode, entry_inode, &name) {
-                    Ok(_) => tx_send(tx, Ok(()), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
-            },
-            FsReq::DirRemoveEntry(tx, dir_inode, entry_inode) => {
-                match dir::dir_remove_entry(dir_inode, entry_inode) {
-                    Ok(_) => tx_send(tx, Ok(()), &ds),
-                    Err(_) => tx_send(tx, Err(FsError::NotFound), &ds)
-                }
-            },
         }
     }
+    let r = end_transaction(commit);
+    if let Some(tx) = ended {
+        tx_send(tx, r, "End transaction");
+    }
+}
+
+// keep or undo the writes of the transaction
+fn end_transaction(commit: bool) -> Result<()> {
+    watch::release(commit);
+    if let Err(e) = disk::end_journal(commit) {
+        return Err(FsError::DiskErr(e));
+    }
+    if !commit {
+        // memory built from undone blocks
+        snapshot::unmount_all();
+        if let Err(e) = dedup::rebuild() {
+            logger::log(&format!("[ERR][FS] Failed to index data blocks. Msg: {e}"));
+            return Err(FsError::InnerError);
+        }
+    }
+    Ok(())
+}
+
+// requests changing the tree, served while no other request is
+fn changes_tree(req: &FsReq) -> bool {
+    match req {
+        FsReq::OpenFile(_, _, flags, _) => {
+            flags.contains(OpenFlags::CREATE) || flags.contains(OpenFlags::TRUNCATE)
+        },
+        FsReq::CreateFile(..) | FsReq::RemoveFile(..)
+        | FsReq::CreateDir(..) | FsReq::RemoveDir(..)
+        | FsReq::DirAddEntry(..) | FsReq::DirRemoveEntry(..)
+        | FsReq::Resize(..) | FsReq::Defrag(..) | FsReq::Check(..)
+        | FsReq::CreateSnapshot(..) | FsReq::DeleteSnapshot(..) | FsReq::RollbackSnapshot(..)
+        | FsReq::Trash(..) | FsReq::RestoreTrash(..) | FsReq::EmptyTrash(..) => true,
+        _ => false
+    }
+}
+
+fn serve(received: FsReq, self_tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>) {
+    let ds = format!("{:?}", &received);
+    let served = {
+        let _tree = match changes_tree(&received) {
+            true => sync::exclusive(),
+            false => sync::shared()
+        };
+        let served = supervised(&ds, || handle(received, self_tx, fd_table));
+        // a request stopped before answering still wrote
+        commit_writes(&ds);
+        served
+    };
+    if !served {
+        let _tree = sync::exclusive();
+        remount();
+    }
+}
+
+// run `f`, catching a panic so that the requests after it are still served;
+// return `false` if it panicked, its reply never sent
+fn supervised(ds: &str, f: impl FnOnce()) -> bool {
+    match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+        Ok(_) => true,
+        Err(p) => {
+            let msg = match p.downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => p.downcast_ref::<String>().cloned().unwrap_or_default()
+            };
+            logger::log(&format!("[ERR][FS] Panicked serving {ds}. Msg: {msg}"));
+            false
+        }
+    }
+}
+
+// commit what the request wrote, if it did not when answering
+fn commit_writes(ds: &str) {
+    if let Err(e) = disk::commit() {
+        logger::log(&format!("[ERR][FS] Failed to commit writes. Request: {ds} Msg: {e}"));
+    }
+}
+
+// rebuild what is kept in memory from the image, after a request stopped
+// half way
+fn remount() {
+    snapshot::unmount_all();
+    match dedup::rebuild() {
+        Ok(_) => logger::log("[FS] Remounted disk."),
+        Err(e) => logger::log(&format!("[ERR][FS] Failed to remount disk. Msg: {e}"))
+    }
+}
+
+// serve one request, the caller holding the tree lock
+fn handle(received: FsReq, self_tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>) {
+    let ds = format!("{:?}", &received);
+    match received {
+        FsReq::Superblock(tx) => {
+            match superblock::superblock() {
+                Ok(sb) => tx_send(tx, Ok(sb), &ds),
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::StatFs(tx) => tx_send(tx, superblock::statfs().map_err(FsError::from), &ds),
+        FsReq::Metadata(tx, path) => {
+            match metadata::metadata(self_tx.clone(), &path) {
+                Ok(m) => tx_send(tx, Ok(m), &ds),
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::OpenFile(tx, path, flags, _) if flags.writable() && snapshot::in_snapshots(&path) => {
+            tx_send(tx, Err(FsError::ReadOnly), &ds)
+        },
+        FsReq::OpenFile(tx, path, flags, uid) => {
+            let created = flags.contains(OpenFlags::CREATE) && path_to_inode(&path).is_err();
+            match file::open_file(self_tx.clone(), fd_table.clone(), &path, flags, uid) {
+                Ok(f) => {
+                    if created {
+                        watch::notify(EventKind::Created, &path);
+                    } else if flags.contains(OpenFlags::TRUNCATE) {
+                        watch::notify(EventKind::Modified, &path);
+                    }
+                    tx_send(tx, Ok(f), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::CreateFile(tx, path, _) if snapshot::in_snapshots(&path) => {
+            tx_send(tx, Err(FsError::ReadOnly), &ds)
+        },
+        FsReq::CreateFile(tx, path, uid) => {
+            match file::create_file(self_tx.clone(), fd_table.clone(), &path, uid) {
+                Ok(f) => {
+                    watch::notify(EventKind::Created, &path);
+                    tx_send(tx, Ok(f), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::RemoveFile(tx, path) if snapshot::in_snapshots(&path) => {
+            tx_send(tx, Err(FsError::ReadOnly), &ds)
+        },
+        FsReq::RemoveFile(tx, path) => {
+            match file::remove_file(self_tx.clone(), fd_table.clone(), &path) {
+                Ok(_) => {
+                    watch::notify(EventKind::Removed, &path);
+                    tx_send(tx, Ok(()), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::OpenDir(tx, path) => {
+            match dir::open_dir(self_tx.clone(), fd_table.clone(), &path) {
+                Ok(d) => tx_send(tx, Ok(d), &ds),
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::CreateDir(tx, path, _) if snapshot::in_snapshots(&path) => {
+            tx_send(tx, Err(FsError::ReadOnly), &ds)
+        },
+        FsReq::CreateDir(tx, path, uid) => {
+            match dir::create_dir(self_tx.clone(), fd_table.clone(), &path, uid) {
+                Ok(d) => {
+                    watch::notify(EventKind::Created, &path);
+                    tx_send(tx, Ok(d), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::RemoveDir(tx, path) if snapshot::in_snapshots(&path) => {
+            tx_send(tx, Err(FsError::ReadOnly), &ds)
+        },
+        FsReq::RemoveDir(tx, path) => {
+            match dir::remove_dir(self_tx.clone(), fd_table.clone(), &path) {
+                Ok(_) => {
+                    watch::notify(EventKind::Removed, &path);
+                    tx_send(tx, Ok(()), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::Resize(tx, block_count) => {
+            match resize::resize(block_count) {
+                Ok(sb) => tx_send(tx, Ok(sb), &ds),
+                Err(e) => tx_send(tx, Err(FsError::ResizeErr(e)), &ds)
+            }
+        },
+        FsReq::Defrag(tx, analyze_only) => {
+            match defrag::defrag(analyze_only) {
+                Ok(r) => tx_send(tx, Ok(r), &ds),
+                Err(e) => tx_send(tx, Err(FsError::DefragErr(e)), &ds)
+            }
+        },
+        FsReq::DedupStats(tx) => tx_send(tx, Ok(dedup::stats()), &ds),
+        FsReq::Check(tx) => tx_send(tx, check::check().map_err(FsError::CheckErr), &ds),
+        FsReq::CreateSnapshot(tx, name) => {
+            tx_send(tx, snapshot::create(&name).map_err(FsError::SnapshotErr), &ds)
+        },
+        FsReq::ListSnapshots(tx) => {
+            tx_send(tx, snapshot::list().map_err(FsError::SnapshotErr), &ds)
+        },
+        FsReq::DeleteSnapshot(tx, name) => {
+            tx_send(tx, snapshot::delete(&name).map_err(FsError::SnapshotErr), &ds)
+        },
+        FsReq::RollbackSnapshot(tx, name) => {
+            // open files would point at inodes about to be replaced
+            if !utils::mutex_lock(fd_table.lock()).is_empty() {
+                tx_send(tx, Err(FsError::SnapshotErr(SnapshotError::Busy)), &ds);
+                return;
+            }
+            tx_send(tx, snapshot::rollback(&name).map_err(FsError::SnapshotErr), &ds)
+        },
+        FsReq::Trash(tx, path, _) if snapshot::in_snapshots(&path) => {
+            tx_send(tx, Err(FsError::ReadOnly), &ds)
+        },
+        FsReq::Trash(tx, path, uid) => {
+            let r = trash::trash(self_tx.clone(), fd_table.clone(), &path, uid);
+            if let Ok(e) = &r {
+                watch::notify_rename(&path, &trash::entry_path(uid, e));
+            }
+            tx_send(tx, r.map_err(FsError::TrashErr), &ds)
+        },
+        FsReq::ListTrash(tx, uid) => {
+            let r = trash::list(self_tx.clone(), fd_table.clone(), uid);
+            tx_send(tx, r.map_err(FsError::TrashErr), &ds)
+        },
+        FsReq::RestoreTrash(tx, _, _, Some(to)) if snapshot::in_snapshots(&to) => {
+            tx_send(tx, Err(FsError::ReadOnly), &ds)
+        },
+        FsReq::RestoreTrash(tx, uid, key, to) => {
+            let r = trash::restore(self_tx.clone(), fd_table.clone(), uid, &key, to.as_deref());
+            if let Ok(e) = &r {
+                watch::notify_rename(&trash::entry_path(uid, e), to.as_deref().unwrap_or(&e.path));
+            }
+            tx_send(tx, r.map_err(FsError::TrashErr), &ds)
+        },
+        FsReq::EmptyTrash(tx, uid) => {
+            let r = trash::empty(self_tx.clone(), fd_table.clone(), uid);
+            if let Ok(v) = &r {
+                v.iter().for_each(|e| watch::notify(EventKind::Removed, &trash::entry_path(uid, e)));
+            }
+            tx_send(tx, r.map_err(FsError::TrashErr), &ds)
+        },
+        FsReq::Watch(tx, path, recursive) => {
+            tx_send(tx, watch::watch(&path, recursive), &ds)
+        },
+        // transactions do not nest
+        FsReq::Begin(tx) => tx_send(tx, Err(FsError::InTransaction), &ds),
+        FsReq::Commit(tx) | FsReq::Abort(tx) => tx_send(tx, Err(FsError::NoTransaction), &ds),
+        FsReq::UpdateInode(tx, addr, attr) => {
+            match sync::write_inode(addr, || metadata::update_inode(addr, attr)) {
+                Ok(_) => {
+                    watch::notify_inode(EventKind::Attrib, addr);
+                    tx_send(tx, Ok(()), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        }
+        FsReq::ReadFile(tx, inode, handle)
+            if !utils::mutex_lock(fd_table.lock()).allows(inode, handle, LockRange::WHOLE, false) => {
+            tx_send(tx, Err(FsError::Locked), &ds)
+        },
+        FsReq::ReadFile(tx, inode, _) => {
+            match sync::read_inode(inode, || file::read_file(inode)) {
+                Ok(v) => tx_send(tx, Ok(v), &ds),
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::ReadFileAt(tx, inode, handle, offset, len)
+            if !utils::mutex_lock(fd_table.lock()).allows(inode, handle, LockRange::new(offset, len), false) => {
+            tx_send(tx, Err(FsError::Locked), &ds)
+        },
+        FsReq::ReadFileAt(tx, inode, _, offset, len) => {
+            match sync::read_inode(inode, || file::read_at(inode, offset, len)) {
+                Ok(v) => tx_send(tx, Ok(v), &ds),
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::SetCompression(tx, inode, compressed) => {
+            match sync::write_inode(inode, || file::set_compressed(inode, compressed)) {
+                Ok(_) => {
+                    watch::notify_inode(EventKind::Attrib, inode);
+                    tx_send(tx, Ok(()), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::WriteFile(tx, inode, handle, _, _)
+            if !utils::mutex_lock(fd_table.lock()).allows(inode, handle, LockRange::WHOLE, true) => {
+            tx_send(tx, Err(FsError::Locked), &ds)
+        },
+        FsReq::WriteFile(tx, inode, _, true, data) => {
+            match sync::write_inode(inode, || file::append_file(inode, &data)) {
+                Ok(_) => {
+                    watch::notify_inode(EventKind::Modified, inode);
+                    tx_send(tx, Ok(()), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::WriteFile(tx, inode, _, false, mut data ) => {
+            match sync::write_inode(inode, || file::write_file(inode, &mut data)) {
+                Ok(_) => {
+                    watch::notify_inode(EventKind::Modified, inode);
+                    tx_send(tx, Ok(()), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::WriteFileAt(tx, inode, handle, offset, data)
+            if !utils::mutex_lock(fd_table.lock()).allows(inode, handle, LockRange::new(offset, data.len() as u32), true) => {
+            tx_send(tx, Err(FsError::Locked), &ds)
+        },
+        FsReq::WriteFileAt(tx, inode, _, offset, data) => {
+            match sync::write_inode(inode, || file::write_at(inode, offset, &data)) {
+                Ok(_) => {
+                    watch::notify_inode(EventKind::Modified, inode);
+                    tx_send(tx, Ok(()), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::AllocatedBlocks(tx, addr) => {
+            match sync::read_inode(addr, || metadata::allocated_blocks(addr)) {
+                Ok(n) => tx_send(tx, Ok(n), &ds),
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::FileLen(tx, inode) => {
+            match sync::read_inode(inode, || file::file_len(inode)) {
+                Ok(n) => tx_send(tx, Ok(n), &ds),
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::Lock(tx, inode, handle, lock) => {
+            match utils::mutex_lock(fd_table.lock()).lock(inode, handle, lock) {
+                true => tx_send(tx, Ok(()), &ds),
+                false => tx_send(tx, Err(FsError::Locked), &ds)
+            }
+        },
+        FsReq::Unlock(tx, inode, handle, range) => {
+            utils::mutex_lock(fd_table.lock()).unlock(inode, handle, range);
+            tx_send(tx, Ok(()), &ds)
+        },
+        FsReq::ReadDir(tx, inode) => {
+            match sync::read_inode(inode, || dir::read_dir(inode)) {
+                Ok(v) => tx_send(tx, Ok(v), &ds),
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::DirAddEntry(tx, dir_inode, entry_inode, name) => {
+            match dir::dir_add_entry(dir_inode, entry_inode, &name) {
+                Ok(_) => {
+                    watch::notify_entry(EventKind::Created, dir_inode, &name);
+                    tx_send(tx, Ok(()), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+        FsReq::DirRemoveEntry(tx, dir_inode, entry_inode) => {
+            // the name is gone with the entry
+            let name = match watch::active() {
+                true => dir::read_dir(dir_inode).ok()
+                    .and_then(|v| v.into_iter().find(|e| e.inode == entry_inode)),
+                false => None
+            };
+            match dir::dir_remove_entry(dir_inode, entry_inode) {
+                Ok(_) => {
+                    if let Some(e) = name {
+                        watch::notify_entry(EventKind::Removed, dir_inode, &e.name);
+                    }
+                    tx_send(tx, Ok(()), &ds)
+                },
+                Err(e) => tx_send(tx, Err(e.into()), &ds)
+            }
+        },
+    }
 }
 
+// what the request wrote is committed before it is answered
 fn tx_send<T>(tx: Sender<Result<T>>, r: Result<T>, msg: &str) {
+    let r = match disk::commit() {
+        Ok(_) => r,
+        Err(e) => {
+            logger::log(&format!("[ERR][FS] Failed to commit writes. Request: {msg} Msg: {e}"));
+            Err(FsError::DiskErr(e))
+        }
+    };
     if let Err(_) = tx.send(r) {
         logger::log(&format!("[ERR][FS] Sending failed! Request: {}", msg));
     }
@@ -429,38 +1272,39 @@ fn tx_send<T>(tx: Sender<Result<T>>, r: Result<T>, msg: &str) {
 /// 
 /// - InvalidPath
 /// - NotFound
-fn path_to_inode(mut path: &str) -> Result<u32> {
-    // assume path is absolute
+fn path_to_inode(path: &str) -> Result<u32> {
+    // "//x", "a/../b" and "/.." resolve as they would in a shell
+    let path = utils::canonical_path(path);
 
     // root dir
     if path == "/" {
         return Ok(0);
     }
 
-    // trim the '/' at the end
-    if path.ends_with('/') {
-        path = &path[..path.len()-1];
-    }
-
-    let mut path_vec: Vec<&str> = path.split('/').collect();
-    if path_vec.len() < 1 {
-        return Err(FsError::InvalidPath);
-    }
-    path_vec.drain(0..1);
-
     let mut inode = 0;
-    for section in path_vec {
-        if section == "" {
-            return Err(FsError::InvalidPath);
+    for section in path[1..].split('/') {
+        if inode == 0 && section == snapshot::SNAPSHOT_DIR {
+            inode = snapshot::SNAP_ROOT;
+            continue;
+        }
+        match dentry::lookup(inode, section) {
+            Some(Some(i)) => {
+                inode = i;
+                continue;
+            },
+            Some(None) => return Err(FsError::NotFound),
+            None => ()
         }
         let dir_now = match dir::read_dir(inode) {
             Ok(v) => v,
             Err(e) => match e {
                 DdError::NotDir => return Err(FsError::NotFound),
                 DdError::NotFound => return Err(FsError::NotFound),
-                _ => panic!("{e:?}")
+                DdError::DirIncorrupted => return Err(FsError::DirErr(e)),
+                _ => return Err(e.into())
             }
         };
+        dentry::fill(inode, &dir_now, section);
         let mut flag = false;
         for ent in dir_now {
             if ent.name == section {
@@ -485,6 +1329,15 @@ pub fn superblock(fs_tx: &mut Sender<FsReq>) -> Result<Superblock> {
     Ok(rx.recv()??)
 }
 
+/// Count used and free blocks and inodes of the disk. Return [StatFs].
+/// 
+/// `fs_tx`: sender for sending request
+pub fn statfs(fs_tx: &mut Sender<FsReq>) -> Result<StatFs> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::StatFs(tx))?;
+    Ok(rx.recv()??)
+}
+
 /// Get metadata of a file or a directory. Return [Metadata].
 /// 
 /// `fs_tx`: sender for sending request
@@ -492,7 +1345,7 @@ pub fn superblock(fs_tx: &mut Sender<FsReq>) -> Result<Superblock> {
 /// `path`: path to file or directory
 pub fn metadata(fs_tx: &mut Sender<FsReq>, path: &str) -> Result<Metadata> {
     let (tx, rx) = mpsc::channel();
-    fs_tx.send(FsReq::Metadata(tx, String::from(path)))?;
+    fs_tx.send(FsReq::Metadata(tx, utils::canonical_path(path)))?;
     Ok(rx.recv()??)
 }
 
@@ -501,9 +1354,13 @@ pub fn metadata(fs_tx: &mut Sender<FsReq>, path: &str) -> Result<Metadata> {
 /// `fs_tx`: sender for sending request
 /// 
 /// `path`: path to file
-pub fn open_file(fs_tx: &mut Sender<FsReq>, path: &str) -> Result<Fd> {
+/// 
+/// `flags`: [OpenFlags] enforced by the [Fd]
+/// 
+/// `uid`: owner of the file if `CREATE` creates it
+pub fn open_file(fs_tx: &mut Sender<FsReq>, path: &str, flags: OpenFlags, uid: u8) -> Result<Fd> {
     let (tx, rx) = mpsc::channel();
-    fs_tx.send(FsReq::OpenFile(tx, String::from(path)))?;
+    fs_tx.send(FsReq::OpenFile(tx, utils::canonical_path(path), flags, uid))?;
     Ok(rx.recv()??)
 }
 
@@ -516,7 +1373,7 @@ pub fn open_file(fs_tx: &mut Sender<FsReq>, path: &str) -> Result<Fd> {
 /// `uid`: creator id
 pub fn create_file(fs_tx: &mut Sender<FsReq>, path: &str, uid: u8) -> Result<Fd> {
     let (tx, rx) = mpsc::channel();
-    fs_tx.send(FsReq::CreateFile(tx, String::from(path), uid))?;
+    fs_tx.send(FsReq::CreateFile(tx, utils::canonical_path(path), uid))?;
     Ok(rx.recv()??)
 }
 
@@ -527,7 +1384,7 @@ pub fn create_file(fs_tx: &mut Sender<FsReq>, path: &str, uid: u8) -> Result<Fd>
 /// `path`: path to file
 pub fn remove_file(fs_tx: &mut Sender<FsReq>, path: &str) -> Result<()> {
     let (tx, rx) = mpsc::channel();
-    fs_tx.send(FsReq::RemoveFile(tx, String::from(path)))?;
+    fs_tx.send(FsReq::RemoveFile(tx, utils::canonical_path(path)))?;
     Ok(rx.recv()??)
 }
 
@@ -538,7 +1395,7 @@ pub fn remove_file(fs_tx: &mut Sender<FsReq>, path: &str) -> Result<()> {
 /// `path`: path to directory
 pub fn open_dir(fs_tx: &mut Sender<FsReq>, path: &str) -> Result<Dd> {
     let (tx, rx) = mpsc::channel();
-    fs_tx.send(FsReq::OpenDir(tx, String::from(path)))?;
+    fs_tx.send(FsReq::OpenDir(tx, utils::canonical_path(path)))?;
     Ok(rx.recv()??)
 }
 
@@ -551,7 +1408,7 @@ pub fn open_dir(fs_tx: &mut Sender<FsReq>, path: &str) -> Result<Dd> {
 /// `uid`: creator id
 pub fn create_dir(fs_tx: &mut Sender<FsReq>, path: &str, uid: u8) -> Result<Dd> {
     let (tx, rx) = mpsc::channel();
-    fs_tx.send(FsReq::CreateDir(tx, String::from(path), uid))?;
+    fs_tx.send(FsReq::CreateDir(tx, utils::canonical_path(path), uid))?;
     Ok(rx.recv()??)
 }
 
@@ -564,6 +1421,229 @@ pub fn create_dir(fs_tx: &mut Sender<FsReq>, path: &str, uid: u8) -> Result<Dd>
 /// `path`: path to directory
 pub fn remove_dir(fs_tx: &mut Sender<FsReq>, path: &str) -> Result<()> {
     let (tx, rx) = mpsc::channel();
-    fs_tx.send(FsReq::RemoveDir(tx, String::from(path)))?;
+    fs_tx.send(FsReq::RemoveDir(tx, utils::canonical_path(path)))?;
+    Ok(rx.recv()??)
+}
+
+/// Grow or shrink the disk. Return the updated [Superblock].
+/// 
+/// `fs_tx`: sender for sending request
+/// 
+/// `block_count`: new size of the disk in blocks
+pub fn resize(fs_tx: &mut Sender<FsReq>, block_count: u32) -> Result<Superblock> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::Resize(tx, block_count))?;
+    Ok(rx.recv()??)
+}
+
+/// Measure fragmentation of every file and, unless `analyze_only`, move
+/// fragmented files into contiguous blocks. Return a [DefragReport].
+/// 
+/// `fs_tx`: sender for sending request
+/// 
+/// `analyze_only`: only measure fragmentation, move nothing
+pub fn defrag(fs_tx: &mut Sender<FsReq>, analyze_only: bool) -> Result<DefragReport> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::Defrag(tx, analyze_only))?;
+    Ok(rx.recv()??)
+}
+
+/// Snapshot every file and directory as `name`. Return its [SnapshotInfo].
+/// 
+/// `fs_tx`: sender for sending request
+/// 
+/// `name`: snapshot name
+pub fn create_snapshot(fs_tx: &mut Sender<FsReq>, name: &str) -> Result<SnapshotInfo> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::CreateSnapshot(tx, String::from(name)))?;
+    Ok(rx.recv()??)
+}
+
+/// List snapshots, oldest first.
+/// 
+/// `fs_tx`: sender for sending request
+pub fn list_snapshots(fs_tx: &mut Sender<FsReq>) -> Result<Vec<SnapshotInfo>> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::ListSnapshots(tx))?;
+    Ok(rx.recv()??)
+}
+
+/// Delete a snapshot.
+/// 
+/// `fs_tx`: sender for sending request
+/// 
+/// `name`: snapshot name
+pub fn delete_snapshot(fs_tx: &mut Sender<FsReq>, name: &str) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::DeleteSnapshot(tx, String::from(name)))?;
+    Ok(rx.recv()??)
+}
+
+/// Bring every file and directory back to their state in a snapshot.
+/// 
+/// `fs_tx`: sender for sending request
+/// 
+/// `name`: snapshot name
+pub fn rollback_snapshot(fs_tx: &mut Sender<FsReq>, name: &str) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::RollbackSnapshot(tx, String::from(name)))?;
+    Ok(rx.recv()??)
+}
+
+/// Move a file or directory to the trash of user `uid`. Return the
+/// [TrashEntry] recording it.
+/// 
+/// `fs_tx`: sender for sending request
+/// 
+/// `path`: absolute path
+pub fn trash(fs_tx: &mut Sender<FsReq>, path: &str, uid: u8) -> Result<TrashEntry> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::Trash(tx, utils::canonical_path(path), uid))?;
+    Ok(rx.recv()??)
+}
+
+/// List the trash of user `uid`, oldest first.
+/// 
+/// `fs_tx`: sender for sending request
+pub fn list_trash(fs_tx: &mut Sender<FsReq>, uid: u8) -> Result<Vec<TrashEntry>> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::ListTrash(tx, uid))?;
+    Ok(rx.recv()??)
+}
+
+/// Put an entry of the trash of user `uid` back. Return the [TrashEntry]
+/// with the path it was restored to.
+/// 
+/// `fs_tx`: sender for sending request
+/// 
+/// `key`: id or original path of the entry
+/// 
+/// `to`: absolute path to restore to, instead of the original one
+pub fn restore(fs_tx: &mut Sender<FsReq>, uid: u8, key: &str, to: Option<&str>) -> Result<TrashEntry> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::RestoreTrash(tx, uid, String::from(key), to.map(utils::canonical_path)))?;
+    Ok(rx.recv()??)
+}
+
+/// Delete for good every entry in the trash of user `uid`, but those with
+/// open files. Return the entries deleted.
+/// 
+/// `fs_tx`: sender for sending request
+pub fn empty_trash(fs_tx: &mut Sender<FsReq>, uid: u8) -> Result<Vec<TrashEntry>> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::EmptyTrash(tx, uid))?;
     Ok(rx.recv()??)
-}
\ No newline at end of file
+}
+
+/// Return how many data blocks are shared and how many blocks that saves.
+/// 
+/// `fs_tx`: sender for sending request
+pub fn dedup_stats(fs_tx: &mut Sender<FsReq>) -> Result<DedupStats> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::DedupStats(tx))?;
+    Ok(rx.recv()??)
+}
+
+/// Check the consistency of the image. Return the [Problem]s found.
+/// 
+/// `fs_tx`: sender for sending request
+pub fn check(fs_tx: &mut Sender<FsReq>) -> Result<Vec<Problem>> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::Check(tx))?;
+    Ok(rx.recv()??)
+}
+
+/// A group of requests applied as one. Requests sent through
+/// [Transaction::tx] are served in order while no other request is, and
+/// either all of their changes are kept by [Transaction::commit] or none by
+/// [Transaction::abort]. Dropping a transaction not yet ended aborts it, and
+/// so does leaving it idle for 30 seconds.
+/// 
+/// !!!NOTE!!!: requests sent through any other sender (including [Fd] and
+/// [Dd] opened before it began) wait for the transaction to end, and
+/// descriptors opened in it cannot be used after it ends.
+pub struct Transaction {
+    tx: Sender<FsReq>,
+    ended: bool,
+}
+
+impl Transaction {
+    /// Sender for requests in the transaction.
+    pub fn tx(&mut self) -> &mut Sender<FsReq> {
+        &mut self.tx
+    }
+
+    /// Keep every change made in the transaction.
+    pub fn commit(mut self) -> Result<()> {
+        self.end(true)
+    }
+
+    /// Undo every change made in the transaction.
+    pub fn abort(mut self) -> Result<()> {
+        self.end(false)
+    }
+
+    fn end(&mut self, commit: bool) -> Result<()> {
+        self.ended = true;
+        let (tx, rx) = mpsc::channel();
+        match commit {
+            true => self.tx.send(FsReq::Commit(tx))?,
+            false => self.tx.send(FsReq::Abort(tx))?
+        }
+        Ok(rx.recv()??)
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.end(false);
+        }
+    }
+}
+
+/// Begin a [Transaction]. Wait for requests being served to finish.
+/// 
+/// `fs_tx`: sender for sending request
+pub fn begin(fs_tx: &mut Sender<FsReq>) -> Result<Transaction> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::Begin(tx))?;
+    Ok(Transaction { tx: rx.recv()??, ended: false })
+}
+
+/// Run `f` in a [Transaction], committed if `f` returns `Ok` and aborted
+/// otherwise.
+/// 
+/// `fs_tx`: sender for sending request
+/// 
+/// `f`: sends its requests through the sender it is given
+pub fn atomically<T, E: From<FsError>>(
+    fs_tx: &mut Sender<FsReq>,
+    f: impl FnOnce(&mut Sender<FsReq>) -> result::Result<T, E>,
+) -> result::Result<T, E> {
+    let mut t = begin(fs_tx)?;
+    match f(t.tx()) {
+        Ok(v) => {
+            t.commit()?;
+            Ok(v)
+        },
+        Err(e) => {
+            let _ = t.abort();
+            Err(e)
+        }
+    }
+}
+
+/// Watch a file or directory. Return a [Watcher] receiving a [WatchEvent]
+/// for every change of it, or of its entries.
+/// 
+/// `fs_tx`: sender for sending request
+/// 
+/// `path`: absolute path of file or directory
+/// 
+/// `recursive`: also watch everything below the directory
+pub fn watch(fs_tx: &mut Sender<FsReq>, path: &str, recursive: bool) -> Result<Watcher> {
+    let (tx, rx) = mpsc::channel();
+    fs_tx.send(FsReq::Watch(tx, utils::canonical_path(path), recursive))?;
+    Ok(rx.recv()??)
+}
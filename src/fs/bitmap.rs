@@ -4,6 +4,8 @@ use crate::sedes::{Serialize, Deserialize, SedesError};
 use super::utils;
 
 pub const BITMAP_SIZE: usize = 128;
+/// Bits held by one [BlockBitmap] (one block).
+pub const BITMAP_BITS: u32 = BITMAP_SIZE as u32 * 64;
 
 // ====== ERROR ======
 
@@ -33,15 +35,19 @@ pub struct BlockBitmap {
 }
 
 impl BlockBitmap {
+    pub fn new() -> Self {
+        Self { data: [0u64; BITMAP_SIZE] }
+    }
+
     fn get_u64(&self, pos: u32) -> Result<u64> {
-        if (pos / 64) as usize > BITMAP_SIZE {
+        if (pos / 64) as usize >= BITMAP_SIZE {
             return Err(BitmapError::InvalidPos);
         }
         Ok(self.data[(pos / 64) as usize])
     }
 
     fn set_u64(&mut self, pos: u32, map: u64) -> Result<()> {
-        if (pos / 64) as usize > BITMAP_SIZE {
+        if (pos / 64) as usize >= BITMAP_SIZE {
             return Err(BitmapError::InvalidPos);
         }
         self.data[(pos / 64) as usize] = map;
@@ -69,6 +75,11 @@ impl BlockBitmap {
         result
     }
 
+    pub fn is_true(&self, pos: u32) -> Result<bool> {
+        let map = self.get_u64(pos)?;
+        Ok(map & (1 << (pos % 64)) > 0)
+    }
+
     pub fn set_true(&mut self, pos: u32) -> Result<()> {
         let map = self.get_u64(pos)?;
         let flag: u64 = 1 << (pos % 64);
@@ -115,7 +126,22 @@ pub struct Bitmap {
 
 impl Bitmap {
     fn get_pos(pos: u32) -> (u32, u32) {
-        (pos / BITMAP_SIZE as u32, pos % BITMAP_SIZE as u32)
+        (pos / BITMAP_BITS, pos % BITMAP_BITS)
+    }
+
+    /// Return how many bits the bitmap holds.
+    pub fn capacity(&self) -> u32 {
+        self.maps.len() as u32 * BITMAP_BITS
+    }
+
+    /// Append an empty block of bits at the end.
+    pub fn push_map(&mut self) {
+        self.maps.push(BlockBitmap::new());
+    }
+
+    /// Keep only the first `len` blocks of bits.
+    pub fn truncate(&mut self, len: usize) {
+        self.maps.truncate(len);
     }
 
     pub fn get_serialized_map(&self, index: usize) -> Result<Vec<u8>> {
@@ -128,7 +154,25 @@ impl Bitmap {
     pub fn next_usable(&self) -> Option<u32> {
         for (i, map) in self.maps.iter().enumerate() {
             if let Some(p) = map.next_usable() {
-                return Some((i * BITMAP_SIZE) as u32 + p);
+                return Some(i as u32 * BITMAP_BITS + p);
+            }
+        }
+        None
+    }
+
+    /// Return the first position of `len` consecutive unused bits.
+    pub fn next_usable_run(&self, len: u32) -> Option<u32> {
+        let mut start = 0;
+        let mut run = 0;
+        for pos in 0..self.capacity() {
+            if self.is_true(pos).unwrap_or(true) {
+                start = pos + 1;
+                run = 0;
+            } else {
+                run += 1;
+                if run == len {
+                    return Some(start);
+                }
             }
         }
         None
@@ -142,6 +186,14 @@ impl Bitmap {
         result
     }
 
+    pub fn is_true(&self, pos: u32) -> Result<bool> {
+        let (map, pos) = Self::get_pos(pos);
+        match self.maps.get(map as usize) {
+            Some(b) => b.is_true(pos),
+            None => Err(BitmapError::InvalidPos)
+        }
+    }
+
     pub fn set_true(&mut self, pos: u32) -> Result<()> {
         let (map, pos) = Self::get_pos(pos);
         match self.maps.get_mut(map as usize) {
@@ -176,7 +228,7 @@ impl Deserialize for Bitmap {
 
         let bytes = &buf[..];
         for i in 0..(buf.len() / BS) {
-            let m = BlockBitmap::deserialize(&mut bytes[i * BS..(i+1) * BS].to_vec()).unwrap();
+            let m = BlockBitmap::deserialize(&mut bytes[i * BS..(i+1) * BS].to_vec())?;
             maps.push(m);
         }
 
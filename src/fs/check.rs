@@ -0,0 +1,286 @@
+/* Consistency Check
+ * Walk the tree from the root and compare what is reached with what the
+ * bitmaps mark as used.
+ *
+ * inodes: every inode reached is used, and every used inode is reached
+ * blocks: every data and index block reached, and every block held by the
+ *     data bitmap, snapshots or commit log, is used; every used block is one
+ *     of them
+ * entries: names are valid and point into the inode table, each inode is
+ *     reached once, and every directory has "." and ".." (the root only ".")
+ *
+ * Inodes and blocks used but not reached only waste space. Anything else
+ * breaks the file system.
+ */
+
+// ====== ERROR ======
+
+use std::{error, fmt, result};
+use super::error::*;
+
+#[derive(Debug)]
+pub enum CheckError {
+    DiskErr(DiskError),
+}
+
+impl error::Error for CheckError {}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CheckError: {:?}", self)
+    }
+}
+
+impl From<DiskError> for CheckError {
+    fn from(e: DiskError) -> Self { Self::DiskErr(e) }
+}
+
+impl From<InodeError> for CheckError {
+    fn from(e: InodeError) -> Self {
+        match e {
+            InodeError::DiskErr(e) => Self::DiskErr(e),
+            _ => Self::DiskErr(DiskError::InvalidAddr)
+        }
+    }
+}
+
+impl From<DataError> for CheckError {
+    fn from(e: DataError) -> Self {
+        match e {
+            DataError::DiskErr(e) => Self::DiskErr(e),
+            _ => Self::DiskErr(DiskError::InvalidAddr)
+        }
+    }
+}
+
+impl From<SnapshotError> for CheckError {
+    fn from(e: SnapshotError) -> Self {
+        match e {
+            SnapshotError::DiskErr(e) => Self::DiskErr(e),
+            _ => Self::DiskErr(DiskError::InvalidAddr)
+        }
+    }
+}
+
+impl From<SuperblockError> for CheckError {
+    fn from(e: SuperblockError) -> Self {
+        match e {
+            SuperblockError::IoErr(e) => Self::DiskErr(DiskError::IoErr(e)),
+            SuperblockError::DiskErr(e) => Self::DiskErr(e),
+            _ => Self::DiskErr(DiskError::InvalidAddr)
+        }
+    }
+}
+
+type Result<T> = result::Result<T, CheckError>;
+
+// ====== PROBLEM ======
+
+/// Something found wrong. `String`s are paths of what is affected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// Inode marked used that no directory leads to.
+    OrphanInode(u32),
+    /// Data block marked used that nothing points at.
+    LeakedBlock(u32),
+    /// Inode reached but marked free.
+    FreeInode(u32, String),
+    /// Block in use but marked free.
+    FreeBlock(u32, String),
+    /// Pointer to a block outside the data region.
+    BadBlock(u32, String),
+    /// Entry of a directory that cannot be right.
+    BadEntry(String, &'static str),
+}
+
+impl Problem {
+    /// Return `true` if the problem only wastes space.
+    pub fn is_leak(&self) -> bool {
+        matches!(self, Self::OrphanInode(_) | Self::LeakedBlock(_))
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OrphanInode(a) => write!(f, "inode {a} is used but not in any directory"),
+            Self::LeakedBlock(a) => write!(f, "block {a} is used but not owned"),
+            Self::FreeInode(a, p) => write!(f, "inode {a} of {p} is marked free"),
+            Self::FreeBlock(a, p) => write!(f, "block {a} of {p} is marked free"),
+            Self::BadBlock(a, p) => write!(f, "{p} points at block {a} outside the data region"),
+            Self::BadEntry(p, why) => write!(f, "{p}: {why}"),
+        }
+    }
+}
+
+// ====== FN ======
+
+use crate::logger;
+use super::{disk, inode, data, dir, snapshot, superblock, utils};
+use super::inode::{Inode, INODE_COUNT, DIR_FLAG};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+struct Walk {
+    problems: Vec<Problem>,
+    // inodes and blocks reached, and the path reaching them first
+    inodes: HashMap<u32, String>,
+    blocks: HashMap<u32, String>,
+}
+
+fn in_data(addr: u32) -> bool {
+    addr >= data::DATA_OFFSET && addr < disk::block_count()
+}
+
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "/" => format!("/{name}"),
+        _ => format!("{dir}/{name}")
+    }
+}
+
+/// Check the mounted image. Return the problems found, none if it is
+/// consistent.
+///
+/// !!!NOTE!!!: the image must not change while checked.
+///
+/// ## Error
+///
+/// - DiskErr
+pub fn check() -> Result<Vec<Problem>> {
+    let mut w = Walk::default();
+
+    // (inode, parent, path)
+    let mut stack = vec![(0u32, 0u32, String::from("/"))];
+    while let Some((addr, parent, path)) = stack.pop() {
+        if w.inodes.contains_key(&addr) {
+            w.problems.push(Problem::BadEntry(path, "inode linked more than once"));
+            continue;
+        }
+        w.inodes.insert(addr, path.clone());
+        let node = inode::load_inode(addr)?;
+        let blocks = match blocks_of(&mut w, &node, &path)? {
+            Some(b) => b,
+            None => continue
+        };
+        if node.mode & DIR_FLAG > 0 {
+            stack.append(&mut entries_of(&mut w, addr, parent, &path, blocks)?);
+        }
+    }
+
+    let used: HashSet<u32> = inode::used_inodes()?.into_iter().collect();
+    for addr in 0..INODE_COUNT {
+        match (used.contains(&addr), w.inodes.get(&addr)) {
+            (true, None) => w.problems.push(Problem::OrphanInode(addr)),
+            (false, Some(p)) => w.problems.push(Problem::FreeInode(addr, p.clone())),
+            _ => ()
+        }
+    }
+
+    // blocks held by the data bitmap, snapshots and commit log
+    let sb = superblock::superblock()?;
+    let mut held: Vec<(u32, &str)> = Vec::new();
+    if sb.data_bitmap_ext != 0 {
+        held.push((sb.data_bitmap_ext, "data bitmap"));
+    }
+    let addrs = data::bitmap_addrs()?;
+    held.extend(addrs[data::BITMAP_BLOCK as usize..].iter().map(|a| (*a, "data bitmap")));
+    held.extend(snapshot::used_blocks()?.into_iter().map(|a| (a, "snapshots")));
+    held.extend((0..sb.log_blocks).map(|i| (data::DATA_OFFSET + i, "commit log")));
+    for (addr, owner) in held {
+        w.blocks.entry(addr).or_insert_with(|| String::from(owner));
+    }
+
+    let bitmap = data::get_bitmap()?;
+    for addr in data::DATA_OFFSET..disk::block_count() {
+        let used = bitmap.is_true(addr - data::DATA_OFFSET).unwrap_or(false);
+        match (used, w.blocks.get(&addr)) {
+            (true, None) => w.problems.push(Problem::LeakedBlock(addr)),
+            (false, Some(p)) => w.problems.push(Problem::FreeBlock(addr, p.clone())),
+            _ => ()
+        }
+    }
+
+    logger::log(&format!(
+        "[FS] Checked {} inodes, {} blocks: {} problems",
+        w.inodes.len(), w.blocks.len(), w.problems.len()
+    ));
+    Ok(w.problems)
+}
+
+// record the index and data blocks of the inode; return the data blocks,
+// or None if it points outside the data region
+fn blocks_of(w: &mut Walk, node: &Inode, path: &str) -> Result<Option<Vec<u32>>> {
+    let mut ptrs: Vec<u32> = node.blocks.iter().copied().take_while(|a| *a != 0).collect();
+    ptrs.extend([node.indirect_block, node.double_block].into_iter().filter(|a| *a != 0));
+    if let Some(a) = ptrs.into_iter().find(|a| !in_data(*a)) {
+        w.problems.push(Problem::BadBlock(a, String::from(path)));
+        return Ok(None);
+    }
+    let index = inode::get_index_blocks(node)?;
+    if let Some(a) = index.iter().copied().find(|a| !in_data(*a)) {
+        w.problems.push(Problem::BadBlock(a, String::from(path)));
+        return Ok(None);
+    }
+    let blocks = inode::get_blocks(node)?;
+    if let Some(a) = blocks.iter().copied().find(|a| !in_data(*a)) {
+        w.problems.push(Problem::BadBlock(a, String::from(path)));
+        return Ok(None);
+    }
+    for a in index.iter().chain(blocks.iter()) {
+        w.blocks.entry(*a).or_insert_with(|| String::from(path));
+    }
+    Ok(Some(blocks))
+}
+
+// check the entries of the directory; return the ones to walk into
+fn entries_of(
+    w: &mut Walk,
+    addr: u32,
+    parent: u32,
+    path: &str,
+    blocks: Vec<u32>,
+) -> Result<Vec<(u32, u32, String)>> {
+    let buf = disk::read_blocks(&blocks)?;
+    let mut names = HashSet::<String>::new();
+    let mut children = Vec::new();
+    for ent in buf.chunks_exact(dir::ENTRY_SIZE) {
+        let name = match std::str::from_utf8(&ent[4..]) {
+            Ok(n) => n.trim_end_matches('\0'),
+            Err(_) => {
+                w.problems.push(Problem::BadEntry(String::from(path), "entry name is not UTF-8"));
+                continue;
+            }
+        };
+        if name.is_empty() {
+            break;
+        }
+        let ent_addr = utils::u8arr_to_u32(&ent[0..4]);
+        if !names.insert(String::from(name)) {
+            w.problems.push(Problem::BadEntry(join(path, name), "name appears more than once"));
+            continue;
+        }
+        if name.contains('/') || name.contains('\0') {
+            w.problems.push(Problem::BadEntry(String::from(path), "entry name is invalid"));
+        } else if name == "." {
+            if ent_addr != addr {
+                w.problems.push(Problem::BadEntry(String::from(path), "'.' is not the directory itself"));
+            }
+        } else if name == ".." {
+            if addr == 0 || ent_addr != parent {
+                w.problems.push(Problem::BadEntry(String::from(path), "'..' is not the parent"));
+            }
+        } else if ent_addr >= INODE_COUNT {
+            w.problems.push(Problem::BadEntry(join(path, name), "inode out of the inode table"));
+        } else {
+            children.push((ent_addr, addr, join(path, name)));
+        }
+    }
+    if !names.contains(".") {
+        w.problems.push(Problem::BadEntry(String::from(path), "'.' is missing"));
+    }
+    if addr != 0 && !names.contains("..") {
+        w.problems.push(Problem::BadEntry(String::from(path), "'..' is missing"));
+    }
+    Ok(children)
+}
@@ -0,0 +1,65 @@
+/* Compressed Stream
+ * chunk count: 4B
+ * chunk length: 4B * chunk count
+ * chunks: deflate of every CHUNK_SIZE bytes of content
+ */
+
+use super::utils;
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+
+/// Content bytes compressed together; reading any byte inflates its chunk.
+pub const CHUNK_SIZE: usize = 16 * 1024;
+const LEVEL: u8 = 6;
+
+/// Compress `buf` into a stream of chunks led by the chunk table.
+pub fn encode(buf: &[u8]) -> Vec<u8> {
+    let chunks: Vec<Vec<u8>> = buf.chunks(CHUNK_SIZE)
+        .map(|c| compress_to_vec(c, LEVEL))
+        .collect();
+    let mut v = Vec::<u8>::new();
+    v.append(&mut utils::u32_to_u8arr(chunks.len() as u32).to_vec());
+    for c in &chunks {
+        v.append(&mut utils::u32_to_u8arr(c.len() as u32).to_vec());
+    }
+    for mut c in chunks {
+        v.append(&mut c);
+    }
+    v
+}
+
+/// Return the length of the chunk table given its first 4 bytes.
+pub fn header_len(head: &[u8]) -> usize {
+    4 + 4 * utils::u8arr_to_u32(&head[0..4]) as usize
+}
+
+/// Return (start, length) of every chunk within the stream, given the
+/// whole chunk table.
+pub fn chunk_table(header: &[u8]) -> Vec<(usize, usize)> {
+    let count = utils::u8arr_to_u32(&header[0..4]) as usize;
+    let mut v = Vec::<(usize, usize)>::with_capacity(count);
+    let mut start = 4 + 4 * count;
+    for i in 0..count {
+        let len = utils::u8arr_to_u32(&header[4 + 4 * i..8 + 4 * i]) as usize;
+        v.push((start, len));
+        start += len;
+    }
+    v
+}
+
+/// Inflate one chunk. Return `None` if it is corrupted.
+pub fn decode_chunk(chunk: &[u8]) -> Option<Vec<u8>> {
+    decompress_to_vec_with_limit(chunk, CHUNK_SIZE).ok()
+}
+
+/// Inflate a whole stream. Return `None` if it is corrupted.
+pub fn decode(stream: &[u8]) -> Option<Vec<u8>> {
+    if stream.len() < 4 || stream.len() < header_len(stream) {
+        return None;
+    }
+    let mut v = Vec::<u8>::new();
+    for (start, len) in chunk_table(stream) {
+        v.append(&mut decode_chunk(stream.get(start..start + len)?)?);
+    }
+    Some(v)
+}
@@ -0,0 +1,145 @@
+/* Encrypted Block Slot
+ * block: 1KB, ChaCha20 ciphertext (superblock: clear)
+ * nonce: 12B
+ * tag: 16B, Poly1305 over the block and its address
+ * padding: 4B
+ */
+
+// ====== ERROR ======
+
+use std::{error, fmt, result};
+
+#[derive(Debug)]
+pub enum CryptError {
+    WrongPassphrase,
+    Corrupted(u32),
+    KdfErr,
+    Locked,
+    TooLong(u32),
+}
+
+impl error::Error for CryptError {}
+
+impl fmt::Display for CryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CryptError: {:?}", self)
+    }
+}
+
+type Result<T> = result::Result<T, CryptError>;
+
+// ====== FN ======
+
+use super::disk;
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, AeadInPlace, Nonce, Tag};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use argon2::Argon2;
+use zeroize::Zeroizing;
+use std::sync::RwLock;
+
+pub const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// Bytes stored after every block of an encrypted image.
+pub const OVERHEAD: u32 = 32;
+
+/// Cipher of the mounted image. Only the derived key is kept, in memory.
+static CIPHER: RwLock<Option<ChaCha20Poly1305>> = RwLock::new(None);
+
+/// Return a random salt for a new image.
+pub fn new_salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// Derive the image key from `passphrase` and `salt` and use it for every
+/// following block access.
+///
+/// ## Error
+///
+/// - KdfErr
+pub fn unlock(passphrase: &str, salt: &[u8]) -> Result<()> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    if Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key[..]).is_err() {
+        return Err(CryptError::KdfErr);
+    }
+    let cipher = match ChaCha20Poly1305::new_from_slice(&key[..]) {
+        Ok(c) => c,
+        Err(_) => return Err(CryptError::KdfErr)
+    };
+    *CIPHER.write().unwrap_or_else(|e| e.into_inner()) = Some(cipher);
+    Ok(())
+}
+
+/// Forget the key.
+pub fn lock() {
+    *CIPHER.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Whether blocks are sealed.
+pub fn enabled() -> bool {
+    CIPHER.read().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+/// Seal one block into its slot. The superblock is only authenticated, so
+/// an image can be recognized before it is unlocked.
+///
+/// ## Error
+///
+/// - Locked: no key
+/// - TooLong: block exceeds the cipher limit
+pub fn seal(addr: u32, block: &[u8]) -> Result<Vec<u8>> {
+    let guard = CIPHER.read().unwrap_or_else(|e| e.into_inner());
+    let cipher = match guard.as_ref() {
+        Some(c) => c,
+        None => return Err(CryptError::Locked)
+    };
+
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let mut slot = block.to_vec();
+    slot.resize(disk::BLOCK_SIZE as usize, 0);
+    let tag = if addr == 0 {
+        let aad = [&addr.to_be_bytes()[..], &slot[..]].concat();
+        cipher.encrypt_in_place_detached(Nonce::from_slice(&nonce), &aad, &mut [])
+    } else {
+        cipher.encrypt_in_place_detached(Nonce::from_slice(&nonce), &addr.to_be_bytes(), &mut slot)
+    }.map_err(|_| CryptError::TooLong(addr))?;
+
+    slot.extend_from_slice(&nonce);
+    slot.extend_from_slice(&tag);
+    slot.resize((disk::BLOCK_SIZE + OVERHEAD) as usize, 0);
+    Ok(slot)
+}
+
+/// Verify and decrypt one slot read from the image. Every slot is sealed
+/// when the image is created or grown, so any slot failing verification is
+/// reported as Corrupted.
+///
+/// ## Error
+///
+/// - Corrupted
+/// - Locked: no key
+pub fn open(addr: u32, mut slot: Vec<u8>) -> Result<Vec<u8>> {
+    let guard = CIPHER.read().unwrap_or_else(|e| e.into_inner());
+    let cipher = match guard.as_ref() {
+        Some(c) => c,
+        None => return Err(CryptError::Locked)
+    };
+
+    let bs = disk::BLOCK_SIZE as usize;
+    let nonce = *Nonce::from_slice(&slot[bs..bs + NONCE_LEN]);
+    let tag = *Tag::from_slice(&slot[bs + NONCE_LEN..bs + NONCE_LEN + TAG_LEN]);
+    slot.truncate(bs);
+    let res = if addr == 0 {
+        let aad = [&addr.to_be_bytes()[..], &slot[..]].concat();
+        cipher.decrypt_in_place_detached(&nonce, &aad, &mut [], &tag)
+    } else {
+        cipher.decrypt_in_place_detached(&nonce, &addr.to_be_bytes(), &mut slot, &tag)
+    };
+    match res {
+        Ok(_) => Ok(slot),
+        Err(_) => Err(CryptError::Corrupted(addr))
+    }
+}
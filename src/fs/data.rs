@@ -1,4 +1,4 @@
-const BITMAP_BLOCK: u32 = 16;
+pub const BITMAP_BLOCK: u32 = 16;
 pub const BITMAP_OFFSET: u32 = 1 + 1 + 256;
 pub const DATA_OFFSET: u32 = BITMAP_OFFSET + BITMAP_BLOCK;
 
@@ -26,32 +26,73 @@ impl From<DiskError> for DataError {
     fn from(e: DiskError) -> Self { Self::DiskErr(e) }
 }
 
+impl From<BitmapError> for DataError {
+    fn from(_: BitmapError) -> Self { Self::InvalidAddr }
+}
+
+impl From<SedesError> for DataError {
+    fn from(_: SedesError) -> Self { Self::InvalidAddr }
+}
+
 type Result<T> = result::Result<T, DataError>;
 
 // ====== FN ======
 
 use crate::sedes::Deserialize;
 
-use super::disk;
+use super::{disk, utils, dedup};
 use super::bitmap::Bitmap;
+use super::superblock::Superblock;
+use std::sync::Mutex;
 
-fn get_bitmap() -> Result<Bitmap> {
-    let addrs: Vec<u32> = (BITMAP_OFFSET..DATA_OFFSET).collect();
+// held around read-modify-write of the bitmap
+static ALLOC: Mutex<()> = Mutex::new(());
+
+/// Return addresses of the blocks holding the data bitmap: the fixed ones
+/// after the inode table, then the extension blocks added by growing.
+/// 
+/// ## Error
+/// 
+/// - DiskErr
+pub fn bitmap_addrs() -> Result<Vec<u32>> {
+    let mut addrs: Vec<u32> = (BITMAP_OFFSET..DATA_OFFSET).collect();
+    let sb = Superblock::deserialize(&mut disk::read_blocks(&vec![0])?)?;
+    if sb.data_bitmap_ext != 0 {
+        let buf = disk::read_blocks(&vec![sb.data_bitmap_ext])?;
+        addrs.append(&mut utils::block_to_addrs(&buf));
+    }
+    Ok(addrs)
+}
+
+/// ## Error
+/// 
+/// - DiskErr
+pub fn get_bitmap() -> Result<Bitmap> {
+    let addrs = bitmap_addrs()?;
     let mut data = match disk::read_blocks(&addrs) {
         Ok(d) => d,
         Err(e) => return Err(DataError::DiskErr(e))
     };
-    Ok(Bitmap::deserialize(&mut data).unwrap())
+    Ok(Bitmap::deserialize(&mut data)?)
 }
 
 fn save_bitmap(bitmap: &Bitmap) -> Result<()> {
-    let mut data = Vec::<(u32, Vec<u8>)>::with_capacity(BITMAP_BLOCK as usize);
-    for i in 0..BITMAP_BLOCK {
-        let bytes = match bitmap.get_serialized_map(i as usize) {
+    save_bitmap_at(bitmap, &bitmap_addrs()?)
+}
+
+/// Write `bitmap` into `addrs`, one block of bits per address.
+/// 
+/// ## Error
+/// 
+/// - DiskErr
+pub fn save_bitmap_at(bitmap: &Bitmap, addrs: &[u32]) -> Result<()> {
+    let mut data = Vec::<(u32, Vec<u8>)>::with_capacity(addrs.len());
+    for (i, addr) in addrs.iter().enumerate() {
+        let bytes = match bitmap.get_serialized_map(i) {
             Ok(b) => b,
             Err(_) => break
         };
-        data.push((BITMAP_OFFSET + i, bytes));
+        data.push((*addr, bytes));
     }
     match disk::write_blocks(&data) {
         Ok(_) => Ok(()),
@@ -59,12 +100,39 @@ fn save_bitmap(bitmap: &Bitmap) -> Result<()> {
     }
 }
 
+/// Mark every bit standing for a block at or past `block_count` as used,
+/// so that nothing is allocated outside the image.
+/// 
+/// ## Error
+/// 
+/// - DiskErr
+pub fn reserve_tail(block_count: u32) -> Result<()> {
+    let _alloc = utils::mutex_lock(ALLOC.lock());
+    let mut bitmap = get_bitmap()?;
+    for pos in block_count.saturating_sub(DATA_OFFSET)..bitmap.capacity() {
+        bitmap.set_true(pos)?;
+    }
+    save_bitmap(&bitmap)
+}
+
+/// Return how many data blocks are free.
+/// 
+/// ## Error
+/// 
+/// - InvalidAddr
+/// - DiskErr
+pub fn free_count() -> Result<u32> {
+    let _alloc = utils::mutex_lock(ALLOC.lock());
+    Ok(get_bitmap()?.rest_usable())
+}
+
 // [PASS]
 /// ## Error
 /// 
 /// - InsufficientUsableBlocks
 /// - DiskErr
 pub fn alloc_blocks(count: u32) -> Result<Vec<u32>> {
+    let _alloc = utils::mutex_lock(ALLOC.lock());
     let mut bitmap = get_bitmap()?;
     if bitmap.rest_usable() < count {
         return Err(DataError::InsufficientUsableBlocks);
@@ -72,8 +140,34 @@ pub fn alloc_blocks(count: u32) -> Result<Vec<u32>> {
 
     let mut v = Vec::<u32>::new();
     for _ in 0..count {
-        let addr = bitmap.next_usable().unwrap();
-        bitmap.set_true(addr).unwrap();
+        let addr = match bitmap.next_usable() {
+            Some(a) => a,
+            None => return Err(DataError::InsufficientUsableBlocks)
+        };
+        bitmap.set_true(addr)?;
+        v.push(addr + DATA_OFFSET);
+    }
+    save_bitmap(&bitmap)?;
+    Ok(v)
+}
+
+/// Allocate `count` blocks with consecutive addresses.
+/// 
+/// ## Error
+/// 
+/// - InsufficientUsableBlocks
+/// - DiskErr
+pub fn alloc_contiguous_blocks(count: u32) -> Result<Vec<u32>> {
+    let _alloc = utils::mutex_lock(ALLOC.lock());
+    let mut bitmap = get_bitmap()?;
+    let start = match bitmap.next_usable_run(count) {
+        Some(p) => p,
+        None => return Err(DataError::InsufficientUsableBlocks)
+    };
+
+    let mut v = Vec::<u32>::with_capacity(count as usize);
+    for addr in start..start + count {
+        bitmap.set_true(addr)?;
         v.push(addr + DATA_OFFSET);
     }
     save_bitmap(&bitmap)?;
@@ -81,13 +175,17 @@ pub fn alloc_blocks(count: u32) -> Result<Vec<u32>> {
 }
 
 // [PASS]
+/// Drop one reference to each block, freeing those no longer shared.
+/// 
 /// ## Error
 /// 
 /// - InvalidAddr
 /// - DiskErr
 pub fn free_blocks(addrs: &Vec<u32>) -> Result<()> {
+    let addrs = dedup::release(addrs);
+    let _alloc = utils::mutex_lock(ALLOC.lock());
     let mut bitmap = get_bitmap()?;
-    for addr in addrs {
+    for addr in &addrs {
         if let Err(_) = bitmap.set_false(*addr - DATA_OFFSET) {
             return Err(DataError::InvalidAddr)
         };
@@ -0,0 +1,324 @@
+/* Deduplicated Block Store
+ * Data blocks written by write_file are indexed by a hash of their content.
+ * A block holding content already on disk is not written again; the block
+ * found is referenced once more instead. A block referenced more than once
+ * is never changed in place: new content goes to another block (copy on
+ * write), and the block is freed when its last reference is released.
+ *
+ * Reference counts are those of the inodes on disk, live or in snapshots,
+ * so the store is rebuilt at mount rather than saved.
+ */
+
+use super::error::*;
+use std::result;
+
+type Result<T> = result::Result<T, DataError>;
+
+// ====== STORE ======
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+struct Store {
+    refs: HashMap<u32, u32>,
+    hashes: HashMap<u32, u64>,
+    index: HashMap<u64, u32>,
+}
+
+impl Store {
+    // block not tracked is owned by exactly one inode
+    fn refs(&self, addr: u32) -> u32 {
+        *self.refs.get(&addr).unwrap_or(&1)
+    }
+
+    fn insert(&mut self, addr: u32, hash: u64, refs: u32) {
+        self.forget(addr);
+        self.refs.insert(addr, refs);
+        self.hashes.insert(addr, hash);
+        self.index.entry(hash).or_insert(addr);
+    }
+
+    fn forget(&mut self, addr: u32) {
+        self.refs.remove(&addr);
+        if let Some(h) = self.hashes.remove(&addr) {
+            if self.index.get(&h) == Some(&addr) {
+                self.index.remove(&h);
+            }
+        }
+    }
+}
+
+static STORE: Mutex<Option<Store>> = Mutex::new(None);
+
+/// Blocks in the store and references to them.
+///
+/// `saved_blocks`: blocks that would be needed without deduplication
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DedupStats {
+    pub blocks: u32,
+    pub refs: u32,
+    pub saved_blocks: u32,
+}
+
+// ====== FN ======
+
+use crate::logger;
+use super::{disk, data, inode, snapshot, utils};
+
+fn hash(block: &[u8]) -> u64 {
+    let mut h = DefaultHasher::new();
+    block.hash(&mut h);
+    h.finish()
+}
+
+/// Index the data blocks of every inode, including those of snapshots, and
+/// count their references.
+///
+/// ## Error
+///
+/// - DiskErr
+pub fn rebuild() -> Result<()> {
+    let mut store = Store::default();
+    let mut nodes = Vec::<inode::Inode>::new();
+    for addr in used_inodes()? {
+        nodes.push(load_inode(addr)?);
+    }
+    match snapshot::all_inodes() {
+        Ok(mut v) => nodes.append(&mut v),
+        Err(SnapshotError::DiskErr(e)) => return Err(DataError::DiskErr(e)),
+        Err(_) => return Err(DataError::InvalidAddr)
+    }
+    for node in nodes {
+        let blocks = get_blocks(&node)?;
+        let buf = disk::read_blocks(&blocks)?;
+        for (i, b) in blocks.iter().enumerate() {
+            match store.refs.get_mut(b) {
+                Some(r) => *r += 1,
+                None => {
+                    let h = hash(&buf[i * BS..(i + 1) * BS]);
+                    store.insert(*b, h, 1);
+                }
+            }
+        }
+    }
+    let stats = stats_of(&store);
+    *utils::mutex_lock(STORE.lock()) = Some(store);
+    logger::log(&format!(
+        "[FS] Indexed {} data blocks, {} shared blocks saved",
+        stats.blocks, stats.saved_blocks
+    ));
+    Ok(())
+}
+
+const BS: usize = disk::BLOCK_SIZE as usize;
+
+// where the content of one chunk goes
+#[derive(Clone, Copy)]
+enum Slot {
+    Shared(u32),
+    InPlace(u32),
+    Same(usize),
+    Fresh(usize),
+}
+
+/// Store `chunks` (one block each, zero padded) as the new content of a file
+/// whose data blocks were `old`. Return the blocks now holding the content,
+/// in order, and the blocks of `old` to be released by
+/// [free_blocks](data::free_blocks) once the inode no longer points at them.
+///
+/// ## Error
+///
+/// - InsufficientUsableBlocks
+/// - DiskErr
+pub fn store(old: &[u32], chunks: &[Vec<u8>]) -> Result<(Vec<u32>, Vec<u32>)> {
+    let mut guard = utils::mutex_lock(STORE.lock());
+    let store = guard.get_or_insert_with(Store::default);
+
+    // decide without touching the store, so that failing leaves it as it was
+    let mut slots = Vec::<Slot>::with_capacity(chunks.len());
+    let mut hashes = Vec::<u64>::with_capacity(chunks.len());
+    let mut extra = HashMap::<u32, u32>::new();
+    let mut overwritten = HashSet::<u32>::new();
+    let mut batch = HashMap::<u64, usize>::new();
+    let mut fresh = 0;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let h = hash(chunk);
+        hashes.push(h);
+        if let Some(j) = batch.get(&h) {
+            if chunks[*j] == *chunk {
+                slots.push(Slot::Same(*j));
+                continue;
+            }
+        }
+        batch.entry(h).or_insert(i);
+        if let Some(a) = store.index.get(&h).copied() {
+            if !overwritten.contains(&a) && disk::read_blocks(&vec![a])? == *chunk {
+                *extra.entry(a).or_insert(0) += 1;
+                slots.push(Slot::Shared(a));
+                continue;
+            }
+        }
+        match old.get(i) {
+            Some(o) if store.refs(*o) + extra.get(o).unwrap_or(&0) == 1
+                && !overwritten.contains(o) => {
+                overwritten.insert(*o);
+                slots.push(Slot::InPlace(*o));
+            },
+            _ => {
+                slots.push(Slot::Fresh(fresh));
+                fresh += 1;
+            }
+        }
+    }
+    let fresh_addrs = data::alloc_blocks(fresh as u32)?;
+
+    // apply
+    let mut blocks = Vec::<u32>::with_capacity(chunks.len());
+    let mut to_write = Vec::<(u32, Vec<u8>)>::new();
+    let mut kept = HashSet::<u32>::new();
+    for (i, slot) in slots.iter().enumerate() {
+        let addr = match *slot {
+            Slot::Shared(a) => {
+                *store.refs.entry(a).or_insert(1) += 1;
+                a
+            },
+            Slot::Same(j) => {
+                let a = blocks[j];
+                *store.refs.entry(a).or_insert(1) += 1;
+                a
+            },
+            Slot::InPlace(a) => {
+                kept.insert(a);
+                store.insert(a, hashes[i], 1);
+                to_write.push((a, chunks[i].clone()));
+                a
+            },
+            Slot::Fresh(k) => {
+                let a = fresh_addrs[k];
+                store.insert(a, hashes[i], 1);
+                to_write.push((a, chunks[i].clone()));
+                a
+            }
+        };
+        blocks.push(addr);
+    }
+    drop(guard);
+    disk::write_blocks(&to_write)?;
+
+    let released = old.iter().copied().filter(|a| !kept.contains(a)).collect();
+    Ok((blocks, released))
+}
+
+/// Drop one reference to each of `addrs`. Return the blocks no longer
+/// referenced, which are to be freed.
+pub fn release(addrs: &[u32]) -> Vec<u32> {
+    let mut guard = utils::mutex_lock(STORE.lock());
+    let store = match guard.as_mut() {
+        Some(s) => s,
+        None => return addrs.to_vec()
+    };
+    let mut v = Vec::<u32>::with_capacity(addrs.len());
+    for addr in addrs {
+        match store.refs.get_mut(addr) {
+            Some(r) if *r > 1 => *r -= 1,
+            _ => {
+                store.forget(*addr);
+                v.push(*addr);
+            }
+        }
+    }
+    v
+}
+
+/// Take one more reference to each of `addrs`.
+pub fn acquire(addrs: &[u32]) {
+    let mut guard = utils::mutex_lock(STORE.lock());
+    let store = guard.get_or_insert_with(Store::default);
+    for addr in addrs {
+        *store.refs.entry(*addr).or_insert(1) += 1;
+    }
+}
+
+/// Return how many times the block is referenced.
+pub fn refs(addr: u32) -> u32 {
+    match utils::mutex_lock(STORE.lock()).as_ref() {
+        Some(s) => s.refs(addr),
+        None => 1
+    }
+}
+
+/// Register `to` as a copy of `from` taking `refs` of its references, e.g. a
+/// block moved by defrag for one of the inodes sharing it. The references
+/// are dropped from `from` by [free_blocks](data::free_blocks).
+pub fn copied(from: u32, to: u32, refs: u32) {
+    let mut guard = utils::mutex_lock(STORE.lock());
+    if let Some(store) = guard.as_mut() {
+        if let Some(h) = store.hashes.get(&from).copied() {
+            store.insert(to, h, refs);
+        }
+    }
+}
+
+/// Follow blocks moved to other addresses, e.g. by shrinking.
+pub fn remap(map: &HashMap<u32, u32>) {
+    let mut guard = utils::mutex_lock(STORE.lock());
+    let store = match guard.as_mut() {
+        Some(s) => s,
+        None => return
+    };
+    let mut moved = Vec::<(u32, u64, u32)>::new();
+    for (from, to) in map {
+        if let Some(h) = store.hashes.get(from).copied() {
+            moved.push((*to, h, store.refs(*from)));
+            store.forget(*from);
+        }
+    }
+    for (to, h, refs) in moved {
+        store.insert(to, h, refs);
+    }
+}
+
+fn stats_of(store: &Store) -> DedupStats {
+    let blocks = store.refs.len() as u32;
+    let refs = store.refs.values().sum();
+    DedupStats { blocks, refs, saved_blocks: refs - blocks }
+}
+
+/// Return how much space sharing blocks saves.
+pub fn stats() -> DedupStats {
+    match utils::mutex_lock(STORE.lock()).as_ref() {
+        Some(s) => stats_of(s),
+        None => DedupStats::default()
+    }
+}
+
+fn used_inodes() -> Result<Vec<u32>> {
+    match inode::used_inodes() {
+        Ok(v) => Ok(v),
+        Err(e) => Err(inode_err(e))
+    }
+}
+
+fn load_inode(addr: u32) -> Result<inode::Inode> {
+    match inode::load_inode(addr) {
+        Ok(i) => Ok(i),
+        Err(e) => Err(inode_err(e))
+    }
+}
+
+fn get_blocks(node: &inode::Inode) -> Result<Vec<u32>> {
+    match inode::get_blocks(node) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(inode_err(e))
+    }
+}
+
+fn inode_err(e: InodeError) -> DataError {
+    match e {
+        InodeError::DiskErr(e) => DataError::DiskErr(e),
+        _ => DataError::InvalidAddr
+    }
+}
@@ -0,0 +1,202 @@
+// ====== ERROR ======
+
+use std::{error, fmt, result};
+use super::error::*;
+
+#[derive(Debug)]
+pub enum DefragError {
+    DirIncorrupted,
+    NoEnoughSpace,
+    Corrupted,
+    DiskErr(DiskError),
+}
+
+impl error::Error for DefragError {}
+
+impl fmt::Display for DefragError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DefragError: {:?}", self)
+    }
+}
+
+impl From<DiskError> for DefragError {
+    fn from(e: DiskError) -> Self { Self::DiskErr(e) }
+}
+
+impl From<DataError> for DefragError {
+    fn from(e: DataError) -> Self {
+        match e {
+            DataError::InsufficientUsableBlocks => Self::NoEnoughSpace,
+            DataError::InvalidAddr => Self::Corrupted,
+            DataError::DiskErr(e) => Self::DiskErr(e),
+        }
+    }
+}
+
+impl From<InodeError> for DefragError {
+    fn from(e: InodeError) -> Self {
+        match e {
+            InodeError::NoUsableBlock | InodeError::DataTooBig => Self::NoEnoughSpace,
+            InodeError::InvalidAddr => Self::Corrupted,
+            InodeError::DiskErr(e) => Self::DiskErr(e),
+        }
+    }
+}
+
+impl From<DdError> for DefragError {
+    fn from(e: DdError) -> Self {
+        match e {
+            DdError::IoErr(e) => Self::DiskErr(DiskError::IoErr(e)),
+            _ => Self::DirIncorrupted
+        }
+    }
+}
+
+type Result<T> = result::Result<T, DefragError>;
+
+// ====== REPORT ======
+
+/// Fragmentation of one file or directory.
+///
+/// `extents`: runs of consecutive data blocks, 1 being contiguous
+#[derive(Debug)]
+pub struct FileFragment {
+    pub inode: u32,
+    pub path: String,
+    pub blocks: u32,
+    pub extents: u32,
+    pub extents_after: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct DefragReport {
+    pub files: Vec<FileFragment>,
+    pub moved_blocks: u32,
+}
+
+// ====== FN ======
+
+use crate::logger;
+use super::{disk, data, inode, dir, dedup};
+use std::collections::HashMap;
+
+/// Count runs of consecutive addresses in `blocks`.
+pub fn extents(blocks: &[u32]) -> u32 {
+    let mut count = 0;
+    let mut last: Option<u32> = None;
+    for b in blocks {
+        match last {
+            Some(l) if l + 1 == *b => (),
+            _ => count += 1,
+        }
+        last = Some(*b);
+    }
+    count
+}
+
+// map every reachable inode to one of its paths
+fn walk(dir_inode: u32, path: &str, paths: &mut HashMap<u32, String>) -> Result<()> {
+    for ent in dir::read_dir(dir_inode)? {
+        if ent.name == "." || ent.name == ".." || paths.contains_key(&ent.inode) {
+            continue;
+        }
+        let sub_path = format!("{}/{}", path.trim_end_matches('/'), ent.name);
+        paths.insert(ent.inode, sub_path.clone());
+        if inode::load_inode(ent.inode)?.mode & inode::DIR_FLAG > 0 {
+            walk(ent.inode, &sub_path, paths)?;
+        }
+    }
+    Ok(())
+}
+
+/// Measure fragmentation of every file and directory. Unless `analyze_only`,
+/// compact directories and move each fragmented file into one run of free
+/// blocks, index blocks following its data. Files without a large enough
+/// run of free blocks, or sharing blocks with other files, are left where
+/// they are.
+///
+/// ## Error
+///
+/// - DirIncorrupted
+/// - DiskErr
+pub fn defrag(analyze_only: bool) -> Result<DefragReport> {
+    let mut paths = HashMap::<u32, String>::new();
+    paths.insert(0, String::from("/"));
+    walk(0, "/", &mut paths)?;
+
+    let mut report = DefragReport::default();
+    let used = inode::used_inodes()?;
+    let total = used.len();
+    for (i, addr) in used.into_iter().enumerate() {
+        let path = match paths.remove(&addr) {
+            Some(p) => p,
+            None => format!("<inode {addr}>")
+        };
+        let mut node = inode::load_inode(addr)?;
+        if !analyze_only && node.mode & inode::DIR_FLAG > 0 {
+            dir::compact_dir(addr)?;
+            node = inode::load_inode(addr)?;
+        }
+        let blocks = inode::get_blocks(&node)?;
+        let extents = extents(&blocks);
+        let mut frag = FileFragment {
+            inode: addr, path, blocks: blocks.len() as u32, extents, extents_after: extents
+        };
+        if !analyze_only && extents > 1 {
+            if let Some(moved) = relocate(addr, &mut node, &blocks)? {
+                report.moved_blocks += moved;
+                frag.extents_after = 1;
+            }
+        }
+        logger::log(&format!(
+            "[FS] Defrag [{}/{total}] {}: {} blocks, {} -> {} extents",
+            i + 1, frag.path, frag.blocks, frag.extents, frag.extents_after
+        ));
+        report.files.push(frag);
+    }
+    Ok(report)
+}
+
+// move data and index blocks of the inode into one run; return how many
+// blocks moved, or None if there is no run long enough or the file shares
+// blocks with others
+fn relocate(addr: u32, node: &mut inode::Inode, blocks: &[u32]) -> Result<Option<u32>> {
+    let index = inode::get_index_blocks(node)?;
+
+    // a block the file holds more than once moves once
+    let mut uses = HashMap::<u32, u32>::new();
+    let mut unique = Vec::<u32>::with_capacity(blocks.len());
+    for b in blocks {
+        let n = uses.entry(*b).or_insert(0);
+        if *n == 0 {
+            unique.push(*b);
+        }
+        *n += 1;
+    }
+    // moving blocks shared with other files would copy them
+    if unique.iter().any(|b| dedup::refs(*b) > uses[b]) {
+        return Ok(None);
+    }
+    let count = (unique.len() + index.len()) as u32;
+    let run = match data::alloc_contiguous_blocks(count) {
+        Ok(r) => r,
+        Err(DataError::InsufficientUsableBlocks) => return Ok(None),
+        Err(e) => return Err(e.into())
+    };
+
+    let moving: Vec<u32> = unique.iter().chain(index.iter()).copied().collect();
+    let map: HashMap<u32, u32> = moving.iter().copied().zip(run.iter().copied()).collect();
+
+    // copy data first, then switch pointers, then release old blocks
+    let mut to_write = Vec::<(u32, Vec<u8>)>::with_capacity(unique.len());
+    for b in &unique {
+        to_write.push((map[b], disk::read_blocks(&vec![*b])?));
+        dedup::copied(*b, map[b], uses[b]);
+    }
+    disk::write_blocks(&to_write)?;
+    inode::remap_blocks(node, &map)?;
+    inode::save_inode(addr, node)?;
+    let old: Vec<u32> = blocks.iter().chain(index.iter()).copied().collect();
+    data::free_blocks(&old)?;
+    Ok(Some(count))
+}
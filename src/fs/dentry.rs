@@ -0,0 +1,72 @@
+/* Dentry Cache
+ * Path components resolved so far: (parent inode, name) -> child inode, or
+ * None if the parent has no entry of that name.
+ *
+ * filled: a lookup missing the cache reads the parent directory and keeps
+ *     all its entries, so siblings resolve from memory
+ * invalidated: by every change of a directory's entries (add, remove, and
+ *     moves made of both), by freeing an inode, and by snapshots mounting
+ *     or unmounting; everything is dropped once it grows past CAPACITY
+ *
+ * Lookups share the tree lock while every change holds it exclusively, so
+ * nothing changes between reading a directory and caching its entries.
+ */
+
+use super::utils;
+use super::dir::Entry;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Components kept before the cache starts over.
+const CAPACITY: usize = 16384;
+
+type Key = (u32, String);
+
+static CACHE: Mutex<Option<HashMap<Key, Option<u32>>>> = Mutex::new(None);
+
+/// Return `Some(Some(child))` if `name` in `parent` is cached, `Some(None)`
+/// if it is cached as missing, and None if it is not cached.
+pub fn lookup(parent: u32, name: &str) -> Option<Option<u32>> {
+    let guard = utils::mutex_lock(CACHE.lock());
+    guard.as_ref()?.get(&(parent, String::from(name))).copied()
+}
+
+/// Cache the entries of `parent` just read, and `name` as missing if it is
+/// not one of them.
+pub fn fill(parent: u32, ents: &[Entry], name: &str) {
+    let mut guard = utils::mutex_lock(CACHE.lock());
+    let map = guard.get_or_insert_with(HashMap::new);
+    if map.len() + ents.len() >= CAPACITY {
+        map.clear();
+    }
+    for ent in ents {
+        map.insert((parent, ent.name.clone()), Some(ent.inode));
+    }
+    map.entry((parent, String::from(name))).or_insert(None);
+}
+
+/// `name` was added to `parent`, pointing at `child`.
+pub fn added(parent: u32, name: &str, child: u32) {
+    if let Some(map) = utils::mutex_lock(CACHE.lock()).as_mut() {
+        map.insert((parent, String::from(name)), Some(child));
+    }
+}
+
+/// `name` was removed from `parent`.
+pub fn removed(parent: u32, name: &str) {
+    if let Some(map) = utils::mutex_lock(CACHE.lock()).as_mut() {
+        map.insert((parent, String::from(name)), None);
+    }
+}
+
+/// Forget the entries of every directory `parent` matches.
+pub fn forget(parent: impl Fn(u32) -> bool) {
+    if let Some(map) = utils::mutex_lock(CACHE.lock()).as_mut() {
+        map.retain(|(p, _), _| !parent(*p));
+    }
+}
+
+/// Forget everything.
+pub fn clear() {
+    *utils::mutex_lock(CACHE.lock()) = None;
+}
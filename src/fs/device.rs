@@ -0,0 +1,219 @@
+/* Block Devices
+ * Where the image lives. The disk module reads and writes whole slots
+ * (blocks as stored, sealed when the image is encrypted) through the mounted
+ * device, and leaves everything else to it.
+ *
+ * file: an image file on the host, the default
+ * memory: a buffer that is gone once the process exits
+ * mmap: an image file mapped into memory
+ *
+ * Blocks are addressed by index. A block is as long as the buffer passed,
+ * which stays the same for as long as an image is mounted.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::RwLock;
+use memmap2::MmapMut;
+
+/// Storage of an image. Implement it to keep images elsewhere, e.g. in a
+/// directory of chunk files or on a remote store, and mount it with
+/// [start_fs_on](super::start_fs_on).
+pub trait BlockDevice: Send + Sync {
+    /// Fill `buf` with block `addr`.
+    fn read_block(&self, addr: u32, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Write `buf` as block `addr`.
+    fn write_block(&self, addr: u32, buf: &[u8]) -> io::Result<()>;
+
+    /// Make every write so far durable.
+    fn flush(&self) -> io::Result<()>;
+
+    /// Return the size of the device in bytes. 0 if it holds no image yet.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Grow or truncate the device to `len` bytes.
+    fn set_size(&self, len: u64) -> io::Result<()>;
+}
+
+fn out_of_range() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "block past the end of device")
+}
+
+// ====== FILE ======
+
+/// An image file on the host.
+#[derive(Debug)]
+pub struct FileDevice {
+    file: File,
+}
+
+impl FileDevice {
+    /// Open the image file at `path`, creating an empty one if missing.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        Ok(Self { file })
+    }
+
+    /// Open the image file at `path` for reading only. Writes to it fail.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).open(path)?;
+        Ok(Self { file })
+    }
+}
+
+impl BlockDevice for FileDevice {
+    fn read_block(&self, addr: u32, buf: &mut [u8]) -> io::Result<()> {
+        self.file.read_exact_at(buf, addr as u64 * buf.len() as u64)
+    }
+
+    fn write_block(&self, addr: u32, buf: &[u8]) -> io::Result<()> {
+        self.file.write_all_at(buf, addr as u64 * buf.len() as u64)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn set_size(&self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)?;
+        self.file.sync_all()
+    }
+}
+
+// ====== MEMORY ======
+
+/// An image kept in memory only.
+#[derive(Debug, Default)]
+pub struct MemDevice {
+    bytes: RwLock<Vec<u8>>,
+}
+
+impl MemDevice {
+    /// An empty device, formatted when mounted.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A device holding `bytes`, e.g. an image read from elsewhere.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self { bytes: RwLock::new(bytes) }
+    }
+}
+
+impl BlockDevice for MemDevice {
+    fn read_block(&self, addr: u32, buf: &mut [u8]) -> io::Result<()> {
+        let bytes = self.bytes.read().unwrap_or_else(|e| e.into_inner());
+        let start = addr as usize * buf.len();
+        let src = bytes.get(start..start + buf.len()).ok_or_else(out_of_range)?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write_block(&self, addr: u32, buf: &[u8]) -> io::Result<()> {
+        let mut bytes = self.bytes.write().unwrap_or_else(|e| e.into_inner());
+        let start = addr as usize * buf.len();
+        let dst = bytes.get_mut(start..start + buf.len()).ok_or_else(out_of_range)?;
+        dst.copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.bytes.read().unwrap_or_else(|e| e.into_inner()).len() as u64)
+    }
+
+    fn set_size(&self, len: u64) -> io::Result<()> {
+        let mut bytes = self.bytes.write().unwrap_or_else(|e| e.into_inner());
+        bytes.resize(len as usize, 0);
+        bytes.shrink_to_fit();
+        Ok(())
+    }
+}
+
+// ====== MMAP ======
+
+#[derive(Debug)]
+struct Mapping {
+    file: File,
+    // None while the file is empty, which cannot be mapped
+    map: Option<MmapMut>,
+}
+
+/// An image file on the host, mapped into memory.
+#[derive(Debug)]
+pub struct MmapDevice {
+    inner: RwLock<Mapping>,
+}
+
+impl MmapDevice {
+    /// Map the image file at `path`, creating an empty one if missing.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let map = map(&file)?;
+        Ok(Self { inner: RwLock::new(Mapping { file, map }) })
+    }
+}
+
+fn map(file: &File) -> io::Result<Option<MmapMut>> {
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: the file is only changed through this mapping while mounted
+    Ok(Some(unsafe { MmapMut::map_mut(file)? }))
+}
+
+impl BlockDevice for MmapDevice {
+    fn read_block(&self, addr: u32, buf: &mut [u8]) -> io::Result<()> {
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let start = addr as usize * buf.len();
+        let src = inner.map.as_ref()
+            .and_then(|m| m.get(start..start + buf.len()))
+            .ok_or_else(out_of_range)?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write_block(&self, addr: u32, buf: &[u8]) -> io::Result<()> {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        let start = addr as usize * buf.len();
+        let dst = inner.map.as_mut()
+            .and_then(|m| m.get_mut(start..start + buf.len()))
+            .ok_or_else(out_of_range)?;
+        dst.copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        match &inner.map {
+            Some(m) => m.flush(),
+            None => Ok(())
+        }
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        Ok(inner.file.metadata()?.len())
+    }
+
+    fn set_size(&self, len: u64) -> io::Result<()> {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(m) = inner.map.take() {
+            m.flush()?;
+        }
+        inner.file.set_len(len)?;
+        inner.file.sync_all()?;
+        inner.map = map(&inner.file)?;
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@
 
 use std::{error, fmt, result, io};
 use super::error::*;
+use super::FsError;
 
 #[derive(Debug)]
 pub enum DdError {
@@ -17,6 +18,7 @@ pub enum DdError {
     DirIncorrupted,
     NoEnoughSpace,
     EntryExists,
+    ReadOnly,
     IoErr(io::Error),
 }
 
@@ -44,6 +46,7 @@ impl From<DiskError> for DdError {
     fn from(e: DiskError) -> Self {
         match e {
             DiskError::InvalidAddr => return Self::DirIncorrupted,
+            DiskError::CryptErr(_) | DiskError::NoPassphrase => Self::DirIncorrupted,
             DiskError::IoErr(e) => return Self::IoErr(e)
         }
     }
@@ -77,7 +80,31 @@ impl From<FdError> for DdError {
             FdError::FileIncorrupted => return DdError::DirIncorrupted,
             FdError::NoEnoughSpace => return DdError::NoEnoughSpace,
             FdError::IoErr(e) => return DdError::IoErr(e),
-            _ => panic!("{e:?}")
+            _ => FsError::from(e).into()
+        }
+    }
+}
+
+// requests answered by the FS thread
+impl From<FsError> for DdError {
+    fn from(e: FsError) -> Self {
+        use super::FsErrorKind as K;
+        match e {
+            FsError::DirErr(e) => e,
+            FsError::IoErr(e) => Self::IoErr(e),
+            FsError::SendErr(s) => Self::SendErr(s),
+            FsError::RecvErr(s) => Self::RecvErr(s),
+            e => match e.kind() {
+                K::InvalidPath => Self::InvalidPath,
+                K::NotFound => Self::NotFound,
+                K::Exists => Self::EntryExists,
+                K::NotDir | K::IsDir => Self::NotDir,
+                K::Busy => Self::DirOccupied,
+                K::NoSpace => Self::NoEnoughSpace,
+                K::Corrupted => Self::DirIncorrupted,
+                K::ReadOnly => Self::ReadOnly,
+                K::Io | K::Other => Self::IoErr(io::Error::other(format!("{e:?}")))
+            }
         }
     }
 }
@@ -86,6 +113,7 @@ impl DdError {
     fn DiskErr(e: DiskError) -> Self {
         match e {
             DiskError::InvalidAddr => return Self::DirIncorrupted,
+            DiskError::CryptErr(_) | DiskError::NoPassphrase => Self::DirIncorrupted,
             DiskError::IoErr(e) => return Self::IoErr(e)
         }
     }
@@ -98,7 +126,7 @@ type Result<T> = result::Result<T, DdError>;
 use crate::logger;
 use crate::sedes::{Serialize, Deserialize};
 use super::{utils, metadata::Metadata};
-use super::{FdTable, FsReq, FsError};
+use super::{FdTable, FsReq};
 use std::sync::mpsc::{self, Sender};
 use std::sync::{Arc, Mutex};
 
@@ -139,7 +167,10 @@ impl Deserialize for Entry {
         }
         let mut me = Self::default();
         me.inode = utils::u8arr_to_u32(&buf[0..4]);
-        me.name = String::from_utf8(buf[4..].to_vec()).unwrap();
+        me.name = match String::from_utf8(buf[4..].to_vec()) {
+            Ok(n) => n,
+            Err(_) => return Err(SedesError::InvalidData)
+        };
         me.name = String::from(me.name.trim_end_matches('\0'));
         Ok(me)
     }
@@ -188,7 +219,7 @@ impl Dd {
         self.tx.send(FsReq::ReadDir(tx, self.inode))?;
         match rx.recv()? {
             Ok(data) => Ok(data),
-            Err(e) => return Err(DdError::NotFound)
+            Err(e) => Err(e.into())
         }
     }
 
@@ -202,7 +233,7 @@ impl Dd {
         self.tx.send(FsReq::DirAddEntry(tx, self.inode, inode, name.to_string()))?;
         match rx.recv()? {
             Ok(_) => Ok(()),
-            Err(e) => return Err(DdError::NotFound)
+            Err(e) => Err(e.into())
         }
     }
 
@@ -214,7 +245,7 @@ impl Dd {
         self.tx.send(FsReq::DirRemoveEntry(tx, self.inode, inode))?;
         match rx.recv()? {
             Ok(_) => Ok(()),
-            Err(e) => return Err(DdError::NotFound)
+            Err(e) => Err(e.into())
         }
     }
 }
@@ -228,7 +259,7 @@ impl Drop for Dd {
 
 // ====== FN ======
 
-use super::{disk, inode, data, file, metadata::metadata};
+use super::{disk, inode, data, file, snapshot, dentry, metadata::metadata};
 use super::FdError;
 use super::path_to_inode;
 
@@ -244,7 +275,7 @@ pub fn open_dir(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str) ->
         Err(e) => match e {
             FsError::InvalidPath => return Err(DdError::InvalidPath),
             FsError::NotFound => return Err(DdError::NotFound),
-            _ => panic!("{e:?}")
+            _ => return Err(e.into())
         }
     };
     let inode = inode::load_inode(inode_addr)?;
@@ -259,7 +290,7 @@ pub fn open_dir(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str) ->
         Ok(d) => d,
         Err(e) => match e {
             FsError::NotDirButFile => return Err(DdError::NotDir),
-            _ => panic!("{e:?}")
+            _ => return Err(e.into())
         }
     };
 
@@ -294,6 +325,7 @@ pub fn create_dir(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str,
     }
 
     let mut inode = inode::alloc_inode(uid, true)?;
+    inode.1.flags |= inode::load_inode(parent_dd.inode_addr())?.flags & inode::COMPRESS_FLAG;
 
     // add parent/new
     dir_add_entry(parent_dd.inode_addr(), inode.0, &dir_name)?;
@@ -304,7 +336,7 @@ pub fn create_dir(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str,
     // write a empty entry
     let emp_ent = Entry { inode: 0, name: String::from("") };
     let data = [(
-        *blocks.get(0).unwrap(),
+        *blocks.first().ok_or(DdError::NoEnoughSpace)?,
         emp_ent.serialize()
     )].to_vec();
     disk::write_blocks(&data)?;
@@ -325,7 +357,7 @@ pub fn create_dir(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str,
         Ok(d) => d,
         Err(e) => match e {
             FsError::NotDirButFile => return Err(DdError::NotDir),
-            _ => panic!("{e:?}")
+            _ => return Err(e.into())
         }
     };
 
@@ -351,7 +383,7 @@ pub fn remove_dir(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str)
         Err(e) => match e {
             FsError::InvalidPath => return Err(DdError::InvalidPath),
             FsError::NotFound => return Err(DdError::NotFound),
-            _ => panic!("{e:?}")
+            _ => return Err(e.into())
         }
     };
     let inode = inode::load_inode(inode_addr)?;
@@ -401,6 +433,13 @@ pub fn remove_dir(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str)
 /// - DirIncorrupted
 /// - IoErr(e)
 pub fn read_dir(dir_inode: u32) -> Result<Vec<Entry>> {
+    if dir_inode == snapshot::SNAP_ROOT {
+        return match snapshot::root_entries() {
+            Ok(v) => Ok(v),
+            Err(SnapshotError::DiskErr(e)) => Err(DdError::DiskErr(e)),
+            Err(_) => Err(DdError::DirIncorrupted)
+        };
+    }
     let inode = inode::load_inode(dir_inode)?;
     if inode.mode & inode::DIR_FLAG == 0 {
         return Err(DdError::NotDir);
@@ -411,12 +450,20 @@ pub fn read_dir(dir_inode: u32) -> Result<Vec<Entry>> {
     let size = data.len() / ENTRY_SIZE;
     let mut v = Vec::<Entry>::with_capacity(size);
     for i in 0..size {
-        let ent = Entry::deserialize(&mut data[i*ENTRY_SIZE..(i+1)*ENTRY_SIZE].to_vec()).unwrap();
+        let ent = match Entry::deserialize(&mut data[i*ENTRY_SIZE..(i+1)*ENTRY_SIZE].to_vec()) {
+            Ok(e) => e,
+            Err(_) => return Err(DdError::DirIncorrupted)
+        };
         if ent.name == "" {
             break
         }
         v.push(ent);
     }
+    if snapshot::is_virtual(dir_inode) {
+        for ent in v.iter_mut() {
+            ent.inode = snapshot::in_same_snapshot(dir_inode, ent.inode);
+        }
+    }
 
     Ok(v)
 }
@@ -447,7 +494,12 @@ pub fn dir_add_entry(dir_inode: u32, entry_inode: u32, name: &str) -> Result<()>
     data.append(&mut ent.serialize());
     let emp_ent = Entry { inode: 0, name: String::from("") };
     data.append(&mut emp_ent.serialize());
-    file::write_file(dir_inode, &mut data)?;
+    if let Err(e) = file::write_file(dir_inode, &mut data) {
+        // whatever was written is no longer what is cached
+        dentry::forget(|p| p == dir_inode);
+        return Err(e.into());
+    }
+    dentry::added(dir_inode, name, entry_inode);
     logger::log(&format!("[FS] Add an entry to directory:\n    \
         [dir_inode_addr] {dir_inode}, \
         [entry_inode_addr] {entry_inode},\n    \
@@ -465,6 +517,7 @@ pub fn dir_remove_entry(dir_inode: u32, entry_inode: u32) -> Result<()> {
     let mut v = read_dir(dir_inode)?;
     for (i, ent) in v.iter().enumerate() {
         if ent.inode == entry_inode {
+            let name = ent.name.clone();
             v.drain(i..i+1);
             let mut data = Vec::<u8>::with_capacity(v.len() * ENTRY_SIZE);
             for ent in v {
@@ -472,7 +525,11 @@ pub fn dir_remove_entry(dir_inode: u32, entry_inode: u32) -> Result<()> {
             }
             let emp_ent = Entry { inode: 0, name: String::from("") };
             data.append(&mut emp_ent.serialize());
-            file::write_file(dir_inode, &mut data)?;
+            if let Err(e) = file::write_file(dir_inode, &mut data) {
+                dentry::forget(|p| p == dir_inode);
+                return Err(e.into());
+            }
+            dentry::removed(dir_inode, &name);
             logger::log(&format!("[FS] Remove an entry from directory: \n    \
                 [dir_inode_addr] {dir_inode}, \
                 [entry_inode_addr] {entry_inode}\
@@ -481,4 +538,21 @@ pub fn dir_remove_entry(dir_inode: u32, entry_inode: u32) -> Result<()> {
         }
     }
     Err(DdError::NotFound)
-}
\ No newline at end of file
+}
+/// Rewrite the directory file so that it holds exactly its entries and the
+/// empty terminating entry.
+/// 
+/// ## Error
+/// 
+/// - NotFound
+/// - NotDir
+/// - DirIncorrupted
+/// - IoErr
+pub fn compact_dir(dir_inode: u32) -> Result<()> {
+    let ents = read_dir(dir_inode)?;
+    let mut data = entries_to_data(&ents);
+    let emp_ent = Entry { inode: 0, name: String::from("") };
+    data.append(&mut emp_ent.serialize());
+    file::write_file(dir_inode, &mut data)?;
+    Ok(())
+}
@@ -6,6 +6,8 @@ use super::error::*;
 #[derive(Debug)]
 pub enum DiskError {
     InvalidAddr,
+    NoPassphrase,
+    CryptErr(CryptError),
     IoErr(io::Error),
 }
 
@@ -21,141 +23,596 @@ impl From<io::Error> for DiskError {
     fn from(e: io::Error) -> Self { Self::IoErr(e) }
 }
 
+impl From<CryptError> for DiskError {
+    fn from(e: CryptError) -> Self { Self::CryptErr(e) }
+}
+
 type Result<T> = result::Result<T, DiskError>;
 
 // ====== FN ======
 
 use crate::logger;
 use crate::sedes::Serialize;
-use super::{superblock, inode, data, dir};
+use super::{superblock, inode, data, dir, crypt, dentry, utils};
+use super::device::BlockDevice;
 
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, Seek, SeekFrom, Write, Read};
+use std::io;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use zeroize::Zeroizing;
 
-const DISK_PATH: & 'static str = "./the_disk";
+pub const DISK_PATH: & 'static str = "./the_disk";
 pub const DISK_SIZE: u32 = 128 * 1024 * 1024;
 pub const BLOCK_SIZE: u32 = 1024;
+/// Blocks of the commit log of a new image: a header, then the copies.
+pub const LOG_BLOCKS: u32 = 65;
 
-pub fn init_disk() -> Result<()> {
-    match fs::metadata(DISK_PATH) {
-        Ok(meta) => {
-            let file_size = meta.len();
-            if file_size < DISK_SIZE as u64 {
-                logger::log("[FS] Insufficient file size. Remove original file.");
-                fs::remove_file(DISK_PATH)?;
-                create_disk()?;
-            }
-        },
-        Err(e) => match e.kind() {
-            io::ErrorKind::NotFound => {
-                logger::log("[FS] Disk file not found.");
-                create_disk()?;
-            },
-            _ => return Err(DiskError::IoErr(e))
-        }
-    };
+const LOG_MAGIC: u32 = 0x5349_4c47;
+// magic, checksum and count before the addresses in the header
+const LOG_HEADER: usize = 16;
 
-    let mut buf = [0u8; 1];
-    {
-        let mut f = File::open(DISK_PATH)?;
-        f.read_exact(&mut buf)?;
+/// Size of the mounted image in blocks.
+static BLOCK_COUNT: AtomicU32 = AtomicU32::new(DISK_SIZE / BLOCK_SIZE);
+/// Bytes each block takes in the image file.
+static SLOT_SIZE: AtomicU32 = AtomicU32::new(BLOCK_SIZE);
+/// Where the mounted image lives.
+static DEVICE: RwLock<Option<Arc<dyn BlockDevice>>> = RwLock::new(None);
+/// Blocks as they were before the running transaction first wrote them.
+static JOURNAL: Mutex<Option<HashMap<u32, Vec<u8>>>> = Mutex::new(None);
+/// Blocks in the commit log of the mounted image, none if it has no log.
+static LOG_SIZE: AtomicU32 = AtomicU32::new(0);
+/// Blocks written and not committed yet.
+static PENDING: Mutex<Pending> = Mutex::new(Pending { blocks: BTreeMap::new(), writers: 0 });
+
+thread_local! {
+    /// Whether the request served by this thread wrote blocks not committed yet.
+    static WRITING: Cell<bool> = const { Cell::new(false) };
+}
+
+// requests writing count themselves in, and the last one out commits what
+// they all wrote
+#[derive(Debug)]
+struct Pending {
+    blocks: BTreeMap<u32, Zeroizing<Vec<u8>>>,
+    writers: usize,
+}
+
+/// Mount the image on `device`, formatting it if it holds none. A new image is encrypted when
+/// `passphrase` is given; an encrypted image cannot be mounted without it.
+/// 
+/// # Error
+/// 
+/// - NoPassphrase
+/// - CryptErr
+/// - IoErr
+pub fn init_disk(dev: Box<dyn BlockDevice>, passphrase: Option<Zeroizing<String>>) -> Result<()> {
+    *DEVICE.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::from(dev));
+    dentry::clear();
+    discard();
+    if device()?.size()? == 0 {
+        logger::log("[FS] Disk file not found.");
+        create_disk(passphrase.as_deref())?;
     }
+
+    let mut buf = [0u8; 1];
+    device()?.read_block(0, &mut buf)?;
     if buf[0] != 227 {
         logger::log("[FS] Found incorrupted disk file. Remove original file.");
-        fs::remove_file(DISK_PATH)?;
-        create_disk()?;
+        device()?.set_size(0)?;
+        create_disk(passphrase.as_deref())?;
     }
 
+    let sb = read_superblock()?;
+    if sb.encrypted != 0 && !crypt::enabled() {
+        let passphrase = match &passphrase {
+            Some(p) => p,
+            None => return Err(DiskError::NoPassphrase)
+        };
+        unlock(passphrase, &sb.salt)?;
+    } else if sb.encrypted == 0 && passphrase.is_some() {
+        logger::log("[FS] Disk is not encrypted. Passphrase ignored.");
+    }
+    replay(&read_superblock()?)?;
+
+    // images formatted before resizing existed do not record their size
+    let mut sb = read_superblock()?;
+    if sb.block_count == 0 {
+        sb.block_count = DISK_SIZE / BLOCK_SIZE;
+        reserve_tail(sb.block_count)?;
+        save_superblock(&sb)?;
+    }
+
+    let file_size = device()?.size()?;
+    if file_size < offset(sb.block_count) {
+        logger::log("[FS] Insufficient file size. Remove original file.");
+        device()?.set_size(0)?;
+        crypt::lock();
+        create_disk(passphrase.as_deref())?;
+        sb = read_superblock()?;
+    }
+    BLOCK_COUNT.store(sb.block_count, Ordering::SeqCst);
+    mount_log(&sb);
+    commit_now()?;
+
     logger::log("[FS] Initialized disk.");
-    logger::log(&format!("[FS] Superblock: {:?}", superblock::superblock().unwrap()));
+    logger::log(&format!("[FS] Superblock: {:?}", sb));
     Ok(())
 }
 
-fn create_disk() -> Result<()> {
-    {
-        let mut f = OpenOptions::new().write(true).create(true).truncate(false).open(DISK_PATH)?;
-        f.seek(SeekFrom::Start(DISK_SIZE as u64 + 1))?;
-        f.write_all(&[1,0])?;
-        f.flush()?;
+/// Mount the image on `device` as it is. Unlike [init_disk], nothing is formatted, repaired or
+/// written, so the device may be read-only: the last commit is read from the log, not replayed.
+/// 
+/// # Error
+/// 
+/// - InvalidAddr: `device` holds no image
+/// - NoPassphrase
+/// - CryptErr
+/// - IoErr
+pub fn open_disk(dev: Box<dyn BlockDevice>, passphrase: Option<Zeroizing<String>>) -> Result<()> {
+    *DEVICE.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::from(dev));
+    dentry::clear();
+    discard();
+    let mut buf = [0u8; 1];
+    if device()?.size()? == 0 || device()?.read_block(0, &mut buf).is_err() || buf[0] != 227 {
+        return Err(DiskError::InvalidAddr);
     }
+
+    let sb = read_superblock()?;
+    if sb.encrypted != 0 && !crypt::enabled() {
+        match &passphrase {
+            Some(p) => unlock(p, &sb.salt)?,
+            None => return Err(DiskError::NoPassphrase)
+        }
+    }
+    let sb = read_superblock()?;
+    replay(&sb)?;
+    // images formatted before resizing existed do not record their size
+    let sb = read_superblock()?;
+    let count = match sb.block_count {
+        0 => DISK_SIZE / BLOCK_SIZE,
+        c => c
+    };
+    BLOCK_COUNT.store(count, Ordering::SeqCst);
+    mount_log(&sb);
+    Ok(())
+}
+
+fn create_disk(passphrase: Option<&String>) -> Result<()> {
+    discard();
+    let block_count = DISK_SIZE / BLOCK_SIZE;
+    let mut sb = superblock::Superblock::new();
+    sb.log_blocks = LOG_BLOCKS;
+    if let Some(p) = passphrase {
+        sb.encrypted = 1;
+        sb.salt = crypt::new_salt();
+        unlock(p, &sb.salt)?;
+    }
+    device()?.set_size(offset(block_count))?;
+    BLOCK_COUNT.store(block_count, Ordering::SeqCst);
+    seal_empty(data::DATA_OFFSET, block_count)?;
     logger::log("[FS] Created disk file.");
 
     // create superblock
-    let buf = superblock::Superblock::new().serialize();
+    let mut buf = sb.serialize();
+    buf.resize(BLOCK_SIZE as usize, 0);
     write_blocks(&[(0, buf)].to_vec())?;
     logger::log("[FS] Initialized superblock.");
 
-    // initialize inode and data bitmap
+    // initialize inode bitmap, inodes and data bitmap
     let buf = [0u8; BLOCK_SIZE as usize];
-    write_blocks(&[(inode::BITMAP_OFFSET, buf.to_vec())].to_vec())?;
     let mut data = Vec::new();
-    for addr in data::BITMAP_OFFSET..data::DATA_OFFSET {
+    for addr in inode::BITMAP_OFFSET..data::DATA_OFFSET {
         data.push((addr, buf.to_vec()));
     }
     write_blocks(&data)?;
+    reserve_tail(block_count)?;
+    alloc_log()?;
 
     // create dir: /
     let (root_inode_addr, _) = match inode::alloc_inode(0, true) {
         Ok(a) => a,
         Err(e) => match e {
             super::inode::InodeError::DiskErr(e) => return Err(e),
-            _ => panic!("{e:?}")
+            _ => return Err(DiskError::InvalidAddr)
         }
     };
     if let Err(e) = dir::dir_add_entry(root_inode_addr, root_inode_addr, ".") {
         match e {
             DdError::InvalidPath => return Err(DiskError::InvalidAddr),
             DdError::IoErr(e) => return Err(DiskError::IoErr(e)),
-            _ => panic!("{e:?}")
+            _ => return Err(DiskError::InvalidAddr)
         }
     }
 
     logger::log("[FS] Initialized root dir.");
 
+    // a crash before this leaves an image to format again
+    commit_now()
+}
+
+fn read_superblock() -> Result<superblock::Superblock> {
+    match superblock::superblock() {
+        Ok(sb) => Ok(sb),
+        Err(e) => match e {
+            SuperblockError::IoErr(e) => Err(DiskError::IoErr(e)),
+            SuperblockError::DiskErr(e) => Err(e),
+            _ => Err(DiskError::InvalidAddr)
+        }
+    }
+}
+
+fn save_superblock(sb: &superblock::Superblock) -> Result<()> {
+    match superblock::save_superblock(sb) {
+        Ok(_) => Ok(()),
+        Err(e) => match e {
+            SuperblockError::IoErr(e) => Err(DiskError::IoErr(e)),
+            SuperblockError::DiskErr(e) => Err(e),
+            _ => Err(DiskError::InvalidAddr)
+        }
+    }
+}
+
+// derive the key and check it against the authenticated superblock
+fn unlock(passphrase: &str, salt: &[u8]) -> Result<()> {
+    crypt::unlock(passphrase, salt)?;
+    SLOT_SIZE.store(BLOCK_SIZE + crypt::OVERHEAD, Ordering::SeqCst);
+    if device()?.size()? == 0 {
+        return Ok(());
+    }
+    match read_blocks(&vec![0]) {
+        Ok(_) => {
+            logger::log("[FS] Unlocked encrypted disk.");
+            Ok(())
+        },
+        Err(DiskError::CryptErr(_)) => {
+            crypt::lock();
+            SLOT_SIZE.store(BLOCK_SIZE, Ordering::SeqCst);
+            Err(DiskError::CryptErr(CryptError::WrongPassphrase))
+        },
+        Err(e) => Err(e)
+    }
+}
+
+fn device() -> Result<Arc<dyn BlockDevice>> {
+    match DEVICE.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        Some(d) => Ok(d.clone()),
+        None => Err(DiskError::IoErr(io::Error::new(io::ErrorKind::NotConnected, "no device mounted")))
+    }
+}
+
+fn slot_size() -> usize {
+    SLOT_SIZE.load(Ordering::SeqCst) as usize
+}
+
+// position of a block in the image file
+fn offset(addr: u32) -> u64 {
+    addr as u64 * SLOT_SIZE.load(Ordering::SeqCst) as u64
+}
+
+// mark data bitmap bits past the end of the image as used
+fn reserve_tail(block_count: u32) -> Result<()> {
+    match data::reserve_tail(block_count) {
+        Ok(_) => Ok(()),
+        Err(e) => match e {
+            DataError::DiskErr(e) => Err(e),
+            _ => Err(DiskError::InvalidAddr)
+        }
+    }
+}
+
+// take the first data blocks of a new image for the commit log
+fn alloc_log() -> Result<()> {
+    match data::alloc_blocks(LOG_BLOCKS) {
+        Ok(v) if v.first() == Some(&data::DATA_OFFSET) => Ok(()),
+        Err(DataError::DiskErr(e)) => Err(e),
+        _ => Err(DiskError::InvalidAddr)
+    }
+}
+
+/// Return the size of the mounted image in blocks.
+pub fn block_count() -> u32 {
+    BLOCK_COUNT.load(Ordering::SeqCst)
+}
+
+/// Grow or truncate the image to `count` blocks.
+/// 
+/// # Error
+/// 
+/// - IoErr
+pub fn set_block_count(count: u32) -> Result<()> {
+    // nothing pending may be left past the end
+    commit_now()?;
+    let old = block_count();
+    device()?.set_size(offset(count))?;
+    BLOCK_COUNT.store(count, Ordering::SeqCst);
+    seal_empty(old.max(data::DATA_OFFSET), count)
+}
+
+// seal zeros into slots `from..to` of an encrypted image, so that every slot
+// read is authenticated
+fn seal_empty(from: u32, to: u32) -> Result<()> {
+    if !crypt::enabled() {
+        return Ok(());
+    }
+    let dev = device()?;
+    let block = [0u8; BLOCK_SIZE as usize];
+    for addr in from..to {
+        dev.write_block(addr, &crypt::seal(addr, &block)?)?;
+    }
     Ok(())
 }
 
 // [PASS]
+/// Blocks written and not committed yet are read as written.
+/// 
 /// # Error
 /// 
 /// - InvalidAddr
+/// - CryptErr: block of an encrypted image failed verification
 /// - IoErr
 pub fn read_blocks(addrs: &Vec<u32>) -> Result<Vec<u8>> {
     let mut v = Vec::<u8>::new();
-    let mut f = File::open(DISK_PATH)?;
+    let dev = device()?;
     for addr in addrs {
-        if *addr >= DISK_SIZE {
+        if *addr >= block_count() {
             return Err(DiskError::InvalidAddr);
         }
-        let mut buf = [0u8; 1024];
-        f.seek(SeekFrom::Start((*addr * BLOCK_SIZE) as u64))?;
-        f.read_exact(&mut buf)?;
-        v.append(&mut buf.to_vec())
+        let pending = utils::mutex_lock(PENDING.lock()).blocks.get(addr).cloned();
+        match pending {
+            Some(block) => v.extend_from_slice(&block),
+            None => v.append(&mut read_block(dev.as_ref(), *addr)?)
+        }
     }
     Ok(v)
 }
 
+fn read_block(dev: &dyn BlockDevice, addr: u32) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; slot_size()];
+    dev.read_block(addr, &mut buf)?;
+    if crypt::enabled() {
+        return Ok(crypt::open(addr, buf)?);
+    }
+    Ok(buf)
+}
+
 // [PASS]
+/// Blocks are kept in memory until [commit] writes them to the image.
+/// 
+/// # Error
+/// 
+/// - InvalidAddr
+/// - CryptErr
+/// - IoErr
 pub fn write_blocks(data: &Vec<(u32, Vec<u8>)>) -> Result<()> {
     for (addr, _) in data {
-        if *addr >= DISK_SIZE {
+        if *addr >= block_count() {
             return Err(DiskError::InvalidAddr);
         }
     }
-    let mut f = OpenOptions::new().write(true).truncate(false).open(DISK_PATH)?;
+    if let Some(journal) = utils::mutex_lock(JOURNAL.lock()).as_mut() {
+        for (addr, _) in data {
+            if !journal.contains_key(addr) {
+                journal.insert(*addr, read_blocks(&vec![*addr])?);
+            }
+        }
+    }
+    if !WRITING.with(|w| w.replace(true)) {
+        utils::mutex_lock(PENDING.lock()).writers += 1;
+    }
     for (addr, buf) in data {
-        f.seek(SeekFrom::Start((*addr * BLOCK_SIZE) as u64))?;
-        if buf.len() < BLOCK_SIZE as usize {
-            f.write_all(&buf[..])?;
-            f.write_all(b"\0")?;
+        let block = if buf.len() < BLOCK_SIZE as usize {
+            // a short buffer only replaces the head of the block
+            let mut old = Zeroizing::new(read_blocks(&vec![*addr])?);
+            old[..buf.len()].copy_from_slice(buf);
+            old[buf.len()] = 0;
+            old
         } else {
-            f.write_all(&buf[..BLOCK_SIZE as usize])?;
+            Zeroizing::new(buf[..BLOCK_SIZE as usize].to_vec())
+        };
+        utils::mutex_lock(PENDING.lock()).blocks.insert(*addr, block);
+    }
+    Ok(())
+}
+
+// write a block in its slot, sealed if the image is encrypted
+fn write_slot(dev: &dyn BlockDevice, addr: u32, block: &[u8]) -> Result<()> {
+    if crypt::enabled() {
+        dev.write_block(addr, &crypt::seal(addr, block)?)?;
+    } else {
+        dev.write_block(addr, block)?;
+    }
+    Ok(())
+}
+
+/* Commit Log
+ * Blocks written by requests are kept in memory, and reach the image
+ * together once no request is writing any more. A crash leaves the image
+ * as it was before them, or after them.
+ *
+ * layout: the first data blocks of the image; a header holding a checksum
+ *     and the addresses committed, then a copy of each block, in order
+ * commit: copies and header are written and flushed, then the blocks in
+ *     place, flushed again before the log is reused
+ * replay: when mounted, the blocks of a header matching its checksum are
+ *     written in place again; a log torn or left half written does not
+ *     match, and the blocks in place were not touched yet
+ *
+ * Images formatted before the log existed are written in place. More blocks
+ * than the log holds are committed in parts, which a crash may split.
+ */
+
+/// Commit what the request served by this thread wrote. It is written once
+/// every request writing alongside is done too, by the last of them; until
+/// then it is read from memory.
+/// 
+/// # Error
+/// 
+/// - CryptErr
+/// - IoErr
+pub fn commit() -> Result<()> {
+    if !WRITING.with(|w| w.replace(false)) {
+        return Ok(());
+    }
+    let mut pending = utils::mutex_lock(PENDING.lock());
+    pending.writers = pending.writers.saturating_sub(1);
+    if pending.writers > 0 {
+        return Ok(());
+    }
+    commit_pending(&mut pending)
+}
+
+// commit every pending block at once, the caller serving the only request
+fn commit_now() -> Result<()> {
+    let mut pending = utils::mutex_lock(PENDING.lock());
+    if WRITING.with(|w| w.replace(false)) {
+        pending.writers = pending.writers.saturating_sub(1);
+    }
+    commit_pending(&mut pending)
+}
+
+// blocks stay pending if writing them fails, so that the next commit
+// writes them again
+fn commit_pending(pending: &mut Pending) -> Result<()> {
+    if pending.blocks.is_empty() {
+        return Ok(());
+    }
+    let dev = device()?;
+    let blocks: Vec<(u32, &[u8])> = pending.blocks.iter().map(|(a, b)| (*a, b.as_slice())).collect();
+    match LOG_SIZE.load(Ordering::SeqCst) {
+        0 => {
+            for (addr, block) in &blocks {
+                write_slot(dev.as_ref(), *addr, block)?;
+            }
+        },
+        size => {
+            for part in blocks.chunks(size as usize - 1) {
+                commit_part(dev.as_ref(), part)?;
+            }
         }
     }
-    f.seek(SeekFrom::Start(DISK_SIZE as u64 + 1))?;
-    f.write_all(&[1,0])?;
-    f.seek(SeekFrom::Start(DISK_SIZE as u64 + 1))?;
-    f.write_all(&[1,0])?;
-    f.flush()?;
+    pending.blocks.clear();
+    Ok(())
+}
+
+fn commit_part(dev: &dyn BlockDevice, part: &[(u32, &[u8])]) -> Result<()> {
+    let mut head = Zeroizing::new(vec![0u8; BLOCK_SIZE as usize]);
+    head[12..16].copy_from_slice(&utils::u32_to_u8arr(part.len() as u32));
+    for (i, (addr, _)) in part.iter().enumerate() {
+        let at = LOG_HEADER + i * 4;
+        head[at..at + 4].copy_from_slice(&utils::u32_to_u8arr(*addr));
+    }
+    let sum = checksum(&head, part.iter().map(|(_, b)| *b));
+    head[0..4].copy_from_slice(&utils::u32_to_u8arr(LOG_MAGIC));
+    head[4..12].copy_from_slice(&utils::u64_to_u8arr(sum));
+
+    for (i, (_, block)) in part.iter().enumerate() {
+        write_slot(dev, data::DATA_OFFSET + 1 + i as u32, block)?;
+    }
+    write_slot(dev, data::DATA_OFFSET, &head)?;
+    dev.flush()?;
+    for (addr, block) in part {
+        write_slot(dev, *addr, block)?;
+    }
+    dev.flush()?;
     Ok(())
-}
\ No newline at end of file
+}
+
+// FNV-1a over the count and addresses of the header, then the blocks; unlike
+// the std hasher, it does not change between builds
+fn checksum<'a>(head: &'a [u8], blocks: impl Iterator<Item = &'a [u8]>) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in std::iter::once(&head[12..]).chain(blocks) {
+        for b in part {
+            h ^= *b as u64;
+            h = h.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    h
+}
+
+// use the log of the image, unless it cannot hold a block or lists more
+// than a header holds
+fn mount_log(sb: &superblock::Superblock) {
+    let n = sb.log_blocks;
+    let usable = n >= 2 && (n - 1) as usize * 4 <= BLOCK_SIZE as usize - LOG_HEADER;
+    LOG_SIZE.store(if usable { n } else { 0 }, Ordering::SeqCst);
+}
+
+// forget what the last image mounted had pending
+fn discard() {
+    let mut pending = utils::mutex_lock(PENDING.lock());
+    pending.blocks.clear();
+    pending.writers = 0;
+    WRITING.with(|w| w.set(false));
+    LOG_SIZE.store(0, Ordering::SeqCst);
+}
+
+// make the blocks of the last commit pending again, if the header matches
+// them; committing them is harmless if they already reached their places
+fn replay(sb: &superblock::Superblock) -> Result<()> {
+    mount_log(sb);
+    let size = LOG_SIZE.load(Ordering::SeqCst);
+    if size == 0 {
+        return Ok(());
+    }
+    let dev = device()?;
+    // a slot failing verification was torn, and its log never committed
+    let head = match read_block(dev.as_ref(), data::DATA_OFFSET) {
+        Ok(h) => h,
+        Err(DiskError::CryptErr(_)) => return Ok(()),
+        Err(e) => return Err(e)
+    };
+    let count = utils::u8arr_to_u32(&head[12..16]);
+    if utils::u8arr_to_u32(&head[0..4]) != LOG_MAGIC || count >= size {
+        return Ok(());
+    }
+    let mut blocks = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let at = LOG_HEADER + i as usize * 4;
+        let addr = utils::u8arr_to_u32(&head[at..at + 4]);
+        if addr >= sb.block_count {
+            return Ok(());
+        }
+        match read_block(dev.as_ref(), data::DATA_OFFSET + 1 + i) {
+            Ok(b) => blocks.push((addr, Zeroizing::new(b))),
+            Err(DiskError::CryptErr(_)) => return Ok(()),
+            Err(e) => return Err(e)
+        }
+    }
+    let sum = checksum(&head, blocks.iter().map(|(_, b)| &b[..BLOCK_SIZE as usize]));
+    if sum != utils::u8arr_to_u64(&head[4..12]) {
+        return Ok(());
+    }
+    utils::mutex_lock(PENDING.lock()).blocks.extend(blocks);
+    logger::log(&format!("[FS] Replayed commit of {count} blocks"));
+    Ok(())
+}
+
+/// Keep what blocks hold before they are written, until
+/// [end_journal] commits or rolls back the writes.
+pub fn begin_journal() {
+    *utils::mutex_lock(JOURNAL.lock()) = Some(HashMap::new());
+}
+
+/// Stop journaling. Unless `commit`, write back every block written since
+/// [begin_journal] as it was.
+/// 
+/// # Error
+/// 
+/// - CryptErr
+/// - IoErr
+pub fn end_journal(commit: bool) -> Result<()> {
+    let journal = match utils::mutex_lock(JOURNAL.lock()).take() {
+        Some(j) => j,
+        None => return Ok(())
+    };
+    if commit {
+        return Ok(());
+    }
+    write_blocks(&journal.into_iter().collect())?;
+    self::commit()?;
+    logger::log("[FS] Rolled back transaction");
+    Ok(())
+}
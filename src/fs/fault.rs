@@ -0,0 +1,429 @@
+/* Fault Injection
+ * A device recording every block write, for crash testing. From the image
+ * as it was when recording began, any crash during the writes since can be
+ * replayed, as power loss would leave the image:
+ *
+ * clean: the first n writes reached the device, nothing after them did
+ * torn: the n-th write reached it only up to a sector boundary
+ * reordered: the first n writes reached it except an earlier one, written
+ *     out of order and lost; a flush keeps writes before it from being lost
+ *
+ * The crash test mounts each crash image, checks it, and reports what it
+ * found for each operation tested.
+ *
+ * Only built for tests, or with the `fault-injection` feature.
+ */
+
+use super::device::BlockDevice;
+use super::utils;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Writes reach a device in whole sectors.
+const SECTOR: usize = 512;
+
+// blocks never written read as zeros
+#[derive(Debug, Clone, Default)]
+struct Blocks {
+    size: u64,
+    blocks: HashMap<u32, Vec<u8>>,
+}
+
+fn read_into(size: u64, block: Option<&Vec<u8>>, addr: u32, buf: &mut [u8]) -> io::Result<()> {
+    if (addr as u64 + 1) * buf.len() as u64 > size {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "block past the end of device"));
+    }
+    match block {
+        Some(b) => buf.copy_from_slice(&b[..buf.len()]),
+        None => buf.fill(0)
+    }
+    Ok(())
+}
+
+impl Blocks {
+    fn read(&self, addr: u32, buf: &mut [u8]) -> io::Result<()> {
+        read_into(self.size, self.blocks.get(&addr), addr, buf)
+    }
+
+    fn write(&mut self, addr: u32, buf: &[u8]) {
+        self.size = self.size.max((addr as u64 + 1) * buf.len() as u64);
+        self.blocks.insert(addr, buf.to_vec());
+    }
+
+    fn set_size(&mut self, len: u64) {
+        self.blocks.retain(|addr, b| (*addr as u64 + 1) * b.len() as u64 <= len);
+        self.size = len;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Recorder {
+    now: Blocks,
+    base: Arc<Blocks>,
+    writes: Vec<(u32, Vec<u8>)>,
+    // writes recorded before each flush
+    flushes: Vec<usize>,
+}
+
+/// A device in memory recording the writes it gets. Clones share the image,
+/// so one can be mounted while another looks at what is written.
+#[derive(Debug, Clone, Default)]
+pub struct FaultDevice {
+    rec: Arc<Mutex<Recorder>>,
+}
+
+impl FaultDevice {
+    /// An empty device, formatted when mounted.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start recording from the image as it is now, forgetting the writes
+    /// recorded so far.
+    pub fn mark(&self) {
+        let mut rec = utils::mutex_lock(self.rec.lock());
+        rec.base = Arc::new(rec.now.clone());
+        rec.writes.clear();
+        rec.flushes.clear();
+    }
+
+    /// Return the writes recorded since [mark](Self::mark).
+    pub fn recording(&self) -> Recording {
+        let rec = utils::mutex_lock(self.rec.lock());
+        Recording {
+            base: rec.base.clone(),
+            writes: Arc::new(rec.writes.clone()),
+            flushes: rec.flushes.clone(),
+        }
+    }
+}
+
+impl BlockDevice for FaultDevice {
+    fn read_block(&self, addr: u32, buf: &mut [u8]) -> io::Result<()> {
+        utils::mutex_lock(self.rec.lock()).now.read(addr, buf)
+    }
+
+    fn write_block(&self, addr: u32, buf: &[u8]) -> io::Result<()> {
+        let mut rec = utils::mutex_lock(self.rec.lock());
+        rec.now.write(addr, buf);
+        rec.writes.push((addr, buf.to_vec()));
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut rec = utils::mutex_lock(self.rec.lock());
+        let n = rec.writes.len();
+        rec.flushes.push(n);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(utils::mutex_lock(self.rec.lock()).now.size)
+    }
+
+    fn set_size(&self, len: u64) -> io::Result<()> {
+        let mut rec = utils::mutex_lock(self.rec.lock());
+        rec.now.set_size(len);
+        // resizing is durable at once
+        let n = rec.writes.len();
+        rec.flushes.push(n);
+        Ok(())
+    }
+}
+
+/// Where power is lost, counting writes since recording began.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crash {
+    /// After the first `n` writes.
+    After(usize),
+    /// After the first `n` writes and `bytes` of the next one.
+    Torn { n: usize, bytes: usize },
+    /// After the first `n` writes, except the `lost` one.
+    Reordered { n: usize, lost: usize },
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::After(n) => write!(f, "after write {n}"),
+            Self::Torn { n, bytes } => write!(f, "write {} torn at {bytes} bytes", n + 1),
+            Self::Reordered { n, lost } => write!(f, "after write {n} with write {} lost", lost + 1),
+        }
+    }
+}
+
+/// Writes recorded by a [FaultDevice].
+#[derive(Debug, Clone)]
+pub struct Recording {
+    base: Arc<Blocks>,
+    writes: Arc<Vec<(u32, Vec<u8>)>>,
+    flushes: Vec<usize>,
+}
+
+impl Recording {
+    /// Return how many writes were recorded.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Return `true` if nothing was written.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Return every crash the writes can end in. Crashes leaving the same
+    /// image as another are left out.
+    pub fn crashes(&self) -> Vec<Crash> {
+        let mut v: Vec<Crash> = (0..=self.len()).map(Crash::After).collect();
+        for (n, (_, buf)) in self.writes.iter().enumerate() {
+            for bytes in (SECTOR..buf.len()).step_by(SECTOR) {
+                v.push(Crash::Torn { n, bytes });
+            }
+        }
+        for n in 2..=self.len() {
+            let durable = self.flushes.iter().copied().filter(|f| *f < n).max().unwrap_or(0);
+            for lost in durable..n - 1 {
+                // a write overwritten before the crash is not missed
+                let addr = self.writes[lost].0;
+                if self.writes[lost + 1..n].iter().all(|(a, _)| *a != addr) {
+                    v.push(Crash::Reordered { n, lost });
+                }
+            }
+        }
+        v
+    }
+
+    /// Return the image as left by `crash`.
+    pub fn image(&self, crash: Crash) -> CrashImage {
+        let (n, skip) = match crash {
+            Crash::After(n) | Crash::Torn { n, .. } => (n, None),
+            Crash::Reordered { n, lost } => (n, Some(lost))
+        };
+        let mut over = Blocks { size: self.base.size, blocks: HashMap::new() };
+        for (i, (addr, buf)) in self.writes[..n].iter().enumerate() {
+            if Some(i) != skip {
+                over.write(*addr, buf);
+            }
+        }
+        let image = CrashImage { base: self.base.clone(), over: RwLock::new(over) };
+        if let Crash::Torn { n, bytes } = crash {
+            let (addr, buf) = &self.writes[n];
+            let mut torn = vec![0u8; buf.len()];
+            // an image too short for the block leaves it all to the write
+            let _ = image.read_block(*addr, &mut torn);
+            torn[..bytes].copy_from_slice(&buf[..bytes]);
+            let _ = image.write_block(*addr, &torn);
+        }
+        image
+    }
+}
+
+/// An image left by a crash: the image recording began from, and the writes
+/// that reached it on top.
+#[derive(Debug)]
+pub struct CrashImage {
+    base: Arc<Blocks>,
+    over: RwLock<Blocks>,
+}
+
+impl BlockDevice for CrashImage {
+    fn read_block(&self, addr: u32, buf: &mut [u8]) -> io::Result<()> {
+        let over = self.over.read().unwrap_or_else(|e| e.into_inner());
+        let block = over.blocks.get(&addr).or_else(|| self.base.blocks.get(&addr));
+        read_into(over.size, block, addr, buf)
+    }
+
+    fn write_block(&self, addr: u32, buf: &[u8]) -> io::Result<()> {
+        self.over.write().unwrap_or_else(|e| e.into_inner()).write(addr, buf);
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.over.read().unwrap_or_else(|e| e.into_inner()).size)
+    }
+
+    fn set_size(&self, len: u64) -> io::Result<()> {
+        self.over.write().unwrap_or_else(|e| e.into_inner()).set_size(len);
+        Ok(())
+    }
+}
+
+// ====== CRASH TEST ======
+
+use crate::logger;
+use super::{disk, check, snapshot, FsReq, FsError, OpenFlags, Problem};
+use super::{start_fs_on, create_file, create_dir, open_file, remove_dir, resize};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+/// Crashes of one operation that left the image with problems.
+///
+/// `leaking`: crashes leaving only inodes or blocks that are used but lost
+///
+/// `broken`: crashes leaving anything worse, with what was found
+#[derive(Debug)]
+pub struct CrashReport {
+    pub op: &'static str,
+    pub writes: usize,
+    pub crashes: usize,
+    pub leaking: usize,
+    pub broken: Vec<(Crash, Vec<Problem>)>,
+}
+
+// what to prepare, and the operation to crash in
+type Step = fn(&mut Sender<FsReq>) -> Result<(), FsError>;
+
+const DATA_LEN: usize = 12 * 1024;
+
+fn prepare_none(_: &mut Sender<FsReq>) -> Result<(), FsError> {
+    Ok(())
+}
+
+fn prepare_write(tx: &mut Sender<FsReq>) -> Result<(), FsError> {
+    create_file(tx, "/crash/data", 0)?;
+    Ok(())
+}
+
+fn prepare_remove(tx: &mut Sender<FsReq>) -> Result<(), FsError> {
+    create_dir(tx, "/crash/gone", 0)?;
+    Ok(())
+}
+
+fn do_create_file(tx: &mut Sender<FsReq>) -> Result<(), FsError> {
+    create_file(tx, "/crash/file", 0)?;
+    Ok(())
+}
+
+fn do_create_dir(tx: &mut Sender<FsReq>) -> Result<(), FsError> {
+    create_dir(tx, "/crash/dir", 0)?;
+    Ok(())
+}
+
+// long enough to need an indirect block
+fn do_write_file(tx: &mut Sender<FsReq>) -> Result<(), FsError> {
+    let mut fd = open_file(tx, "/crash/data", OpenFlags::WRITE, 0)?;
+    let data: Vec<u8> = (0..DATA_LEN).map(|i| (i % 251) as u8).collect();
+    fd.write(&data)?;
+    Ok(())
+}
+
+fn do_remove_dir(tx: &mut Sender<FsReq>) -> Result<(), FsError> {
+    remove_dir(tx, "/crash/gone")
+}
+
+const OPS: [(&str, Step, Step); 4] = [
+    ("create_file", prepare_none, do_create_file),
+    ("create_dir", prepare_none, do_create_dir),
+    ("write_file", prepare_write, do_write_file),
+    ("remove_dir", prepare_remove, do_remove_dir),
+];
+
+/// Blocks of the image tested: small, so that checking each crash is quick.
+const IMAGE_BLOCKS: u32 = 1024;
+
+/// Run `create_file`, `create_dir`, `write_file` and `remove_dir` on a fresh
+/// image recorded by a [FaultDevice]. Then mount the image left by every
+/// crash they can end in, and check it.
+///
+/// !!!NOTE!!!: mounts images of its own; never run it in a process serving a
+/// file system.
+///
+/// ## Error
+///
+/// - errors of the operations, or of checking an image
+pub fn crash_test() -> Result<Vec<CrashReport>, FsError> {
+    run_ops(&OPS)
+}
+
+fn run_ops(ops: &[(&'static str, Step, Step)]) -> Result<Vec<CrashReport>, FsError> {
+    let dev = FaultDevice::new();
+    let (mut tx, rx) = mpsc::channel();
+    let (started_tx, started_rx) = mpsc::channel();
+    let mounted = Box::new(dev.clone());
+    let self_tx = tx.clone();
+    thread::spawn(move || start_fs_on(mounted, started_tx, self_tx, rx, None));
+    if started_rx.recv()?.is_err() {
+        return Err(FsError::InnerError);
+    }
+    resize(&mut tx, IMAGE_BLOCKS)?;
+    create_dir(&mut tx, "/crash", 0)?;
+
+    let mut recorded = Vec::with_capacity(ops.len());
+    for (op, prepare, run) in ops {
+        prepare(&mut tx)?;
+        dev.mark();
+        run(&mut tx)?;
+        recorded.push((*op, dev.recording()));
+    }
+    // the file system is left idle from here on: every image is checked on
+    // its own, in place of the recorded one
+    drop(tx);
+
+    recorded.into_iter().map(|(op, rec)| check_crashes(op, &rec)).collect()
+}
+
+fn check_crashes(op: &'static str, rec: &Recording) -> Result<CrashReport, FsError> {
+    let crashes = rec.crashes();
+    let mut report = CrashReport { op, writes: rec.len(), crashes: crashes.len(), leaking: 0, broken: Vec::new() };
+    for crash in crashes {
+        snapshot::unmount_all();
+        disk::init_disk(Box::new(rec.image(crash)), None).map_err(FsError::DiskErr)?;
+        let problems = check::check()?;
+        if problems.is_empty() {
+            continue;
+        }
+        match problems.iter().all(|p| p.is_leak()) {
+            true => report.leaking += 1,
+            false => report.broken.push((crash, problems))
+        }
+    }
+    logger::log(&format!(
+        "[FS] Crash test of {op}: {} writes, {} crashes, {} leaking, {} broken",
+        report.writes, report.crashes, report.leaking, report.broken.len()
+    ));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the image is mounted globally, so one test runs at a time
+    static MOUNTED: Mutex<()> = Mutex::new(());
+
+    fn assert_consistent(op: &str) {
+        let _mounted = utils::mutex_lock(MOUNTED.lock());
+        logger::mute(true);
+        let ops: Vec<_> = OPS.into_iter().filter(|(name, ..)| *name == op).collect();
+        let report = run_ops(&ops).unwrap().remove(0);
+        assert!(report.crashes > 0);
+        assert_eq!(report.leaking, 0, "{report:?}");
+        assert!(report.broken.is_empty(), "{report:?}");
+    }
+
+    #[test]
+    fn create_file_survives_crashes() {
+        assert_consistent("create_file");
+    }
+
+    #[test]
+    fn create_dir_survives_crashes() {
+        assert_consistent("create_dir");
+    }
+
+    #[test]
+    fn write_file_survives_crashes() {
+        assert_consistent("write_file");
+    }
+
+    #[test]
+    fn remove_dir_survives_crashes() {
+        assert_consistent("remove_dir");
+    }
+}
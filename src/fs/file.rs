@@ -16,6 +16,13 @@ pub enum FdError {
     FileOccupied,
     FileIncorrupted,
     NoEnoughSpace,
+    ReadOnly,
+    Locked,
+    TimedOut,
+    InvalidFlags,
+    NotReadable,
+    NotWritable,
+    InvalidOffset,
     IoErr(io::Error),
 }
 
@@ -35,14 +42,63 @@ impl From<mpsc::RecvError> for FdError {
     fn from(e: mpsc::RecvError) -> Self { Self::RecvErr(format!("{e:?}")) }
 }
 
+// an FdError passed through io::Read or io::Write comes back as it was
 impl From<io::Error> for FdError {
-    fn from(e: io::Error) -> Self { Self::IoErr(e) }
+    fn from(e: io::Error) -> Self {
+        match e.downcast::<FdError>() {
+            Ok(e) => e,
+            Err(e) => Self::IoErr(e)
+        }
+    }
+}
+
+impl From<FdError> for io::Error {
+    fn from(e: FdError) -> Self {
+        let kind = match e {
+            FdError::IoErr(e) => return e,
+            FdError::NotFound => io::ErrorKind::NotFound,
+            FdError::NotReadable | FdError::NotWritable | FdError::ReadOnly => io::ErrorKind::PermissionDenied,
+            FdError::Locked => io::ErrorKind::WouldBlock,
+            FdError::TimedOut => io::ErrorKind::TimedOut,
+            FdError::InvalidOffset => io::ErrorKind::InvalidInput,
+            FdError::NoEnoughSpace => io::ErrorKind::StorageFull,
+            _ => io::ErrorKind::Other
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+// requests answered by the FS thread
+impl From<super::FsError> for FdError {
+    fn from(e: super::FsError) -> Self {
+        use super::{FsError, FsErrorKind as K};
+        match e {
+            FsError::FileErr(e) => e,
+            FsError::Locked => Self::Locked,
+            FsError::IoErr(e) => Self::IoErr(e),
+            FsError::SendErr(s) => Self::SendErr(s),
+            FsError::RecvErr(s) => Self::RecvErr(s),
+            e => match e.kind() {
+                K::InvalidPath => Self::InvalidPath,
+                K::NotFound => Self::NotFound,
+                K::Exists => Self::FileExists,
+                K::IsDir => Self::NotFile,
+                K::NotDir => Self::ParentNotDir,
+                K::ReadOnly => Self::ReadOnly,
+                K::Busy => Self::FileOccupied,
+                K::NoSpace => Self::NoEnoughSpace,
+                K::Corrupted => Self::FileIncorrupted,
+                K::Io | K::Other => Self::IoErr(io::Error::other(format!("{e:?}")))
+            }
+        }
+    }
 }
 
 impl From<DiskError> for FdError {
     fn from(e: DiskError) -> Self {
         match e {
             DiskError::InvalidAddr => return Self::FileIncorrupted,
+            DiskError::CryptErr(_) | DiskError::NoPassphrase => Self::FileIncorrupted,
             DiskError::IoErr(e) => return Self::IoErr(e)
         }
     }
@@ -73,6 +129,7 @@ impl FdError {
     fn DiskErr(e: DiskError) -> Self {
         match e {
             DiskError::InvalidAddr => return Self::FileIncorrupted,
+            DiskError::CryptErr(_) | DiskError::NoPassphrase => Self::FileIncorrupted,
             DiskError::IoErr(e) => return Self::IoErr(e)
         }
     }
@@ -84,9 +141,62 @@ type Result<T> = result::Result<T, FdError>;
 
 use crate::logger;
 use super::{FsReq, FdTable, metadata::Metadata};
-use super::utils;
+use super::{utils, FileLock, LockRange, LockWait};
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
+use std::{thread, time::{Duration, Instant}};
+
+/// Blocks read or written at a time through [io::Read] and [io::Write].
+pub const STREAM_BLOCKS: u32 = 8;
+
+/// How a file is opened. Combine with `|`.
+/// 
+/// `READ`, `WRITE`: what the [Fd] may do; at least one of them or `APPEND`
+/// 
+/// `APPEND`: writes add to the end of file, implies `WRITE`
+/// 
+/// `CREATE`: create the file if it does not exist
+/// 
+/// `EXCLUSIVE`: with `CREATE`, fail if the file exists
+/// 
+/// `TRUNCATE`: empty the file when opening it for writing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OpenFlags(u8);
+
+impl OpenFlags {
+    pub const READ: Self = Self(1);
+    pub const WRITE: Self = Self(1 << 1);
+    pub const APPEND: Self = Self(1 << 2);
+    pub const CREATE: Self = Self(1 << 3);
+    pub const EXCLUSIVE: Self = Self(1 << 4);
+    pub const TRUNCATE: Self = Self(1 << 5);
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn readable(&self) -> bool {
+        self.contains(Self::READ)
+    }
+
+    pub fn writable(&self) -> bool {
+        self.contains(Self::WRITE) || self.contains(Self::APPEND)
+    }
+
+    fn is_valid(&self) -> bool {
+        (self.readable() || self.writable())
+            && (!self.contains(Self::TRUNCATE) || self.writable())
+            && (!self.contains(Self::EXCLUSIVE) || self.contains(Self::CREATE))
+    }
+}
+
+impl std::ops::BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
 
 /// File descriptor.
 /// 
@@ -95,16 +205,33 @@ use std::sync::{Arc, Mutex};
 /// `read`: Read file content
 /// 
 /// `write`: Write content to file
+/// 
+/// `lock`: Lock the file, or a range of it, until unlocked or dropped
+/// 
+/// `unlock`: Drop locks taken by `lock`
+/// 
+/// It is also [io::Read], [io::Write] and [io::Seek], moving at most
+/// [STREAM_BLOCKS] blocks at a time through the channel.
 pub struct Fd {
     inode: u32,
+    handle: u64,
+    flags: OpenFlags,
     meta: Metadata,
     tx: Sender<FsReq>,
     table: Arc<Mutex<FdTable>>,
+    pos: u32,
 }
 
 impl Fd {
-    pub fn new(inode: u32, meta: Metadata, tx: Sender<FsReq>, table: Arc<Mutex<FdTable>>) -> Self {
-        Self { inode, meta, tx, table }
+    pub fn new(
+        inode: u32, handle: u64, flags: OpenFlags, meta: Metadata, tx: Sender<FsReq>, table: Arc<Mutex<FdTable>>
+    ) -> Self {
+        Self { inode, handle, flags, meta, tx, table, pos: 0 }
+    }
+
+    /// Return the [OpenFlags] the file was opened with.
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
     }
 
     /// Return a [u32] representing the virtual address of file inode.
@@ -119,23 +246,160 @@ impl Fd {
 
     /// Read file content. Return byte array [Vec]<[u8]> representing the content.
     pub fn read(&mut self) -> Result<Vec<u8>> {
+        if !self.flags.readable() {
+            return Err(FdError::NotReadable);
+        }
+        let (tx, rx) = mpsc::channel();
+        self.tx.send(FsReq::ReadFile(tx, self.inode, self.handle))?;
+        match rx.recv()? {
+            Ok(data) => Ok(data),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    /// Read at most `len` bytes of file content starting at `offset`.
+    pub fn read_at(&mut self, offset: u32, len: u32) -> Result<Vec<u8>> {
+        if !self.flags.readable() {
+            return Err(FdError::NotReadable);
+        }
         let (tx, rx) = mpsc::channel();
-        self.tx.send(FsReq::ReadFile(tx, self.inode))?;
+        self.tx.send(FsReq::ReadFileAt(tx, self.inode, self.handle, offset, len))?;
         match rx.recv()? {
             Ok(data) => Ok(data),
-            Err(e) => return Err(FdError::NotFound)
+            Err(e) => Err(e.into())
         }
     }
 
-    /// Write content to file.
+    /// Write content to file, or add it to the end if opened with `APPEND`.
     /// 
     /// `data`: Content as byte array ([Vec]<[u8]>)
     pub fn write(&mut self, data: &Vec<u8>) -> Result<()> {
+        if !self.flags.writable() {
+            return Err(FdError::NotWritable);
+        }
+        let (tx, rx) = mpsc::channel();
+        let append = self.flags.contains(OpenFlags::APPEND);
+        self.tx.send(FsReq::WriteFile(tx, self.inode, self.handle, append, data.clone()))?;
+        match rx.recv()? {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    /// Write `data` over file content from `offset`, growing the file if it
+    /// goes past the end.
+    /// 
+    /// ## Error
+    /// 
+    /// - InvalidOffset: `offset` past the end of file
+    pub fn write_at(&mut self, offset: u32, data: &[u8]) -> Result<()> {
+        if !self.flags.writable() {
+            return Err(FdError::NotWritable);
+        }
         let (tx, rx) = mpsc::channel();
-        self.tx.send(FsReq::WriteFile(tx, self.inode, data.clone()))?;
+        self.tx.send(FsReq::WriteFileAt(tx, self.inode, self.handle, offset, data.to_vec()))?;
         match rx.recv()? {
             Ok(_) => Ok(()),
-            Err(e) => return Err(FdError::NotFound)
+            Err(e) => Err(e.into())
+        }
+    }
+
+    /// Return the length of file content in bytes.
+    pub fn len(&mut self) -> Result<u32> {
+        let (tx, rx) = mpsc::channel();
+        self.tx.send(FsReq::FileLen(tx, self.inode))?;
+        match rx.recv()? {
+            Ok(n) => Ok(n),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    // bytes from the position to the end of its chunk, at most `n`
+    fn chunk(&self, n: usize) -> usize {
+        let size = (STREAM_BLOCKS * disk::BLOCK_SIZE) as usize;
+        n.min(size - self.pos as usize % size)
+    }
+
+    /// Lock the file, or a range of it, for this descriptor.
+    /// 
+    /// `wait`: what to do while another descriptor holds a conflicting lock
+    /// 
+    /// ## Error
+    /// 
+    /// - Locked: conflicting lock, not waiting
+    /// - TimedOut: conflicting lock still held after the timeout
+    pub fn lock(&mut self, lock: FileLock, wait: LockWait) -> Result<()> {
+        const RETRY: Duration = Duration::from_millis(10);
+        let start = Instant::now();
+        loop {
+            let (tx, rx) = mpsc::channel();
+            self.tx.send(FsReq::Lock(tx, self.inode, self.handle, lock))?;
+            match rx.recv()? {
+                Ok(_) => return Ok(()),
+                Err(FsError::Locked) => {},
+                Err(e) => return Err(e.into())
+            }
+            match wait {
+                LockWait::NonBlocking => return Err(FdError::Locked),
+                LockWait::Timeout(d) if start.elapsed() >= d => return Err(FdError::TimedOut),
+                _ => thread::sleep(RETRY)
+            }
+        }
+    }
+
+    /// Drop locks of this descriptor overlapping `range`, or all of them if
+    /// `range` is None.
+    pub fn unlock(&mut self, range: Option<LockRange>) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        self.tx.send(FsReq::Unlock(tx, self.inode, self.handle, range))?;
+        match rx.recv()? {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.into())
+        }
+    }
+}
+
+impl io::Read for Fd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.chunk(buf.len());
+        let data = self.read_at(self.pos, n as u32)?;
+        buf[..data.len()].copy_from_slice(&data);
+        self.pos += data.len() as u32;
+        Ok(data.len())
+    }
+}
+
+impl io::Write for Fd {
+    /// Write at the position, or add to the end if opened with `APPEND`.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.chunk(buf.len());
+        if self.flags.contains(OpenFlags::APPEND) {
+            self.write(&buf[..n].to_vec())?;
+        } else {
+            self.write_at(self.pos, &buf[..n])?;
+        }
+        self.pos += n as u32;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for Fd {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            io::SeekFrom::Start(n) => (0, n as i64),
+            io::SeekFrom::Current(d) => (self.pos as i64, d),
+            io::SeekFrom::End(d) => (self.len()? as i64, d)
+        };
+        match u32::try_from(base + delta) {
+            Ok(p) => {
+                self.pos = p;
+                Ok(p as u64)
+            },
+            Err(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position"))
         }
     }
 }
@@ -143,23 +407,47 @@ impl Fd {
 impl Drop for Fd {
     fn drop(&mut self) {
         let mut lock = utils::mutex_lock(self.table.lock());
-        lock.try_drop(self.inode);
+        lock.release_locks(self.handle);
+        lock.close_file(self.inode, self.flags);
     }
 }
 
 // ====== FN ======
 
 use super::FsError;
-use super::{disk, inode, data, dir};
+use super::{disk, inode, data, dir, compress, dedup, snapshot};
 use super::{path_to_inode, metadata::metadata};
 
-pub fn open_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str) -> Result<Fd> {
+/// `uid`: owner of the file if `CREATE` creates it
+/// 
+/// ## Error
+/// 
+/// - InvalidFlags
+/// - InvalidPath
+/// - NotFound
+/// - NotFile
+/// - FileExists: `CREATE` and `EXCLUSIVE` given
+/// - Locked: `TRUNCATE` given and another handle holds a mandatory lock
+/// - ParentNotFound
+/// - ParentNotDir
+/// - NoEnoughSpace
+/// - FileIncorrupted
+/// - IoErr
+pub fn open_file(
+    tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str, flags: OpenFlags, uid: u8
+) -> Result<Fd> {
+    if !flags.is_valid() {
+        return Err(FdError::InvalidFlags);
+    }
     let inode_addr = match path_to_inode(path) {
+        Ok(_) if flags.contains(OpenFlags::EXCLUSIVE) => return Err(FdError::FileExists),
         Ok(i) => i,
         Err(e) => match e {
             FsError::InvalidPath => return Err(FdError::InvalidPath),
+            FsError::NotFound if flags.contains(OpenFlags::CREATE) => new_file(tx.clone(), fd_table.clone(), path, uid)?,
             FsError::NotFound => return Err(FdError::NotFound),
-            _ => panic!("{e:?}")
+            FsError::DirErr(_) => return Err(FdError::FileIncorrupted),
+            _ => return Err(e.into())
         }
     };
     let inode = inode::load_inode(inode_addr)?;
@@ -167,18 +455,32 @@ pub fn open_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str) -
     if metadata.is_dir() {
         return Err(FdError::NotFile);
     }
+    if flags.writable() && snapshot::is_virtual(inode_addr) {
+        return Err(FdError::ReadOnly);
+    }
+    if flags.contains(OpenFlags::TRUNCATE) {
+        // held while truncating, so that no lock is taken in between
+        let lock = utils::mutex_lock(fd_table.lock());
+        if !lock.allows(inode_addr, FdTable::NO_HANDLE, LockRange::WHOLE, true) {
+            return Err(FdError::Locked);
+        }
+        if inode.size > 1 {
+            write_file(inode_addr, &mut Vec::new())?;
+        }
+    }
 
     // add into fd table
     let mut lock = utils::mutex_lock(fd_table.lock());
-    let fd = match lock.get_file(tx.clone(), inode_addr, fd_table.clone()) {
+    let fd = match lock.get_file(tx.clone(), inode_addr, flags, fd_table.clone()) {
         Ok(f) => f,
         Err(e) => match e {
-            FsError::NotDirButFile => return Err(FdError::NotFile),
-            _ => panic!("{e:?}")
+            FsError::NotFileButDir => return Err(FdError::NotFile),
+            _ => return Err(e.into())
         }
     };
+    let (readers, writers) = lock.usage(inode_addr);
 
-    logger::log(&format!("[FS] Open file: {path}"));
+    logger::log(&format!("[FS] Open file: {path} ({readers} readers, {writers} writers)"));
     Ok(fd)
 }
 
@@ -193,6 +495,22 @@ pub fn open_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str) -
 /// - FileIncorrupted
 /// - IoErr(e)
 pub fn create_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str, uid: u8) -> Result<Fd> {
+    let inode_addr = new_file(tx.clone(), fd_table.clone(), path, uid)?;
+
+    // add into fd table
+    let mut lock = utils::mutex_lock(fd_table.lock());
+    let fd = match lock.get_file(tx.clone(), inode_addr, OpenFlags::READ | OpenFlags::WRITE, fd_table.clone()) {
+        Ok(f) => f,
+        Err(e) => match e {
+            FsError::NotFileButDir => return Err(FdError::NotFile),
+            _ => return Err(e.into())
+        }
+    };
+    Ok(fd)
+}
+
+// create an empty file, return its inode address
+fn new_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str, uid: u8) -> Result<u32> {
     let mut path_vec: Vec<&str> = path.split('/').collect();
     let file_name = String::from(match path_vec.pop() {
         Some(n) => n,
@@ -205,7 +523,7 @@ pub fn create_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str,
             DdError::NotFound => return Err(FdError::ParentNotFound),
             DdError::NotDir => return Err(FdError::ParentNotDir),
             DdError::DirIncorrupted => return Err(FdError::FileIncorrupted),
-            _ => panic!("{e:?}")
+            _ => return Err(FsError::from(e).into())
         }
     };
     match metadata(tx.clone(), path) {
@@ -214,11 +532,12 @@ pub fn create_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str,
             MetadataError::InvalidPath => return Err(FdError::InvalidPath),
             MetadataError::NotFound => (),
             MetadataError::DiskErr(e) => return Err(FdError::DiskErr(e)),
-            _ => panic!("{e:?}")
+            _ => return Err(FsError::from(e).into())
         }
     }
 
     let mut inode = inode::alloc_inode(uid, false)?;
+    inode.1.flags |= inode::load_inode(parent_dd.inode_addr())?.flags & inode::COMPRESS_FLAG;
 
     // add parent/new
     if let Err(e) = dir::dir_add_entry(parent_dd.inode_addr(), inode.0, &file_name) {
@@ -227,7 +546,7 @@ pub fn create_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str,
             DdError::NoEnoughSpace => return Err(FdError::NoEnoughSpace),
             DdError::EntryExists => return Err(FdError::FileExists),
             DdError::IoErr(e) => return Err(FdError::IoErr(e)),
-            _ => panic!("{e:?}")
+            _ => return Err(FsError::from(e).into())
         }
     }
 
@@ -236,7 +555,7 @@ pub fn create_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str,
 
     // write a EOF
     let data = [(
-        *blocks.get(0).unwrap(),
+        *blocks.first().ok_or(FdError::NoEnoughSpace)?,
         [0u8].to_vec()
     )].to_vec();
     disk::write_blocks(&data)?;
@@ -245,18 +564,8 @@ pub fn create_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str,
     inode::update_blocks(&mut inode.1, &blocks)?;
     inode::save_inode(inode.0, &inode.1)?;
 
-    // add into fd table
-    let mut lock = utils::mutex_lock(fd_table.lock());
-    let fd = match lock.get_file(tx.clone(), inode.0, fd_table.clone()) {
-        Ok(f) => f,
-        Err(e) => match e {
-            FsError::NotDirButFile => return Err(FdError::NotFile),
-            _ => panic!("{e:?}")
-        }
-    };
-
     logger::log(&format!("[FS] Create file by user{uid}: {path}"));
-    Ok(fd)
+    Ok(inode.0)
 }
 
 // [PASS]
@@ -276,7 +585,7 @@ pub fn remove_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str)
         Err(e) => match e {
             FsError::InvalidPath => return Err(FdError::InvalidPath),
             FsError::NotFound => return Err(FdError::NotFound),
-            _ => panic!("{e:?}")
+            _ => return Err(e.into())
         }
     };
     let inode = inode::load_inode(inode_addr)?;
@@ -301,7 +610,7 @@ pub fn remove_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str)
         Err(e) => match e {
             DdError::NotFound => return Err(FdError::ParentNotFound),
             DdError::NotDir => return Err(FdError::ParentNotDir),
-            _ => panic!("{e:?}")
+            _ => return Err(FsError::from(e).into())
         }
     };
 
@@ -310,7 +619,7 @@ pub fn remove_file(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str)
         match e {
             DdError::DirIncorrupted => return Err(FdError::FileIncorrupted),
             DdError::IoErr(e) => return Err(FdError::IoErr(e)),
-            _ => panic!("{e:?}")
+            _ => return Err(FsError::from(e).into())
         }
     }
 
@@ -337,62 +646,255 @@ pub fn read_file(inode: u32) -> Result<Vec<u8>> {
     let blocks = inode::get_blocks(&inode)?;
     let mut buf = disk::read_blocks(&blocks)?;
 
-    // trim end
-    let mut end_at = 0;
-    for b in &buf {
-        if *b == 0 {
-            break
+    if inode.is_compressed() {
+        if inode.stored_size == 0 {
+            return Ok(Vec::new());
         }
-        end_at += 1;
+        buf.truncate(inode.stored_size as usize);
+        return match compress::decode(&buf) {
+            Some(v) => Ok(v),
+            None => Err(FdError::FileIncorrupted)
+        };
     }
-    buf.drain(end_at..);
+
+    // content ends before the terminating 0, wherever else 0s are
+    buf.truncate(inode.size.saturating_sub(1) as usize);
 
     Ok(buf)
 }
 
+// read bytes [start, end) of what is stored in `blocks`
+fn read_stored(blocks: &[u32], start: usize, end: usize) -> Result<Vec<u8>> {
+    const BS: usize = disk::BLOCK_SIZE as usize;
+    if start >= end {
+        return Ok(Vec::new());
+    }
+    let range = match blocks.get(start / BS..end.div_ceil(BS)) {
+        Some(r) => r,
+        None => return Err(FdError::FileIncorrupted)
+    };
+    let buf = disk::read_blocks(&range.to_vec())?;
+    Ok(buf[start % BS..start % BS + end - start].to_vec())
+}
+
+/// Read at most `len` bytes of file content starting at `offset`, touching
+/// only the blocks (or compressed chunks) that hold them.
+/// 
+/// ## Error
+/// 
+/// - NotFound
+/// - FileIncorrupted
+/// - IoErr
+pub fn read_at(inode: u32, offset: u32, len: u32) -> Result<Vec<u8>> {
+    let inode = inode::load_inode(inode)?;
+    let content_len = inode.size.saturating_sub(1) as usize;
+    let start = (offset as usize).min(content_len);
+    let end = (offset as usize).saturating_add(len as usize).min(content_len);
+    if start >= end {
+        return Ok(Vec::new());
+    }
+    let blocks = inode::get_blocks(&inode)?;
+
+    if !inode.is_compressed() {
+        return read_stored(&blocks, start, end);
+    }
+
+    // locate the chunks through the table at the head of the stream
+    let head = read_stored(&blocks, 0, 4)?;
+    let header = read_stored(&blocks, 0, compress::header_len(&head))?;
+    let table = compress::chunk_table(&header);
+    let first = start / compress::CHUNK_SIZE;
+    let last = (end - 1) / compress::CHUNK_SIZE;
+    let (from, to) = match (table.get(first), table.get(last)) {
+        (Some(f), Some(l)) => (f.0, l.0 + l.1),
+        _ => return Err(FdError::FileIncorrupted)
+    };
+    let stream = read_stored(&blocks, from, to)?;
+    let mut buf = Vec::<u8>::with_capacity((last - first + 1) * compress::CHUNK_SIZE);
+    for (chunk_start, chunk_len) in &table[first..=last] {
+        let chunk = &stream[chunk_start - from..chunk_start - from + chunk_len];
+        match compress::decode_chunk(chunk) {
+            Some(mut v) => buf.append(&mut v),
+            None => return Err(FdError::FileIncorrupted)
+        }
+    }
+    let skip = start - first * compress::CHUNK_SIZE;
+    Ok(buf[skip..skip + end - start].to_vec())
+}
+
+/// Add `buf` to the end of file content.
+/// 
+/// ## Error
+/// 
+/// - NotFound
+/// - NoEnoughSpace
+/// - FileIncorrupted
+/// - ReadOnly
+/// - IoErr
+pub fn append_file(inode_addr: u32, buf: &[u8]) -> Result<()> {
+    write_at(inode_addr, file_len(inode_addr)?, buf)
+}
+
+/// Return the length of file content in bytes.
+/// 
+/// ## Error
+/// 
+/// - NotFound
+/// - IoErr
+pub fn file_len(inode_addr: u32) -> Result<u32> {
+    Ok(inode::load_inode(inode_addr)?.size.saturating_sub(1))
+}
+
+/// Write `buf` over file content from `offset`, growing the file if it goes
+/// past the end. Only the blocks holding the bytes written are rewritten,
+/// but the whole content of a compressed file is.
+/// 
+/// ## Error
+/// 
+/// - InvalidOffset: `offset` past the end of file
+/// - NotFound
+/// - NoEnoughSpace
+/// - FileIncorrupted
+/// - ReadOnly
+/// - IoErr
+pub fn write_at(inode_addr: u32, offset: u32, buf: &[u8]) -> Result<()> {
+    const BS: usize = disk::BLOCK_SIZE as usize;
+    if snapshot::is_virtual(inode_addr) {
+        return Err(FdError::ReadOnly);
+    }
+    let mut inode = inode::load_inode(inode_addr)?;
+    let content_len = inode.size.saturating_sub(1) as usize;
+    let offset = offset as usize;
+    if offset > content_len {
+        return Err(FdError::InvalidOffset);
+    }
+    if buf.is_empty() {
+        return Ok(());
+    }
+    if inode.is_compressed() {
+        let mut data = read_file(inode_addr)?;
+        let end = (offset + buf.len()).min(data.len());
+        data.splice(offset..end, buf.iter().copied());
+        return write_file(inode_addr, &mut data);
+    }
+    let new_len = content_len.max(offset + buf.len());
+    if new_len + 1 > inode::MAX_SIZE as usize {
+        return Err(FdError::NoEnoughSpace);
+    }
+
+    // blocks from the one at `offset` to the one with the end, or the EOF
+    let old = inode::get_blocks(&inode)?;
+    let first = offset / BS;
+    let last = match new_len > content_len {
+        true => new_len / BS,
+        false => (offset + buf.len() - 1) / BS
+    };
+    let held = old[first..(last + 1).min(old.len())].to_vec();
+    let mut stored = disk::read_blocks(&held)?;
+    stored.resize((last - first + 1) * BS, 0);
+    let at = offset - first * BS;
+    stored[at..at + buf.len()].copy_from_slice(buf);
+    if new_len > content_len {
+        stored[new_len - first * BS] = 0;
+    }
+
+    let chunks: Vec<Vec<u8>> = stored.chunks(BS).map(|c| c.to_vec()).collect();
+    let (blocks, released) = dedup::store(&held, &chunks)?;
+    let mut all = old[..first].to_vec();
+    all.extend_from_slice(&blocks);
+    all.extend_from_slice(old.get(last + 1..).unwrap_or(&[]));
+    if let Err(e) = inode::update_blocks(&mut inode, &all) {
+        undo_store(blocks, &held, &released)?;
+        return Err(e.into());
+    }
+    inode.size = new_len as u32 + 1;
+    inode.stored_size = inode.size;
+    inode::save_inode(inode_addr, &inode)?;
+    data::free_blocks(&released)?;
+
+    Ok(())
+}
+
+// drop the references taken by dedup::store, except the blocks rewritten in place
+fn undo_store(mut blocks: Vec<u32>, old: &[u32], released: &[u32]) -> Result<()> {
+    for kept in old.iter().filter(|a| !released.contains(a)) {
+        if let Some(p) = blocks.iter().position(|b| b == kept) {
+            blocks.remove(p);
+        }
+    }
+    data::free_blocks(&blocks)?;
+    Ok(())
+}
+
 // [PASS]
 /// ## Error
 /// 
 /// - NotFound
 /// - NoEnoughSpace
 /// - FileIncorrupted
+/// - ReadOnly
 /// - IoErr
 pub fn write_file(inode_addr: u32, buf: &mut Vec<u8>) -> Result<()> {
-    buf.push(0);
-    let blocks_len = buf.len().div_ceil(disk::BLOCK_SIZE as usize);
+    if snapshot::is_virtual(inode_addr) {
+        return Err(FdError::ReadOnly);
+    }
     let mut inode = inode::load_inode(inode_addr)?;
-    let mut blocks = inode::get_blocks(&inode)?;
-    inode.size = buf.len() as u32;
-
-    if blocks.len() > blocks_len {
-        // free
-        let to_free_count = blocks.len() - blocks_len;
-        let mut to_free = Vec::<u32>::with_capacity(to_free_count);
-        for _ in 0..to_free_count {
-            to_free.push(blocks.pop().unwrap());
-        }
-        data::free_blocks(&to_free)?;
-        inode::update_blocks(&mut inode, &blocks)?;
-    } else if blocks.len() < blocks_len {
-        // alloc
-        let mut to_append = data::alloc_blocks((blocks_len - blocks.len()) as u32)?;
-        blocks.append(&mut to_append);
-        inode::update_blocks(&mut inode, &blocks)?;
+    if inode.is_compressed() {
+        inode.size = buf.len() as u32 + 1;
+        *buf = compress::encode(buf);
+    } else {
+        buf.push(0);
+        inode.size = buf.len() as u32;
+    }
+    inode.stored_size = buf.len() as u32;
+    if buf.len() > inode::MAX_SIZE as usize {
+        return Err(FdError::NoEnoughSpace);
+    }
+    let old = inode::get_blocks(&inode)?;
+
+    // blocks with content already on disk are shared, not written
+    let chunks: Vec<Vec<u8>> = buf.chunks(disk::BLOCK_SIZE as usize).map(|c| {
+        let mut c = c.to_vec();
+        c.resize(disk::BLOCK_SIZE as usize, 0);
+        c
+    }).collect();
+    let (blocks, released) = dedup::store(&old, &chunks)?;
+    if let Err(e) = inode::update_blocks(&mut inode, &blocks) {
+        undo_store(blocks, &old, &released)?;
+        return Err(e.into());
     }
     inode::save_inode(inode_addr, &inode)?;
+    data::free_blocks(&released)?;
+
+    Ok(())
+}
 
-    if blocks_len == 0 {
+/// Turn compression of a file or directory on or off. File content is
+/// rewritten in the new form; a directory only passes the flag on to
+/// entries created in it later.
+/// 
+/// ## Error
+/// 
+/// - NotFound
+/// - NoEnoughSpace
+/// - FileIncorrupted
+/// - IoErr
+pub fn set_compressed(inode_addr: u32, compressed: bool) -> Result<()> {
+    let mut inode = inode::load_inode(inode_addr)?;
+    if (inode.flags & inode::COMPRESS_FLAG > 0) == compressed {
         return Ok(());
     }
-
-    let buf = &buf[..];
-    let mut data = Vec::<(u32, Vec<u8>)>::with_capacity(blocks_len);
-    let last_block = blocks.pop().unwrap();
-    for (i, addr) in blocks.iter().enumerate() {
-        data.push((*addr, buf[i*disk::BLOCK_SIZE as usize..(i+1)*disk::BLOCK_SIZE as usize].to_vec()));
+    let is_dir = inode.mode & inode::DIR_FLAG > 0;
+    let mut content = if is_dir { Vec::new() } else { read_file(inode_addr)? };
+    if compressed {
+        inode.flags |= inode::COMPRESS_FLAG;
+    } else {
+        inode.flags &= !inode::COMPRESS_FLAG;
     }
-    data.push((last_block, buf[(blocks_len-1)*disk::BLOCK_SIZE as usize..].to_vec()));
-    disk::write_blocks(&data)?;
-
+    inode::save_inode(inode_addr, &inode)?;
+    if !is_dir {
+        write_file(inode_addr, &mut content)?;
+    }
+    logger::log(&format!("[FS] Set compression of inode {inode_addr}: {compressed}"));
     Ok(())
-}
\ No newline at end of file
+}
@@ -23,6 +23,14 @@ impl From<DiskError> for InodeError {
     fn from(e: DiskError) -> Self { Self::DiskErr(e) }
 }
 
+impl From<BitmapError> for InodeError {
+    fn from(_: BitmapError) -> Self { Self::InvalidAddr }
+}
+
+impl From<SedesError> for InodeError {
+    fn from(_: SedesError) -> Self { Self::InvalidAddr }
+}
+
 type Result<T> = result::Result<T, InodeError>;
 
 // ====== INODE ======
@@ -36,6 +44,7 @@ pub const INODE_OFFSET: u32 = BITMAP_OFFSET + 1;
 const INODE_SIZE: usize = 64;
 pub const INODE_COUNT: u32 = 4096;
 const INODE_PER_BLOCK: u32 = super::disk::BLOCK_SIZE / INODE_SIZE as u32;
+const ADDR_PER_BLOCK: usize = super::disk::BLOCK_SIZE as usize / 4;
 const MAX_BLOCKS: u32 = 8 + 256 + 256 * 256;
 pub const MAX_SIZE: u32 = 1024 * MAX_BLOCKS;
 
@@ -47,6 +56,10 @@ pub const OTHER_RWX_FLAG: (u8, u8, u8) = (
     1 << 2, 1 << 1, 1
 );
 
+/// In `flags`: file data is stored compressed; on a directory, files and
+/// directories created in it get the flag.
+pub const COMPRESS_FLAG: u8 = 1;
+
 #[derive(Debug, Default)]
 pub struct Inode {
     pub uid: u8,                // 1
@@ -56,7 +69,9 @@ pub struct Inode {
     pub blocks: [u32; 8],       // 32
     pub indirect_block: u32,    // 4
     pub double_block: u32,      // 4
-    // acquire 14 to 64
+    pub flags: u8,              // 1
+    pub stored_size: u32,       // 4
+    // acquire 9 to 64
 }
 
 impl Inode {
@@ -74,6 +89,11 @@ impl Inode {
         inode
     }
 
+    /// Return `true` if file data is stored compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.flags & COMPRESS_FLAG > 0 && self.mode & DIR_FLAG == 0
+    }
+
     /// Update to now
     pub fn update_timestamp(&mut self) {
         let dt = Local::now();
@@ -93,7 +113,9 @@ impl Serialize for Inode {
         }
         v.append(&mut utils::u32_to_u8arr(self.indirect_block).to_vec());
         v.append(&mut utils::u32_to_u8arr(self.double_block).to_vec());
-        v.append(&mut [0u8; 14].to_vec());
+        v.push(self.flags);
+        v.append(&mut utils::u32_to_u8arr(self.stored_size).to_vec());
+        v.append(&mut [0u8; 9].to_vec());
         v
     }
 }
@@ -114,6 +136,8 @@ impl Deserialize for Inode {
         }
         me.indirect_block = utils::u8arr_to_u32(&bytes[42..46]);
         me.double_block = utils::u8arr_to_u32(&bytes[46..50]);
+        me.flags = u8::from_be(bytes[50]);
+        me.stored_size = utils::u8arr_to_u32(&bytes[51..55]);
         Ok(me)
     }
 }
@@ -124,7 +148,9 @@ impl Clone for Inode {
             uid: self.uid, mode: self.mode, size: self.size,
             timestamp: self.timestamp, blocks: self.blocks.clone(),
             indirect_block: self.indirect_block,
-            double_block: self.double_block
+            double_block: self.double_block,
+            flags: self.flags,
+            stored_size: self.stored_size
         }
     }
 }
@@ -133,8 +159,14 @@ impl Copy for Inode {}
 
 // ====== FN ======
 
-use super::{disk, data};
+use super::{disk, data, snapshot, dentry};
 use super::bitmap::BlockBitmap;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+
+// held around read-modify-write of the bitmap, and of the inode table
+static BITMAP: Mutex<()> = Mutex::new(());
+static TABLE: RwLock<()> = RwLock::new(());
 
 type Bitmap = BlockBitmap;
 
@@ -142,7 +174,7 @@ type Bitmap = BlockBitmap;
 fn get_bitmap() -> Result<Bitmap> {
     let addrs: Vec<u32> = vec![BITMAP_OFFSET];
     let mut data = disk::read_blocks(&addrs)?;
-    Ok(Bitmap::deserialize(&mut data).unwrap())
+    Ok(Bitmap::deserialize(&mut data)?)
 }
 
 // [PASS]
@@ -157,12 +189,13 @@ fn save_bitmap(bitmap: &Bitmap) -> Result<()> {
 /// - NoUsableBlock
 /// - DiskErr
 pub fn alloc_inode(owner: u8, is_dir: bool) -> Result<(u32, Inode)> {
+    let _bitmap = utils::mutex_lock(BITMAP.lock());
     let mut bitmap = get_bitmap()?;
     let addr = match bitmap.next_usable() {
         Some(p) => p,
         None => return Err(InodeError::NoUsableBlock)
     };
-    bitmap.set_true(addr).unwrap();
+    bitmap.set_true(addr)?;
     let inode = Inode::new(owner, is_dir);
     save_bitmap(&bitmap)?;
     save_inode(addr, &inode)?;
@@ -175,40 +208,88 @@ pub fn alloc_inode(owner: u8, is_dir: bool) -> Result<(u32, Inode)> {
 /// - InvalidAddr
 /// - DiskErr
 pub fn free_inode(addr: u32) -> Result<()> {
+    let _bitmap = utils::mutex_lock(BITMAP.lock());
     let mut bitmap = get_bitmap()?;
     if let Err(_) = bitmap.set_false(addr) {
         return Err(InodeError::InvalidAddr);
     }
     save_bitmap(&bitmap)?;
+    // a directory reusing the inode has other entries
+    dentry::forget(|p| p == addr);
     Ok(())
 }
 
+/// Mark exactly the inodes in `addrs` as used.
+/// 
+/// ## Error
+/// 
+/// - DiskErr
+pub fn set_used_inodes(addrs: &[u32]) -> Result<()> {
+    let _bitmap = utils::mutex_lock(BITMAP.lock());
+    let mut bitmap = Bitmap::new();
+    for addr in addrs {
+        bitmap.set_true(*addr)?;
+    }
+    save_bitmap(&bitmap)
+}
+
+/// Return how many inodes are in use.
+/// 
+/// ## Error
+/// 
+/// - InvalidAddr
+/// - DiskErr
+pub fn used_count() -> Result<u32> {
+    let _bitmap = utils::mutex_lock(BITMAP.lock());
+    let bitmap = get_bitmap()?;
+    let mut count = 0;
+    for addr in 0..INODE_COUNT {
+        if bitmap.is_true(addr)? {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
 // [PASS]
 /// ## Error
 /// 
 /// - InvalidAddr
 /// - DiskErr
 pub fn load_inode(addr: u32) -> Result<Inode> {
-    if addr > INODE_COUNT {
+    if snapshot::is_virtual(addr) {
+        return match snapshot::load_inode(addr) {
+            Ok(i) => Ok(i),
+            Err(SnapshotError::DiskErr(e)) => Err(InodeError::DiskErr(e)),
+            Err(_) => Err(InodeError::InvalidAddr)
+        };
+    }
+    if addr >= INODE_COUNT {
         return Err(InodeError::InvalidAddr);
     }
     let block = INODE_OFFSET + addr / INODE_PER_BLOCK;
     let pos = addr % INODE_PER_BLOCK;
+    let _table = TABLE.read().unwrap_or_else(|e| e.into_inner());
     let buf = disk::read_blocks(&vec![block])?;
     Ok(Inode::deserialize(&mut buf[
         pos as usize * INODE_SIZE
         ..(pos + 1) as usize * INODE_SIZE
-    ].to_vec()).unwrap())
+    ].to_vec())?)
 
 }
 
 // [PASS]
 /// ## Error
 /// 
+/// - InvalidAddr: including inodes of snapshots, which are read-only
 /// - DiskErr
 pub fn save_inode(addr: u32, inode: &Inode) -> Result<()> {
+    if addr >= INODE_COUNT {
+        return Err(InodeError::InvalidAddr);
+    }
     let block = INODE_OFFSET + addr / INODE_PER_BLOCK;
     let pos = addr % INODE_PER_BLOCK;
+    let _table = TABLE.write().unwrap_or_else(|e| e.into_inner());
     let mut buf = disk::read_blocks(&vec![block])?;
     let s_inode = inode.serialize();
     buf.splice(
@@ -267,133 +348,185 @@ pub fn get_blocks(inode: &Inode) -> Result<Vec<u32>> {
 }
 
 fn read_ind_block(addr: u32) -> Result<(bool, Vec<u32>)> {
+    if addr == 0 {
+        return Ok((true, Vec::new()))
+    }
     let buf = disk::read_blocks(&[addr].to_vec())?;
-    const ADDR_COUNT: usize = disk::BLOCK_SIZE as usize / 4;
-    let mut v = Vec::<u32>::with_capacity(ADDR_COUNT);
-    for i in 0..ADDR_COUNT {
-        let addr = utils::u8arr_to_u32(&buf[i*4..(i+1)*4]);
-        if addr == 0 {
-            return Ok((true, v))
+    let v = utils::block_to_addrs(&buf);
+    Ok((v.len() < ADDR_PER_BLOCK, v))
+}
+
+fn alloc_ind_block() -> Result<u32> {
+    match data::alloc_blocks(1) {
+        Ok(v) => Ok(v[0]),
+        Err(e) => match e {
+            DataError::InsufficientUsableBlocks => Err(InodeError::NoUsableBlock),
+            DataError::DiskErr(e) => Err(InodeError::DiskErr(e)),
+            DataError::InvalidAddr => Err(InodeError::InvalidAddr)
         }
-        v.push(addr);
     }
-    Ok((false, v))
 }
 
+/// Point `inode` at `blocks`, allocating index blocks as needed and freeing
+/// the ones no longer used. Data blocks themselves are not freed.
+/// 
 /// ## Error
 /// 
 /// - DataTooBig
+/// - NoUsableBlock
 /// - DiskErr
 pub fn update_blocks(inode: &mut Inode, blocks: &Vec<u32>) -> Result<()> {
     if blocks.len() > MAX_BLOCKS as usize {
         return Err(InodeError::DataTooBig);
     }
-    let mut it = blocks.iter();
+    let mut it = blocks.iter().copied().take_while(|b| *b != 0);
     for i in 0..8 {
-        let block = match it.next() {
-            Some(b) => *b,
-            None => return Ok(())
-        };
-        if block == 0 {
-            return Ok(())
-        }
-        inode.blocks[i] = block;
+        inode.blocks[i] = it.next().unwrap_or(0);
     }
+    let mut to_write = Vec::<(u32, Vec<u8>)>::new();
+    let mut to_free = Vec::<u32>::new();
 
     // set indirect block
-    if inode.indirect_block == 0 {
-        inode.indirect_block = match data::alloc_blocks(1) {
-            Ok(v) => *v.get(0).unwrap(),
-            Err(e) => match e {
-                DataError::InsufficientUsableBlocks => return Err(InodeError::NoUsableBlock),
-                DataError::DiskErr(e) => return Err(InodeError::DiskErr(e)),
-                _ => panic!("{e:?}")
-            }
-        };
+    let ind: Vec<u32> = it.by_ref().take(ADDR_PER_BLOCK).collect();
+    if ind.is_empty() {
+        if inode.indirect_block != 0 {
+            to_free.push(inode.indirect_block);
+            inode.indirect_block = 0;
+        }
+    } else {
+        if inode.indirect_block == 0 {
+            inode.indirect_block = alloc_ind_block()?;
+        }
+        to_write.push((inode.indirect_block, utils::addrs_to_block(&ind)));
     }
-    let mut buf = Vec::<u8>::with_capacity(disk::BLOCK_SIZE as usize);
-    for _ in 0..INODE_PER_BLOCK {
-        let block = match it.next() {
-            Some(b) => *b,
-            None => {
-                disk::write_blocks(&[(inode.indirect_block, buf)].to_vec())?;
-                return Ok(())
-            }
+
+    // set double indirect block
+    let old_double = read_ind_block(inode.double_block)?.1;
+    let mut double = Vec::<u32>::new();
+    loop {
+        let ind: Vec<u32> = it.by_ref().take(ADDR_PER_BLOCK).collect();
+        if ind.is_empty() {
+            break
+        }
+        let addr = match old_double.get(double.len()) {
+            Some(a) => *a,
+            None => alloc_ind_block()?
         };
-        if block == 0 {
-            disk::write_blocks(&[(inode.indirect_block, buf)].to_vec())?;
-            return Ok(())
+        to_write.push((addr, utils::addrs_to_block(&ind)));
+        double.push(addr);
+    }
+    if old_double.len() > double.len() {
+        to_free.extend_from_slice(&old_double[double.len()..]);
+    }
+    if double.is_empty() {
+        if inode.double_block != 0 {
+            to_free.push(inode.double_block);
+            inode.double_block = 0;
+        }
+    } else {
+        if inode.double_block == 0 {
+            inode.double_block = alloc_ind_block()?;
         }
-        buf.append(&mut utils::u32_to_u8arr(block).to_vec());
+        to_write.push((inode.double_block, utils::addrs_to_block(&double)));
     }
-    disk::write_blocks(&[(inode.indirect_block, buf)].to_vec())?;
 
-    // set double indirect block
-    if inode.double_block == 0 {
-        inode.double_block = match data::alloc_blocks(1) {
-            Ok(v) => *v.get(0).unwrap(),
-            Err(e) => match e {
-                DataError::InsufficientUsableBlocks => return Err(InodeError::NoUsableBlock),
+    disk::write_blocks(&to_write)?;
+    if !to_free.is_empty() {
+        if let Err(e) = data::free_blocks(&to_free) {
+            match e {
                 DataError::DiskErr(e) => return Err(InodeError::DiskErr(e)),
-                _ => panic!("{e:?}")
+                _ => return Err(InodeError::InvalidAddr)
             }
-        };
+        }
+    }
+    Ok(())
+}
+
+/// Return the index blocks of the inode: the indirect block, the double
+/// indirect block and the indirect blocks it points to.
+/// 
+/// ## Error
+/// 
+/// - DiskErr
+pub fn get_index_blocks(inode: &Inode) -> Result<Vec<u32>> {
+    let mut v = Vec::<u32>::new();
+    if inode.indirect_block != 0 {
+        v.push(inode.indirect_block);
     }
-    let buf = &disk::read_blocks(&[inode.double_block].to_vec())?;
-    let addr_count = disk::BLOCK_SIZE as usize / 4;
-    let mut double = Vec::<u32>::with_capacity(addr_count);
-    for i in 0..addr_count {
-        double.push(utils::u8arr_to_u32(&buf[i*4..(i+1)*4]));
+    if inode.double_block != 0 {
+        v.push(inode.double_block);
+        v.append(&mut read_ind_block(inode.double_block)?.1);
     }
+    Ok(v)
+}
 
-    let mut block_buf = Vec::<(u32, Vec<u8>)>::new();
-    for i in 0..addr_count {
-        let ind_block = double.get_mut(i).unwrap();
-        if *ind_block == 0 {
-            *ind_block = match data::alloc_blocks(1) {
-                Ok(v) => *v.get(0).unwrap(),
-                Err(e) => match e {
-                    DataError::InsufficientUsableBlocks => return Err(InodeError::NoUsableBlock),
-                    DataError::DiskErr(e) => return Err(InodeError::DiskErr(e)),
-                    _ => panic!("{e:?}")
-                }
-            };
+/// Rewrite every pointer of the inode, including the ones kept in index
+/// blocks, that appears as a key in `map` to the mapped address. Index blocks
+/// are written at their new address; copying data blocks is up to the caller.
+/// 
+/// Return `true` if any pointer was changed.
+/// 
+/// ## Error
+/// 
+/// - DiskErr
+pub fn remap_blocks(inode: &mut Inode, map: &HashMap<u32, u32>) -> Result<bool> {
+    let get = |a: u32| *map.get(&a).unwrap_or(&a);
+    let mut changed = false;
+    let mut to_write = Vec::<(u32, Vec<u8>)>::new();
+
+    for b in inode.blocks.iter_mut() {
+        if *b != 0 && get(*b) != *b {
+            *b = get(*b);
+            changed = true;
         }
-        let mut buf = Vec::<u8>::with_capacity(disk::BLOCK_SIZE as usize);
-        for _ in 0..INODE_PER_BLOCK {
-            let block = match it.next() {
-                Some(b) => *b,
-                None => {
-                    block_buf.push((*ind_block, buf));
-                    let mut double_buf = Vec::<u8>::with_capacity(addr_count);
-                    for addr in double {
-                        double_buf.append(&mut utils::u32_to_u8arr(addr).to_vec());
-                    }
-                    block_buf.push((inode.double_block, double_buf));
-                    disk::write_blocks(&block_buf)?;
-                    return Ok(())
-                }
-            };
-            if block == 0 {
-                block_buf.push((*ind_block, buf));
-                let mut double_buf = Vec::<u8>::with_capacity(addr_count);
-                for addr in double {
-                    double_buf.append(&mut utils::u32_to_u8arr(addr).to_vec());
-                }
-                block_buf.push((inode.double_block, double_buf));
-                disk::write_blocks(&block_buf)?;
-                return Ok(())
+    }
+
+    if inode.indirect_block != 0 {
+        let old = read_ind_block(inode.indirect_block)?.1;
+        let new: Vec<u32> = old.iter().map(|a| get(*a)).collect();
+        if new != old || get(inode.indirect_block) != inode.indirect_block {
+            inode.indirect_block = get(inode.indirect_block);
+            to_write.push((inode.indirect_block, utils::addrs_to_block(&new)));
+            changed = true;
+        }
+    }
+
+    if inode.double_block != 0 {
+        let old_double = read_ind_block(inode.double_block)?.1;
+        let mut double = Vec::<u32>::with_capacity(old_double.len());
+        for ind in old_double.iter() {
+            let old = read_ind_block(*ind)?.1;
+            let new: Vec<u32> = old.iter().map(|a| get(*a)).collect();
+            if new != old || get(*ind) != *ind {
+                to_write.push((get(*ind), utils::addrs_to_block(&new)));
+                changed = true;
             }
-            buf.append(&mut utils::u32_to_u8arr(block).to_vec());
+            double.push(get(*ind));
+        }
+        if double != old_double || get(inode.double_block) != inode.double_block {
+            inode.double_block = get(inode.double_block);
+            to_write.push((inode.double_block, utils::addrs_to_block(&double)));
+            changed = true;
         }
-        block_buf.push((*ind_block, buf));
     }
 
-    let mut double_buf = Vec::<u8>::with_capacity(addr_count);
-    for addr in double {
-        double_buf.append(&mut utils::u32_to_u8arr(addr).to_vec());
+    disk::write_blocks(&to_write)?;
+    Ok(changed)
+}
+
+/// Return virtual addresses of all allocated inodes.
+/// 
+/// ## Error
+/// 
+/// - DiskErr
+pub fn used_inodes() -> Result<Vec<u32>> {
+    let _bitmap = utils::mutex_lock(BITMAP.lock());
+    let bitmap = get_bitmap()?;
+    let mut v = Vec::<u32>::new();
+    for addr in 0..INODE_COUNT {
+        if bitmap.is_true(addr)? {
+            v.push(addr);
+        }
     }
-    block_buf.push((inode.double_block, double_buf));
-    disk::write_blocks(&block_buf)?;
-    Ok(())
-}
\ No newline at end of file
+    Ok(v)
+}
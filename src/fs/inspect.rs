@@ -0,0 +1,249 @@
+/* Offline Inspection
+ * Look into an image without serving it: the image is mounted as it is, on
+ * a device that may be read-only, and read with the disk layer directly.
+ *
+ * superblock: as stored
+ * inodes: every field, whether the inode bitmap marks it used, and its
+ *     block map (data blocks, then index blocks)
+ * entries: every slot of a directory's blocks, empty ones included
+ * bitmaps: the state of a range of inodes or blocks
+ * files: content of an inode, decompressed
+ *
+ * !!!NOTE!!!: mounts the image of its own; never use it in a process serving
+ * a file system.
+ */
+
+// ====== ERROR ======
+
+use std::{error, fmt, result};
+use super::error::*;
+
+#[derive(Debug)]
+pub enum InspectError {
+    NotAnImage,
+    InvalidInode,
+    NotDir,
+    Corrupted,
+    DiskErr(DiskError),
+}
+
+impl error::Error for InspectError {}
+
+impl fmt::Display for InspectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InspectError: {:?}", self)
+    }
+}
+
+impl From<DiskError> for InspectError {
+    fn from(e: DiskError) -> Self { Self::DiskErr(e) }
+}
+
+impl From<SuperblockError> for InspectError {
+    fn from(e: SuperblockError) -> Self {
+        match e {
+            SuperblockError::IoErr(e) => Self::DiskErr(DiskError::IoErr(e)),
+            SuperblockError::DiskErr(e) => Self::DiskErr(e),
+            SuperblockError::NotInitialized => Self::NotAnImage,
+            _ => Self::Corrupted
+        }
+    }
+}
+
+impl From<InodeError> for InspectError {
+    fn from(e: InodeError) -> Self {
+        match e {
+            InodeError::DiskErr(e) => Self::DiskErr(e),
+            InodeError::InvalidAddr => Self::InvalidInode,
+            _ => Self::Corrupted
+        }
+    }
+}
+
+impl From<DataError> for InspectError {
+    fn from(e: DataError) -> Self {
+        match e {
+            DataError::DiskErr(e) => Self::DiskErr(e),
+            _ => Self::Corrupted
+        }
+    }
+}
+
+impl From<FdError> for InspectError {
+    fn from(e: FdError) -> Self {
+        match e {
+            FdError::IoErr(e) => Self::DiskErr(DiskError::IoErr(e)),
+            FdError::NotFound => Self::InvalidInode,
+            _ => Self::Corrupted
+        }
+    }
+}
+
+type Result<T> = result::Result<T, InspectError>;
+
+// ====== FN ======
+
+use super::{disk, inode, data, file, utils};
+use super::dir::ENTRY_SIZE;
+use super::device::BlockDevice;
+use super::superblock::{self, Superblock};
+use zeroize::Zeroizing;
+
+/// An inode as stored.
+///
+/// `used`: the inode bitmap marks it used
+///
+/// `blocks`: data blocks, in order; `index_blocks`: indirect blocks holding
+/// their addresses
+#[derive(Debug, Clone)]
+pub struct InodeInfo {
+    pub ino: u32,
+    pub used: bool,
+    pub uid: u8,
+    pub mode: u8,
+    pub size: u32,
+    pub timestamp: u32,
+    pub flags: u8,
+    pub stored_size: u32,
+    pub direct: [u32; 8],
+    pub indirect_block: u32,
+    pub double_block: u32,
+    pub blocks: Vec<u32>,
+    pub index_blocks: Vec<u32>,
+}
+
+impl InodeInfo {
+    pub fn is_dir(&self) -> bool {
+        self.mode & inode::DIR_FLAG > 0
+    }
+}
+
+/// Slot `slot` of a directory. An empty slot has no name.
+#[derive(Debug, Clone)]
+pub struct RawEntry {
+    pub slot: usize,
+    pub inode: u32,
+    pub name: String,
+}
+
+/// Bitmaps of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitmapKind {
+    Inode,
+    Data,
+}
+
+/// Mount the image on `device` without changing it, and return its
+/// superblock.
+///
+/// ## Error
+///
+/// - NotAnImage
+/// - DiskErr: NoPassphrase and CryptErr included
+pub fn open_image(device: Box<dyn BlockDevice>, passphrase: Option<String>) -> Result<Superblock> {
+    match disk::open_disk(device, passphrase.map(Zeroizing::new)) {
+        Ok(_) => (),
+        Err(DiskError::InvalidAddr) => return Err(InspectError::NotAnImage),
+        Err(e) => return Err(e.into())
+    }
+    Ok(superblock::superblock()?)
+}
+
+/// ## Error
+///
+/// - InvalidInode
+/// - Corrupted: block map points outside of the image
+/// - DiskErr
+pub fn inode_info(ino: u32) -> Result<InodeInfo> {
+    if ino >= inode::INODE_COUNT {
+        return Err(InspectError::InvalidInode);
+    }
+    let inode = inode::load_inode(ino)?;
+    let blocks = inode::get_blocks(&inode).map_err(|_| InspectError::Corrupted)?;
+    let index_blocks = inode::get_index_blocks(&inode).map_err(|_| InspectError::Corrupted)?;
+    Ok(InodeInfo {
+        ino,
+        used: inode::used_inodes()?.contains(&ino),
+        uid: inode.uid,
+        mode: inode.mode,
+        size: inode.size,
+        timestamp: inode.timestamp,
+        flags: inode.flags,
+        stored_size: inode.stored_size,
+        direct: inode.blocks,
+        indirect_block: inode.indirect_block,
+        double_block: inode.double_block,
+        blocks,
+        index_blocks,
+    })
+}
+
+/// Return every slot in the blocks of directory `ino`.
+///
+/// ## Error
+///
+/// - InvalidInode
+/// - NotDir
+/// - Corrupted
+/// - DiskErr
+pub fn raw_entries(ino: u32) -> Result<Vec<RawEntry>> {
+    let info = inode_info(ino)?;
+    if !info.is_dir() {
+        return Err(InspectError::NotDir);
+    }
+    let buf = disk::read_blocks(&info.blocks)?;
+    let mut v = Vec::<RawEntry>::with_capacity(buf.len() / ENTRY_SIZE);
+    for (slot, bytes) in buf.chunks_exact(ENTRY_SIZE).enumerate() {
+        let name = &bytes[4..];
+        let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        v.push(RawEntry {
+            slot,
+            inode: utils::u8arr_to_u32(&bytes[0..4]),
+            name: String::from_utf8_lossy(&name[..end]).to_string(),
+        });
+    }
+    Ok(v)
+}
+
+/// Return whether each of `count` inodes, or blocks, from `start` is used.
+/// Blocks before the data area are always used; the range stops at the end
+/// of the image.
+///
+/// ## Error
+///
+/// - Corrupted
+/// - DiskErr
+pub fn bitmap_range(kind: BitmapKind, start: u32, count: u32) -> Result<Vec<bool>> {
+    let end = start.saturating_add(count);
+    match kind {
+        BitmapKind::Inode => {
+            let used = inode::used_inodes()?;
+            Ok((start..end.min(inode::INODE_COUNT)).map(|i| used.contains(&i)).collect())
+        },
+        BitmapKind::Data => {
+            let bitmap = data::get_bitmap()?;
+            let mut v = Vec::<bool>::new();
+            for addr in start..end.min(disk::block_count()) {
+                match addr.checked_sub(data::DATA_OFFSET) {
+                    Some(pos) => v.push(bitmap.is_true(pos).map_err(|_| InspectError::Corrupted)?),
+                    None => v.push(true)
+                }
+            }
+            Ok(v)
+        }
+    }
+}
+
+/// Return content of file `ino`, decompressed.
+///
+/// ## Error
+///
+/// - InvalidInode
+/// - Corrupted
+/// - DiskErr
+pub fn file_content(ino: u32) -> Result<Vec<u8>> {
+    if ino >= inode::INODE_COUNT {
+        return Err(InspectError::InvalidInode);
+    }
+    Ok(file::read_file(ino)?)
+}
@@ -0,0 +1,139 @@
+/* File Locks
+ * Held per inode by the handle of an open file (see FdTable), and released
+ * when the handle is dropped.
+ *
+ * shared: conflicts with exclusive locks of other handles
+ * exclusive: conflicts with any lock of other handles
+ * mandatory: also refuses reads (exclusive) or writes (any) by other handles
+ *     over the locked range; advisory locks only conflict with other locks
+ */
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+/// Bytes [start, end) of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl LockRange {
+    /// The whole file, however long it grows.
+    pub const WHOLE: Self = Self { start: 0, end: u32::MAX };
+
+    /// `len` bytes from `start`. `len` 0 reaches past the end of file.
+    pub fn new(start: u32, len: u32) -> Self {
+        match len {
+            0 => Self { start, end: u32::MAX },
+            _ => Self { start, end: start.saturating_add(len) }
+        }
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileLock {
+    pub kind: LockKind,
+    pub range: LockRange,
+    pub mandatory: bool,
+}
+
+impl FileLock {
+    /// Advisory lock of the whole file.
+    pub fn new(kind: LockKind) -> Self {
+        Self { kind, range: LockRange::WHOLE, mandatory: false }
+    }
+
+    /// Lock only `range`.
+    pub fn range(mut self, range: LockRange) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Enforce the lock on reads and writes of other handles.
+    pub fn mandatory(mut self) -> Self {
+        self.mandatory = true;
+        self
+    }
+
+    fn conflicts(&self, other: &Self) -> bool {
+        self.range.overlaps(&other.range)
+            && (self.kind == LockKind::Exclusive || other.kind == LockKind::Exclusive)
+    }
+}
+
+/// How long to wait for a conflicting lock to go.
+#[derive(Debug, Clone, Copy)]
+pub enum LockWait {
+    NonBlocking,
+    Timeout(Duration),
+    Blocking,
+}
+
+#[derive(Debug)]
+struct Held {
+    owner: u64,
+    lock: FileLock,
+}
+
+#[derive(Debug, Default)]
+pub struct LockTable {
+    held: HashMap<u32, Vec<Held>>,
+}
+
+impl LockTable {
+    /// Take `lock` on `inode` for handle `owner`. A lock the handle already
+    /// holds on the same range is replaced, e.g. upgraded to exclusive.
+    /// Return `false` if a lock of another handle conflicts.
+    pub fn try_lock(&mut self, inode: u32, owner: u64, lock: FileLock) -> bool {
+        let v = self.held.entry(inode).or_default();
+        if v.iter().any(|h| h.owner != owner && h.lock.conflicts(&lock)) {
+            return false;
+        }
+        v.retain(|h| h.owner != owner || h.lock.range != lock.range);
+        v.push(Held { owner, lock });
+        true
+    }
+
+    /// Drop the locks of `owner` on `inode` overlapping `range`, or all of
+    /// them if `range` is None.
+    pub fn unlock(&mut self, inode: u32, owner: u64, range: Option<LockRange>) {
+        if let Some(v) = self.held.get_mut(&inode) {
+            v.retain(|h| h.owner != owner || range.is_some_and(|r| !r.overlaps(&h.lock.range)));
+            if v.is_empty() {
+                self.held.remove(&inode);
+            }
+        }
+    }
+
+    /// Drop every lock of `owner`.
+    pub fn release(&mut self, owner: u64) {
+        self.held.retain(|_, v| {
+            v.retain(|h| h.owner != owner);
+            !v.is_empty()
+        });
+    }
+
+    /// Return `true` if mandatory locks of other handles allow `owner` to
+    /// read, or write, `range` of `inode`.
+    pub fn allows(&self, inode: u32, owner: u64, range: LockRange, write: bool) -> bool {
+        let v = match self.held.get(&inode) {
+            Some(v) => v,
+            None => return true
+        };
+        !v.iter().any(|h| {
+            h.owner != owner && h.lock.mandatory && h.lock.range.overlaps(&range)
+                && (write || h.lock.kind == LockKind::Exclusive)
+        })
+    }
+}
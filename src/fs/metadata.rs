@@ -6,9 +6,13 @@ use super::disk;
 
 #[derive(Debug)]
 pub enum MetadataError {
+    SendErr(String),
     RecvErr(String),
     InvalidPath,
     NotFound,
+    ReadOnly,
+    NoEnoughSpace,
+    Corrupted,
     DiskErr(disk::DiskError),
 }
 
@@ -24,10 +28,35 @@ impl From<disk::DiskError> for MetadataError {
     fn from(e: disk::DiskError) -> Self { Self::DiskErr(e) }
 }
 
+impl From<mpsc::SendError<super::FsReq>> for MetadataError {
+    fn from(e: mpsc::SendError<super::FsReq>) -> Self { Self::SendErr(format!("{e:?}")) }
+}
+
 impl From<mpsc::RecvError> for MetadataError {
     fn from(e: mpsc::RecvError) -> Self { Self::RecvErr(format!("{e:?}")) }
 }
 
+// requests answered by the FS thread
+impl From<super::FsError> for MetadataError {
+    fn from(e: super::FsError) -> Self {
+        use super::{FsError, FsErrorKind as K};
+        match e {
+            FsError::MetadataErr(e) => e,
+            FsError::IoErr(e) => Self::DiskErr(DiskError::IoErr(e)),
+            FsError::SendErr(s) => Self::SendErr(s),
+            FsError::RecvErr(s) => Self::RecvErr(s),
+            e => match e.kind() {
+                K::InvalidPath => Self::InvalidPath,
+                K::NotFound => Self::NotFound,
+                K::ReadOnly => Self::ReadOnly,
+                K::NoSpace => Self::NoEnoughSpace,
+                K::Corrupted => Self::Corrupted,
+                _ => Self::DiskErr(DiskError::IoErr(std::io::Error::other(format!("{e:?}"))))
+            }
+        }
+    }
+}
+
 type Result<T> = std::result::Result<T, MetadataError>;
 
 // ====== METADATA ======
@@ -54,6 +83,30 @@ impl Clone for Rwx {
 
 impl Copy for Rwx {}
 
+/// Unix permission bits to (owner, others) permission. Simdisk has no group:
+/// its bits are left out.
+pub fn mode_to_rwx(mode: u32) -> (Rwx, Rwx) {
+    let rwx = |bits: u32| Rwx { read: bits & 4 > 0, write: bits & 2 > 0, execute: bits & 1 > 0 };
+    (rwx(mode >> 6 & 7), rwx(mode & 7))
+}
+
+/// (owner, others) permission to unix permission bits. The group gets the
+/// permission of others.
+pub fn rwx_to_mode(rwx: &(Rwx, Rwx)) -> u32 {
+    let bits = |p: &Rwx| (p.read as u32) << 2 | (p.write as u32) << 1 | p.execute as u32;
+    bits(&rwx.0) << 6 | bits(&rwx.1) << 3 | bits(&rwx.1)
+}
+
+/// Attributes of an inode to change; `None` keeps the stored value.
+///
+/// `mode`: only permission bits are taken
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InodeAttr {
+    pub mode: Option<u8>,
+    pub uid: Option<u8>,
+    pub timestamp: Option<u32>,
+}
+
 pub struct Metadata {
     addr: u32,
     inode: inode::Inode,
@@ -70,6 +123,11 @@ impl Metadata {
         self.inode.mode & inode::DIR_FLAG > 0
     }
 
+    /// Return the inode number, the same for every name of one file/directory.
+    pub fn ino(&self) -> u32 {
+        self.addr
+    }
+
     /// Return uid ([u8]) of file/directory owner.
     pub fn owner(&self) -> u8 {
         self.inode.uid
@@ -80,6 +138,48 @@ impl Metadata {
         self.inode.size
     }
 
+    /// Return bytes taken on disk by file/directory data, in whole blocks.
+    pub fn physical_size(&self) -> u32 {
+        let stored = match self.inode.stored_size {
+            0 => self.inode.size,
+            s => s
+        };
+        stored.max(1).div_ceil(disk::BLOCK_SIZE) * disk::BLOCK_SIZE
+    }
+
+    /// Return blocks allocated to file/directory, index blocks included.
+    pub fn allocated_blocks(&self) -> Result<u32> {
+        let (tx, rx) = mpsc::channel();
+        self.tx.send(FsReq::AllocatedBlocks(tx, self.addr))?;
+        match rx.recv()? {
+            Ok(n) => Ok(n),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    /// Return `true` if compression is on: file data is stored compressed, or
+    /// entries created in the directory will be.
+    pub fn is_compressed(&self) -> bool {
+        self.inode.flags & inode::COMPRESS_FLAG > 0
+    }
+
+    /// Turn compression on or off.
+    pub fn set_compressed(&mut self, compressed: bool) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        self.tx.send(FsReq::SetCompression(tx, self.addr, compressed))?;
+        match rx.recv()? {
+            Ok(_) => {
+                if compressed {
+                    self.inode.flags |= inode::COMPRESS_FLAG;
+                } else {
+                    self.inode.flags &= !inode::COMPRESS_FLAG;
+                }
+                Ok(())
+            },
+            Err(e) => Err(e.into())
+        }
+    }
+
     /// Return [Rwx] unit: (owner_permission, others_permission)
     pub fn permission(&self) -> (Rwx, Rwx) {
         (
@@ -100,61 +200,80 @@ impl Metadata {
     pub fn set_permission(&mut self, permission: (Rwx, Rwx)) -> Result<()> {
         match permission.0.read {
             true => self.inode.mode |= inode::OWNER_RWX_FLAG.0,
-            false => self.inode.mode |= !inode::OWNER_RWX_FLAG.0
+            false => self.inode.mode &= !inode::OWNER_RWX_FLAG.0
         }
         match permission.0.write {
             true => self.inode.mode |= inode::OWNER_RWX_FLAG.1,
-            false => self.inode.mode |= !inode::OWNER_RWX_FLAG.1
+            false => self.inode.mode &= !inode::OWNER_RWX_FLAG.1
         }
         match permission.0.execute {
             true => self.inode.mode |= inode::OWNER_RWX_FLAG.2,
-            false => self.inode.mode |= !inode::OWNER_RWX_FLAG.2
+            false => self.inode.mode &= !inode::OWNER_RWX_FLAG.2
         }
 
         match permission.1.read {
             true => self.inode.mode |= inode::OTHER_RWX_FLAG.0,
-            false => self.inode.mode |= !inode::OTHER_RWX_FLAG.0
+            false => self.inode.mode &= !inode::OTHER_RWX_FLAG.0
         }
         match permission.1.write {
             true => self.inode.mode |= inode::OTHER_RWX_FLAG.1,
-            false => self.inode.mode |= !inode::OTHER_RWX_FLAG.1
+            false => self.inode.mode &= !inode::OTHER_RWX_FLAG.1
         }
         match permission.1.execute {
             true => self.inode.mode |= inode::OTHER_RWX_FLAG.2,
-            false => self.inode.mode |= !inode::OTHER_RWX_FLAG.2
+            false => self.inode.mode &= !inode::OTHER_RWX_FLAG.2
         }
 
-        let (tx, rx) = mpsc::channel();
-        self.tx.send(FsReq::UpdateInode(tx, self.addr, self.inode)).unwrap();
-        match rx.recv()? {
-            Ok(_) => {
-                logger::log(&format!("[FS] Update permission for inode {}.", self.addr));
-                Ok(())
-            },
-            Err(e) => return Err(MetadataError::NotFound)
-        }
+        let attr = InodeAttr { mode: Some(self.inode.mode), ..Default::default() };
+        self.update(attr, "permission")
     }
 
     /// Return unit (month, date, hour, minute).
     /// 
     /// Note: month starts from 0
     pub fn timestamp(&self) -> (u32, u32, u32, u32) {
-        let dt = DateTime::from_timestamp(self.inode.timestamp as i64, 0).unwrap();
+        let dt = DateTime::from_timestamp(self.inode.timestamp as i64, 0).unwrap_or_default();
         (dt.month0(), dt.day(), dt.hour(), dt.minute())
     }
 
+    /// `uid`: new owner
+    pub fn set_owner(&mut self, uid: u8) -> Result<()> {
+        self.inode.uid = uid;
+
+        self.update(InodeAttr { uid: Some(uid), ..Default::default() }, "owner")
+    }
+
+    /// Return the time of last modification, in seconds since the epoch.
+    pub fn mtime(&self) -> u32 {
+        self.inode.timestamp
+    }
+
+    /// `mtime`: seconds since the epoch
+    pub fn set_mtime(&mut self, mtime: u32) -> Result<()> {
+        self.inode.timestamp = mtime;
+
+        let attr = InodeAttr { timestamp: Some(self.inode.timestamp), ..Default::default() };
+        self.update(attr, "timestamp")
+    }
+
     /// Update to now
     pub fn update_timestamp(&mut self) -> Result<()> {
         self.inode.update_timestamp();
 
+        let attr = InodeAttr { timestamp: Some(self.inode.timestamp), ..Default::default() };
+        self.update(attr, "timestamp")
+    }
+
+    // write `attr` over the stored inode, not the copy held here
+    fn update(&mut self, attr: InodeAttr, what: &str) -> Result<()> {
         let (tx, rx) = mpsc::channel();
-        self.tx.send(FsReq::UpdateInode(tx, self.addr, self.inode)).unwrap();
+        self.tx.send(FsReq::UpdateInode(tx, self.addr, attr))?;
         match rx.recv()? {
             Ok(_) => {
-                logger::log(&format!("[FS] Update timestamp for inode {}.", self.addr));
+                logger::log(&format!("[FS] Update {} for inode {}.", what, self.addr));
                 Ok(())
             },
-            Err(e) => return Err(MetadataError::NotFound)
+            Err(e) => Err(e.into())
         }
     }
 }
@@ -176,7 +295,7 @@ pub fn metadata(tx: Sender<FsReq>, path: &str) -> Result<Metadata> {
         Err(e) => match e {
             FsError::NotFound => return Err(MetadataError::NotFound),
             FsError::InvalidPath => return Err(MetadataError::InvalidPath),
-            _ => panic!("{e:?}")
+            _ => return Err(e.into())
         }
     };
     let inode = match inode::load_inode(inode_addr) {
@@ -184,10 +303,46 @@ pub fn metadata(tx: Sender<FsReq>, path: &str) -> Result<Metadata> {
         Err(e) => match e {
             InodeError::InvalidAddr => return Err(MetadataError::NotFound),
             InodeError::DiskErr(e) => return Err(MetadataError::DiskErr(e)),
-            _ => panic!("{e:?}")
+            _ => return Err(FsError::from(e).into())
         }
     };
 
     logger::log(&format!("[FS] Get metadata of \"{path}\""));
     Ok(Metadata::new(inode_addr, inode, tx))
-}
\ No newline at end of file
+}
+
+/// Count data and index blocks of inode `addr`.
+/// 
+/// ## Error
+/// 
+/// - NotFound
+/// - DiskErr
+pub fn allocated_blocks(addr: u32) -> Result<u32> {
+    let inode = inode::load_inode(addr).map_err(FsError::from)?;
+    let data = inode::get_blocks(&inode).map_err(FsError::from)?;
+    let index = inode::get_index_blocks(&inode).map_err(FsError::from)?;
+    Ok((data.len() + index.len()) as u32)
+}
+
+/// Load inode `addr`, change `attr` in it and save it back.
+///
+/// ## Error
+///
+/// - NotFound
+/// - DiskErr
+pub fn update_inode(addr: u32, attr: InodeAttr) -> Result<()> {
+    let mut inode = inode::load_inode(addr).map_err(FsError::from)?;
+    if let Some(mode) = attr.mode {
+        let rwx = inode::OWNER_RWX_FLAG.0 | inode::OWNER_RWX_FLAG.1 | inode::OWNER_RWX_FLAG.2
+            | inode::OTHER_RWX_FLAG.0 | inode::OTHER_RWX_FLAG.1 | inode::OTHER_RWX_FLAG.2;
+        inode.mode = (inode.mode & !rwx) | (mode & rwx);
+    }
+    if let Some(uid) = attr.uid {
+        inode.uid = uid;
+    }
+    if let Some(timestamp) = attr.timestamp {
+        inode.timestamp = timestamp;
+    }
+    inode::save_inode(addr, &inode).map_err(FsError::from)?;
+    Ok(())
+}
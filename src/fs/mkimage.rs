@@ -0,0 +1,122 @@
+/* Image Building
+ * Format a new image and fill it with a tree made elsewhere, e.g. read from
+ * a host directory, before anything serves it.
+ *
+ * entries: created in the order given, so a directory has to come before
+ *     what is in it; "/" is not created, only given its attributes; names
+ *     longer than a directory entry holds are refused
+ * attributes: owner, mode and mtime are set once everything is written, in
+ *     reverse order, as writing into a directory changes its mtime
+ */
+
+// ====== ERROR ======
+
+use std::{error, fmt, result};
+use super::FsError;
+
+#[derive(Debug)]
+pub enum ImageError {
+    NotEmpty,
+    EntryErr(String, FsError),
+    FsErr(FsError),
+}
+
+impl error::Error for ImageError {}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ImageError: {:?}", self)
+    }
+}
+
+impl From<FsError> for ImageError {
+    fn from(e: FsError) -> Self { Self::FsErr(e) }
+}
+
+type Result<T> = result::Result<T, ImageError>;
+
+// ====== BUILD ======
+
+use crate::logger;
+use super::dir;
+use super::device::BlockDevice;
+use super::metadata::mode_to_rwx;
+use super::{start_fs_on, create_file, create_dir, metadata, FsReq};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+/// A file or directory to put in a new image.
+///
+/// `path`: absolute in the image
+///
+/// `mode`: unix permission bits; group bits are not kept
+///
+/// `data`: content of a file
+#[derive(Debug, Clone, Default)]
+pub struct ImageEntry {
+    pub path: String,
+    pub is_dir: bool,
+    pub uid: u8,
+    pub mode: u32,
+    pub mtime: u32,
+    pub data: Vec<u8>,
+}
+
+fn create(tx: &mut Sender<FsReq>, entry: &ImageEntry) -> result::Result<(), FsError> {
+    if entry.path == "/" {
+        return Ok(());
+    }
+    // a longer name would be cut short in the directory
+    let name = entry.path.rsplit('/').next().unwrap_or("");
+    if name.len() > dir::NAME_LEN {
+        return Err(FsError::InvalidPath);
+    }
+    match entry.is_dir {
+        true => create_dir(tx, &entry.path, entry.uid).map(|_| ()),
+        false => create_file(tx, &entry.path, entry.uid)
+            .and_then(|mut fd| fd.write(&entry.data).map_err(FsError::from)),
+    }
+}
+
+fn set_attributes(tx: &mut Sender<FsReq>, entry: &ImageEntry) -> result::Result<(), FsError> {
+    let mut meta = metadata(tx, &entry.path)?;
+    meta.set_permission(mode_to_rwx(entry.mode))?;
+    meta.set_mtime(entry.mtime)?;
+    if entry.path == "/" {
+        meta.set_owner(entry.uid)?;
+    }
+    Ok(())
+}
+
+/// Format a new image on `device`, encrypted if `passphrase` is given, and
+/// create `entries` in it.
+///
+/// !!!NOTE!!!: mounts the image of its own; never run it in a process serving
+/// a file system.
+///
+/// ## Error
+///
+/// - NotEmpty: `device` already holds something
+/// - EntryErr: creating an entry failed, e.g. as it does not fit
+/// - FsErr: formatting failed
+pub fn make_image(device: Box<dyn BlockDevice>, entries: &[ImageEntry], passphrase: Option<String>) -> Result<()> {
+    if device.size().map_err(FsError::IoErr)? != 0 {
+        return Err(ImageError::NotEmpty);
+    }
+    let (mut tx, rx) = mpsc::channel();
+    let (started_tx, started_rx) = mpsc::channel();
+    let self_tx = tx.clone();
+    thread::spawn(move || start_fs_on(device, started_tx, self_tx, rx, passphrase));
+    if started_rx.recv().map_err(FsError::from)?.is_err() {
+        return Err(ImageError::FsErr(FsError::InnerError));
+    }
+
+    for entry in entries {
+        create(&mut tx, entry).map_err(|e| ImageError::EntryErr(entry.path.clone(), e))?;
+    }
+    for entry in entries.iter().rev() {
+        set_attributes(&mut tx, entry).map_err(|e| ImageError::EntryErr(entry.path.clone(), e))?;
+    }
+    logger::log(&format!("[FS] Built image of {} entries", entries.len()));
+    Ok(())
+}
@@ -0,0 +1,240 @@
+// ====== ERROR ======
+
+use std::{error, fmt, result};
+use super::error::*;
+
+#[derive(Debug)]
+pub enum ResizeError {
+    TooSmall,
+    TooBig,
+    NoEnoughSpace,
+    DiskErr(DiskError),
+}
+
+impl error::Error for ResizeError {}
+
+impl fmt::Display for ResizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ResizeError: {:?}", self)
+    }
+}
+
+impl From<DiskError> for ResizeError {
+    fn from(e: DiskError) -> Self { Self::DiskErr(e) }
+}
+
+impl From<DataError> for ResizeError {
+    fn from(e: DataError) -> Self {
+        match e {
+            DataError::InsufficientUsableBlocks => Self::NoEnoughSpace,
+            DataError::DiskErr(e) => Self::DiskErr(e),
+            DataError::InvalidAddr => Self::DiskErr(DiskError::InvalidAddr)
+        }
+    }
+}
+
+impl From<InodeError> for ResizeError {
+    fn from(e: InodeError) -> Self {
+        match e {
+            InodeError::NoUsableBlock | InodeError::DataTooBig => Self::NoEnoughSpace,
+            InodeError::DiskErr(e) => Self::DiskErr(e),
+            InodeError::InvalidAddr => Self::DiskErr(DiskError::InvalidAddr)
+        }
+    }
+}
+
+impl From<BitmapError> for ResizeError {
+    fn from(_: BitmapError) -> Self { Self::DiskErr(DiskError::InvalidAddr) }
+}
+
+impl From<SuperblockError> for ResizeError {
+    fn from(e: SuperblockError) -> Self {
+        match e {
+            SuperblockError::IoErr(e) => Self::DiskErr(DiskError::IoErr(e)),
+            SuperblockError::DiskErr(e) => Self::DiskErr(e),
+            SuperblockError::NoEnoughSpace => Self::NoEnoughSpace,
+            _ => Self::DiskErr(DiskError::InvalidAddr)
+        }
+    }
+}
+
+type Result<T> = result::Result<T, ResizeError>;
+
+// ====== FN ======
+
+use crate::logger;
+use super::{disk, data, inode, superblock, utils, dedup, snapshot};
+use super::bitmap::BITMAP_BITS;
+use super::superblock::Superblock;
+use std::collections::HashMap;
+
+/// Smallest image: the fixed layout and commit log plus a handful of data
+/// blocks.
+const MIN_BLOCK_COUNT: u32 = data::DATA_OFFSET + disk::LOG_BLOCKS + 64;
+
+/// Extension bitmap blocks are listed in one block.
+const MAX_BITMAP_EXT: usize = disk::BLOCK_SIZE as usize / 4;
+
+/// Grow or shrink the image to `block_count` blocks. Return the updated
+/// [Superblock].
+///
+/// Growing appends blocks, extending the data bitmap into the new space when
+/// it is too short. Shrinking moves every block in the truncated tail into
+/// free blocks below the new end and rewrites the inodes pointing at them.
+///
+/// ## Error
+///
+/// - TooSmall
+/// - TooBig
+/// - NoEnoughSpace
+/// - DiskErr
+pub fn resize(block_count: u32) -> Result<Superblock> {
+    if block_count < MIN_BLOCK_COUNT {
+        return Err(ResizeError::TooSmall);
+    }
+    let sb = superblock::superblock()?;
+    let old = sb.block_count;
+    let sb = if block_count > old {
+        grow(sb, block_count)?
+    } else if block_count < old {
+        shrink(sb, block_count)?
+    } else {
+        sb
+    };
+    logger::log(&format!("[FS] Resize disk: {old} -> {block_count} blocks"));
+    Ok(sb)
+}
+
+fn grow(mut sb: Superblock, block_count: u32) -> Result<Superblock> {
+    let old = sb.block_count;
+    let bits = block_count - data::DATA_OFFSET;
+    let mut bitmap = data::get_bitmap()?;
+    let mut addrs = data::bitmap_addrs()?;
+
+    // new bitmap blocks and the list of them are taken from the new space
+    let mut next = old;
+    let mut reserved = Vec::<u32>::new();
+    while bitmap.capacity() < bits {
+        if sb.data_bitmap_ext == 0 {
+            sb.data_bitmap_ext = next;
+            reserved.push(next);
+            next += 1;
+        }
+        if addrs.len() - data::BITMAP_BLOCK as usize >= MAX_BITMAP_EXT {
+            return Err(ResizeError::TooBig);
+        }
+        bitmap.push_map();
+        addrs.push(next);
+        reserved.push(next);
+        next += 1;
+    }
+    if next >= block_count {
+        return Err(ResizeError::TooSmall);
+    }
+
+    disk::set_block_count(block_count)?;
+    for pos in old - data::DATA_OFFSET..bits {
+        bitmap.set_false(pos)?;
+    }
+    for addr in reserved {
+        bitmap.set_true(addr - data::DATA_OFFSET)?;
+    }
+    for pos in bits..bitmap.capacity() {
+        bitmap.set_true(pos)?;
+    }
+
+    if sb.data_bitmap_ext != 0 {
+        let ext = &addrs[data::BITMAP_BLOCK as usize..];
+        disk::write_blocks(&vec![(sb.data_bitmap_ext, utils::addrs_to_block(ext))])?;
+    }
+    data::save_bitmap_at(&bitmap, &addrs)?;
+    sb.block_count = block_count;
+    superblock::save_superblock(&sb)?;
+    Ok(sb)
+}
+
+fn shrink(mut sb: Superblock, block_count: u32) -> Result<Superblock> {
+    let old = sb.block_count;
+    let bits = block_count - data::DATA_OFFSET;
+    let old_bits = old - data::DATA_OFFSET;
+    let mut bitmap = data::get_bitmap()?;
+    let mut addrs = data::bitmap_addrs()?;
+
+    // drop bitmap blocks the smaller image does not need
+    let maps = (bits.div_ceil(BITMAP_BITS) as usize).max(data::BITMAP_BLOCK as usize);
+    let mut dropped = Vec::<u32>::new();
+    if addrs.len() > maps {
+        dropped.extend_from_slice(&addrs[maps..]);
+        addrs.truncate(maps);
+        if addrs.len() <= data::BITMAP_BLOCK as usize && sb.data_bitmap_ext != 0 {
+            dropped.push(sb.data_bitmap_ext);
+            sb.data_bitmap_ext = 0;
+        }
+    }
+    for addr in &dropped {
+        bitmap.set_false(addr - data::DATA_OFFSET)?;
+    }
+
+    // every used block in the tail has to move below the new end
+    let mut moving = Vec::<u32>::new();
+    for pos in bits..old_bits {
+        if bitmap.is_true(pos)? {
+            moving.push(pos);
+        }
+    }
+    let mut free = 0;
+    for pos in 0..bits {
+        if !bitmap.is_true(pos)? {
+            free += 1;
+        }
+    }
+    if moving.len() > free {
+        return Err(ResizeError::NoEnoughSpace);
+    }
+    for pos in bits..bitmap.capacity() {
+        bitmap.set_true(pos)?;
+    }
+    let mut map = HashMap::<u32, u32>::with_capacity(moving.len());
+    for pos in moving {
+        let to = match bitmap.next_usable() {
+            Some(p) => p,
+            None => return Err(ResizeError::NoEnoughSpace)
+        };
+        bitmap.set_true(to)?;
+        map.insert(pos + data::DATA_OFFSET, to + data::DATA_OFFSET);
+    }
+
+    // copy block contents, then rewrite whatever points at them
+    for (from, to) in &map {
+        let buf = disk::read_blocks(&vec![*from])?;
+        disk::write_blocks(&vec![(*to, buf)])?;
+    }
+    for addr in inode::used_inodes()? {
+        let mut i = inode::load_inode(addr)?;
+        if inode::remap_blocks(&mut i, &map)? {
+            inode::save_inode(addr, &i)?;
+        }
+    }
+    dedup::remap(&map);
+    sb.snapshot_table = match snapshot::remap(&map, sb.snapshot_table) {
+        Ok(t) => t,
+        Err(SnapshotError::DiskErr(e)) => return Err(ResizeError::DiskErr(e)),
+        Err(SnapshotError::NoEnoughSpace) => return Err(ResizeError::NoEnoughSpace),
+        Err(_) => return Err(ResizeError::DiskErr(DiskError::InvalidAddr))
+    };
+    for addr in addrs.iter_mut() {
+        *addr = *map.get(addr).unwrap_or(addr);
+    }
+    sb.data_bitmap_ext = *map.get(&sb.data_bitmap_ext).unwrap_or(&sb.data_bitmap_ext);
+
+    bitmap.truncate(addrs.len());
+    if sb.data_bitmap_ext != 0 {
+        let ext = &addrs[data::BITMAP_BLOCK as usize..];
+        disk::write_blocks(&vec![(sb.data_bitmap_ext, utils::addrs_to_block(ext))])?;
+    }
+    data::save_bitmap_at(&bitmap, &addrs)?;
+    sb.block_count = block_count;
+    superblock::save_superblock(&sb)?;
+    disk::set_block_count(block_count)?;
+    Ok(sb)
+}
@@ -0,0 +1,551 @@
+/* Snapshot Table: 1 block, pointed to by the superblock
+ * entry: 48B * MAX_SNAPSHOTS
+ *     name: 32B
+ *     timestamp: 4B
+ *     manifest: 4B, first block of the manifest
+ *     inodes: 4B
+ *     blocks: 4B, data blocks referenced
+ *
+ * Manifest: chain of blocks, each led by the address of the next (4B)
+ *     inode address: 4B
+ *     inode: 64B
+ *
+ * A snapshot inode has its own index blocks but shares data blocks with the
+ * live tree and other snapshots, holding one reference to each.
+ */
+
+// ====== ERROR ======
+
+use std::{error, fmt, result};
+use super::error::*;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    InvalidName,
+    Exists,
+    NotFound,
+    TooMany,
+    Busy,
+    NoEnoughSpace,
+    Corrupted,
+    DiskErr(DiskError),
+}
+
+impl error::Error for SnapshotError {}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SnapshotError: {:?}", self)
+    }
+}
+
+impl From<DiskError> for SnapshotError {
+    fn from(e: DiskError) -> Self { Self::DiskErr(e) }
+}
+
+impl From<DataError> for SnapshotError {
+    fn from(e: DataError) -> Self {
+        match e {
+            DataError::InsufficientUsableBlocks => Self::NoEnoughSpace,
+            DataError::DiskErr(e) => Self::DiskErr(e),
+            DataError::InvalidAddr => Self::Corrupted
+        }
+    }
+}
+
+impl From<InodeError> for SnapshotError {
+    fn from(e: InodeError) -> Self {
+        match e {
+            InodeError::NoUsableBlock | InodeError::DataTooBig => Self::NoEnoughSpace,
+            InodeError::DiskErr(e) => Self::DiskErr(e),
+            InodeError::InvalidAddr => Self::Corrupted
+        }
+    }
+}
+
+impl From<SuperblockError> for SnapshotError {
+    fn from(e: SuperblockError) -> Self {
+        match e {
+            SuperblockError::IoErr(e) => Self::DiskErr(DiskError::IoErr(e)),
+            SuperblockError::DiskErr(e) => Self::DiskErr(e),
+            SuperblockError::NoEnoughSpace => Self::NoEnoughSpace,
+            SuperblockError::NotInitialized | SuperblockError::Corrupted => Self::Corrupted
+        }
+    }
+}
+
+type Result<T> = result::Result<T, SnapshotError>;
+
+// ====== TABLE ======
+
+use crate::sedes::{Serialize, Deserialize};
+use super::{disk, data, inode, superblock, utils};
+use super::inode::Inode;
+
+const ENTRY_SIZE: usize = 48;
+const NAME_LEN: usize = 32;
+pub const MAX_SNAPSHOTS: usize = disk::BLOCK_SIZE as usize / ENTRY_SIZE;
+
+/// Snapshots are mounted read-only under this directory of the root.
+pub const SNAPSHOT_DIR: &str = ".snapshots";
+
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub timestamp: u32,
+    pub inodes: u32,
+    pub blocks: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Entry {
+    info: SnapshotInfo,
+    manifest: u32,
+}
+
+fn read_table(addr: u32) -> Result<Vec<Option<Entry>>> {
+    let mut v = Vec::<Option<Entry>>::with_capacity(MAX_SNAPSHOTS);
+    if addr == 0 {
+        v.resize(MAX_SNAPSHOTS, None);
+        return Ok(v);
+    }
+    let buf = disk::read_blocks(&vec![addr])?;
+    for i in 0..MAX_SNAPSHOTS {
+        let b = &buf[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE];
+        let manifest = utils::u8arr_to_u32(&b[40..44]);
+        if manifest == 0 {
+            v.push(None);
+            continue;
+        }
+        let name = String::from_utf8_lossy(&b[..NAME_LEN]).trim_end_matches('\0').to_string();
+        v.push(Some(Entry {
+            info: SnapshotInfo {
+                name,
+                timestamp: utils::u8arr_to_u32(&b[32..36]),
+                inodes: utils::u8arr_to_u32(&b[36..40]),
+                blocks: utils::u8arr_to_u32(&b[44..48]),
+            },
+            manifest,
+        }));
+    }
+    Ok(v)
+}
+
+fn table_to_block(table: &[Option<Entry>]) -> Vec<u8> {
+    let mut v = Vec::<u8>::with_capacity(disk::BLOCK_SIZE as usize);
+    for ent in table {
+        match ent {
+            Some(e) => {
+                let mut name = e.info.name.as_bytes().to_vec();
+                name.resize(NAME_LEN, 0);
+                v.append(&mut name);
+                v.extend_from_slice(&utils::u32_to_u8arr(e.info.timestamp));
+                v.extend_from_slice(&utils::u32_to_u8arr(e.info.inodes));
+                v.extend_from_slice(&utils::u32_to_u8arr(e.manifest));
+                v.extend_from_slice(&utils::u32_to_u8arr(e.info.blocks));
+            },
+            None => v.extend_from_slice(&[0u8; ENTRY_SIZE])
+        }
+    }
+    v.resize(disk::BLOCK_SIZE as usize, 0);
+    v
+}
+
+fn load_table() -> Result<Vec<Option<Entry>>> {
+    read_table(superblock::superblock()?.snapshot_table)
+}
+
+// the table block is allocated with the first snapshot and freed with the last
+fn save_table(table: &[Option<Entry>]) -> Result<()> {
+    let mut sb = superblock::superblock()?;
+    let empty = table.iter().all(|e| e.is_none());
+    if empty {
+        if sb.snapshot_table != 0 {
+            data::free_blocks(&vec![sb.snapshot_table])?;
+            sb.snapshot_table = 0;
+            superblock::save_superblock(&sb)?;
+        }
+        return Ok(());
+    }
+    if sb.snapshot_table == 0 {
+        sb.snapshot_table = data::alloc_blocks(1)?[0];
+        superblock::save_superblock(&sb)?;
+    }
+    disk::write_blocks(&vec![(sb.snapshot_table, table_to_block(table))])?;
+    Ok(())
+}
+
+fn find(table: &[Option<Entry>], name: &str) -> Option<usize> {
+    table.iter().position(|e| matches!(e, Some(e) if e.info.name == name))
+}
+
+// ====== MANIFEST ======
+
+const CHAIN_PAYLOAD: usize = disk::BLOCK_SIZE as usize - 4;
+const RECORD_SIZE: usize = 4 + 64;
+
+// inode address and the inode as it was
+type Record = (u32, Inode);
+
+fn write_chain(bytes: &[u8]) -> Result<u32> {
+    let count = bytes.len().div_ceil(CHAIN_PAYLOAD).max(1);
+    let addrs = data::alloc_blocks(count as u32)?;
+    write_chain_at(&addrs, bytes)?;
+    Ok(addrs[0])
+}
+
+fn write_chain_at(addrs: &[u32], bytes: &[u8]) -> Result<()> {
+    let mut chunks = bytes.chunks(CHAIN_PAYLOAD);
+    let mut to_write = Vec::<(u32, Vec<u8>)>::with_capacity(addrs.len());
+    for (i, addr) in addrs.iter().enumerate() {
+        let next = *addrs.get(i + 1).unwrap_or(&0);
+        let mut buf = utils::u32_to_u8arr(next).to_vec();
+        buf.extend_from_slice(chunks.next().unwrap_or(&[]));
+        buf.resize(disk::BLOCK_SIZE as usize, 0);
+        to_write.push((*addr, buf));
+    }
+    disk::write_blocks(&to_write)?;
+    Ok(())
+}
+
+// return the bytes of the chain and the blocks holding it
+fn read_chain(first: u32) -> Result<(Vec<u8>, Vec<u32>)> {
+    let mut bytes = Vec::<u8>::new();
+    let mut addrs = Vec::<u32>::new();
+    let mut next = first;
+    while next != 0 {
+        let buf = disk::read_blocks(&vec![next])?;
+        addrs.push(next);
+        bytes.extend_from_slice(&buf[4..]);
+        next = utils::u8arr_to_u32(&buf[0..4]);
+    }
+    Ok((bytes, addrs))
+}
+
+fn read_manifest(ent: &Entry) -> Result<(Vec<Record>, Vec<u32>)> {
+    let (bytes, addrs) = read_chain(ent.manifest)?;
+    let mut v = Vec::<Record>::with_capacity(ent.info.inodes as usize);
+    for r in bytes.chunks(RECORD_SIZE).take(ent.info.inodes as usize) {
+        let addr = utils::u8arr_to_u32(&r[0..4]);
+        match Inode::deserialize(&mut r[4..].to_vec()) {
+            Ok(i) => v.push((addr, i)),
+            Err(_) => return Err(SnapshotError::Corrupted)
+        }
+    }
+    Ok((v, addrs))
+}
+
+fn manifest_bytes(records: &[Record]) -> Vec<u8> {
+    let mut v = Vec::<u8>::with_capacity(records.len() * RECORD_SIZE);
+    for (addr, rec) in records {
+        v.extend_from_slice(&utils::u32_to_u8arr(*addr));
+        v.append(&mut rec.serialize());
+    }
+    v
+}
+
+// drop the references a snapshot inode holds
+fn release(rec: &Inode) -> Result<()> {
+    data::free_blocks(&inode::get_blocks(rec)?)?;
+    data::free_blocks(&inode::get_index_blocks(rec)?)?;
+    Ok(())
+}
+
+// an inode pointing at `blocks` through index blocks of its own
+fn with_blocks(node: &Inode, blocks: &Vec<u32>) -> Result<Inode> {
+    let mut rec = *node;
+    rec.blocks = [0; 8];
+    rec.indirect_block = 0;
+    rec.double_block = 0;
+    inode::update_blocks(&mut rec, blocks)?;
+    dedup::acquire(blocks);
+    Ok(rec)
+}
+
+// ====== VIRTUAL INODES ======
+
+use super::{dedup, dentry};
+use super::dir::Entry as DirEntry;
+use std::collections::{hash_map, HashMap};
+use std::sync::Mutex;
+
+/// Inodes of mounted snapshots have virtual addresses with this bit set:
+/// the snapshot slot in bits 16..31 and the inode address below.
+const SNAP_FLAG: u32 = 1 << 31;
+/// Virtual address of `/.snapshots`.
+pub const SNAP_ROOT: u32 = u32::MAX;
+
+/// Inodes of snapshots read so far, by slot.
+static MOUNTED: Mutex<Option<HashMap<u32, HashMap<u32, Inode>>>> = Mutex::new(None);
+
+/// Return `true` if `addr` is the virtual address of a snapshot inode.
+pub fn is_virtual(addr: u32) -> bool {
+    addr & SNAP_FLAG != 0
+}
+
+/// Forget the inodes read so far, e.g. after their blocks were rewritten.
+pub fn unmount_all() {
+    *utils::mutex_lock(MOUNTED.lock()) = None;
+    dentry::clear();
+}
+
+/// Return `true` if `path` (absolute) is `/.snapshots` or below it.
+pub fn in_snapshots(path: &str) -> bool {
+    let rest = path.trim_start_matches('/');
+    rest == SNAPSHOT_DIR || rest.starts_with(&format!("{SNAPSHOT_DIR}/"))
+}
+
+/// Load the inode behind a virtual address.
+///
+/// ## Error
+///
+/// - NotFound
+/// - DiskErr
+pub fn load_inode(vaddr: u32) -> Result<Inode> {
+    if vaddr == SNAP_ROOT {
+        let mut root = Inode::new(0, true);
+        root.mode &= !inode::OWNER_RWX_FLAG.1;
+        return Ok(root);
+    }
+    let slot = (vaddr & !SNAP_FLAG) >> 16;
+    let addr = vaddr & 0xffff;
+    let mut guard = utils::mutex_lock(MOUNTED.lock());
+    let mounted = guard.get_or_insert_with(HashMap::new);
+    if let hash_map::Entry::Vacant(v) = mounted.entry(slot) {
+        let ent = match load_table()?.get(slot as usize) {
+            Some(Some(e)) => e.clone(),
+            _ => return Err(SnapshotError::NotFound)
+        };
+        v.insert(read_manifest(&ent)?.0.into_iter().collect());
+    }
+    match mounted[&slot].get(&addr) {
+        Some(i) => Ok(*i),
+        None => Err(SnapshotError::NotFound)
+    }
+}
+
+/// Return the entries of `/.snapshots`: one directory per snapshot.
+///
+/// ## Error
+///
+/// - DiskErr
+pub fn root_entries() -> Result<Vec<DirEntry>> {
+    let mut v = vec![DirEntry { inode: SNAP_ROOT, name: String::from(".") }];
+    for (slot, ent) in load_table()?.iter().enumerate() {
+        if let Some(e) = ent {
+            v.push(DirEntry { inode: SNAP_FLAG | (slot as u32) << 16, name: e.info.name.clone() });
+        }
+    }
+    Ok(v)
+}
+
+/// Translate an inode address found in a directory of a snapshot into the
+/// virtual address within the same snapshot.
+pub fn in_same_snapshot(dir_vaddr: u32, addr: u32) -> u32 {
+    (dir_vaddr & 0xffff_0000) | addr
+}
+
+fn unmount(slot: usize) {
+    if let Some(m) = utils::mutex_lock(MOUNTED.lock()).as_mut() {
+        m.remove(&(slot as u32));
+    }
+    dentry::forget(|p| p == SNAP_ROOT || is_virtual(p) && (p & !SNAP_FLAG) >> 16 == slot as u32);
+}
+
+// ====== FN ======
+
+use crate::logger;
+
+/// List snapshots in creation order.
+///
+/// ## Error
+///
+/// - DiskErr
+pub fn list() -> Result<Vec<SnapshotInfo>> {
+    let mut v: Vec<SnapshotInfo> = load_table()?.into_iter().flatten().map(|e| e.info).collect();
+    v.sort_by_key(|i| i.timestamp);
+    Ok(v)
+}
+
+/// Record the current state of every inode as snapshot `name`. Data blocks
+/// are shared, not copied.
+///
+/// ## Error
+///
+/// - InvalidName
+/// - Exists
+/// - TooMany
+/// - NoEnoughSpace
+/// - DiskErr
+pub fn create(name: &str) -> Result<SnapshotInfo> {
+    if name.is_empty() || name.len() > NAME_LEN || name.contains('/') || name == "." || name == ".." {
+        return Err(SnapshotError::InvalidName);
+    }
+    let mut table = load_table()?;
+    if find(&table, name).is_some() {
+        return Err(SnapshotError::Exists);
+    }
+    let slot = match table.iter().position(|e| e.is_none()) {
+        Some(s) => s,
+        None => return Err(SnapshotError::TooMany)
+    };
+
+    let mut records = Vec::<Record>::new();
+    let mut blocks = 0;
+    let res = (|| -> Result<u32> {
+        for addr in inode::used_inodes()? {
+            let node = inode::load_inode(addr)?;
+            let b = inode::get_blocks(&node)?;
+            blocks += b.len() as u32;
+            records.push((addr, with_blocks(&node, &b)?));
+        }
+        write_chain(&manifest_bytes(&records))
+    })();
+    let manifest = match res {
+        Ok(m) => m,
+        Err(e) => {
+            for (_, rec) in &records {
+                release(rec)?;
+            }
+            return Err(e);
+        }
+    };
+
+    let mut info = SnapshotInfo {
+        name: String::from(name), timestamp: 0, inodes: records.len() as u32, blocks
+    };
+    let mut now = Inode::default();
+    now.update_timestamp();
+    info.timestamp = now.timestamp;
+    table[slot] = Some(Entry { info: info.clone(), manifest });
+    save_table(&table)?;
+    unmount(slot);
+    logger::log(&format!("[FS] Create snapshot {name}: {} inodes, {blocks} blocks", info.inodes));
+    Ok(info)
+}
+
+/// Delete snapshot `name`, freeing blocks only it referenced.
+///
+/// ## Error
+///
+/// - NotFound
+/// - DiskErr
+pub fn delete(name: &str) -> Result<()> {
+    let mut table = load_table()?;
+    let (slot, ent) = match find(&table, name).and_then(|s| Some((s, table[s].take()?))) {
+        Some(s) => s,
+        None => return Err(SnapshotError::NotFound)
+    };
+    let (records, chain) = read_manifest(&ent)?;
+    for (_, rec) in &records {
+        release(rec)?;
+    }
+    data::free_blocks(&chain)?;
+    save_table(&table)?;
+    unmount(slot);
+    logger::log(&format!("[FS] Delete snapshot {name}"));
+    Ok(())
+}
+
+/// Replace every inode with its state in snapshot `name`. The snapshot is
+/// kept.
+///
+/// ## Error
+///
+/// - NotFound
+/// - NoEnoughSpace
+/// - DiskErr
+pub fn rollback(name: &str) -> Result<()> {
+    let table = load_table()?;
+    let ent = match find(&table, name) {
+        Some(s) => match table[s].clone() {
+            Some(e) => e,
+            None => return Err(SnapshotError::NotFound)
+        },
+        None => return Err(SnapshotError::NotFound)
+    };
+    let (records, _) = read_manifest(&ent)?;
+
+    // blocks still in the snapshot keep a reference and survive
+    for addr in inode::used_inodes()? {
+        let node = inode::load_inode(addr)?;
+        data::free_blocks(&inode::get_blocks(&node)?)?;
+        data::free_blocks(&inode::get_index_blocks(&node)?)?;
+    }
+    let addrs: Vec<u32> = records.iter().map(|r| r.0).collect();
+    inode::set_used_inodes(&addrs)?;
+    for (addr, rec) in &records {
+        let node = with_blocks(rec, &inode::get_blocks(rec)?)?;
+        inode::save_inode(*addr, &node)?;
+    }
+    // names cached resolve to inodes replaced, or hide ones brought back
+    unmount_all();
+    logger::log(&format!("[FS] Roll back to snapshot {name}"));
+    Ok(())
+}
+
+/// Return the inodes of every snapshot.
+///
+/// ## Error
+///
+/// - DiskErr
+pub fn all_inodes() -> Result<Vec<Inode>> {
+    let mut v = Vec::<Inode>::new();
+    for ent in load_table()?.into_iter().flatten() {
+        v.extend(read_manifest(&ent)?.0.into_iter().map(|r| r.1));
+    }
+    Ok(v)
+}
+
+/// Return every block snapshots keep in use: the table, the manifests, and
+/// the index and data blocks of snapshot inodes, including data blocks shared
+/// with the live tree.
+///
+/// ## Error
+///
+/// - DiskErr
+pub fn used_blocks() -> Result<Vec<u32>> {
+    let table = superblock::superblock()?.snapshot_table;
+    if table == 0 {
+        return Ok(Vec::new());
+    }
+    let mut v = vec![table];
+    for ent in read_table(table)?.into_iter().flatten() {
+        let (records, mut chain) = read_manifest(&ent)?;
+        v.append(&mut chain);
+        for (_, rec) in records {
+            v.append(&mut inode::get_blocks(&rec)?);
+            v.append(&mut inode::get_index_blocks(&rec)?);
+        }
+    }
+    Ok(v)
+}
+
+/// Follow blocks moved to other addresses by shrinking. `table` is the
+/// snapshot table before the move; return where it is now.
+///
+/// ## Error
+///
+/// - DiskErr
+pub fn remap(map: &HashMap<u32, u32>, table: u32) -> Result<u32> {
+    let get = |a: u32| *map.get(&a).unwrap_or(&a);
+    if table == 0 {
+        return Ok(0);
+    }
+    let mut entries = read_table(table)?;
+    for ent in entries.iter_mut().flatten() {
+        let (mut records, chain) = read_manifest(ent)?;
+        for (_, rec) in records.iter_mut() {
+            inode::remap_blocks(rec, map)?;
+        }
+
+        // rewrite the chain where it now is
+        let chain: Vec<u32> = chain.into_iter().map(get).collect();
+        write_chain_at(&chain, &manifest_bytes(&records))?;
+        ent.manifest = get(ent.manifest);
+    }
+    let table = get(table);
+    disk::write_blocks(&vec![(table, table_to_block(&entries))])?;
+    unmount_all();
+    Ok(table)
+}
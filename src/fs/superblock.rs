@@ -6,6 +6,9 @@ use super::error::*;
 #[derive(Debug)]
 pub enum SuperblockError {
     NotInitialized,
+    NoEnoughSpace,
+    Corrupted,
+    DiskErr(DiskError),
     IoErr(io::Error),
 }
 
@@ -21,6 +24,35 @@ impl From<io::Error> for SuperblockError {
     fn from(e: io::Error) -> Self { Self::IoErr(e) }
 }
 
+impl From<DiskError> for SuperblockError {
+    fn from(e: DiskError) -> Self {
+        match e {
+            DiskError::IoErr(e) => Self::IoErr(e),
+            _ => Self::DiskErr(e)
+        }
+    }
+}
+
+impl From<DataError> for SuperblockError {
+    fn from(e: DataError) -> Self {
+        match e {
+            DataError::InsufficientUsableBlocks => Self::NoEnoughSpace,
+            DataError::InvalidAddr => Self::Corrupted,
+            DataError::DiskErr(e) => e.into(),
+        }
+    }
+}
+
+impl From<InodeError> for SuperblockError {
+    fn from(e: InodeError) -> Self {
+        match e {
+            InodeError::NoUsableBlock | InodeError::DataTooBig => Self::NoEnoughSpace,
+            InodeError::InvalidAddr => Self::Corrupted,
+            InodeError::DiskErr(e) => e.into(),
+        }
+    }
+}
+
 type Result<T> = result::Result<T, SuperblockError>;
 
 // ====== SUPERBLOCK ======
@@ -28,8 +60,9 @@ type Result<T> = result::Result<T, SuperblockError>;
 use crate::sedes::{Serialize, Deserialize};
 use super::utils;
 use super::{disk, data, inode};
+use super::crypt::SALT_LEN;
 
-const SUPERBLOCK_SIZE: usize = 30;
+const SUPERBLOCK_SIZE: usize = 63;
 
 #[derive(Debug)]
 pub struct Superblock {
@@ -41,7 +74,13 @@ pub struct Superblock {
     pub inode_offset: u32,          // 4
     pub data_offset: u32,           // 4
     pub max_file_size: u32,         // 4
-    pub magic: u8                   // 1
+    pub magic: u8,                  // 1
+    pub block_count: u32,           // 4
+    pub data_bitmap_ext: u32,       // 4
+    pub encrypted: u8,              // 1
+    pub salt: [u8; SALT_LEN],       // 16
+    pub snapshot_table: u32,        // 4
+    pub log_blocks: u32,            // 4
 }
 
 impl Superblock {
@@ -55,6 +94,12 @@ impl Superblock {
             data_offset: data::DATA_OFFSET,
             max_file_size: inode::MAX_SIZE,
             magic: 172,
+            block_count: disk::DISK_SIZE / disk::BLOCK_SIZE,
+            data_bitmap_ext: 0,
+            encrypted: 0,
+            salt: [0u8; SALT_LEN],
+            snapshot_table: 0,
+            log_blocks: 0,
         }
     }
 }
@@ -71,6 +116,12 @@ impl Serialize for Superblock {
         v.append(&mut utils::u32_to_u8arr(self.data_offset).to_vec());
         v.append(&mut utils::u32_to_u8arr(self.max_file_size).to_vec());
         v.push(self.magic);
+        v.append(&mut utils::u32_to_u8arr(self.block_count).to_vec());
+        v.append(&mut utils::u32_to_u8arr(self.data_bitmap_ext).to_vec());
+        v.push(self.encrypted);
+        v.extend_from_slice(&self.salt);
+        v.append(&mut utils::u32_to_u8arr(self.snapshot_table).to_vec());
+        v.append(&mut utils::u32_to_u8arr(self.log_blocks).to_vec());
         v
     }
 }
@@ -90,27 +141,74 @@ impl Deserialize for Superblock {
         me.data_offset = utils::u8arr_to_u32(&bytes[21..25]);
         me.max_file_size = utils::u8arr_to_u32(&bytes[25..29]);
         me.magic = u8::from_be(bytes[29]);
+        me.block_count = utils::u8arr_to_u32(&bytes[30..34]);
+        me.data_bitmap_ext = utils::u8arr_to_u32(&bytes[34..38]);
+        me.encrypted = bytes[38];
+        me.salt.copy_from_slice(&bytes[39..55]);
+        me.snapshot_table = utils::u8arr_to_u32(&bytes[55..59]);
+        me.log_blocks = utils::u8arr_to_u32(&bytes[59..63]);
         Ok(me)
     }
 }
 
+/// Usage of the mounted image, counted from the bitmaps.
+/// 
+/// `used_blocks`: includes the superblock, bitmaps and inode table
+#[derive(Debug, Clone, Copy)]
+pub struct StatFs {
+    pub block_size: u32,
+    pub blocks: u32,
+    pub used_blocks: u32,
+    pub free_blocks: u32,
+    pub inodes: u32,
+    pub used_inodes: u32,
+    pub free_inodes: u32,
+}
+
 // ====== FN ======
 
 // [PASS]
 /// ## Error
 /// 
 /// - NotInitialized
+/// - DiskErr
 /// - IoErr
 pub fn superblock() -> Result<Superblock> {
-    let mut buf = match disk::read_blocks(&[0].to_vec()) {
-        Ok(b) => b,
-        Err(e) => match e {
-            DiskError::IoErr(e) => return Err(SuperblockError::IoErr(e)),
-            _ => panic!("{e:?}")
-        }
-    };
-    if *(buf.get(0).unwrap()) != 227 {
+    let mut buf = disk::read_blocks(&[0].to_vec())?;
+    if buf.first() != Some(&227) {
         return Err(SuperblockError::NotInitialized);
     }
-    Ok(Superblock::deserialize(&mut buf).unwrap())
+    Superblock::deserialize(&mut buf).map_err(|_| SuperblockError::NotInitialized)
+}
+
+/// Count used and free blocks and inodes of the image.
+/// 
+/// ## Error
+/// 
+/// - NotInitialized
+/// - DiskErr
+/// - IoErr
+pub fn statfs() -> Result<StatFs> {
+    let sb = superblock()?;
+    // bits past the end of the image are kept set
+    let free_blocks = data::free_count()?.min(sb.block_count.saturating_sub(sb.data_offset));
+    let used_inodes = inode::used_count()?;
+    Ok(StatFs {
+        block_size: sb.block_size,
+        blocks: sb.block_count,
+        used_blocks: sb.block_count - free_blocks,
+        free_blocks,
+        inodes: sb.inode_count,
+        used_inodes,
+        free_inodes: sb.inode_count.saturating_sub(used_inodes),
+    })
+}
+
+/// ## Error
+/// 
+/// - DiskErr
+/// - IoErr
+pub fn save_superblock(sb: &Superblock) -> Result<()> {
+    disk::write_blocks(&[(0, sb.serialize())].to_vec())?;
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,71 @@
+/* Request Concurrency
+ * Requests are served by a pool of workers.
+ *
+ * tree lock: requests changing the tree (create, remove, snapshots,
+ *     resize...) hold it exclusively, every other request shares it
+ * inode locks: a request sharing the tree reads file content under the read
+ *     lock of its inode, and changes it under the write lock
+ * allocation: inode and data modules lock their bitmaps and the inode table
+ *     around each read-modify-write of their blocks
+ */
+
+use super::utils;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+static TREE: RwLock<()> = RwLock::new(());
+static INODES: Mutex<Option<HashMap<u32, Arc<RwLock<()>>>>> = Mutex::new(None);
+
+/// Held while serving one request.
+pub enum TreeGuard {
+    Shared { _guard: RwLockReadGuard<'static, ()> },
+    Exclusive { _guard: RwLockWriteGuard<'static, ()> },
+}
+
+/// Share the tree with other requests that do not change it.
+pub fn shared() -> TreeGuard {
+    TreeGuard::Shared { _guard: TREE.read().unwrap_or_else(|e| e.into_inner()) }
+}
+
+/// Wait for every other request to finish, and keep them off the tree.
+pub fn exclusive() -> TreeGuard {
+    TreeGuard::Exclusive { _guard: TREE.write().unwrap_or_else(|e| e.into_inner()) }
+}
+
+fn inode_lock(addr: u32) -> Arc<RwLock<()>> {
+    let mut guard = utils::mutex_lock(INODES.lock());
+    guard.get_or_insert_with(HashMap::new).entry(addr).or_default().clone()
+}
+
+// forget the lock of the inode once nobody waits for it
+fn forget(addr: u32, lock: Arc<RwLock<()>>) {
+    let mut guard = utils::mutex_lock(INODES.lock());
+    drop(lock);
+    if let Some(map) = guard.as_mut() {
+        if map.get(&addr).is_some_and(|l| Arc::strong_count(l) == 1) {
+            map.remove(&addr);
+        }
+    }
+}
+
+/// Run `f` under the read lock of the inode.
+pub fn read_inode<T>(addr: u32, f: impl FnOnce() -> T) -> T {
+    let lock = inode_lock(addr);
+    let r = {
+        let _g = lock.read().unwrap_or_else(|e| e.into_inner());
+        f()
+    };
+    forget(addr, lock);
+    r
+}
+
+/// Run `f` under the write lock of the inode.
+pub fn write_inode<T>(addr: u32, f: impl FnOnce() -> T) -> T {
+    let lock = inode_lock(addr);
+    let r = {
+        let _g = lock.write().unwrap_or_else(|e| e.into_inner());
+        f()
+    };
+    forget(addr, lock);
+    r
+}
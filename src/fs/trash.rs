@@ -0,0 +1,481 @@
+/* Trash
+ * /.trash/<uid>/<id>: entry removed by the user, file or whole directory
+ * /.trash/<uid>/.index: one line per entry
+ *     <id> <deletion time> <blocks> <original path>
+ *
+ * Entries are moved, not copied: the inode keeps its data and only changes
+ * parent. Whenever something is moved to the trash, entries older than
+ * MAX_AGE are purged, then the oldest ones until the trash fits in QUOTA.
+ */
+
+// ====== ERROR ======
+
+use std::{error, fmt, result};
+use super::error::*;
+
+#[derive(Debug)]
+pub enum TrashError {
+    InvalidPath,
+    NotFound,
+    InTrash,
+    Occupied,
+    Exists,
+    ParentNotFound,
+    ParentNotDir,
+    NoEnoughSpace,
+    Incorrupted,
+    DiskErr(DiskError),
+}
+
+impl error::Error for TrashError {}
+
+impl fmt::Display for TrashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TrashError: {:?}", self)
+    }
+}
+
+impl From<DiskError> for TrashError {
+    fn from(e: DiskError) -> Self { Self::DiskErr(e) }
+}
+
+impl From<InodeError> for TrashError {
+    fn from(e: InodeError) -> Self {
+        match e {
+            InodeError::InvalidAddr => Self::Incorrupted,
+            InodeError::NoUsableBlock | InodeError::DataTooBig => Self::NoEnoughSpace,
+            InodeError::DiskErr(e) => Self::DiskErr(e),
+        }
+    }
+}
+
+impl From<DataError> for TrashError {
+    fn from(e: DataError) -> Self {
+        match e {
+            DataError::InvalidAddr => Self::Incorrupted,
+            DataError::InsufficientUsableBlocks => Self::NoEnoughSpace,
+            DataError::DiskErr(e) => Self::DiskErr(e),
+        }
+    }
+}
+
+impl From<DdError> for TrashError {
+    fn from(e: DdError) -> Self {
+        match e {
+            DdError::InvalidPath => Self::InvalidPath,
+            DdError::NotFound => Self::NotFound,
+            DdError::ParentNotFound => Self::ParentNotFound,
+            DdError::NotDir | DdError::ParentNotDir => Self::ParentNotDir,
+            DdError::DirExists | DdError::EntryExists => Self::Exists,
+            DdError::DirOccupied => Self::Occupied,
+            DdError::NoEnoughSpace => Self::NoEnoughSpace,
+            DdError::IoErr(e) => Self::DiskErr(DiskError::IoErr(e)),
+            _ => Self::Incorrupted,
+        }
+    }
+}
+
+impl From<FdError> for TrashError {
+    fn from(e: FdError) -> Self {
+        match e {
+            FdError::InvalidPath => Self::InvalidPath,
+            FdError::NotFound => Self::NotFound,
+            FdError::ParentNotFound => Self::ParentNotFound,
+            FdError::NotFile | FdError::ParentNotDir => Self::ParentNotDir,
+            FdError::FileExists => Self::Exists,
+            FdError::FileOccupied => Self::Occupied,
+            FdError::NoEnoughSpace => Self::NoEnoughSpace,
+            FdError::IoErr(e) => Self::DiskErr(DiskError::IoErr(e)),
+            _ => Self::Incorrupted,
+        }
+    }
+}
+
+type Result<T> = result::Result<T, TrashError>;
+
+// ====== INDEX ======
+
+use crate::logger;
+use super::{data, dir, file, inode, utils};
+use super::{path_to_inode, FdTable, FsReq, FsError};
+use chrono::Local;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+/// Trash directories of every user are kept under this directory of the root.
+pub const TRASH_DIR: &str = ".trash";
+const INDEX_FILE: &str = ".index";
+
+/// Entries are purged this long (seconds) after being deleted.
+pub const MAX_AGE: u32 = 30 * 24 * 60 * 60;
+/// Blocks the trash of one user may hold.
+pub const QUOTA: u32 = 8 * 1024;
+
+#[derive(Debug, Clone, Default)]
+pub struct TrashEntry {
+    pub id: u32,
+    pub timestamp: u32,
+    pub blocks: u32,
+    pub path: String,
+}
+
+fn parse_index(buf: &[u8]) -> Vec<TrashEntry> {
+    let mut v = Vec::<TrashEntry>::new();
+    for line in String::from_utf8_lossy(buf).lines() {
+        let fields: Vec<&str> = line.splitn(4, ' ').collect();
+        if fields.len() < 4 {
+            continue;
+        }
+        if let (Ok(id), Ok(timestamp), Ok(blocks)) =
+            (fields[0].parse(), fields[1].parse(), fields[2].parse()) {
+            v.push(TrashEntry { id, timestamp, blocks, path: fields[3].to_string() });
+        }
+    }
+    v
+}
+
+fn index_bytes(entries: &[TrashEntry]) -> Vec<u8> {
+    let mut s = String::new();
+    for e in entries {
+        s += &format!("{} {} {} {}\n", e.id, e.timestamp, e.blocks, e.path);
+    }
+    s.into_bytes()
+}
+
+fn now() -> u32 {
+    Local::now().timestamp() as u32
+}
+
+/// Return `true` if `path` is the trash or inside it.
+pub fn in_trash(path: &str) -> bool {
+    let root = format!("/{TRASH_DIR}");
+    path == root || path.starts_with(&(root + "/"))
+}
+
+/// Return where `entry` of user `uid` is kept in the trash.
+pub fn entry_path(uid: u8, entry: &TrashEntry) -> String {
+    format!("/{TRASH_DIR}/{uid}/{}", entry.id)
+}
+
+fn find(dir_inode: u32, name: &str) -> Result<Option<u32>> {
+    for ent in dir::read_dir(dir_inode)? {
+        if ent.name == name {
+            return Ok(Some(ent.inode));
+        }
+    }
+    Ok(None)
+}
+
+fn lookup(path: &str) -> Result<Option<u32>> {
+    match path_to_inode(path) {
+        Ok(i) => Ok(Some(i)),
+        Err(FsError::NotFound) => Ok(None),
+        Err(FsError::InvalidPath) => Err(TrashError::InvalidPath),
+        Err(_) => Err(TrashError::Incorrupted)
+    }
+}
+
+fn split_path(path: &str) -> Result<(String, String)> {
+    let path = path.trim_end_matches('/');
+    let pos = match path.rfind('/') {
+        Some(p) => p,
+        None => return Err(TrashError::InvalidPath)
+    };
+    let name = &path[pos + 1..];
+    if name.is_empty() || name == "." || name == ".." || name.len() > dir::NAME_LEN {
+        return Err(TrashError::InvalidPath);
+    }
+    Ok((String::from("/") + path[..pos].trim_start_matches('/'), name.to_string()))
+}
+
+// trash directory of the user, and its index file
+struct Bin {
+    dir: u32,
+    index: u32,
+    entries: Vec<TrashEntry>,
+}
+
+fn open_bin(tx: &Sender<FsReq>, fd_table: &Arc<Mutex<FdTable>>, uid: u8, create: bool) -> Result<Option<Bin>> {
+    let root = format!("/{TRASH_DIR}");
+    let path = format!("{root}/{uid}");
+    if create {
+        if lookup(&root)?.is_none() {
+            dir::create_dir(tx.clone(), fd_table.clone(), &root, 0)?;
+        }
+        if lookup(&path)?.is_none() {
+            dir::create_dir(tx.clone(), fd_table.clone(), &path, uid)?;
+        }
+        if lookup(&format!("{path}/{INDEX_FILE}"))?.is_none() {
+            file::create_file(tx.clone(), fd_table.clone(), &format!("{path}/{INDEX_FILE}"), uid)?;
+        }
+    }
+    let dir_inode = match lookup(&path)? {
+        Some(i) => i,
+        None => return Ok(None)
+    };
+    let index = match find(dir_inode, INDEX_FILE)? {
+        Some(i) => i,
+        None => return Ok(None)
+    };
+
+    // entries removed from the trash directory by hand are forgotten
+    let names: Vec<String> = dir::read_dir(dir_inode)?.into_iter().map(|e| e.name).collect();
+    let entries = parse_index(&file::read_file(index)?)
+        .into_iter()
+        .filter(|e| names.contains(&e.id.to_string()))
+        .collect();
+    Ok(Some(Bin { dir: dir_inode, index, entries }))
+}
+
+fn save_bin(bin: &Bin) -> Result<()> {
+    file::write_file(bin.index, &mut index_bytes(&bin.entries))?;
+    Ok(())
+}
+
+// every inode of the tree rooted at `addr`
+fn tree(addr: u32) -> Result<Vec<u32>> {
+    let mut v = vec![addr];
+    let node = inode::load_inode(addr)?;
+    if node.mode & inode::DIR_FLAG != 0 {
+        for ent in dir::read_dir(addr)? {
+            if ent.name == "." || ent.name == ".." {
+                continue;
+            }
+            v.append(&mut tree(ent.inode)?);
+        }
+    }
+    Ok(v)
+}
+
+fn tree_blocks(addr: u32) -> Result<u32> {
+    let mut n = 0;
+    for a in tree(addr)? {
+        n += inode::get_blocks(&inode::load_inode(a)?)?.len() as u32;
+    }
+    Ok(n)
+}
+
+// move the entry `addr` from `from` to `to` under `name`
+fn move_entry(addr: u32, from: u32, to: u32, name: &str) -> Result<()> {
+    dir::dir_remove_entry(from, addr)?;
+    if let Err(e) = dir::dir_add_entry(to, addr, name) {
+        let _ = dir::dir_add_entry(from, addr, name);
+        return Err(e.into());
+    }
+    if inode::load_inode(addr)?.mode & inode::DIR_FLAG != 0 {
+        dir::dir_remove_entry(addr, from)?;
+        dir::dir_add_entry(addr, to, "..")?;
+    }
+    Ok(())
+}
+
+// free every inode of the entry for good
+fn purge(fd_table: &Arc<Mutex<FdTable>>, bin: &Bin, id: u32) -> Result<()> {
+    let addr = match find(bin.dir, &id.to_string())? {
+        Some(a) => a,
+        None => return Ok(())
+    };
+    let addrs = tree(addr)?;
+    {
+        let lock = utils::mutex_lock(fd_table.lock());
+        if addrs.iter().any(|a| lock.check(*a).is_some()) {
+            return Err(TrashError::Occupied);
+        }
+    }
+    dir::dir_remove_entry(bin.dir, addr)?;
+    for a in addrs {
+        let node = inode::load_inode(a)?;
+        data::free_blocks(&inode::get_blocks(&node)?)?;
+        data::free_blocks(&inode::get_index_blocks(&node)?)?;
+        inode::free_inode(a)?;
+    }
+    Ok(())
+}
+
+// purge by age, then by quota, keeping entries still open
+fn apply_policy(fd_table: &Arc<Mutex<FdTable>>, bin: &mut Bin, keep: u32) -> Result<()> {
+    let limit = now().saturating_sub(MAX_AGE);
+    let mut total: u32 = bin.entries.iter().map(|e| e.blocks).sum();
+    let mut kept = Vec::<TrashEntry>::with_capacity(bin.entries.len());
+    bin.entries.sort_by_key(|e| (e.timestamp, e.id));
+    for e in std::mem::take(&mut bin.entries) {
+        if e.id != keep && (e.timestamp < limit || total > QUOTA) {
+            match purge(fd_table, bin, e.id) {
+                Ok(_) => {
+                    logger::log(&format!("[FS] Purge trash entry {}: {}", e.id, e.path));
+                    total -= e.blocks;
+                    continue;
+                },
+                Err(TrashError::Occupied) => {},
+                Err(err) => {
+                    kept.push(e);
+                    bin.entries = kept;
+                    return Err(err);
+                }
+            }
+        }
+        kept.push(e);
+    }
+    bin.entries = kept;
+    Ok(())
+}
+
+// ====== FN ======
+
+/// Move `path` into the trash of user `uid`, recording where it came from.
+///
+/// ## Error
+///
+/// - InvalidPath
+/// - NotFound
+/// - InTrash: `path` is already in the trash
+/// - Occupied
+/// - NoEnoughSpace
+/// - Incorrupted
+/// - DiskErr
+pub fn trash(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, path: &str, uid: u8) -> Result<TrashEntry> {
+    if in_trash(path) {
+        return Err(TrashError::InTrash);
+    }
+    let (parent_path, _) = split_path(path)?;
+    let addr = match lookup(path)? {
+        Some(a) => a,
+        None => return Err(TrashError::NotFound)
+    };
+    let parent = match lookup(&parent_path)? {
+        Some(a) => a,
+        None => return Err(TrashError::ParentNotFound)
+    };
+    if utils::mutex_lock(fd_table.lock()).check(addr).is_some() {
+        return Err(TrashError::Occupied);
+    }
+
+    let mut bin = match open_bin(&tx, &fd_table, uid, true)? {
+        Some(b) => b,
+        None => return Err(TrashError::Incorrupted)
+    };
+    let entry = TrashEntry {
+        id: bin.entries.iter().map(|e| e.id).max().unwrap_or(0) + 1,
+        timestamp: now(),
+        blocks: tree_blocks(addr)?,
+        path: path.trim_end_matches('/').to_string(),
+    };
+    move_entry(addr, parent, bin.dir, &entry.id.to_string())?;
+    bin.entries.push(entry.clone());
+    save_bin(&bin)?;
+    logger::log(&format!("[FS] Move to trash of user{uid}: {path}"));
+
+    apply_policy(&fd_table, &mut bin, entry.id)?;
+    save_bin(&bin)?;
+    Ok(entry)
+}
+
+/// Return entries in the trash of user `uid`, oldest first.
+///
+/// ## Error
+///
+/// - Incorrupted
+/// - DiskErr
+pub fn list(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, uid: u8) -> Result<Vec<TrashEntry>> {
+    let mut v = match open_bin(&tx, &fd_table, uid, false)? {
+        Some(b) => b.entries,
+        None => Vec::new()
+    };
+    v.sort_by_key(|e| (e.timestamp, e.id));
+    Ok(v)
+}
+
+/// Put an entry of the trash of user `uid` back. `key` is the id of the
+/// entry or its original path, the latest deleted one if there are several.
+/// The entry goes to `to`, or where it was deleted from.
+///
+/// ## Error
+///
+/// - InvalidPath
+/// - NotFound
+/// - Exists: something else is at the destination now
+/// - ParentNotFound
+/// - ParentNotDir
+/// - NoEnoughSpace
+/// - Incorrupted
+/// - DiskErr
+pub fn restore(
+    tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, uid: u8, key: &str, to: Option<&str>
+) -> Result<TrashEntry> {
+    let mut bin = match open_bin(&tx, &fd_table, uid, false)? {
+        Some(b) => b,
+        None => return Err(TrashError::NotFound)
+    };
+    let pos = match key.parse::<u32>() {
+        Ok(id) => bin.entries.iter().position(|e| e.id == id),
+        Err(_) => bin.entries.iter()
+            .enumerate()
+            .filter(|(_, e)| e.path == key.trim_end_matches('/'))
+            .max_by_key(|(_, e)| (e.timestamp, e.id))
+            .map(|(i, _)| i)
+    };
+    let pos = match pos {
+        Some(p) => p,
+        None => return Err(TrashError::NotFound)
+    };
+    let mut entry = bin.entries[pos].clone();
+    let dest = to.unwrap_or(&entry.path).trim_end_matches('/').to_string();
+    if in_trash(&dest) {
+        return Err(TrashError::InvalidPath);
+    }
+
+    let (parent_path, name) = split_path(&dest)?;
+    if lookup(&dest)?.is_some() {
+        return Err(TrashError::Exists);
+    }
+    let parent = match lookup(&parent_path)? {
+        Some(a) => a,
+        None => return Err(TrashError::ParentNotFound)
+    };
+    if inode::load_inode(parent)?.mode & inode::DIR_FLAG == 0 {
+        return Err(TrashError::ParentNotDir);
+    }
+    let addr = match find(bin.dir, &entry.id.to_string())? {
+        Some(a) => a,
+        None => return Err(TrashError::NotFound)
+    };
+
+    move_entry(addr, bin.dir, parent, &name)?;
+    bin.entries.remove(pos);
+    save_bin(&bin)?;
+    logger::log(&format!("[FS] Restore from trash of user{uid}: {dest}"));
+    entry.path = dest;
+    Ok(entry)
+}
+
+/// Purge every entry in the trash of user `uid`, but those with open files.
+/// Return the entries purged.
+///
+/// ## Error
+///
+/// - Incorrupted
+/// - DiskErr
+pub fn empty(tx: Sender<FsReq>, fd_table: Arc<Mutex<FdTable>>, uid: u8) -> Result<Vec<TrashEntry>> {
+    let mut bin = match open_bin(&tx, &fd_table, uid, false)? {
+        Some(b) => b,
+        None => return Ok(Vec::new())
+    };
+    let mut purged = Vec::<TrashEntry>::new();
+    let mut res = Ok(());
+    for e in std::mem::take(&mut bin.entries) {
+        if res.is_ok() {
+            match purge(&fd_table, &bin, e.id) {
+                Ok(_) => {
+                    purged.push(e);
+                    continue;
+                },
+                Err(TrashError::Occupied) => {},
+                Err(err) => res = Err(err)
+            }
+        }
+        bin.entries.push(e);
+    }
+    save_bin(&bin)?;
+    logger::log(&format!("[FS] Empty trash of user{uid}: {} entries", purged.len()));
+    res.map(|_| purged)
+}
@@ -15,6 +15,22 @@ pub fn mutex_lock<T>(result: LockResult<MutexGuard<'_, T>>) -> MutexGuard<'_, T>
 
 }
 
+/// Resolve `path` as absolute: ".", "..", repeated and trailing '/' are
+/// resolved, ".." stays at the root.
+pub fn canonical_path(path: &str) -> String {
+    let mut sections = Vec::<&str>::new();
+    for section in path.split('/') {
+        match section {
+            "" | "." => (),
+            ".." => {
+                sections.pop();
+            },
+            s => sections.push(s)
+        }
+    }
+    String::from("/") + &sections.join("/")
+}
+
 pub fn u32_to_u8arr(a: u32) -> [u8; 4] {
     let mut arr = [0u8; 4];
     arr[0] = ( a >> 24)              as u8;
@@ -55,4 +71,26 @@ pub fn count_ones_in_u64(n: u64) -> u32 {
         b = b << 1;
     }
     a
-}
\ No newline at end of file
+}
+/// Pack block addresses into one block, padding the rest with zero.
+pub fn addrs_to_block(addrs: &[u32]) -> Vec<u8> {
+    let mut buf = Vec::<u8>::with_capacity(super::disk::BLOCK_SIZE as usize);
+    for addr in addrs {
+        buf.append(&mut u32_to_u8arr(*addr).to_vec());
+    }
+    buf.resize(super::disk::BLOCK_SIZE as usize, 0);
+    buf
+}
+
+/// Unpack block addresses from one block, stopping at the first zero.
+pub fn block_to_addrs(buf: &[u8]) -> Vec<u32> {
+    let mut v = Vec::<u32>::new();
+    for chunk in buf.chunks_exact(4) {
+        let addr = u8arr_to_u32(chunk);
+        if addr == 0 {
+            break
+        }
+        v.push(addr);
+    }
+    v
+}
@@ -0,0 +1,237 @@
+/* Watches
+ * A watch on a path gets an event whenever a request changing the path, or
+ * an entry of it if it is a directory, succeeds. A recursive watch also gets
+ * events from anywhere below the path.
+ *
+ * by path: requests carrying paths (create, remove, trash...) are matched
+ *     against the watched paths
+ * by inode: requests carrying inodes (write, update inode...) are matched by
+ *     looking the inode up under the watched paths
+ * transaction: events are held until it ends, and dropped if it is aborted
+ */
+
+use std::fmt;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender, Receiver};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Created,
+    Modified,
+    Removed,
+    Renamed,
+    Attrib,
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchEvent {
+    pub kind: EventKind,
+    /// Absolute path of the file or directory changed.
+    pub path: String,
+    /// New path of a renamed one.
+    pub to: Option<String>,
+}
+
+impl fmt::Display for WatchEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            EventKind::Created => "CREATED",
+            EventKind::Modified => "MODIFIED",
+            EventKind::Removed => "REMOVED",
+            EventKind::Renamed => "RENAMED",
+            EventKind::Attrib => "ATTRIB",
+        };
+        match &self.to {
+            Some(to) => write!(f, "{kind} {} -> {to}", self.path),
+            None => write!(f, "{kind} {}", self.path)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Watch {
+    path: String,
+    inode: u32,
+    recursive: bool,
+    tx: Sender<WatchEvent>,
+}
+
+static WATCHES: Mutex<Option<HashMap<u64, Watch>>> = Mutex::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+/// Events of the running transaction.
+static HELD: Mutex<Option<Vec<WatchEvent>>> = Mutex::new(None);
+
+/// Receives the events of one watch, until dropped.
+#[derive(Debug)]
+pub struct Watcher {
+    id: u64,
+    rx: Receiver<WatchEvent>,
+}
+
+impl Watcher {
+    /// Wait for the next event, at most `timeout`.
+    pub fn wait(&self, timeout: Duration) -> Option<WatchEvent> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        if let Some(m) = utils::mutex_lock(WATCHES.lock()).as_mut() {
+            m.remove(&self.id);
+        }
+    }
+}
+
+// ====== FN ======
+
+use super::{utils, inode, dir, path_to_inode, FsError};
+
+/// Watch `path` (absolute), and everything below it if `recursive`.
+///
+/// ## Error
+///
+/// - InvalidPath
+/// - NotFound
+pub fn watch(path: &str, recursive: bool) -> Result<Watcher, FsError> {
+    let inode = path_to_inode(path)?;
+    let path = match path.trim_end_matches('/') {
+        "" => String::from("/"),
+        p => String::from(p)
+    };
+    let (tx, rx) = mpsc::channel();
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    utils::mutex_lock(WATCHES.lock())
+        .get_or_insert_with(HashMap::new)
+        .insert(id, Watch { path, inode, recursive, tx });
+    Ok(Watcher { id, rx })
+}
+
+/// Hold events from now on, e.g. when a transaction begins.
+pub fn hold() {
+    *utils::mutex_lock(HELD.lock()) = Some(Vec::new());
+}
+
+/// Stop holding events, and send those held if `deliver`.
+pub fn release(deliver: bool) {
+    let held = utils::mutex_lock(HELD.lock()).take();
+    if let (Some(events), true) = (held, deliver) {
+        events.into_iter().for_each(send);
+    }
+}
+
+/// Report a change of `path` (absolute).
+pub fn notify(kind: EventKind, path: &str) {
+    emit(WatchEvent { kind, path: String::from(path), to: None });
+}
+
+/// Report `from` moved to `to`.
+pub fn notify_rename(from: &str, to: &str) {
+    emit(WatchEvent { kind: EventKind::Renamed, path: String::from(from), to: Some(String::from(to)) });
+}
+
+/// Report a change of the file or directory at inode `addr`.
+pub fn notify_inode(kind: EventKind, addr: u32) {
+    if let Some(path) = path_of(addr) {
+        notify(kind, &path);
+    }
+}
+
+/// Report a change of the entry `name` of the directory at inode `dir_addr`.
+pub fn notify_entry(kind: EventKind, dir_addr: u32, name: &str) {
+    if let Some(path) = path_of(dir_addr) {
+        notify(kind, &join(&path, name));
+    }
+}
+
+/// Return `true` if anything is watched, so changes are worth looking up.
+pub fn active() -> bool {
+    utils::mutex_lock(WATCHES.lock()).as_ref().is_some_and(|m| !m.is_empty())
+}
+
+fn emit(ev: WatchEvent) {
+    if !active() {
+        return;
+    }
+    if let Some(held) = utils::mutex_lock(HELD.lock()).as_mut() {
+        held.push(ev);
+        return;
+    }
+    send(ev);
+}
+
+fn send(ev: WatchEvent) {
+    let guard = utils::mutex_lock(WATCHES.lock());
+    let watches = match guard.as_ref() {
+        Some(m) => m,
+        None => return
+    };
+    for w in watches.values() {
+        let hit = covers(w, &ev.path) || ev.to.as_deref().is_some_and(|to| covers(w, to));
+        if hit {
+            let _ = w.tx.send(ev.clone());
+        }
+    }
+}
+
+// `path` is the watched path, an entry of it, or below it for a recursive one
+fn covers(w: &Watch, path: &str) -> bool {
+    if path == w.path || parent(path) == w.path {
+        return true;
+    }
+    w.recursive && (w.path == "/" || path.starts_with(&format!("{}/", w.path)))
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) => "/",
+        Some(i) => &path[..i],
+        None => ""
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "/" => format!("/{name}"),
+        _ => format!("{dir}/{name}")
+    }
+}
+
+// path of the inode, looked up under the watched paths only
+fn path_of(addr: u32) -> Option<String> {
+    let roots: Vec<(String, u32, bool)> = match utils::mutex_lock(WATCHES.lock()).as_ref() {
+        Some(m) => m.values().map(|w| (w.path.clone(), w.inode, w.recursive)).collect(),
+        None => return None
+    };
+    for (path, root, recursive) in roots {
+        if root == addr {
+            return Some(path);
+        }
+        if let Some(p) = find(&path, root, addr, recursive) {
+            return Some(p);
+        }
+    }
+    None
+}
+
+fn find(path: &str, dir_addr: u32, addr: u32, recursive: bool) -> Option<String> {
+    let is_dir = inode::load_inode(dir_addr).is_ok_and(|i| i.mode & inode::DIR_FLAG > 0);
+    if !is_dir {
+        return None;
+    }
+    let ents = dir::read_dir(dir_addr).ok()?;
+    let ents = ents.into_iter().filter(|e| e.name != "." && e.name != "..");
+    let mut subdirs = Vec::new();
+    for e in ents {
+        if e.inode == addr {
+            return Some(join(path, &e.name));
+        }
+        if recursive {
+            subdirs.push(e);
+        }
+    }
+    subdirs.into_iter().find_map(|e| find(&join(path, &e.name), e.inode, addr, true))
+}
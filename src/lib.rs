@@ -1,4 +1,5 @@
 pub mod logger;
+pub mod passphrase;
 mod sedes;
 
 mod fs;
@@ -6,5 +7,11 @@ mod services;
 
 mod server;
 
-pub use fs::start_fs;
-pub use server::{PORT, SdReq, SdRes, start_server};
\ No newline at end of file
+pub use fs::{start_fs, start_fs_on, BlockDevice, FileDevice, MemDevice, MmapDevice, DISK_PATH};
+pub use fs::Problem;
+#[cfg(feature = "fault-injection")]
+pub use fs::{FaultDevice, Recording, Crash, CrashImage, CrashReport, crash_test};
+pub use fs::{Superblock, InspectError, InodeInfo, RawEntry, BitmapKind};
+pub use fs::{open_image, inode_info, raw_entries, bitmap_range, file_content};
+pub use fs::{ImageError, ImageEntry, make_image};
+pub use server::{PORT, SdReq, SdRes, HostFile, Body, BodyWriter, start_server};
\ No newline at end of file
@@ -1,6 +1,15 @@
 // [PASS]
 
 use chrono::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static MUTED: AtomicBool = AtomicBool::new(false);
+
+/// Stop printing messages, or start again, e.g. in tools printing their own
+/// output.
+pub fn mute(muted: bool) {
+    MUTED.store(muted, Ordering::SeqCst);
+}
 
 /// Add a timestamp like `[hh:mm:ss] ` before the message to print.
 /// 
@@ -13,6 +22,9 @@ use chrono::prelude::*;
 /// logger::log(&format!("to format: {}", 10));
 /// ```
 pub fn log(msg: &str) {
+    if MUTED.load(Ordering::SeqCst) {
+        return;
+    }
     let now = Local::now();
     println!("[{:0>2}:{:0>2}:{:0>2}] {msg}", now.hour(), now.minute(), now.second());
 }
\ No newline at end of file
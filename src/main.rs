@@ -10,20 +10,50 @@
  * data bitmap: 16 blocks
  */
 
+use std::env;
+use std::io;
 use std::sync::mpsc;
 use std::thread;
 use simdisk::{
-    start_fs,
+    start_fs_on,
     start_server,
     logger,
+    BlockDevice,
+    FileDevice,
+    MemDevice,
+    MmapDevice,
+    DISK_PATH,
 };
+use simdisk::passphrase::passphrase;
+
+// --memory: keep the disk in memory, lost on exit
+// --mmap: map the disk file into memory
+fn device() -> io::Result<Box<dyn BlockDevice>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--memory") {
+        return Ok(Box::new(MemDevice::new()));
+    }
+    if args.iter().any(|a| a == "--mmap") {
+        return Ok(Box::new(MmapDevice::open(DISK_PATH)?));
+    }
+    Ok(Box::new(FileDevice::open(DISK_PATH)?))
+}
 
 fn main() {
     logger::log("[MAIN] Simdisk starting...");
+    // -p: prompt for the passphrase of an encrypted disk
+    let passphrase = passphrase(env::args().skip(1).any(|a| a == "-p"));
+    let device = match device() {
+        Ok(d) => d,
+        Err(e) => {
+            logger::log(&format!("[ERR][MAIN] Failed to open disk file. Msg: {e}"));
+            return;
+        }
+    };
     let (fs_tx, fs_rx) = mpsc::channel();
     let (started_tx, started_rx) = mpsc::channel();
     let ft = fs_tx.clone();
-    thread::spawn(|| start_fs(started_tx, ft, fs_rx));
+    thread::spawn(|| start_fs_on(device, started_tx, ft, fs_rx, passphrase));
 
     if let Err(e) = started_rx.recv().unwrap() {
         logger::log(e);
@@ -32,4 +62,4 @@ fn main() {
     logger::log("[MAIN] Simdisk started.");
 
     start_server(fs_tx);
-}
\ No newline at end of file
+}
@@ -0,0 +1,49 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/// Environment variable holding the passphrase of an encrypted image.
+pub const PASSPHRASE_ENV: &str = "SIMDISK_PASSPHRASE";
+
+/// Return the passphrase of an encrypted image: typed at a prompt on stderr
+/// if `prompt`, otherwise taken from [PASSPHRASE_ENV] if set. The variable is
+/// removed once read, so that child processes do not see it.
+pub fn passphrase(prompt: bool) -> Option<String> {
+    if prompt {
+        eprint!("passphrase: ");
+        io::stderr().flush().ok()?;
+        return read_hidden().ok();
+    }
+    let p = env::var(PASSPHRASE_ENV).ok()?;
+    env::remove_var(PASSPHRASE_ENV);
+    Some(p)
+}
+
+// turn echo of the terminal on stdin on or off
+fn set_echo(on: bool) -> io::Result<()> {
+    let status = Command::new("stty")
+        .arg(if on { "echo" } else { "-echo" })
+        .stdin(Stdio::inherit())
+        .status()?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other("stty failed")),
+    }
+}
+
+// read a line from stdin, not shown if it is a terminal
+fn read_hidden() -> io::Result<String> {
+    let tty = io::stdin().is_terminal();
+    if tty {
+        set_echo(false)?;
+    }
+    let mut buf = String::new();
+    let r = io::stdin().read_line(&mut buf);
+    if tty {
+        // the newline typed was not shown either
+        eprintln!();
+        set_echo(true)?;
+    }
+    r?;
+    Ok(buf.trim_end_matches(['\r', '\n']).to_string())
+}
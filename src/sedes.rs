@@ -3,6 +3,7 @@ use std::{error, fmt};
 #[derive(Debug)]
 pub enum SedesError {
     DeserialBufferTooSmall,
+    InvalidData,
 }
 
 impl error::Error for SedesError {}
@@ -8,44 +8,272 @@ pub struct SdReq {
     pub cmd: String,
     pub args: Vec<String>,
     pub redirect: String,
+    /// Files sent along, their bodies following this line.
+    #[serde(default)]
+    pub files: Vec<HostFile>,
 }
 
 #[derive(Default, Debug, serde::Serialize, serde::Deserialize)]
 pub struct SdRes {
     pub wd: String,
     pub result: String,
+    /// More responses follow, e.g. of a streaming command.
+    #[serde(default)]
+    pub more: bool,
+    /// Files sent along, their bodies following this line.
+    #[serde(default)]
+    pub files: Vec<HostFile>,
+}
+
+/// A file or directory carried between the shell and simdisk.
+/// 
+/// The json line of [SdReq] or [SdRes] is followed by the body of every
+/// file carried, in order, as a [Body] (empty for a directory). Files sent
+/// back come in responses with `more` set, ahead of the result.
+#[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct HostFile {
+    /// Path below the directory carried from, '/' separated. Parents come
+    /// before what is in them.
+    pub path: String,
+    pub is_dir: bool,
+    /// Unix permission bits.
+    pub mode: u32,
+    /// Seconds since the epoch.
+    pub mtime: u32,
 }
 
 // ====== FN ======
 
 pub const PORT: u16 = 7735;
 
+/// Bytes of a body carried at most at a time.
+pub const CHUNK: usize = 8 * 1024;
+
+// length of the chunk ending a body its sender failed to read whole
+const CUT: u32 = u32::MAX;
+
 use crate::logger;
 use super::{fs, services};
-use std::io::{Write, BufReader, BufRead};
+use std::io::{self, Read, Write, BufReader, BufRead, BufWriter};
 use std::sync::mpsc;
 use std::net::{TcpStream, TcpListener};
 use threadpool::ThreadPool;
 
+/// Body of a file carried, read from the connection.
+/// 
+/// A body comes as chunks of at most [CHUNK] bytes, each after its length
+/// (u32, big endian), and ends with an empty chunk. Reading a body its
+/// sender cut short fails. What is left of it is skipped when dropped.
+pub struct Body<'a> {
+    reader: &'a mut dyn Read,
+    // bytes left in the chunk
+    left: usize,
+    ended: bool,
+    cut: bool,
+}
+
+impl<'a> Body<'a> {
+    /// Body coming next from `reader`.
+    pub fn new(reader: &'a mut dyn Read) -> Self {
+        Self { reader, left: 0, ended: false, cut: false }
+    }
+
+    /// Return true if the sender cut the body short, once read to there.
+    pub fn is_cut(&self) -> bool {
+        self.cut
+    }
+
+    /// Skip what is left of the body, to get to what follows it.
+    pub fn skip(&mut self) -> io::Result<()> {
+        match io::copy(self, &mut io::sink()) {
+            Err(_) if self.cut => Ok(()),
+            r => r.map(|_| ()),
+        }
+    }
+}
+
+impl Read for Body<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.left == 0 && !self.ended {
+            let mut len = [0u8; 4];
+            self.reader.read_exact(&mut len)?;
+            match u32::from_be_bytes(len) {
+                0 => self.ended = true,
+                CUT => {
+                    self.ended = true;
+                    self.cut = true;
+                },
+                n if n as usize <= CHUNK => self.left = n as usize,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "chunk too long")),
+            }
+        }
+        if self.cut {
+            return Err(io::Error::other("cut short by the sender"));
+        }
+        if self.ended || buf.is_empty() {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.left);
+        let n = self.reader.read(&mut buf[..n])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.left -= n;
+        Ok(n)
+    }
+}
+
+impl Drop for Body<'_> {
+    fn drop(&mut self) {
+        let _ = self.skip();
+    }
+}
+
+/// Body of a file carried, written to the connection as a [Body] is read.
+/// 
+/// Every write goes as a chunk, ended by [BodyWriter::finish].
+pub struct BodyWriter<'a> {
+    writer: &'a mut dyn Write,
+}
+
+impl<'a> BodyWriter<'a> {
+    /// Body going next to `writer`.
+    pub fn new(writer: &'a mut dyn Write) -> Self {
+        Self { writer }
+    }
+
+    /// End the body.
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.write_all(&0u32.to_be_bytes())
+    }
+
+    /// End the body short, for what it came from could not be read whole.
+    pub fn cut(self) -> io::Result<()> {
+        self.writer.write_all(&CUT.to_be_bytes())
+    }
+
+    /// Send what is left in `from`, at most [CHUNK] bytes at a time, and end
+    /// the body; cut it short if reading `from` fails.
+    /// 
+    /// The outer error is of the connection, the inner one of `from`.
+    pub fn send_from(mut self, from: &mut dyn Read) -> io::Result<io::Result<u64>> {
+        let mut buf = vec![0u8; CHUNK];
+        let mut sent = 0;
+        loop {
+            match from.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.write_all(&buf[..n])?;
+                    sent += n as u64;
+                },
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.cut()?;
+                    return Ok(Err(e));
+                }
+            }
+        }
+        self.finish()?;
+        Ok(Ok(sent))
+    }
+}
+
+impl Write for BodyWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(CHUNK);
+        if n == 0 {
+            return Ok(0);
+        }
+        self.writer.write_all(&(n as u32).to_be_bytes())?;
+        self.writer.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Connection of a command exchanging files with the shell.
+pub struct Transfer<'a> {
+    reader: &'a mut dyn Read,
+    writer: &'a mut dyn Write,
+    wd: String,
+    // bodies of the request, and how many were taken
+    bodies: usize,
+    taken: usize,
+}
+
+impl Transfer<'_> {
+    /// Body of the next file of the request.
+    pub fn body(&mut self) -> Body<'_> {
+        self.taken += 1;
+        Body::new(self.reader)
+    }
+
+    /// Send `file` back, ahead of the result. Its body is written to what is
+    /// returned, and finished.
+    pub fn send(&mut self, file: HostFile) -> io::Result<BodyWriter<'_>> {
+        let res = SdRes { wd: self.wd.clone(), result: String::new(), more: true, files: vec![file] };
+        let res_msg = serde_json::to_string(&res).unwrap() + "\n";
+        self.writer.write_all(res_msg.as_bytes())?;
+        Ok(BodyWriter::new(self.writer))
+    }
+
+    // skip bodies of the request left by the command
+    fn skip_rest(&mut self) -> io::Result<()> {
+        while self.taken < self.bodies {
+            self.body().skip()?;
+        }
+        Ok(())
+    }
+}
+
 type HandlerMap = std::collections::HashMap<
     String,
     fn (services::Context, Vec<&str>) -> (services::Context, String)
 >;
 
+// commands sending their output as it comes, until the client is gone
+type StreamMap = std::collections::HashMap<
+    String,
+    fn (services::Context, Vec<&str>, &mut dyn FnMut(&str) -> bool) -> (services::Context, String)
+>;
+
+// commands taking files from the shell or sending files back
+type TransferMap = std::collections::HashMap<
+    String,
+    fn (services::Context, Vec<&str>, Vec<HostFile>, &mut Transfer) -> (services::Context, String)
+>;
+
 pub fn start_server(fs_tx: mpsc::Sender<fs::FsReq>) {
     // init handler map
     let mut map = HandlerMap::new();
     map.insert(String::from("login"), services::login);
     map.insert(String::from("info"), services::info);
+    map.insert(String::from("df"), services::df);
+    map.insert(String::from("du"), services::du);
     map.insert(String::from("cd"), services::cd);
     map.insert(String::from("ls"), services::ls);
     map.insert(String::from("touch"), services::touch);
-    map.insert(String::from("cat"), services::cat);
     map.insert(String::from("echo"), services::echo);
     map.insert(String::from("cp"), services::cp);
     map.insert(String::from("rm"), services::rm);
     map.insert(String::from("mkdir"), services::mkdir);
     map.insert(String::from("check"), services::check);
+    map.insert(String::from("resize"), services::resize);
+    map.insert(String::from("defrag"), services::defrag);
+    map.insert(String::from("compress"), services::compress);
+    map.insert(String::from("snapshot"), services::snapshot);
+    map.insert(String::from("trash"), services::trash);
+    map.insert(String::from("restore"), services::restore);
+    let mut stream_map = StreamMap::new();
+    stream_map.insert(String::from("cat"), services::cat);
+    stream_map.insert(String::from("watch"), services::watch);
+    let mut transfer_map = TransferMap::new();
+    transfer_map.insert(String::from("put"), services::put);
+    transfer_map.insert(String::from("get"), services::get);
+    transfer_map.insert(String::from("tar"), services::tar);
 
     // start tcp listener
     let listener = match TcpListener::bind(format!("127.0.0.1:{PORT}")) {
@@ -69,14 +297,22 @@ pub fn start_server(fs_tx: mpsc::Sender<fs::FsReq>) {
 
         let tx = fs_tx.clone();
         let m = map.clone();
-        pool.execute(move || if let Err(e) = route(tx, stream, m) {
+        let sm = stream_map.clone();
+        let fm = transfer_map.clone();
+        pool.execute(move || if let Err(e) = route(tx, stream, m, sm, fm) {
             logger::log(&format!("[SERVER] IoErr: {e:?}"));
         })
     }
 }
 
 // run in seperated thread
-pub fn route(fs_tx: mpsc::Sender<fs::FsReq>, mut stream: TcpStream, map: HandlerMap) -> Result<(), std::io::Error> {
+pub fn route(
+    fs_tx: mpsc::Sender<fs::FsReq>,
+    mut stream: TcpStream,
+    map: HandlerMap,
+    stream_map: StreamMap,
+    transfer_map: TransferMap,
+) -> Result<(), std::io::Error> {
     // extract stream as json: SdReq
     let mut req = String::new();
     let mut reader = BufReader::new(&mut stream);
@@ -89,6 +325,100 @@ pub fn route(fs_tx: mpsc::Sender<fs::FsReq>, mut stream: TcpStream, map: Handler
         }
     };
 
+    // call the corresponding streaming service
+    if let Some(handler) = stream_map.get(&req.cmd) {
+        logger::log(&format!(
+            "[SERVER] From user{} received streaming commad: {}\n    \
+            with args: {:?}\n    \
+            redirecting to: {}",
+            &req.uid, &req.cmd, &req.args, &req.redirect
+        ));
+        let ctx = services::Context { uid: req.uid, wd: req.wd.clone(), tx: fs_tx.clone() };
+        let args: Vec<&str> = req.args.iter().map(|s| s.as_str()).collect();
+
+        // output goes to the client, or to the file redirected to (emptied)
+        let opened = match req.redirect.as_str() {
+            "" => Ok(None),
+            redirect => services::open_output(ctx.clone(), redirect).and_then(|mut fd| {
+                match fd.write(&Vec::new()) {
+                    Ok(_) => Ok(Some(fd)),
+                    Err(_) => Err(format!("shell: Cannot write to '{redirect}': Inner Error.\n"))
+                }
+            })
+        };
+        let result = match opened {
+            Err(msg) => msg,
+            Ok(mut file) => {
+                let mut gone = false;
+                let mut failed = false;
+                let wd = req.wd.clone();
+                let mut emit = |s: &str| match &mut file {
+                    Some(fd) if !s.is_empty() => {
+                        failed = fd.write_all(s.as_bytes()).is_err();
+                        !failed
+                    },
+                    // nothing to write, but the client may be gone
+                    _ => {
+                        let res = SdRes { wd: wd.clone(), result: String::from(s), more: true, files: Vec::new() };
+                        let res_msg = serde_json::to_string(&res).unwrap() + "\n";
+                        gone = stream.write_all(res_msg.as_bytes()).and_then(|_| stream.flush()).is_err();
+                        !gone
+                    }
+                };
+                let (_, result) = handler(ctx, args, &mut emit);
+                if gone {
+                    return Ok(());
+                }
+                match file {
+                    Some(mut fd) => match failed || fd.write_all(result.as_bytes()).is_err() {
+                        true => format!("shell: Cannot write to '{}': Inner Error.\n", req.redirect),
+                        false => String::from("\n")
+                    },
+                    None => result
+                }
+            }
+        };
+
+        // end the stream as json: SdRes
+        let res = SdRes { wd: req.wd, result, more: false, files: Vec::new() };
+        let res_msg = serde_json::to_string(&res).unwrap() + "\n";
+        stream.write_all(res_msg.as_bytes())?;
+        stream.flush()?;
+        return Ok(());
+    }
+
+    // call the corresponding service exchanging files
+    if let Some(handler) = transfer_map.get(&req.cmd) {
+        logger::log(&format!(
+            "[SERVER] From user{} received commad: {}\n    \
+            with args: {:?}\n    \
+            with files: {}\n    \
+            redirecting to: {}",
+            &req.uid, &req.cmd, &req.args, req.files.len(), &req.redirect
+        ));
+        let ctx = services::Context { uid: req.uid, wd: req.wd.clone(), tx: fs_tx.clone() };
+        let ctx_o = ctx.clone();
+        let args: Vec<&str> = req.args.iter().map(|s| s.as_str()).collect();
+        let mut writer = BufWriter::new(reader.get_ref().try_clone()?);
+        let mut transfer = Transfer {
+            reader: &mut reader,
+            writer: &mut writer,
+            wd: req.wd,
+            bodies: req.files.len(),
+            taken: 0,
+        };
+        let (ctx, s) = handler(ctx, args, req.files, &mut transfer);
+        transfer.skip_rest()?;
+        let o = services::output(ctx_o, s, &req.redirect);
+
+        // end with the result as json: SdRes
+        let res = SdRes { wd: ctx.wd, result: o, more: false, files: Vec::new() };
+        let res_msg = serde_json::to_string(&res).unwrap() + "\n";
+        writer.write_all(res_msg.as_bytes())?;
+        writer.flush()?;
+        return Ok(());
+    }
+
     // call the corresponding service
     match map.get(&req.cmd) {
         Some(handler) => {
@@ -105,7 +435,7 @@ pub fn route(fs_tx: mpsc::Sender<fs::FsReq>, mut stream: TcpStream, map: Handler
             let o = services::output(ctx_o, s, &req.redirect);
 
             // return result as json: SdRes
-            let res = SdRes { wd: ctx.wd, result: o };
+            let res = SdRes { wd: ctx.wd, result: o, more: false, files: Vec::new() };
             let mut res_msg = serde_json::to_string(&res).unwrap();
             res_msg = res_msg + "\n";
 
@@ -122,6 +452,8 @@ pub fn route(fs_tx: mpsc::Sender<fs::FsReq>, mut stream: TcpStream, map: Handler
             let res = SdRes {
                 wd: req.wd,
                 result: format!("Unknown command: {}\n", req.cmd),
+                more: false,
+                files: Vec::new(),
             };
             let mut res_msg = serde_json::to_string(&res).unwrap();
             res_msg = res_msg + "\n";
@@ -3,6 +3,8 @@ mod utils;
 mod login;
 mod output;
 mod info;
+mod df;
+mod du;
 mod cd;
 mod ls;
 mod mkdir;
@@ -12,11 +14,23 @@ mod echo;
 mod cp;
 mod rm;
 mod check;
+mod resize;
+mod defrag;
+mod compress;
+mod snapshot;
+mod trash;
+mod restore;
+mod watch;
+mod put;
+mod get;
+mod tar;
 
 pub use {
     login::login,
-    output::output,
+    output::{output, open_output},
     info::info,
+    df::df,
+    du::du,
     cd::cd,
     ls::ls,
     mkdir::mkdir,
@@ -26,6 +40,16 @@ pub use {
     cp::cp,
     rm::rm,
     check::check,
+    resize::resize,
+    defrag::defrag,
+    compress::compress,
+    snapshot::snapshot,
+    trash::trash,
+    restore::restore,
+    watch::watch,
+    put::put,
+    get::get,
+    tar::tar,
 };
 
 pub struct Context {
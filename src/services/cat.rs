@@ -17,13 +17,16 @@
  */
 use getopts::Options;
 use super::{Context, utils, permission};
-use crate::fs::{metadata, open_file};
+use crate::fs::{metadata, open_file, OpenFlags};
+use std::io::Read;
 
 // define uasge and permission
 const USAGE: &str = "Usage: cat [-nb] <file1> <file2> ...\n";
 const PERMISSION: (bool, bool, bool) = (true, false, false);
+// bytes read at a time
+const CHUNK: usize = 8 * 1024;
 
-pub fn cat(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
+pub fn cat(mut ctx: Context, args: Vec<&str>, emit: &mut dyn FnMut(&str) -> bool) -> (Context, String) {
     if args.len() < 1 {
         return (ctx, String::from(USAGE));
     }
@@ -54,22 +57,24 @@ pub fn cat(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
     let number_lines = matches.opt_present("n");
     let number_non_empty_lines = matches.opt_present("b");
 
-    let mut return_str = String::new();
-
     // iterate path in paths
     for path in &matches.free {
         // check if exists
-        let file_path = match utils::convert_path_to_abs(&ctx.wd, &path) {
+        let file_path = match utils::abs_path(&ctx, path) {
             Ok(p) => p,
             Err(e) => {
-                return_str += &format!("Cannot convert '{}' to absolute path\n", path);
+                if !emit(&format!("Cannot convert '{}' to absolute path\n", path)) {
+                    break;
+                }
                 continue;
             },
         };
         let meta = match metadata(&mut ctx.tx, &file_path) {
             Ok(m) => m,
             Err(e) => {
-                return_str += &format!("Cannot find '{}'\n", path);
+                if !emit(&format!("Cannot find '{}'\n", path)) {
+                    break;
+                }
                 continue;
             },
         };
@@ -77,52 +82,91 @@ pub fn cat(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
         // check permission
         let rwx = permission::check_permission(ctx.uid, &meta, PERMISSION);
         if !rwx {
-            return_str += &format!("Permission denied\n");
+            if !emit("Permission denied\n") {
+                break;
+            }
             continue;
         }
 
         // open file
-        if meta.is_dir() {
-            return_str += &format!("'{}' is a directory\n", file_path)[..];
+        if meta.is_dir() && !emit(&format!("'{}' is a directory\n", file_path)) {
+            break;
         }
-        let mut file_fd = match open_file(&mut ctx.tx, &file_path) {
+        let mut file_fd = match open_file(&mut ctx.tx, &file_path, OpenFlags::READ, ctx.uid) {
             Ok(fd) => fd,
             Err(e) => {
-                return_str += &format!("Cannot open file: '{}'\n", path);
-                continue;
-            },
-        };
-
-        // get original file vector
-        let file_vec = match file_fd.read() {
-            Ok(v) => v,
-            Err(e) => {
-                return_str += &format!("Cannot read file: '{}'\n", path);
+                if !emit(&format!("Cannot open file: '{}'\n", path)) {
+                    break;
+                }
                 continue;
             },
         };
 
-        // split file vector with "\n"
-        let lines = file_vec.split(|&c| c == b'\n');
+        // read a chunk at a time, numbering lines as they start
+        let mut chunk = vec![0u8; CHUNK];
         let mut output_vec = Vec::new();
         let mut line_num = 1;
+        let mut line_start = true;
+        let mut gone = false;
+        loop {
+            let n = match Read::read(&mut file_fd, &mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(_) => {
+                    output_vec.extend(format!("Cannot read file: '{}'\n", path).bytes());
+                    break;
+                }
+            };
+            for &c in &chunk[..n] {
+                // "-n" -> input line number at the beginning
+                // "-b" -> input line number at the beginning of non empty line
+                if line_start && (number_lines || number_non_empty_lines && c != b'\n') {
+                    output_vec.extend(format!("{:>6}\t", line_num).bytes());
+                    line_num += 1;
+                }
+                line_start = c == b'\n';
+                output_vec.push(c);
+            }
 
-        for line in lines {
-            // "-n" -> input line number at the beginning
-            // "-b" -> input line number at the beginning of non empty line
-            if number_lines || number_non_empty_lines && !line.is_empty() {
-                output_vec.extend(format!("{:>6}\t", line_num).bytes());
-                line_num += 1;
+            // send what is complete, a character cut at the end waits
+            let cut = complete_len(&output_vec);
+            if !emit(&String::from_utf8_lossy(&output_vec[..cut])) {
+                gone = true;
+                break;
             }
+            output_vec.drain(..cut);
+        }
+        if gone {
+            break;
+        }
 
-            // append output vector
-            output_vec.extend(line);
-            output_vec.push(b'\n');
+        // the last line ends with the file
+        if line_start && number_lines {
+            output_vec.extend(format!("{:>6}\t", line_num).bytes());
+        }
+        output_vec.push(b'\n');
+        if !emit(&String::from_utf8_lossy(&output_vec)) {
+            break;
         }
-        // convert output vector to string
-        return_str.push_str(&String::from_utf8_lossy(&output_vec));
-        // return_str += &String::from_utf8_lossy(&output_vec).into_owned();
     }
 
-    (ctx, return_str)
-}
\ No newline at end of file
+    (ctx, String::new())
+}
+
+// length of `v` without a UTF-8 character cut at the end
+fn complete_len(v: &[u8]) -> usize {
+    for back in 1..=v.len().min(3) {
+        let b = v[v.len() - back];
+        // not a continuation byte
+        if b & 0xC0 != 0x80 {
+            let need = match b {
+                0xF0.. => 4,
+                0xE0.. => 3,
+                0xC0.. => 2,
+                _ => 1
+            };
+            return if need > back { v.len() - back } else { v.len() };
+        }
+    }
+    v.len()
+}
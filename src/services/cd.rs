@@ -35,7 +35,7 @@ pub fn cd(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
 
     // get dir path
     let path = &matches.free[0]; 
-    let dir_path = match utils::convert_path_to_abs(&ctx.wd, &path) {
+    let dir_path = match utils::abs_path(&ctx, path) {
         Ok(p) => p,
         Err(_) => return (ctx, format!("cd: Cannot convert path '{}' to absolute path\n", path)),
     };
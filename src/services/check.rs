@@ -1,6 +1,50 @@
+ /*
+ * check the file system
+ * if nothing is wrong
+ *     return "Everything is OK."
+ * add each problem to return str, leaks last
+ * add summary to return str
+ */
+use getopts::Options;
 use super::Context;
+use crate::fs::check as check_disk;
+
+const USAGE: &str = "Usage: check\n";
 
 pub fn check(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
-    // check if file system is ok
-    return (ctx, String::from("Everything is OK.\n"));
-}
\ No newline at end of file
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("h") || !matches.free.is_empty() {
+        return (ctx, String::from(USAGE));
+    }
+
+    let mut problems = match check_disk(&mut ctx.tx) {
+        Ok(p) => p,
+        Err(_) => return (ctx, String::from("check: Cannot read the disk\n")),
+    };
+    if problems.is_empty() {
+        return (ctx, String::from("Everything is OK.\n"));
+    }
+
+    problems.sort_by_key(|p| p.is_leak());
+    let leaks = problems.iter().filter(|p| p.is_leak()).count();
+    let mut return_str = String::new();
+    for p in &problems {
+        return_str += &format!("{p}\n");
+    }
+    return_str += &format!(
+        "check: {} problems, {} only wasting space\n",
+        problems.len(), leaks
+    );
+    (ctx, return_str)
+}
@@ -0,0 +1,85 @@
+ /*
+ * iterate path in paths:
+ *     if path doesn't exist
+ *         return err
+ *     if user cannot write path
+ *         return err
+ *     set compression of path (-u: unset)
+ *     (-v: +) print logical and physical size
+ */
+use getopts::Options;
+use super::{Context, utils, permission};
+use crate::fs::metadata;
+
+const USAGE: &str = "Usage: compress [-uv] <file>...\n";
+const PERMISSION: (bool, bool, bool) = (false, true, false);
+
+pub fn compress(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
+    if args.is_empty() {
+        return (ctx, String::from(USAGE));
+    }
+
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+    opts.optflag("u", "", "Turn compression off");
+    opts.optflag("v", "", "Print sizes after the change");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        return (ctx, String::from(USAGE));
+    }
+
+    // convert parameters to bool variables
+    let compressed = !matches.opt_present("u");
+    let verbose = matches.opt_present("v");
+
+    let mut return_str = String::new();
+
+    // iterate path in paths
+    for path in &matches.free {
+        let new_path = match utils::abs_path(&ctx, path) {
+            Ok(p) => p,
+            Err(_) => {
+                return_str += &format!("compress: Cannot convert '{}' to absolute path\n", path);
+                continue;
+            }
+        };
+        let mut meta = match metadata(&mut ctx.tx, &new_path) {
+            Ok(m) => m,
+            Err(_) => {
+                return_str += &format!("compress: Cannot find '{}'\n", path);
+                continue;
+            }
+        };
+
+        // check permission
+        if !permission::check_permission(ctx.uid, &meta, PERMISSION) {
+            return_str += &format!("compress: Permission denied: '{path}'\n");
+            continue;
+        }
+
+        if meta.set_compressed(compressed).is_err() {
+            return_str += &format!("compress: Cannot change compression of '{}'\n", path);
+            continue;
+        }
+
+        if verbose {
+            match metadata(&mut ctx.tx, &new_path) {
+                Ok(m) => return_str += &format!(
+                    "'{}': {} bytes, {} bytes on disk\n", path, m.size(), m.physical_size()
+                ),
+                Err(_) => return_str += &format!("compress: Cannot find '{}'\n", path),
+            }
+        }
+    }
+
+    (ctx, return_str)
+}
@@ -34,54 +34,60 @@
  *       (-v: +) copy file to new_path
  */
 
- // todo: cp <host> ...
+ // "host:" paths are turned into put/get by the shell
 use getopts::Options;
 use super::{Context, utils, permission};
-use crate::fs::{metadata, open_dir, open_file, create_dir, create_file};
+use crate::fs::{metadata, open_dir, open_file, create_dir, create_file, OpenFlags, FsError, FsErrorKind};
+use std::io;
 
 // define uasge and permission
 const USAGE: &str = "Usage: cp [-r] [-v] SOURSE DEST\n";
 const PERMISSION_SRC: (bool, bool, bool) = (true, false, false);
 const PERMISSION_TGT: (bool, bool, bool) = (false, true, false);
 
-fn copy(ctx: &mut Context, src_path: &str, tgt_path: &str, copy_dir: bool, verbose: bool) -> String {
-    let mut return_str = String::new();
-
+// append messages to `out`; Err if copying has to stop, i.e. the disk is full
+fn copy(ctx: &mut Context, src_path: &str, tgt_path: &str, copy_dir: bool, verbose: bool, out: &mut String) -> Result<(), FsErrorKind> {
     let src_meta = match metadata(&mut ctx.tx, src_path) {
         Ok(m) => m,
-        Err(_) => return format!("Cannot find '{}'\n", src_path),
+        Err(e) => {
+            *out += &format!("Cannot access '{}': {}\n", src_path, e.kind());
+            return Ok(());
+        }
     };
 
     // check source permission
     let rwx = permission::check_permission(ctx.uid, &src_meta, PERMISSION_SRC);
     if !rwx {
-        return format!("Permission denied\n");
+        *out += "Permission denied\n";
+        return Ok(());
     }
 
     // target path must be new
     if let Ok(_) = metadata(&mut ctx.tx, tgt_path) {
-        return format!("'{}' exists\n", tgt_path);
+        *out += &format!("'{}' exists\n", tgt_path);
+        return Ok(());
     }
     
     // if source path is a dir
     if src_meta.is_dir() {
         if !copy_dir {
-            return format!("cp: -r not specified; omitting directory '{}'\n", tgt_path);
+            *out += &format!("cp: -r not specified; omitting directory '{}'\n", tgt_path);
+            return Ok(());
         }
 
         let mut tgt_dd = match create_dir(&mut ctx.tx, &tgt_path, ctx.uid) {
             Ok(dd) => dd,
-            Err(_) => return format!("Cannot create directory: '{}'\n", tgt_path),
+            Err(e) => return fail(out, format!("Cannot create directory: '{}'", tgt_path), e.kind()),
         };
 
         // get sub entris of source dir
         let mut src_dd = match open_dir(&mut ctx.tx, &src_path) {
             Ok(dd) => dd,
-            Err(_) => return format!("Cannot open directory: '{}'\n", src_path),
+            Err(e) => return fail(out, format!("Cannot open directory: '{}'", src_path), e.kind()),
         };
         let src_vec = match src_dd.read() {
             Ok(v) => v,
-            Err(_) => return format!("Cannot read file: '{}'\n", src_path),
+            Err(e) => return fail(out, format!("Cannot read directory: '{}'", src_path), FsError::from(e).kind()),
         };
         
         // iterate entry in sub entris
@@ -93,44 +99,51 @@ fn copy(ctx: &mut Context, src_path: &str, tgt_path: &str, copy_dir: bool, verbo
 
             // get sub path
             let parent_path = src_path;
-            let sub_path = match utils::convert_path_to_abs(&parent_path, &sub_entry.name) {
-                Ok(p) => p,
-                Err(_) => return format!("Cannot convert '{}' to absolute path\n", sub_entry.name),
-            };
+            let sub_path = utils::canonicalize(parent_path, &sub_entry.name);
 
             // copy recursively
             let mut tgt_path = String::from(tgt_path);
             tgt_path += "/";
             tgt_path += &sub_entry.name;
-            copy(ctx, &sub_path, &tgt_path, copy_dir, verbose);
+            copy(ctx, &sub_path, &tgt_path, copy_dir, verbose, out)?;
         }
     } else {
         // read source file
-        let mut src_fd = match open_file(&mut ctx.tx, &src_path) {
+        let mut src_fd = match open_file(&mut ctx.tx, src_path, OpenFlags::READ, ctx.uid) {
             Ok(fd) => fd,
-            Err(_) => return format!("Cannot open file: '{}'\n", &src_path),
+            Err(e) => return fail(out, format!("Cannot open file: '{}'", &src_path), e.kind()),
         };
         let mut tgt_fd = match create_file(&mut ctx.tx, &tgt_path, ctx.uid) {
             Ok(fd) => fd,
-            Err(_) => return format!("Cannot create file: '{}'\n", &tgt_path),
-        };
-
-        let src_vec = match src_fd.read() {
-            Ok(v) => v,
-            Err(_) => return format!("Cannot read file: '{}'\n", &src_path),
+            Err(e) => return fail(out, format!("Cannot create file: '{}'", &tgt_path), e.kind()),
         };
 
-        // write into target file
-        if let Err(_) = tgt_fd.write(&src_vec) {
-            return format!("Cannot write to file: '{}'\n", &tgt_path)
+        // copy a block at a time
+        if let Err(e) = io::copy(&mut src_fd, &mut tgt_fd) {
+            let kind = match e.kind() {
+                io::ErrorKind::StorageFull => FsErrorKind::NoSpace,
+                io::ErrorKind::WouldBlock => FsErrorKind::Busy,
+                io::ErrorKind::PermissionDenied => FsErrorKind::ReadOnly,
+                _ => FsErrorKind::Io
+            };
+            return fail(out, format!("Cannot copy '{}' to '{}'", &src_path, &tgt_path), kind);
         }
     }
 
     // add detailed info
     if verbose {
-        return_str = format!("'{}' -> '{}'\n", src_path, tgt_path);
+        *out += &format!("'{}' -> '{}'\n", src_path, tgt_path);
+    }
+    Ok(())
+}
+
+// report the failure; stop copying if there is no space left
+fn fail(out: &mut String, msg: String, kind: FsErrorKind) -> Result<(), FsErrorKind> {
+    *out += &format!("{msg}: {kind}\n");
+    match kind {
+        FsErrorKind::NoSpace => Err(kind),
+        _ => Ok(())
     }
-    return return_str;
 }
 
 pub fn cp(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
@@ -167,11 +180,11 @@ pub fn cp(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
         0 => return (ctx, format!("Missing src path. {USAGE}")),
         1 => return (ctx, format!("Missing tgt path. {USAGE}")),
         2 => {
-            let src_path = match utils::convert_path_to_abs(&ctx.wd, &matches.free[0]) {
+            let src_path = match utils::abs_path(&ctx, &matches.free[0]) {
                 Ok(p) => p,
                 Err(_) => return (ctx, format!("Cannot convert '{}' to absolute path\n", &matches.free[0])),
             };
-            let tgt_path = match utils::convert_path_to_abs(&ctx.wd, &matches.free[1]) {
+            let tgt_path = match utils::abs_path(&ctx, &matches.free[1]) {
                 Ok(p) => p,
                 Err(_) => return (ctx, format!("Cannot convert '{}' to absolute path\n", &matches.free[1])),
             };
@@ -190,22 +203,22 @@ pub fn cp(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
                             .next()
                             .unwrap_or(&src_path)
                             .to_string();
-                        let tgt_path_new = format!("{}/{}", tgt_path, src_path_append);
-                        let tmp = copy(&mut ctx, &src_path, &tgt_path_new, copy_dir, verbose);
-                        return (ctx, tmp);
+                        let tgt_path_new = utils::canonicalize(&tgt_path, &src_path_append);
+                        let _ = copy(&mut ctx, &src_path, &tgt_path_new, copy_dir, verbose, &mut return_str);
+                        return (ctx, return_str);
                     } else {
-                        let tmp = copy(&mut ctx, &src_path, &tgt_path, copy_dir, verbose);
-                        return (ctx, tmp);
+                        let _ = copy(&mut ctx, &src_path, &tgt_path, copy_dir, verbose, &mut return_str);
+                        return (ctx, return_str);
                     }
                 }
                 Err(_) => {
-                    let tmp = copy(&mut ctx, &src_path, &tgt_path, copy_dir, verbose);
-                    return (ctx, tmp)
+                    let _ = copy(&mut ctx, &src_path, &tgt_path, copy_dir, verbose, &mut return_str);
+                    return (ctx, return_str)
                 }
             };
         }
         _ => {
-            let tgt_path = match utils::convert_path_to_abs(&ctx.wd, &matches.free[len - 1]) {
+            let tgt_path = match utils::abs_path(&ctx, &matches.free[len - 1]) {
                 Ok(p) => p,
                 Err(_) => return (ctx, format!("Cannot convert '{}' to absolute path\n\n", &matches.free[len - 1])),
             };
@@ -220,7 +233,7 @@ pub fn cp(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
 
                     if m.is_dir() {
                         for src in &matches.free[0..len - 1] {
-                            let src_path = match utils::convert_path_to_abs(&ctx.wd, &src) {
+                            let src_path = match utils::abs_path(&ctx, src) {
                                 Ok(p) => p,
                                 Err(_) => {
                                     return_str += &format!("Cannot convert '{}' to absolute path\n\n", src);
@@ -232,8 +245,10 @@ pub fn cp(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
                                 .next()
                                 .unwrap_or(&src_path)
                                 .to_string();
-                            let tgt_path_new = format!("{}/{}", tgt_path, src_path_append);
-                            return_str += &copy(&mut ctx, &src_path, &tgt_path_new, copy_dir, verbose);
+                            let tgt_path_new = utils::canonicalize(&tgt_path, &src_path_append);
+                            if copy(&mut ctx, &src_path, &tgt_path_new, copy_dir, verbose, &mut return_str).is_err() {
+                                break;
+                            }
                         }
                         return (ctx, return_str);
                     } else {
@@ -0,0 +1,77 @@
+ /*
+ * if user is not admin
+ *     return err
+ * defrag(-n)
+ * iterate file in report
+ *     if file is fragmented or -v is specified
+ *         add "path: blocks, extents -> extents" to return str
+ * add summary to return str
+ */
+use getopts::Options;
+use super::{Context, permission};
+use crate::fs::defrag as defrag_disk;
+
+const USAGE: &str = "Usage: defrag [-nv]\n";
+
+pub fn defrag(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+    opts.optflag("n", "", "Only report fragmentation, move nothing");
+    opts.optflag("v", "", "List every file, not only fragmented ones");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("h") || !matches.free.is_empty() {
+        return (ctx, String::from(USAGE));
+    }
+
+    // check permission
+    if !permission::is_admin(ctx.uid) {
+        return (ctx, String::from("defrag: Permission denied: Only admin can defragment the disk\n"));
+    }
+
+    // convert parameters to bool variables
+    let analyze_only = matches.opt_present("n");
+    let verbose = matches.opt_present("v");
+
+    let report = match defrag_disk(&mut ctx.tx, analyze_only) {
+        Ok(r) => r,
+        Err(_) => return (ctx, String::from("defrag: Cannot defragment disk\n")),
+    };
+
+    let mut return_str = String::new();
+    let total = report.files.len();
+    let mut fragmented = 0;
+    let mut fixed = 0;
+    for (i, file) in report.files.iter().enumerate() {
+        if file.extents > 1 {
+            fragmented += 1;
+        }
+        if file.extents_after < file.extents {
+            fixed += 1;
+        }
+        if file.extents > 1 || verbose {
+            return_str += &format!(
+                "[{:>4}/{total}] {}: {} blocks, {} -> {} extents\n",
+                i + 1, file.path, file.blocks, file.extents, file.extents_after
+            );
+        }
+    }
+    if analyze_only {
+        return_str += &format!("defrag: {total} files, {fragmented} fragmented\n");
+    } else {
+        return_str += &format!(
+            "defrag: {total} files, {fragmented} fragmented, {fixed} defragmented, {} blocks moved\n",
+            report.moved_blocks
+        );
+    }
+
+    (ctx, return_str)
+}
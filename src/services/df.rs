@@ -0,0 +1,71 @@
+ /*
+ * statfs()
+ * if -i is specified
+ *     add inodes: total, used, free, use% to return str
+ * else
+ *     add blocks: total, used, free, use% to return str
+ *     (in 1K blocks, or human-readable sizes if -h is specified)
+ */
+use getopts::Options;
+use super::{Context, utils};
+use crate::fs::statfs;
+
+const USAGE: &str = "Usage: df [-h] [-i]\n";
+
+// used / total, rounded up like df does
+fn percent(used: u32, total: u32) -> u64 {
+    if total == 0 {
+        return 0;
+    }
+    (used as u64 * 100).div_ceil(total as u64)
+}
+
+pub fn df(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("", "help", "Help");
+    opts.optflag("h", "", "Print sizes in human-readable format");
+    opts.optflag("i", "", "List inode usage instead of blocks");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("help") || !matches.free.is_empty() {
+        return (ctx, String::from(USAGE));
+    }
+
+    let st = match statfs(&mut ctx.tx) {
+        Ok(s) => s,
+        Err(e) => return (ctx, format!("df: Cannot read disk usage: {}\n", e.kind())),
+    };
+
+    if matches.opt_present("i") {
+        let return_str = format!(
+            "{:>10} {:>10} {:>10} {:>5} Mounted on\n{:>10} {:>10} {:>10} {:>4}% /\n",
+            "Inodes", "IUsed", "IFree", "IUse%",
+            st.inodes, st.used_inodes, st.free_inodes, percent(st.used_inodes, st.inodes)
+        );
+        return (ctx, return_str);
+    }
+
+    let bytes = |blocks: u32| blocks as u64 * st.block_size as u64;
+    let size = |blocks: u32| match matches.opt_present("h") {
+        true => utils::human_size(bytes(blocks)),
+        false => (bytes(blocks) / 1024).to_string(),
+    };
+    let header = match matches.opt_present("h") {
+        true => "Size",
+        false => "1K-blocks",
+    };
+    let return_str = format!(
+        "{:>10} {:>10} {:>10} {:>5} Mounted on\n{:>10} {:>10} {:>10} {:>4}% /\n",
+        header, "Used", "Avail", "Use%",
+        size(st.blocks), size(st.used_blocks), size(st.free_blocks), percent(st.used_blocks, st.blocks)
+    );
+    (ctx, return_str)
+}
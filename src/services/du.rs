@@ -0,0 +1,191 @@
+ /*
+ * iterate path in paths:
+ *     if path doesn't exist
+ *         return_str += err message
+ *         continue
+ *     if inode of path has been counted
+ *         continue
+ *     walk(path, depth 0)
+ *     if path is a file
+ *         add "allocated  logical  path" to return str
+ *
+ * ---fn walk(path, depth) -> usage
+ *     if inode of path has been counted
+ *         return nothing
+ *     usage = allocated blocks (index blocks included) and size of path
+ *     if path is a dir
+ *         iterate sub_entry in path Dd
+ *             if sub_entry is ".." or "."
+ *                 continue
+ *             usage += walk(sub_path, depth + 1)
+ *         if depth is within -d (-s: 0, default: any)
+ *             add "allocated  logical  path" to return str
+ *     return usage
+ */
+use getopts::Options;
+use super::{Context, utils, permission};
+use crate::fs::{metadata, open_dir, superblock, FsError, Metadata};
+use std::collections::HashSet;
+
+const USAGE: &str = "Usage: du [-s] [-h] [-d depth] [path]...\n";
+const PERMISSION: (bool, bool, bool) = (true, false, false);
+
+// space taken by a tree: allocated blocks, and logical size in bytes
+#[derive(Default)]
+struct Usage {
+    blocks: u64,
+    bytes: u64,
+}
+
+struct Walk {
+    block_size: u64,
+    max_depth: Option<usize>,
+    human: bool,
+    // inodes already counted, reached through another name
+    seen: HashSet<u32>,
+    out: String,
+}
+
+impl Walk {
+    fn size(&self, bytes: u64) -> String {
+        match self.human {
+            true => utils::human_size(bytes),
+            false => bytes.div_ceil(1024).to_string(),
+        }
+    }
+
+    fn print(&mut self, usage: &Usage, path: &str) {
+        let line = format!("{:>8} {:>8}  {}\n", self.size(usage.blocks * self.block_size), self.size(usage.bytes), path);
+        self.out += &line;
+    }
+
+    fn walk(&mut self, ctx: &mut Context, path: &str, meta: &Metadata, depth: usize) -> Usage {
+        if !self.seen.insert(meta.ino()) {
+            return Usage::default();
+        }
+        let mut usage = Usage {
+            blocks: match meta.allocated_blocks() {
+                Ok(n) => n as u64,
+                Err(e) => {
+                    self.out += &format!("du: Cannot access '{}': {}\n", path, FsError::from(e).kind());
+                    0
+                }
+            },
+            bytes: meta.size() as u64,
+        };
+        if !meta.is_dir() {
+            return usage;
+        }
+
+        // sum up sub entries
+        if !permission::check_permission(ctx.uid, meta, PERMISSION) {
+            self.out += &format!("du: Cannot read directory '{path}': Permission denied\n");
+        } else {
+            match open_dir(&mut ctx.tx, path).map(|mut dd| dd.read()) {
+                Ok(Ok(vec)) => {
+                    for sub_entry in vec {
+                        if sub_entry.name == ".." || sub_entry.name == "." {
+                            continue;
+                        }
+                        let sub_path = utils::canonicalize(path, &sub_entry.name);
+                        let sub_meta = match metadata(&mut ctx.tx, &sub_path) {
+                            Ok(m) => m,
+                            Err(e) => {
+                                self.out += &format!("du: Cannot access '{}': {}\n", sub_path, e.kind());
+                                continue;
+                            }
+                        };
+                        let sub_usage = self.walk(ctx, &sub_path, &sub_meta, depth + 1);
+                        usage.blocks += sub_usage.blocks;
+                        usage.bytes += sub_usage.bytes;
+                    }
+                },
+                Ok(Err(e)) => self.out += &format!("du: Cannot read directory '{}': {}\n", path, FsError::from(e).kind()),
+                Err(e) => self.out += &format!("du: Cannot open directory '{}': {}\n", path, e.kind()),
+            }
+        }
+
+        if self.max_depth.is_none_or(|d| depth <= d) {
+            self.print(&usage, path);
+        }
+        usage
+    }
+}
+
+pub fn du(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("", "help", "Help");
+    opts.optflag("s", "", "Only print the total of every path");
+    opts.optflag("h", "", "Print sizes in human-readable format");
+    opts.optopt("d", "", "Print directories at most depth levels below path", "depth");
+
+    // parse args
+    let mut matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("help") {
+        return (ctx, String::from(USAGE));
+    }
+
+    let max_depth = match (matches.opt_present("s"), matches.opt_str("d")) {
+        (true, Some(_)) => return (ctx, String::from("du: Cannot both summarize and limit depth\n")),
+        (true, None) => Some(0),
+        (false, Some(d)) => match d.parse::<usize>() {
+            Ok(d) => Some(d),
+            Err(_) => return (ctx, format!("du: Invalid depth '{d}'\n")),
+        },
+        (false, None) => None,
+    };
+
+    let block_size = match superblock(&mut ctx.tx) {
+        Ok(sb) => sb.block_size as u64,
+        Err(e) => return (ctx, format!("du: Cannot read superblock: {}\n", e.kind())),
+    };
+
+    // if no path is specified, use the working directory
+    if matches.free.is_empty() {
+        matches.free.push(String::from("."));
+    }
+
+    let mut walk = Walk {
+        block_size,
+        max_depth,
+        human: matches.opt_present("h"),
+        seen: HashSet::new(),
+        out: String::new(),
+    };
+
+    // iterate path in paths
+    for path in &matches.free {
+        let abs_path = match utils::abs_path(&ctx, path) {
+            Ok(p) => p,
+            Err(e) => {
+                walk.out += &format!("du: Cannot access '{path}': {e}\n");
+                continue;
+            }
+        };
+        let meta = match metadata(&mut ctx.tx, &abs_path) {
+            Ok(m) => m,
+            Err(e) => {
+                walk.out += &format!("du: Cannot access '{}': {}\n", path, e.kind());
+                continue;
+            }
+        };
+        // already counted under an earlier path
+        if walk.seen.contains(&meta.ino()) {
+            continue;
+        }
+        let usage = walk.walk(&mut ctx, &abs_path, &meta, 0);
+        // directories print themselves
+        if !meta.is_dir() {
+            walk.print(&usage, &abs_path);
+        }
+    }
+
+    (ctx, walk.out)
+}
@@ -0,0 +1,154 @@
+ /*
+ * get SOURCE..., the shell writing the files sent back on the host
+ *
+ * iterate path in paths:
+ *     if path doesn't exist
+ *         return_str += err message
+ *         continue
+ *     if path is a dir and -r is not specified
+ *         return_str += err message
+ *         continue
+ *     pack(path, name of path)
+ *
+ * ---fn pack(path, name)
+ *     check permission of path
+ *     send name, mode, mtime (and the body if path is a file)
+ *     (-v: +) add "'path' -> 'host:name'" to return str
+ *     if path is a dir
+ *         iterate sub_entry in path Dd
+ *             if sub_entry is ".." or "."
+ *                 continue
+ *             pack(sub_path, name/sub_entry)
+ */
+use getopts::Options;
+use super::{Context, utils, permission};
+use crate::fs::{metadata, open_dir, open_file, rwx_to_mode, FsError, Metadata, OpenFlags};
+use crate::server::{HostFile, Transfer};
+use std::io;
+
+const USAGE: &str = "Usage: get [-r] [-v] SOURCE... HOST_DEST\n";
+const PERMISSION: (bool, bool, bool) = (true, false, false);
+
+// send `path` as `name` to the shell, and what is in it
+fn pack(
+    ctx: &mut Context, path: &str, name: &str, meta: &Metadata, verbose: bool, transfer: &mut Transfer, out: &mut String
+) -> io::Result<()> {
+    if !permission::check_permission(ctx.uid, meta, PERMISSION) {
+        *out += &format!("get: Permission denied: '{path}'\n");
+        return Ok(());
+    }
+    let file = HostFile {
+        path: String::from(name),
+        is_dir: meta.is_dir(),
+        mode: rwx_to_mode(&meta.permission()),
+        mtime: meta.mtime(),
+    };
+
+    if meta.is_dir() {
+        transfer.send(file)?.finish()?;
+    } else {
+        let mut fd = match open_file(&mut ctx.tx, path, OpenFlags::READ, ctx.uid) {
+            Ok(fd) => fd,
+            Err(e) => {
+                *out += &format!("get: Cannot read '{}': {}\n", path, e.kind());
+                return Ok(());
+            }
+        };
+        if let Err(e) = transfer.send(file)?.send_from(&mut fd)? {
+            *out += &format!("get: Cannot read '{}': {}\n", path, FsError::from(e).kind());
+            return Ok(());
+        }
+    }
+    if verbose {
+        *out += &format!("'{path}' -> 'host:{name}'\n");
+    }
+    if !meta.is_dir() {
+        return Ok(());
+    }
+
+    // get sub entries
+    let vec = match open_dir(&mut ctx.tx, path).map(|mut dd| dd.read()) {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) => {
+            *out += &format!("get: Cannot read directory '{}': {}\n", path, FsError::from(e).kind());
+            return Ok(());
+        },
+        Err(e) => {
+            *out += &format!("get: Cannot open directory '{}': {}\n", path, e.kind());
+            return Ok(());
+        }
+    };
+    for sub_entry in vec {
+        if sub_entry.name == ".." || sub_entry.name == "." {
+            continue;
+        }
+        let sub_path = utils::canonicalize(path, &sub_entry.name);
+        let sub_meta = match metadata(&mut ctx.tx, &sub_path) {
+            Ok(m) => m,
+            Err(e) => {
+                *out += &format!("get: Cannot access '{}': {}\n", sub_path, e.kind());
+                continue;
+            }
+        };
+        pack(ctx, &sub_path, &format!("{name}/{}", sub_entry.name), &sub_meta, verbose, transfer, out)?;
+    }
+    Ok(())
+}
+
+pub fn get(mut ctx: Context, args: Vec<&str>, _files: Vec<HostFile>, transfer: &mut Transfer) -> (Context, String) {
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+    opts.optflag("r", "", "Copy directories recursively");
+    opts.optflag("v", "", "Explain what is being done");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        return (ctx, String::from(USAGE));
+    }
+    let recursive = matches.opt_present("r");
+    let verbose = matches.opt_present("v");
+
+    let mut return_str = String::new();
+
+    // iterate path in paths
+    for path in &matches.free {
+        let abs_path = match utils::abs_path(&ctx, path) {
+            Ok(p) => p,
+            Err(e) => {
+                return_str += &format!("get: Cannot access '{path}': {e}\n");
+                continue;
+            }
+        };
+        let (_, name) = utils::split_path(&abs_path);
+        if name.is_empty() {
+            return_str += "get: Cannot copy the root directory\n";
+            continue;
+        }
+        let meta = match metadata(&mut ctx.tx, &abs_path) {
+            Ok(m) => m,
+            Err(e) => {
+                return_str += &format!("get: Cannot access '{}': {}\n", path, e.kind());
+                continue;
+            }
+        };
+        if meta.is_dir() && !recursive {
+            return_str += &format!("get: -r not specified; omitting directory '{path}'\n");
+            continue;
+        }
+        // the shell is gone
+        if let Err(e) = pack(&mut ctx, &abs_path, name, &meta, verbose, transfer, &mut return_str) {
+            return_str += &format!("get: {e}\n");
+            break;
+        }
+    }
+
+    (ctx, return_str)
+}
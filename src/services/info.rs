@@ -1,19 +1,55 @@
-use super::Context;
+use super::{Context, utils};
+use crate::fs::{superblock, statfs, dedup_stats};
 
-pub fn info(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
+pub fn info(mut ctx: Context, _args: Vec<&str>) -> (Context, String) {
     // get file system info
-    let return_str = String::from("
+    let sb = match superblock(&mut ctx.tx) {
+        Ok(sb) => sb,
+        Err(e) => return (ctx, format!("info: Cannot read superblock: {}\n", e.kind())),
+    };
+    let mut return_str = format!("
         Disk Struture
-        size: 128MB
-        block size: 1KB
-        block count: 128 * 1024
+        size: {}
+        block size: {}B
+        block count: {}
         superblock: 1 blocks
-        inode bitmap: 1 blocks
+        inode bitmap: {} blocks
         inode size: 64B
-        inode count: 4096
-        inode: 256 blocks
-        data bitmap: 16 blocks
-    ");
+        inode count: {}
+        inode: {} blocks
+        data bitmap: {} blocks
+        encrypted: {}
+    ",
+        utils::human_size(sb.block_count as u64 * sb.block_size as u64),
+        sb.block_size,
+        sb.block_count,
+        sb.inode_offset - sb.inode_bitmap_offset,
+        sb.inode_count,
+        sb.data_bitmap_offset - sb.inode_offset,
+        sb.data_offset - sb.data_bitmap_offset,
+        if sb.encrypted != 0 { "yes" } else { "no" },
+    );
 
-    return (ctx, return_str);
-}
\ No newline at end of file
+    // usage
+    if let Ok(st) = statfs(&mut ctx.tx) {
+        return_str += &format!("
+        Usage
+        used blocks: {}
+        free blocks: {}
+        used inodes: {}
+        free inodes: {}
+    ", st.used_blocks, st.free_blocks, st.used_inodes, st.free_inodes);
+    }
+
+    // deduplication
+    if let Ok(s) = dedup_stats(&mut ctx.tx) {
+        return_str += &format!("
+        Deduplication
+        data blocks: {}
+        references: {}
+        saved: {} blocks
+    ", s.blocks, s.refs, s.saved_blocks);
+    }
+
+    (ctx, return_str)
+}
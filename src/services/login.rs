@@ -1,68 +1,55 @@
-use super::{Context, permission};
-use crate::fs::{open_dir, create_dir, remove_dir, remove_file, metadata};
-use crate::fs::FsError;
+use super::{Context, permission, utils};
+use crate::fs::{open_dir, create_dir, remove_dir, remove_file, metadata, atomically};
+use crate::fs::{FsError, FsReq};
+use std::sync::mpsc::Sender;
 
 const PERMISSION: (bool, bool, bool) = (true, true, true);
 
 pub fn login(mut ctx: Context, _: Vec<&str>) -> (Context, String) {
+    // check and create homes with no other session in between
+    let uid = ctx.uid;
+    match atomically(&mut ctx.tx, |tx| found_home(tx, uid)) {
+        Ok(path) => {
+            ctx.wd = path;
+            (ctx, String::new())
+        },
+        Err(_) => (ctx, String::from("Cannot login! Failed to found home!\n"))
+    }
+}
+
+fn found_home(tx: &mut Sender<FsReq>, uid: u8) -> Result<String, FsError> {
     // found or create home_path
-    let home_path = "/home";
-    match open_dir(&mut ctx.tx, home_path) {
+    let home_path = utils::HOME_DIR;
+    match open_dir(tx, home_path) {
         Ok(_) => (),
-        Err(e) => {
-            match e {
-                FsError::NotFound => {
-                    if let Err(_) = create_dir(&mut ctx.tx, home_path, 0) {
-                        return (ctx, String::from("Cannot login! Failed to found home!\n"))
-                    }
-                },
-                FsError::NotDirButFile => {
-                    if let Err(_) = remove_file(&mut ctx.tx, home_path) {
-                        return (ctx, String::from("Cannot login! Failed to found home!\n"))
-                    }
-                    if let Err(_) = create_dir(&mut ctx.tx, home_path, 0) {
-                        return (ctx, String::from("Cannot login! Failed to found home!\n"))
-                    }
-                },
-                _ => return (ctx, String::from("Cannot login! Failed to found home!\n"))
-            }
-        }
+        Err(FsError::NotFound) => {
+            create_dir(tx, home_path, 0)?;
+        },
+        Err(FsError::NotDirButFile) => {
+            remove_file(tx, home_path)?;
+            create_dir(tx, home_path, 0)?;
+        },
+        Err(e) => return Err(e)
     };
 
     // found or create "/home/<uid>"
-    let path = format!("{home_path}/{}", ctx.uid.to_string());
-    match metadata(&mut ctx.tx, &path) {
+    let path = utils::home_dir(uid);
+    match metadata(tx, &path) {
         Ok(m) => {
             // check permission
-            if !permission::check_permission(ctx.uid, &m, PERMISSION) {
-                if let Err(_) = remove_dir(&mut ctx.tx, &path) {
-                    return (ctx, String::from("Cannot login! Failed to found home!\n"))
-                }
-                if let Err(_) = create_dir(&mut ctx.tx, &path, ctx.uid) {
-                    return (ctx, String::from("Cannot login! Failed to found home!\n"))
-                }
+            if !permission::check_permission(uid, &m, PERMISSION) {
+                remove_dir(tx, &path)?;
+                create_dir(tx, &path, uid)?;
             }
         },
-        Err(e) => {
-            match e {
-                FsError::NotFound => {
-                    if let Err(_) = create_dir(&mut ctx.tx, &path, ctx.uid) {
-                        return (ctx, String::from("Cannot login! Failed to found home!\n"))
-                    }
-                },
-                FsError::NotDirButFile => {
-                    if let Err(_) = remove_dir(&mut ctx.tx, &path) {
-                        return (ctx, String::from("Cannot login! Failed to found home!\n"))
-                    }
-                    if let Err(_) = create_dir(&mut ctx.tx, &path, ctx.uid) {
-                        return (ctx, String::from("Cannot login! Failed to found home!\n"))
-                    }
-                },
-                _ => return (ctx, String::from("Cannot login! Failed to found home!\n"))
-            }
-        }
+        Err(FsError::NotFound) => {
+            create_dir(tx, &path, uid)?;
+        },
+        Err(FsError::NotDirButFile) => {
+            remove_file(tx, &path)?;
+            create_dir(tx, &path, uid)?;
+        },
+        Err(e) => return Err(e)
     }
-
-    ctx.wd = path;
-    return (ctx, String::new())
-}
\ No newline at end of file
+    Ok(path)
+}
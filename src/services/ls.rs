@@ -13,7 +13,7 @@
  *                 entry_name += '/'
  *             add entry_name to vec
  *             if -l is specified
- *                 add long list to return str
+ *                 add long list (logical and physical size) to return str
  *             add filename to return str
  *             add line feed or spaces
  *     else
@@ -80,7 +80,7 @@ pub fn ls(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
 
     // iterate path in paths
     for path in &matches.free {
-        let new_path = match utils::convert_path_to_abs(&ctx.wd, &path) {
+        let new_path = match utils::abs_path(&ctx, path) {
             Ok(p) => p,
             Err(_) => return (ctx, format!("ls: Cannot convert '{}' to absolute path\n", &path)),
         };
@@ -116,13 +116,7 @@ pub fn ls(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
                 // get sub path
                 let mut sub_name = sub_entry.name;
                 let parent_path = new_path.clone();
-                let sub_path = match utils::convert_path_to_abs(&parent_path, &sub_name) {
-                    Ok(p) => p,
-                    Err(_) => {
-                        return_str += &format!("ls: Cannot convert '{}' to absolute path\n", &sub_name);
-                        continue;
-                    }
-                };
+                let sub_path = utils::canonicalize(&parent_path, &sub_name);
                 let sub_meta = match metadata(&mut ctx.tx, &sub_path) {
                     Ok(m) => m,
                     Err(_) => {
@@ -160,9 +154,10 @@ pub fn ls(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
                     permission_str += &get_rwx(&others_rwx)[..]; 
                     let owner_str = String::from("user") + &sub_meta.owner().to_string();
                     let size_str = sub_meta.size().to_string();
+                    let physical_str = sub_meta.physical_size().to_string();
                     let (mo, d, h, mi) = sub_meta.timestamp();
                     let time_str = format!("{} {:>2} {:0>2}:{:0>2}", MONTH[mo as usize], d, h, mi);
-                    return_str += &format!("{:>7} {:>8} {:>10} {:>12} ", permission_str, owner_str, size_str, time_str);
+                    return_str += &format!("{:>7} {:>8} {:>10} {:>10} {:>12} ", permission_str, owner_str, size_str, physical_str, time_str);
                 }
                 return_str += &sub_name;
 
@@ -178,7 +173,7 @@ pub fn ls(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
         }
         else {
             // get file
-            let new_path = match utils::convert_path_to_abs(&ctx.wd, &path) {
+            let new_path = match utils::abs_path(&ctx, path) {
                 Ok(p) => p,
                 Err(_) => return (ctx, format!("ls: Cannot convert '{}' to absolute path\n", path)),
             };
@@ -214,9 +209,10 @@ pub fn ls(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
                 permission_str += &get_rwx(&others_rwx)[..]; 
                 let owner_str = String::from("user") + &meta.owner().to_string();
                 let size_str = meta.size().to_string();
+                let physical_str = meta.physical_size().to_string();
                 let (mo, d, h, mi) = meta.timestamp();
                 let time_str = format!("{} {:>2} {:0>2}:{:0>2}", MONTH[mo as usize], d, h, mi);
-                return_str += &format!("{:>7} {:>8} {:>10} {:>12} ", permission_str, owner_str, size_str, time_str);
+                return_str += &format!("{:>7} {:>8} {:>10} {:>10} {:>12} ", permission_str, owner_str, size_str, physical_str, time_str);
             }
 
             return_str += &filename;
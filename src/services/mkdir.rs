@@ -54,7 +54,7 @@ pub fn mkdir(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
 
     // iterate path in paths
     for path in &matches.free {
-        let dir_path = match utils::convert_path_to_abs(&ctx.wd, &path) {
+        let dir_path = match utils::abs_path(&ctx, path) {
             Ok(p) => p,
             Err(_) => {
                 return_str += &format!("mkdir: Cannot convert \"{}\" to absolute path\n", path);
@@ -64,10 +64,11 @@ pub fn mkdir(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
 
         if let Ok(_) = metadata(&mut ctx.tx, &dir_path) {
             return_str += &format!("mkdir: cannot create directory \"{}\": File exists\n", path);
+            continue;
         }
 
         // split path
-        let (parent_path, _) = split_path(&dir_path);
+        let (parent_path, _) = utils::split_path(&dir_path);
 
         match metadata(&mut ctx.tx, &parent_path) {
             Ok(m) => {
@@ -86,7 +87,7 @@ pub fn mkdir(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
                             return_str += &format!("mkdir: created directory \"{}\"\n", path);
                         }
                     },
-                    Err(_) => return_str += &format!("Cannot create directory \"{}\"\n", path),
+                    Err(e) => return_str += &format!("mkdir: cannot create directory \"{}\": {}\n", path, e.kind()),
                 }
             }
             Err(_) => {
@@ -106,20 +107,6 @@ pub fn mkdir(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
     (ctx, return_str)
 }
 
-// split parent path and sub path
-fn split_path(path: &str) -> (&str, &str) {
-    // split at the last '/' or '\'
-    if let Some(index) = path.rfind('/') {
-        let (parent_path, sub_path) = path.split_at(index);
-        (&parent_path[..index], &sub_path[1..])
-    } else if let Some(index) = path.rfind('\\') {
-        let (parent_path, sub_path) = path.split_at(index);
-        (&parent_path[..index], &sub_path[1..])
-    } else {
-        ("./", &path)
-    }
-}
-
 // create dir layer by layer
 fn create_nested_dir(ctx: &mut Context, path: &str, verbose: bool) -> String {
     let mut return_str = String::new();
@@ -161,7 +148,10 @@ fn create_nested_dir(ctx: &mut Context, path: &str, verbose: bool) -> String {
                                 return_str += &format!("mkdir: created directory \"{}\"\n", current_path);
                             }
                         },
-                        Err(_) => return_str += &format!("Cannot create directory \"{}\"\n", current_path),
+                        Err(e) => {
+                            return_str += &format!("mkdir: cannot create directory \"{}\": {}\n", current_path, e.kind());
+                            break;
+                        }
                     }
                 }
             }
@@ -1,28 +1,46 @@
 use super::{Context, utils, permission};
-use crate::fs::{metadata, open_file, create_file};
-use crate::fs::FsError;
+use crate::fs::{metadata, open_file, create_file, Fd, OpenFlags};
+use crate::fs::{FsError, FdError, FileLock, LockKind, LockWait};
+use std::time::Duration;
 
 const PERMISSION: (bool, bool, bool) = (false, true, false);
+// how long to wait for other sessions writing the same file
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
 
-pub fn output(mut ctx: Context, s: String, redirect: &str) -> String {
+pub fn output(ctx: Context, s: String, redirect: &str) -> String {
     if redirect == "" {
         return s;
     }
 
+    let mut fd = match open_output(ctx, redirect) {
+        Ok(f) => f,
+        Err(msg) => return msg
+    };
+
+    // write file
+    match fd.write(&s.into_bytes()) {
+        Ok(_) => String::from("\n"),
+        Err(_) => format!("shell: Cannot write to '{redirect}': Inner Error.\n")
+    }
+}
+
+/// Open the file at `redirect` for output, created if missing and locked
+/// against other sessions. Return an error message on failure.
+pub fn open_output(mut ctx: Context, redirect: &str) -> Result<Fd, String> {
     // output s to files in redirects
-    let abs_path = match utils::convert_path_to_abs(&ctx.wd, redirect) {
+    let abs_path = match utils::abs_path(&ctx, redirect) {
         Ok(s) => s,
         Err(_) => {
-            return format!("shell: Cannot write to '{redirect}': Invalid redirect.\n");
+            return Err(format!("shell: Cannot write to '{redirect}': Invalid redirect.\n"));
         }
     };
 
     // open file
-    let mut fd = match open_file(&mut ctx.tx, &abs_path) {
+    let mut fd = match open_file(&mut ctx.tx, &abs_path, OpenFlags::WRITE, ctx.uid) {
         Ok(mut f) => {
             let m = f.metadata();
             if !permission::check_permission(ctx.uid, &m, PERMISSION) {
-                return format!("shell: Permission denied: '{redirect}'\n");
+                return Err(format!("shell: Permission denied: '{redirect}'\n"));
             }
             f
         },
@@ -34,34 +52,41 @@ pub fn output(mut ctx: Context, s: String, redirect: &str) -> String {
                     match metadata(&mut ctx.tx, parent) {
                         Ok(m) => {
                             if !permission::check_permission(ctx.uid, &m, PERMISSION) {
-                                return format!("shell: Permission denied: {redirect}\n");
+                                return Err(format!("shell: Permission denied: {redirect}\n"));
                             }
                             match create_file(&mut ctx.tx, &abs_path, ctx.uid) {
                                 Ok(f) => f,
                                 Err(_) => {
-                                    return format!("shell: Cannot write to '{redirect}': Error when creating file.\n");
+                                    return Err(format!("shell: Cannot write to '{redirect}': Error when creating file.\n"));
                                 }
                             }
                         },
                         Err(_) => {
-                            return format!("shell: Cannot write to '{redirect}': No such file or directory.\n");
+                            return Err(format!("shell: Cannot write to '{redirect}': No such file or directory.\n"));
                         }
                     }
                 },
                 FsError::NotFileButDir => {
-                    return format!("shell: Cannot write to '{redirect}': Is a directory.\n");
+                    return Err(format!("shell: Cannot write to '{redirect}': Is a directory.\n"));
                 },
                 _ => {
-                    return format!("shell: Cannot write to '{redirect}': Inner Error.");
+                    return Err(format!("shell: Cannot write to '{redirect}': Inner Error."));
                 }
             }
         }
     };
 
-    // write file
-    if let Err(_) = fd.write(&s.as_bytes().to_vec()) {
-        return format!("shell: Cannot write to '{redirect}': Inner Error.\n");
+    // keep other sessions off the file while it is rewritten
+    let lock = FileLock::new(LockKind::Exclusive).mandatory();
+    match fd.lock(lock, LockWait::Timeout(LOCK_TIMEOUT)) {
+        Ok(_) => {},
+        Err(FdError::TimedOut) => {
+            return Err(format!("shell: Cannot write to '{redirect}': File is locked.\n"));
+        },
+        Err(_) => {
+            return Err(format!("shell: Cannot write to '{redirect}': Inner Error.\n"));
+        }
     }
 
-    return String::from("\n");
+    Ok(fd)
 }
\ No newline at end of file
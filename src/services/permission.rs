@@ -15,4 +15,11 @@ pub fn check_permission(uid: u8, meta: &Metadata, permission: (bool, bool, bool)
         let rwx = meta.permission().1; 
         (rwx.read || !r) && (rwx.write || !w) && (rwx.execute || !x)
     }
-}
\ No newline at end of file
+}
+// uid of the administrator
+pub const ADMIN_UID: u8 = 0;
+
+// check if user is the administrator
+pub fn is_admin(uid: u8) -> bool {
+    uid == ADMIN_UID
+}
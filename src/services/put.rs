@@ -0,0 +1,153 @@
+ /*
+ * put DEST, with files read from the host by the shell
+ * (a file carried without '/' in its path is a source)
+ *
+ * if DEST is a dir
+ *     target of source = DEST/source
+ * else if there is one source
+ *     target of source = DEST
+ * else
+ *     return err
+ * iterate file in files:
+ *     if a dir holding file failed
+ *         continue
+ *     if file is a source
+ *         check permission of target parent
+ *         if target exists
+ *             return_str += err message
+ *             continue
+ *     create dir, or create file and copy its body in
+ *     (-v: +) add "'host:file' -> 'target'" to return str
+ * iterate created file in reverse order:
+ *     set mode and mtime (of dirs after what is in them)
+ */
+use getopts::Options;
+use super::{Context, utils, permission};
+use crate::fs::{metadata, create_dir, create_file, mode_to_rwx, FsError, FsErrorKind};
+use crate::server::{HostFile, Transfer};
+use std::io;
+
+const USAGE: &str = "Usage: put [-r] [-v] HOST_SOURCE... DEST\n";
+const PERMISSION: (bool, bool, bool) = (false, true, false);
+
+pub fn put(mut ctx: Context, args: Vec<&str>, files: Vec<HostFile>, transfer: &mut Transfer) -> (Context, String) {
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+    opts.optflag("r", "", "Copy directories recursively (packed by the shell)");
+    opts.optflag("v", "", "Explain what is being done");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() != 1 {
+        return (ctx, String::from(USAGE));
+    }
+    let verbose = matches.opt_present("v");
+
+    let dest = match utils::abs_path(&ctx, &matches.free[0]) {
+        Ok(p) => p,
+        Err(e) => return (ctx, format!("put: Cannot access '{}': {e}\n", matches.free[0])),
+    };
+    let sources = files.iter().filter(|f| !f.path.contains('/')).count();
+    if sources == 0 {
+        return (ctx, format!("put: Missing host source. {USAGE}"));
+    }
+    let into_dir = match metadata(&mut ctx.tx, &dest) {
+        Ok(m) => m.is_dir(),
+        Err(_) => false,
+    };
+    if !into_dir && sources > 1 {
+        return (ctx, format!("put: Target '{dest}' is not a directory\n"));
+    }
+
+    // where a file carried lands
+    let target = |path: &str| {
+        let (source, rest) = path.split_once('/').unwrap_or((path, ""));
+        match into_dir {
+            true => utils::canonicalize(&utils::canonicalize(&dest, source), rest),
+            false => utils::canonicalize(&dest, rest),
+        }
+    };
+
+    let mut return_str = String::new();
+    // files created, and paths of dirs not created
+    let mut created = Vec::<(String, &HostFile)>::new();
+    let mut failed = Vec::<&str>::new();
+    for file in &files {
+        // skipped unless copied
+        let mut body = transfer.body();
+        if failed.iter().any(|f| file.path.strip_prefix(f).is_some_and(|rest| rest.starts_with('/'))) {
+            continue;
+        }
+        if file.path.split('/').any(|s| s.is_empty() || s == "." || s == "..") {
+            return_str += &format!("put: Invalid name 'host:{}'\n", file.path);
+            failed.push(&file.path);
+            continue;
+        }
+        let tgt_path = target(&file.path);
+
+        // only sources land in a dir not created here
+        if !file.path.contains('/') {
+            let (parent_path, _) = utils::split_path(&tgt_path);
+            match metadata(&mut ctx.tx, parent_path) {
+                Ok(m) => if !permission::check_permission(ctx.uid, &m, PERMISSION) {
+                    return_str += &format!("put: Permission denied: '{tgt_path}'\n");
+                    failed.push(&file.path);
+                    continue;
+                },
+                Err(e) => {
+                    return_str += &format!("put: Cannot access '{}': {}\n", parent_path, e.kind());
+                    failed.push(&file.path);
+                    continue;
+                }
+            }
+            if metadata(&mut ctx.tx, &tgt_path).is_ok() {
+                return_str += &format!("put: '{tgt_path}' exists\n");
+                failed.push(&file.path);
+                continue;
+            }
+        }
+
+        let r = match file.is_dir {
+            true => create_dir(&mut ctx.tx, &tgt_path, ctx.uid).map(|_| ()),
+            false => create_file(&mut ctx.tx, &tgt_path, ctx.uid)
+                .and_then(|mut fd| io::copy(&mut body, &mut fd).map(|_| ()).map_err(FsError::from)),
+        };
+        match r {
+            Ok(_) => {
+                if verbose {
+                    return_str += &format!("'host:{}' -> '{}'\n", file.path, tgt_path);
+                }
+                created.push((tgt_path, file));
+            },
+            Err(e) => {
+                return_str += &format!("put: Cannot create '{}': {}\n", tgt_path, e.kind());
+                // nothing more fits
+                if matches!(e.kind(), FsErrorKind::NoSpace) {
+                    break;
+                }
+                failed.push(&file.path);
+            }
+        }
+    }
+
+    // writing into a dir changes its mtime: dirs go after what is in them
+    for (tgt_path, file) in created.iter().rev() {
+        let r = metadata(&mut ctx.tx, tgt_path).and_then(|mut m| {
+            m.set_permission(mode_to_rwx(file.mode))?;
+            m.set_mtime(file.mtime)?;
+            Ok(())
+        });
+        if let Err(e) = r {
+            return_str += &format!("put: Cannot set mode and time of '{}': {}\n", tgt_path, e.kind());
+        }
+    }
+
+    (ctx, return_str)
+}
@@ -0,0 +1,83 @@
+ /*
+ * if user is not admin
+ *     return err
+ * parse size: <n>[K|M|G], in bytes
+ * resize(size / block_size)
+ * return new size
+ */
+use getopts::Options;
+use super::{Context, permission};
+use crate::fs::{superblock, resize as resize_disk, FsError, ResizeError};
+
+const USAGE: &str = "Usage: resize <size>[K|M|G]\n";
+
+// parse "64M" style size into bytes
+fn parse_size(s: &str) -> Option<u64> {
+    let (num, unit) = match s.chars().last() {
+        Some('K') | Some('k') => (&s[..s.len()-1], 1024),
+        Some('M') | Some('m') => (&s[..s.len()-1], 1024 * 1024),
+        Some('G') | Some('g') => (&s[..s.len()-1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    match num.parse::<u64>() {
+        Ok(n) => n.checked_mul(unit),
+        Err(_) => None,
+    }
+}
+
+pub fn resize(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
+    if args.len() < 1 {
+        return (ctx, String::from(USAGE));
+    }
+
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() != 1 {
+        return (ctx, String::from(USAGE));
+    }
+
+    // check permission
+    if !permission::is_admin(ctx.uid) {
+        return (ctx, String::from("resize: Permission denied: Only admin can resize the disk\n"));
+    }
+
+    let sb = match superblock(&mut ctx.tx) {
+        Ok(sb) => sb,
+        Err(_) => return (ctx, String::from("resize: Cannot read superblock\n")),
+    };
+    let size = match parse_size(&matches.free[0]) {
+        Some(s) => s,
+        None => return (ctx, format!("resize: Invalid size '{}'\n", matches.free[0])),
+    };
+    let block_count = match u32::try_from(size / sb.block_size as u64) {
+        Ok(n) => n,
+        Err(_) => return (ctx, format!("resize: Size too big: '{}'\n", matches.free[0])),
+    };
+
+    match resize_disk(&mut ctx.tx, block_count) {
+        Ok(sb) => {
+            let size = sb.block_count as u64 * sb.block_size as u64;
+            (ctx, format!("resize: Disk is now {} blocks ({} bytes)\n", sb.block_count, size))
+        },
+        Err(FsError::ResizeErr(e)) => {
+            let msg = match e {
+                ResizeError::TooSmall => "Size too small",
+                ResizeError::TooBig => "Size too big",
+                ResizeError::NoEnoughSpace => "Not enough free space to shrink",
+                ResizeError::DiskErr(_) => "Disk error",
+            };
+            (ctx, format!("resize: Cannot resize disk: {msg}\n"))
+        },
+        Err(_) => (ctx, String::from("resize: Cannot resize disk\n")),
+    }
+}
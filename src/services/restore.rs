@@ -0,0 +1,66 @@
+ /*
+ * iterate key in keys:
+ *     key is the id of an entry in the user's trash, or its original path
+ *     restore entry to -o path if specified, or to its original path
+ *     if failed
+ *         return_str += err message
+ */
+use getopts::Options;
+use super::{Context, utils};
+use super::trash::err_msg;
+use crate::fs::restore as restore_entry;
+
+const USAGE: &str = "Usage: restore [-o <path>] <id|path>...\n";
+
+pub fn restore(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+    opts.optopt("o", "", "Restore to this path instead", "PATH");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        return (ctx, String::from(USAGE));
+    }
+
+    let to = match matches.opt_str("o") {
+        Some(p) => {
+            if matches.free.len() > 1 {
+                return (ctx, String::from("restore: Only one entry can be restored with -o\n"));
+            }
+            match utils::abs_path(&ctx, &p) {
+                Ok(p) => Some(p),
+                Err(_) => return (ctx, format!("restore: Cannot convert '{p}' to absolute path\n")),
+            }
+        },
+        None => None,
+    };
+
+    let mut return_str = String::new();
+    for key in &matches.free {
+        // an id, or a path deleted from
+        let key = match key.parse::<u32>() {
+            Ok(_) => key.clone(),
+            Err(_) => match utils::abs_path(&ctx, key) {
+                Ok(p) => p,
+                Err(_) => {
+                    return_str += &format!("restore: Cannot convert '{key}' to absolute path\n");
+                    continue;
+                }
+            },
+        };
+        match restore_entry(&mut ctx.tx, ctx.uid, &key, to.as_deref()) {
+            Ok(e) => return_str += &format!("restore: Restored '{}'\n", e.path),
+            Err(e) => return_str += &format!("restore: Cannot restore '{key}': {}\n", err_msg(e)),
+        }
+    }
+
+    (ctx, return_str)
+}
@@ -4,11 +4,15 @@
  *     if path doesn't exist
  *         return_str += err message
  *         continue
+ *     if path is a dir and -r is not specified
+ *         continue
+ *     if --force-delete is not specified and path is not in trash
+ *         check permission of every sub entry
+ *         move path to trash
+ *         continue
  *     if path is a file
  *         remove_file(path)
  *     else
- *         if -r is not specified
- *             continue
  *         remove_dir_recursively(path)            
  * 
  * ---fn remove_dir_recursively(dir_path) -> str
@@ -23,24 +27,23 @@
  */
 use getopts::Options;
 use super::{Context, utils, permission};
-use crate::fs::{metadata, open_dir, remove_dir, remove_file};
+use crate::fs::{metadata, open_dir, remove_dir, remove_file, trash, FsError, TrashError};
 
 // define uasge and permission
-const USAGE: &str = "Usage: rm [-r] <file>...\n";
+const USAGE: &str = "Usage: rm [-r] [--force-delete] <file>...\n";
 const PERMISSION: (bool, bool, bool) = (false, true, false);
 
 fn remove_dir_recursively(ctx: &mut Context, dir_path: &str) -> String {
-    let return_str = String::new();
 
     {
         // get sub entrys of dir
         let mut dir_dd = match open_dir(&mut ctx.tx, &dir_path) {
             Ok(m) => m,
-            Err(_) => return format!("rm: Cannot find directory '{}'\n", dir_path),
+            Err(e) => return format!("rm: Cannot open directory '{}': {}\n", dir_path, e.kind()),
         };
         let vec = match dir_dd.read() {
             Ok(v) => v,
-            Err(_) => return format!("rm: Cannot read directory '{}'\n", dir_path),
+            Err(e) => return format!("rm: Cannot read directory '{}': {}\n", dir_path, FsError::from(e).kind()),
         };
 
         // iterate entry in sub entrys
@@ -52,13 +55,10 @@ fn remove_dir_recursively(ctx: &mut Context, dir_path: &str) -> String {
 
             // get sub path
             let parent_path = dir_path;
-            let sub_path = match utils::convert_path_to_abs(&parent_path, &sub_entry.name) {
-                Ok(p) => p,
-                Err(_) => return format!("rm: Cannot convert '{}' to absolute path\n", sub_entry.name),
-            };
+            let sub_path = utils::canonicalize(parent_path, &sub_entry.name);
             let sub_meta = match metadata(&mut ctx.tx, &sub_path) {
                 Ok(m) => m,
-                Err(_) => return format!("rm: Cannot find '{}'\n", sub_entry.name),
+                Err(e) => return format!("rm: Cannot access '{}': {}\n", sub_path, e.kind()),
             };
 
             // check permission
@@ -69,22 +69,57 @@ fn remove_dir_recursively(ctx: &mut Context, dir_path: &str) -> String {
 
             // if sub meta is a dir
             if sub_meta.is_dir() {
-                remove_dir_recursively(ctx, &sub_path);
+                let s = remove_dir_recursively(ctx, &sub_path);
+                if !s.is_empty() {
+                    return s;
+                }
             } else {
                 // remove file
-                if let Err(_) = remove_file(&mut ctx.tx, &sub_path) {
-                    return format!("rm: Cannot remove file '{}'\n", sub_entry.name);
+                if let Err(e) = remove_file(&mut ctx.tx, &sub_path) {
+                    return format!("rm: Cannot remove file '{}': {}\n", sub_path, e.kind());
                 }
             }
         }
     }
 
     // remove dir (itself)
-    if let Err(_) = remove_dir(&mut ctx.tx, &dir_path) {
-        return format!("rm: Cannot remove directory '{}'\n", dir_path);
+    if let Err(e) = remove_dir(&mut ctx.tx, dir_path) {
+        return format!("rm: Cannot remove directory '{}': {}\n", dir_path, e.kind());
     }
 
-    return_str
+    String::new()
+}
+
+fn check_dir_recursively(ctx: &mut Context, dir_path: &str) -> Option<String> {
+    let mut dir_dd = match open_dir(&mut ctx.tx, dir_path) {
+        Ok(m) => m,
+        Err(e) => return Some(format!("rm: Cannot open directory '{}': {}\n", dir_path, e.kind())),
+    };
+    let vec = match dir_dd.read() {
+        Ok(v) => v,
+        Err(e) => return Some(format!("rm: Cannot read directory '{}': {}\n", dir_path, FsError::from(e).kind())),
+    };
+    drop(dir_dd);
+
+    for sub_entry in vec {
+        if sub_entry.name == ".." || sub_entry.name == "." {
+            continue;
+        }
+        let sub_path = utils::canonicalize(dir_path, &sub_entry.name);
+        let sub_meta = match metadata(&mut ctx.tx, &sub_path) {
+            Ok(m) => m,
+            Err(e) => return Some(format!("rm: Cannot access '{}': {}\n", sub_path, e.kind())),
+        };
+        if !permission::check_permission(ctx.uid, &sub_meta, PERMISSION) {
+            return Some(format!("rm: Permission denied: '{sub_path}'\n"));
+        }
+        if sub_meta.is_dir() {
+            if let Some(s) = check_dir_recursively(ctx, &sub_path) {
+                return Some(s);
+            }
+        }
+    }
+    None
 }
 
 pub fn rm(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
@@ -96,6 +131,7 @@ pub fn rm(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
     let mut opts = Options::new();
     opts.optflag("h", "", "Help");
     opts.optflag("r", "", "Remove directories and their contents recursively");
+    opts.optflag("", "force-delete", "Delete for good instead of moving to trash");
 
     // parse args
     let matches = match opts.parse(&args) {
@@ -115,13 +151,14 @@ pub fn rm(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
 
     // convert parameters to bool variables
     let remove_dir = matches.opt_present("r");
+    let force_delete = matches.opt_present("force-delete");
 
     let mut return_str = String::new();
 
     // iterate path in paths
     for path in matches.free {
         // open path
-        let new_path = match utils::convert_path_to_abs(&ctx.wd, &path) {
+        let new_path = match utils::abs_path(&ctx, &path) {
             Ok(p) => p,
             Err(_) => {
                 return_str += &format!("rm: Cannot convert '{}' to absolute path\n", path);
@@ -130,8 +167,8 @@ pub fn rm(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
         };
         let meta = match metadata(&mut ctx.tx, &new_path) {
             Ok(m) => m,
-            Err(_) => {
-                return_str += &format!("rm: Cannot find '{}'\n", path);
+            Err(e) => {
+                return_str += &format!("rm: Cannot access '{}': {}\n", path, e.kind());
                 continue;
             },
         };
@@ -140,6 +177,30 @@ pub fn rm(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
         let rwx = permission::check_permission(ctx.uid, &meta, PERMISSION);
         if !rwx {
             return_str += &format!("rm: Permission denied: '{path}'\n");
+            continue;
+        }
+
+        if meta.is_dir() && !remove_dir {
+            return_str += &format!("rm: Cannot remove '{path}': Is a directory\n");
+            continue;
+        }
+
+        // move to trash, unless already in it
+        if !force_delete {
+            if meta.is_dir() {
+                if let Some(s) = check_dir_recursively(&mut ctx, &new_path) {
+                    return_str += &s;
+                    continue;
+                }
+            }
+            match trash(&mut ctx.tx, &new_path, ctx.uid) {
+                Ok(_) => continue,
+                Err(FsError::TrashErr(TrashError::InTrash)) => {},
+                Err(e) => {
+                    return_str += &format!("rm: Cannot move '{path}' to trash: {}\n", e.kind());
+                    continue;
+                }
+            }
         }
 
         // check if path is a sir
@@ -153,8 +214,8 @@ pub fn rm(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
             }
         } else {
             // remove file
-            if let Err(_) = remove_file(&mut ctx.tx, &new_path) {
-                return_str += &format!("rm: Cannot remove file '{path}'\n");
+            if let Err(e) = remove_file(&mut ctx.tx, &new_path) {
+                return_str += &format!("rm: Cannot remove file '{path}': {}\n", e.kind());
             }
         }
     }
@@ -0,0 +1,94 @@
+ /*
+ * if user is not admin
+ *     return err
+ * match subcommand
+ *     create <name>: create snapshot, return its size
+ *     list: add "name  time  inodes  blocks" of every snapshot to return str
+ *     delete <name>: delete snapshot
+ *     rollback <name>: roll back to snapshot
+ * snapshots are browsed read-only under /.snapshots/<name>
+ */
+use getopts::Options;
+use chrono::{Local, TimeZone};
+use super::{Context, permission};
+use crate::fs::{
+    create_snapshot, list_snapshots, delete_snapshot, rollback_snapshot,
+    FsError, SnapshotError,
+};
+
+const USAGE: &str = "Usage: snapshot create <name>\n       \
+    snapshot list\n       \
+    snapshot delete <name>\n       \
+    snapshot rollback <name>\n";
+
+fn err_msg(e: FsError) -> &'static str {
+    match e {
+        FsError::SnapshotErr(e) => match e {
+            SnapshotError::InvalidName => "Invalid name",
+            SnapshotError::Exists => "Snapshot exists",
+            SnapshotError::NotFound => "No such snapshot",
+            SnapshotError::TooMany => "Too many snapshots",
+            SnapshotError::Busy => "Files are open",
+            SnapshotError::NoEnoughSpace => "Not enough free space",
+            SnapshotError::Corrupted => "Snapshot table corrupted",
+            SnapshotError::DiskErr(_) => "Disk error",
+        },
+        _ => "Inner error",
+    }
+}
+
+pub fn snapshot(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        return (ctx, String::from(USAGE));
+    }
+
+    // check permission
+    if !permission::is_admin(ctx.uid) {
+        return (ctx, String::from("snapshot: Permission denied: Only admin can manage snapshots\n"));
+    }
+
+    let cmd = matches.free[0].as_str();
+    let name = matches.free.get(1).map(|s| s.as_str());
+    match (cmd, name, matches.free.len()) {
+        ("list", None, 1) => {
+            let list = match list_snapshots(&mut ctx.tx) {
+                Ok(l) => l,
+                Err(e) => return (ctx, format!("snapshot: Cannot list snapshots: {}\n", err_msg(e))),
+            };
+            let mut return_str = String::new();
+            for s in list {
+                let time = match Local.timestamp_opt(s.timestamp as i64, 0).single() {
+                    Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    None => String::from("-"),
+                };
+                return_str += &format!("{:<32} {} {:>6} inodes {:>8} blocks\n", s.name, time, s.inodes, s.blocks);
+            }
+            (ctx, return_str)
+        },
+        ("create", Some(name), 2) => match create_snapshot(&mut ctx.tx, name) {
+            Ok(s) => (ctx, format!("snapshot: Created '{}': {} inodes, {} blocks\n", s.name, s.inodes, s.blocks)),
+            Err(e) => (ctx, format!("snapshot: Cannot create '{name}': {}\n", err_msg(e))),
+        },
+        ("delete", Some(name), 2) => match delete_snapshot(&mut ctx.tx, name) {
+            Ok(_) => (ctx, String::new()),
+            Err(e) => (ctx, format!("snapshot: Cannot delete '{name}': {}\n", err_msg(e))),
+        },
+        ("rollback", Some(name), 2) => match rollback_snapshot(&mut ctx.tx, name) {
+            Ok(_) => (ctx, format!("snapshot: Rolled back to '{name}'\n")),
+            Err(e) => (ctx, format!("snapshot: Cannot roll back to '{name}': {}\n", err_msg(e))),
+        },
+        _ => (ctx, String::from(USAGE)),
+    }
+}
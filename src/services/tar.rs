@@ -0,0 +1,682 @@
+ /*
+ * tar -c [-v] -f ARCHIVE PATH...
+ *     write to ARCHIVE, or send to the shell if ARCHIVE is "host:..."
+ *     iterate path in paths:
+ *         pack(path): path and what is in it, with mode, owner and mtime,
+ *         as ustar: a header per member, then its bytes padded to a block
+ *     end with two zero blocks
+ *
+ * tar -x [-v] -f ARCHIVE [-C DIR]
+ *     read ARCHIVE, or take it from the shell if ARCHIVE is "host:..."
+ *     iterate member in archive, as its header is read:
+ *         create parent dirs missing
+ *         create dir, or create (or truncate) file and copy its bytes in
+ *     iterate created member in reverse order:
+ *         set mode and mtime (and owner if user is admin, and the archive
+ *         uid fits; otherwise it stays the user's)
+ *
+ * tar -t [-v] -f ARCHIVE
+ *     list members (-v: with mode, owner, size and mtime)
+ */
+use getopts::Options;
+use super::{Context, utils, permission, open_output};
+use crate::fs::{metadata, open_dir, open_file, create_dir, create_file};
+use crate::fs::{mode_to_rwx, rwx_to_mode, Fd, FsError, FsErrorKind, Metadata, OpenFlags};
+use crate::fs::{FileLock, LockKind, LockWait};
+use crate::server::{HostFile, Transfer};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use chrono::DateTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "Usage: tar -c|-x|-t [-v] -f ARCHIVE [-C DIR] [PATH]...\n";
+const PERMISSION_READ: (bool, bool, bool) = (true, false, false);
+const PERMISSION_WRITE: (bool, bool, bool) = (false, true, false);
+const HOST_PREFIX: &str = "host:";
+// how long to wait for other sessions writing a file to pack
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+// ====== USTAR ======
+
+const BLOCK: usize = 512;
+// bytes copied at a time: whole blocks
+const CHUNK: usize = 16 * BLOCK;
+
+// a file or directory in an archive, its bytes apart
+struct Member {
+    path: String,
+    is_dir: bool,
+    mode: u32,
+    // as stored: may not fit a simdisk uid
+    uid: u32,
+    mtime: u32,
+    size: u64,
+}
+
+// write `value` in octal, NUL terminated, filling `field`
+fn put_octal(field: &mut [u8], value: u64) {
+    let len = field.len() - 1;
+    let s = format!("{:0len$o}", value);
+    field[..len].copy_from_slice(&s.as_bytes()[s.len() - len..]);
+    field[len] = 0;
+}
+
+fn get_octal(field: &[u8]) -> Option<u64> {
+    let s: String = field.iter()
+        .take_while(|b| **b != 0)
+        .map(|b| *b as char)
+        .collect();
+    match s.trim() {
+        "" => Some(0),
+        s => u64::from_str_radix(s, 8).ok(),
+    }
+}
+
+fn get_str(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+// sum of header bytes, the checksum field counted as spaces
+fn checksum(header: &[u8]) -> u64 {
+    header.iter().enumerate()
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' as u64 } else { *b as u64 })
+        .sum()
+}
+
+// split `name` into the prefix and name fields of the header
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    name.match_indices('/')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= 155 && !rest.is_empty() && rest.len() <= 100)
+}
+
+// header of `m`, or None if its name does not fit
+fn encode(m: &Member) -> Option<[u8; BLOCK]> {
+    let name = match m.is_dir {
+        true => format!("{}/", m.path),
+        false => m.path.clone(),
+    };
+    let (prefix, name) = split_name(&name)?;
+
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    put_octal(&mut header[100..108], m.mode as u64);
+    put_octal(&mut header[108..116], m.uid as u64);
+    put_octal(&mut header[116..124], 0);
+    put_octal(&mut header[124..136], m.size);
+    put_octal(&mut header[136..148], m.mtime as u64);
+    header[156] = if m.is_dir { b'5' } else { b'0' };
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    let uname = format!("user{}", m.uid);
+    header[265..265 + uname.len()].copy_from_slice(uname.as_bytes());
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    let sum = format!("{:06o}\0 ", checksum(&header));
+    header[148..156].copy_from_slice(sum.as_bytes());
+    Some(header)
+}
+
+// names are relative, without "." and ".."
+fn clean_name(name: &str) -> Option<String> {
+    let mut sections = Vec::<&str>::new();
+    for s in name.split('/') {
+        match s {
+            "" | "." => (),
+            ".." => return None,
+            s => sections.push(s),
+        }
+    }
+    Some(sections.join("/"))
+}
+
+// members of an archive read from `input`: a header, then the bytes of each
+struct Decoder<'a> {
+    input: &'a mut dyn Read,
+    archive: &'a str,
+    // offset of the next header
+    pos: u64,
+    // bytes of the member read last not read yet, and the padding after them
+    body: u64,
+    pad: u64,
+    // why the archive could not be read on
+    broken: Option<String>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(input: &'a mut dyn Read, archive: &'a str) -> Self {
+        Self { input, archive, pos: 0, body: 0, pad: 0, broken: None }
+    }
+
+    // why the archive cannot be read on
+    fn reason(&self, e: io::Error) -> String {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => String::from("tar: Invalid archive: Unexpected end\n"),
+            _ => format!("tar: Cannot read '{}': {}\n", self.archive, FsError::from(e).kind()),
+        }
+    }
+
+    // note why the archive cannot be read on, while reading bytes of a member
+    fn fail(&mut self, e: io::Error) -> io::Error {
+        let kind = e.kind();
+        self.broken = Some(self.reason(e));
+        kind.into()
+    }
+
+    // read past the bytes of the member read last
+    fn skip(&mut self) -> Result<(), String> {
+        let left = self.body + self.pad;
+        self.body = 0;
+        self.pad = 0;
+        let r = match io::copy(&mut (&mut *self.input).take(left), &mut io::sink()) {
+            Ok(n) if n < left => Err(io::ErrorKind::UnexpectedEof.into()),
+            r => r.map(|_| ()),
+        };
+        r.map_err(|e| self.reason(e))
+    }
+
+    // header of the next member, None at the end of the archive
+    fn next(&mut self, out: &mut String) -> Result<Option<Member>, String> {
+        loop {
+            self.skip()?;
+            let mut header = [0u8; BLOCK];
+            match self.input.read_exact(&mut header) {
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(self.reason(e)),
+            }
+            if header.iter().all(|b| *b == 0) {
+                return Ok(None);
+            }
+            let at = self.pos;
+            let field = |range: std::ops::Range<usize>| get_octal(&header[range])
+                .ok_or(format!("tar: Invalid archive: Bad header at {at}\n"));
+            if field(148..156)? != checksum(&header) {
+                return Err(format!("tar: Invalid archive: Bad checksum at {at}\n"));
+            }
+            let size = field(124..136)?;
+            self.body = size;
+            self.pad = size.next_multiple_of(BLOCK as u64) - size;
+            self.pos = at + BLOCK as u64 + size + self.pad;
+
+            let mut name = get_str(&header[..100]);
+            let prefix = get_str(&header[345..500]);
+            if header[257..262] == *b"ustar" && !prefix.is_empty() {
+                name = format!("{prefix}/{name}");
+            }
+            let is_dir = match header[156] {
+                b'0' | b'\0' | b'7' => false,
+                b'5' => true,
+                _ => {
+                    *out += &format!("tar: Skipping '{name}': Unsupported type\n");
+                    continue;
+                }
+            };
+            let path = match clean_name(&name) {
+                Some(p) if !p.is_empty() => p,
+                Some(_) => continue,
+                None => {
+                    *out += &format!("tar: Skipping '{name}': Path outside of archive\n");
+                    continue;
+                }
+            };
+            return Ok(Some(Member {
+                path,
+                is_dir,
+                mode: field(100..108)? as u32 & 0o777,
+                uid: field(108..116)?.try_into().unwrap_or(u32::MAX),
+                mtime: field(136..148)?.try_into().unwrap_or(0),
+                size: if is_dir { 0 } else { size },
+            }));
+        }
+    }
+}
+
+// the bytes of the member read last
+impl Read for Decoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.body.try_into().unwrap_or(usize::MAX));
+        if n == 0 {
+            return Ok(0);
+        }
+        let n = match self.input.read(&mut buf[..n]) {
+            Ok(0) => return Err(self.fail(io::ErrorKind::UnexpectedEof.into())),
+            Ok(n) => n,
+            Err(e) => return Err(self.fail(e)),
+        };
+        self.body -= n as u64;
+        Ok(n)
+    }
+}
+
+// ====== CREATE ======
+
+// copy `size` bytes of `fd` to `output` padded to a block, zeros standing
+// for what could not be read
+fn copy_body(fd: &mut Fd, size: u64, path: &str, output: &mut dyn Write, out: &mut String) -> io::Result<()> {
+    let mut buf = vec![0u8; CHUNK];
+    let mut left = size;
+    while left > 0 {
+        let n = left.min(CHUNK as u64) as usize;
+        let n = match Read::read(fd, &mut buf[..n]) {
+            Ok(0) => {
+                *out += &format!("tar: '{path}': File shrank, padded with zeros\n");
+                break;
+            },
+            Ok(n) => n,
+            Err(e) => {
+                *out += &format!("tar: Cannot read '{}': {}\n", path, FsError::from(e).kind());
+                break;
+            }
+        };
+        output.write_all(&buf[..n])?;
+        left -= n as u64;
+    }
+    let pad = left + size.next_multiple_of(BLOCK as u64) - size;
+    io::copy(&mut io::repeat(0).take(pad), output).map(|_| ())
+}
+
+// write `path` as `name` to `output`, and what is in it
+fn pack(
+    ctx: &mut Context, path: &str, name: &str, meta: &Metadata, verbose: bool, output: &mut dyn Write, out: &mut String
+) -> io::Result<()> {
+    if !permission::check_permission(ctx.uid, meta, PERMISSION_READ) {
+        *out += &format!("tar: Permission denied: '{path}'\n");
+        return Ok(());
+    }
+
+    // the dir packed from itself is not a member
+    if !name.is_empty() {
+        let mut member = Member {
+            path: String::from(name),
+            is_dir: meta.is_dir(),
+            mode: rwx_to_mode(&meta.permission()),
+            uid: meta.owner() as u32,
+            mtime: meta.mtime(),
+            size: 0,
+        };
+        let mut fd = None;
+        if !meta.is_dir() {
+            // read under a shared lock, to match the size in the header
+            let r = open_file(&mut ctx.tx, path, OpenFlags::READ, ctx.uid).and_then(|mut fd| {
+                fd.lock(FileLock::new(LockKind::Shared), LockWait::Timeout(LOCK_TIMEOUT))?;
+                Ok((fd.len()?, fd))
+            });
+            match r {
+                Ok((size, f)) => {
+                    member.size = size as u64;
+                    fd = Some(f);
+                },
+                Err(e) => {
+                    *out += &format!("tar: Cannot read '{}': {}\n", path, e.kind());
+                    return Ok(());
+                }
+            }
+        }
+        match encode(&member) {
+            Some(header) => {
+                output.write_all(&header)?;
+                if let Some(fd) = &mut fd {
+                    copy_body(fd, member.size, path, output, out)?;
+                }
+                if verbose {
+                    *out += &format!("{name}\n");
+                }
+            },
+            None => *out += &format!("tar: Skipping '{name}': Name too long\n"),
+        }
+    }
+    if !meta.is_dir() {
+        return Ok(());
+    }
+
+    // get sub entries
+    let vec = match open_dir(&mut ctx.tx, path).map(|mut dd| dd.read()) {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) => {
+            *out += &format!("tar: Cannot read directory '{}': {}\n", path, FsError::from(e).kind());
+            return Ok(());
+        },
+        Err(e) => {
+            *out += &format!("tar: Cannot open directory '{}': {}\n", path, e.kind());
+            return Ok(());
+        }
+    };
+    for sub_entry in vec {
+        if sub_entry.name == ".." || sub_entry.name == "." {
+            continue;
+        }
+        let sub_path = utils::canonicalize(path, &sub_entry.name);
+        let sub_name = match name.is_empty() {
+            true => sub_entry.name.clone(),
+            false => format!("{name}/{}", sub_entry.name),
+        };
+        let sub_meta = match metadata(&mut ctx.tx, &sub_path) {
+            Ok(m) => m,
+            Err(e) => {
+                *out += &format!("tar: Cannot access '{}': {}\n", sub_path, e.kind());
+                continue;
+            }
+        };
+        pack(ctx, &sub_path, &sub_name, &sub_meta, verbose, output, out)?;
+    }
+    Ok(())
+}
+
+// write the members of `paths` to `output`, then the end of the archive
+fn write_archive(ctx: &mut Context, paths: &[String], verbose: bool, output: &mut dyn Write, out: &mut String) -> io::Result<()> {
+    // iterate path in paths
+    for path in paths {
+        let abs_path = match utils::abs_path(ctx, path) {
+            Ok(p) => p,
+            Err(e) => {
+                *out += &format!("tar: Cannot access '{path}': {e}\n");
+                continue;
+            }
+        };
+        let meta = match metadata(&mut ctx.tx, &abs_path) {
+            Ok(m) => m,
+            Err(e) => {
+                *out += &format!("tar: Cannot access '{}': {}\n", path, e.kind());
+                continue;
+            }
+        };
+        // named relative to the working directory, or to the root
+        let name = match abs_path.strip_prefix(&ctx.wd) {
+            Some("") => "",
+            Some(rest) if rest.starts_with('/') || ctx.wd == "/" => rest.trim_start_matches('/'),
+            _ => abs_path.trim_start_matches('/'),
+        };
+        let name = String::from(name);
+        pack(ctx, &abs_path, &name, &meta, verbose, output, out)?;
+    }
+    output.write_all(&[0u8; 2 * BLOCK])?;
+    output.flush()
+}
+
+fn create(ctx: &mut Context, archive: &str, paths: &[String], verbose: bool, transfer: &mut Transfer) -> String {
+    let mut return_str = String::new();
+
+    // to the shell
+    if let Some(host_path) = archive.strip_prefix(HOST_PREFIX) {
+        let mtime = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as u32);
+        let file = HostFile {
+            path: String::from(host_path),
+            is_dir: false,
+            mode: 0o644,
+            mtime,
+        };
+        let r = transfer.send(file).and_then(|body| {
+            let mut output = BufWriter::with_capacity(CHUNK, body);
+            write_archive(ctx, paths, verbose, &mut output, &mut return_str)?;
+            output.into_inner().map_err(io::IntoInnerError::into_error)?.finish()
+        });
+        if let Err(e) = r {
+            return_str += &format!("tar: Cannot send '{archive}': {e}\n");
+        }
+        return return_str;
+    }
+
+    // into simdisk, emptied first
+    let fd = match open_output(ctx.clone(), archive) {
+        Ok(mut fd) => match fd.write(&Vec::new()) {
+            Ok(_) => fd,
+            Err(e) => return format!("tar: Cannot write '{}': {}\n", archive, FsError::from(e).kind()),
+        },
+        Err(msg) => return msg,
+    };
+    let mut output = BufWriter::with_capacity(CHUNK, fd);
+    if let Err(e) = write_archive(ctx, paths, verbose, &mut output, &mut return_str) {
+        return_str += &format!("tar: Cannot write '{}': {}\n", archive, FsError::from(e).kind());
+    }
+    return_str
+}
+
+// ====== EXTRACT ======
+
+// ARCHIVE to read, taken from the shell or opened in simdisk
+fn open_archive<'a>(
+    ctx: &mut Context, archive: &str, files: Vec<HostFile>, transfer: &'a mut Transfer
+) -> Result<Box<dyn Read + 'a>, String> {
+    if archive.starts_with(HOST_PREFIX) {
+        if files.is_empty() {
+            return Err(format!("tar: Missing host archive '{archive}'\n"));
+        }
+        return Ok(Box::new(transfer.body()));
+    }
+    let abs_path = utils::abs_path(ctx, archive)
+        .map_err(|e| format!("tar: Cannot access '{archive}': {e}\n"))?;
+    let meta = metadata(&mut ctx.tx, &abs_path)
+        .map_err(|e| format!("tar: Cannot access '{}': {}\n", archive, e.kind()))?;
+    if !permission::check_permission(ctx.uid, &meta, PERMISSION_READ) {
+        return Err(format!("tar: Permission denied: '{archive}'\n"));
+    }
+    match open_file(&mut ctx.tx, &abs_path, OpenFlags::READ, ctx.uid) {
+        Ok(fd) => Ok(Box::new(BufReader::with_capacity(CHUNK, fd))),
+        Err(e) => Err(format!("tar: Cannot read '{}': {}\n", archive, e.kind())),
+    }
+}
+
+// check that a new entry can go in the dir holding `path`
+fn check_parent(ctx: &mut Context, path: &str) -> Result<(), String> {
+    let (parent_path, _) = utils::split_path(path);
+    match metadata(&mut ctx.tx, parent_path) {
+        Ok(m) if !m.is_dir() => Err(format!("tar: Cannot create '{path}': Not a directory\n")),
+        Ok(m) if !permission::check_permission(ctx.uid, &m, PERMISSION_WRITE) => {
+            Err(format!("tar: Permission denied: '{path}'\n"))
+        },
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("tar: Cannot access '{}': {}\n", parent_path, e.kind())),
+    }
+}
+
+// create dir `path` if missing
+fn make_dir(ctx: &mut Context, path: &str) -> Result<(), (String, FsErrorKind)> {
+    match metadata(&mut ctx.tx, path) {
+        Ok(m) if m.is_dir() => return Ok(()),
+        Ok(_) => return Err((format!("tar: Cannot create directory '{path}'"), FsErrorKind::NotDir)),
+        Err(_) => (),
+    }
+    check_parent(ctx, path).map_err(|msg| (msg, FsErrorKind::Other))?;
+    create_dir(&mut ctx.tx, path, ctx.uid)
+        .map(|_| ())
+        .map_err(|e| (format!("tar: Cannot create directory '{path}'"), e.kind()))
+}
+
+// create or truncate file `path`, with what is read from `data`
+fn make_file(ctx: &mut Context, path: &str, data: &mut dyn Read) -> Result<(), (String, FsErrorKind)> {
+    let fd = match metadata(&mut ctx.tx, path) {
+        Ok(m) if m.is_dir() => return Err((format!("tar: Cannot create file '{path}'"), FsErrorKind::IsDir)),
+        Ok(m) => {
+            if !permission::check_permission(ctx.uid, &m, PERMISSION_WRITE) {
+                return Err((format!("tar: Permission denied: '{path}'\n"), FsErrorKind::Other));
+            }
+            open_file(&mut ctx.tx, path, OpenFlags::WRITE | OpenFlags::TRUNCATE, ctx.uid)
+        },
+        Err(_) => {
+            check_parent(ctx, path).map_err(|msg| (msg, FsErrorKind::Other))?;
+            create_file(&mut ctx.tx, path, ctx.uid)
+        }
+    };
+    fd.and_then(|mut fd| io::copy(data, &mut fd).map(|_| ()).map_err(FsError::from))
+        .map_err(|e| (format!("tar: Cannot write file '{path}'"), e.kind()))
+}
+
+fn extract(ctx: &mut Context, archive: &mut Decoder, dir: &str, verbose: bool) -> String {
+    let mut return_str = String::new();
+    if let Err(msg) = check_parent(ctx, &utils::canonicalize(dir, "x")) {
+        return msg;
+    }
+
+    // members written
+    let mut done = Vec::<(String, Member)>::new();
+    'members: loop {
+        let m = match archive.next(&mut return_str) {
+            Ok(Some(m)) => m,
+            Ok(None) => break,
+            Err(msg) => {
+                return_str += &msg;
+                break;
+            }
+        };
+        let tgt_path = utils::canonicalize(dir, &m.path);
+
+        // dirs holding member may be missing in archive
+        let mut parent = String::from(dir);
+        for section in m.path.split('/').rev().skip(1).collect::<Vec<_>>().into_iter().rev() {
+            parent = utils::canonicalize(&parent, section);
+            if let Err((msg, kind)) = make_dir(ctx, &parent) {
+                return_str += &msg;
+                if !msg.ends_with('\n') {
+                    return_str += &format!(": {kind}\n");
+                }
+                continue 'members;
+            }
+        }
+
+        let r = match m.is_dir {
+            true => make_dir(ctx, &tgt_path),
+            false => make_file(ctx, &tgt_path, archive),
+        };
+        match r {
+            Ok(_) => {
+                if verbose {
+                    return_str += &format!("{}\n", m.path);
+                }
+                done.push((tgt_path, m));
+            },
+            Err((msg, kind)) => {
+                // the archive ends here
+                if let Some(msg) = archive.broken.take() {
+                    return_str += &msg;
+                    break;
+                }
+                return_str += &msg;
+                if !msg.ends_with('\n') {
+                    return_str += &format!(": {kind}\n");
+                }
+                // nothing more fits
+                if matches!(kind, FsErrorKind::NoSpace) {
+                    break;
+                }
+            }
+        }
+    }
+
+    // writing into a dir changes its mtime: dirs go after what is in them
+    let admin = permission::is_admin(ctx.uid);
+    for (tgt_path, m) in done.iter().rev() {
+        let r = metadata(&mut ctx.tx, tgt_path).and_then(|mut meta| {
+            // leave dirs of others alone
+            if meta.owner() != ctx.uid && !admin {
+                return Ok(());
+            }
+            meta.set_permission(mode_to_rwx(m.mode))?;
+            meta.set_mtime(m.mtime)?;
+            if admin {
+                match u8::try_from(m.uid) {
+                    Ok(uid) => meta.set_owner(uid)?,
+                    // owned by who extracts it, as it was created
+                    Err(_) => return_str += &format!(
+                        "tar: '{}': uid {} out of range, owned by user{}\n", tgt_path, m.uid, ctx.uid
+                    ),
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = r {
+            return_str += &format!("tar: Cannot set mode and time of '{}': {}\n", tgt_path, e.kind());
+        }
+    }
+    return_str
+}
+
+// ====== LIST ======
+
+fn list(archive: &mut Decoder, verbose: bool) -> String {
+    let mut return_str = String::new();
+    loop {
+        let m = match archive.next(&mut return_str) {
+            Ok(Some(m)) => m,
+            Ok(None) => break,
+            Err(msg) => {
+                return_str += &msg;
+                break;
+            }
+        };
+        let name = match m.is_dir {
+            true => format!("{}/", m.path),
+            false => m.path,
+        };
+        if !verbose {
+            return_str += &format!("{name}\n");
+            continue;
+        }
+        let mut mode = String::from(if m.is_dir { "d" } else { "-" });
+        for (i, c) in "rwxrwxrwx".chars().enumerate() {
+            mode.push(if m.mode & (0o400 >> i) > 0 { c } else { '-' });
+        }
+        let time = DateTime::from_timestamp(m.mtime as i64, 0).unwrap_or_default();
+        return_str += &format!(
+            "{} {:>8} {:>10} {} {}\n",
+            mode, format!("user{}", m.uid), m.size, time.format("%Y-%m-%d %H:%M"), name
+        );
+    }
+    return_str
+}
+
+pub fn tar(mut ctx: Context, args: Vec<&str>, files: Vec<HostFile>, transfer: &mut Transfer) -> (Context, String) {
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+    opts.optflag("c", "", "Create an archive");
+    opts.optflag("x", "", "Extract an archive");
+    opts.optflag("t", "", "List an archive");
+    opts.optflag("v", "", "List members processed");
+    opts.optopt("f", "", "Archive, in simdisk or \"host:...\"", "ARCHIVE");
+    opts.optopt("C", "", "Extract into DIR", "DIR");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    let modes = ["c", "x", "t"].iter().filter(|m| matches.opt_present(m)).count();
+    if matches.opt_present("h") || modes != 1 {
+        return (ctx, String::from(USAGE));
+    }
+    let archive = match matches.opt_str("f") {
+        Some(a) => a,
+        None => return (ctx, format!("tar: Missing archive. {USAGE}")),
+    };
+    let verbose = matches.opt_present("v");
+
+    if matches.opt_present("c") {
+        if matches.free.is_empty() {
+            return (ctx, format!("tar: Nothing to archive. {USAGE}"));
+        }
+        let return_str = create(&mut ctx, &archive, &matches.free, verbose, transfer);
+        return (ctx, return_str);
+    }
+
+    let mut input = match open_archive(&mut ctx, &archive, files, transfer) {
+        Ok(i) => i,
+        Err(msg) => return (ctx, msg),
+    };
+    let mut archive = Decoder::new(&mut *input, &archive);
+    if matches.opt_present("t") {
+        return (ctx, list(&mut archive, verbose));
+    }
+
+    let dir = match utils::abs_path(&ctx, &matches.opt_str("C").unwrap_or(String::from("."))) {
+        Ok(p) => p,
+        Err(e) => return (ctx, format!("tar: Cannot access directory: {e}\n")),
+    };
+    let return_str = extract(&mut ctx, &mut archive, &dir, verbose);
+    (ctx, return_str)
+}
@@ -13,7 +13,8 @@
  */
 use getopts::Options;
 use super::{Context, utils, permission};
-use crate::fs::{metadata, create_file};
+use crate::fs::{metadata, create_file, atomically, FsError, FsErrorKind, FsReq};
+use std::sync::mpsc::Sender;
 
 const USAGE: &str = "Usage: touch <file>...\n";
 const PERMISSION: (bool, bool, bool) = (false, true, false);
@@ -47,7 +48,7 @@ pub fn touch(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
 
     // iterate path in paths
     for path in &matches.free {
-        let new_path = match utils::convert_path_to_abs(&ctx.wd, &path) {
+        let new_path = match utils::abs_path(&ctx, path) {
             Ok(p) => p,
             Err(_) => {
                 return_str += &format!("touch: Cannot convert '{}' to absolute path\n", path);
@@ -55,35 +56,55 @@ pub fn touch(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
             }
         };
 
-        // update timestamp
-        if let Ok(mut m) = metadata(&mut ctx.tx, &new_path) {
-            if let Err(_) = m.update_timestamp() {
-                return_str += &format!("touch: Cannot update timestamp: '{}'\n", path);
-            }
-            continue;
-        }
-
-        // split path
-        let (parent_path, _) = utils::split_path(&new_path);
-
-        match metadata(&mut ctx.tx, parent_path) {
-            Ok(m) => {
-                // check permission
-                let rwx = permission::check_permission(ctx.uid, &m, PERMISSION);
-                if !rwx {
-                    return_str += &format!("touch: Permission denied: '{path}'\n");
-                }
-
-                // create file
-                if let Err(_) = create_file(&mut ctx.tx, &new_path, ctx.uid) {
-                    return_str += &format!("touch: Cannot create file: '{}'\n", path);
-                }
-            }
-            Err(_) => {
-                return_str += &format!("touch: cannot touch '{}': No such file or directory\n", path);
+        // check and create with no other session in between
+        let uid = ctx.uid;
+        match atomically(&mut ctx.tx, |tx| touch_one(tx, uid, &new_path)) {
+            Ok(_) => (),
+            Err(TouchError::Timestamp(kind)) => {
+                return_str += &format!("touch: Cannot update timestamp: '{}': {}\n", path, kind);
+            },
+            Err(TouchError::PermissionDenied) => {
+                return_str += &format!("touch: Permission denied: '{path}'\n");
+            },
+            Err(TouchError::NoParent(kind)) => {
+                return_str += &format!("touch: cannot touch '{}': {}\n", path, kind);
+            },
+            Err(TouchError::FsErr(kind)) => {
+                return_str += &format!("touch: Cannot create file: '{}': {}\n", path, kind);
             }
         }
     }
 
     (ctx, return_str)
-}
\ No newline at end of file
+}
+
+enum TouchError {
+    Timestamp(FsErrorKind),
+    PermissionDenied,
+    NoParent(FsErrorKind),
+    FsErr(FsErrorKind),
+}
+
+impl From<FsError> for TouchError {
+    fn from(e: FsError) -> Self { Self::FsErr(e.kind()) }
+}
+
+fn touch_one(tx: &mut Sender<FsReq>, uid: u8, path: &str) -> Result<(), TouchError> {
+    // update timestamp
+    if let Ok(mut m) = metadata(tx, path) {
+        return m.update_timestamp().map_err(|e| TouchError::Timestamp(FsError::from(e).kind()));
+    }
+
+    // split path
+    let (parent_path, _) = utils::split_path(path);
+    let m = metadata(tx, parent_path).map_err(|e| TouchError::NoParent(e.kind()))?;
+
+    // check permission
+    if !permission::check_permission(uid, &m, PERMISSION) {
+        return Err(TouchError::PermissionDenied);
+    }
+
+    // create file
+    create_file(tx, path, uid)?;
+    Ok(())
+}
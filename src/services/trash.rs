@@ -0,0 +1,74 @@
+ /*
+ * match subcommand
+ *     list (default): add "id  time  blocks  original path" of every entry
+ *         in the user's trash to return str
+ *     empty: delete every entry in the user's trash for good
+ * entries are kept under /.trash/<uid>, and purged by age or quota when
+ * more are added
+ */
+use getopts::Options;
+use chrono::{Local, TimeZone};
+use super::Context;
+use crate::fs::{list_trash, empty_trash, FsError, TrashError};
+
+const USAGE: &str = "Usage: trash [list]\n       \
+    trash empty\n";
+
+pub fn err_msg(e: FsError) -> &'static str {
+    match e {
+        FsError::TrashErr(e) => match e {
+            TrashError::InvalidPath => "Invalid path",
+            TrashError::NotFound => "No such entry in trash",
+            TrashError::InTrash => "Already in trash",
+            TrashError::Occupied => "File is open",
+            TrashError::Exists => "Destination exists",
+            TrashError::ParentNotFound => "Parent directory not found",
+            TrashError::ParentNotDir => "Parent is not a directory",
+            TrashError::NoEnoughSpace => "Not enough free space",
+            TrashError::Incorrupted | TrashError::DiskErr(_) => "Disk error",
+        },
+        FsError::ReadOnly => "Read-only file system",
+        _ => "Inner error",
+    }
+}
+
+pub fn trash(mut ctx: Context, args: Vec<&str>) -> (Context, String) {
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() > 1 {
+        return (ctx, String::from(USAGE));
+    }
+
+    match matches.free.first().map(|s| s.as_str()).unwrap_or("list") {
+        "list" => {
+            let list = match list_trash(&mut ctx.tx, ctx.uid) {
+                Ok(l) => l,
+                Err(e) => return (ctx, format!("trash: Cannot list trash: {}\n", err_msg(e))),
+            };
+            let mut return_str = String::new();
+            for e in list {
+                let time = match Local.timestamp_opt(e.timestamp as i64, 0).single() {
+                    Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    None => String::from("-"),
+                };
+                return_str += &format!("{:>4} {} {:>8} blocks {}\n", e.id, time, e.blocks, e.path);
+            }
+            (ctx, return_str)
+        },
+        "empty" => match empty_trash(&mut ctx.tx, ctx.uid) {
+            Ok(v) => (ctx, format!("trash: Deleted {} entries\n", v.len())),
+            Err(e) => (ctx, format!("trash: Cannot empty trash: {}\n", err_msg(e))),
+        },
+        _ => (ctx, String::from(USAGE)),
+    }
+}
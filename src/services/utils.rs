@@ -1,67 +1,67 @@
-// convert path to absolute path
-pub fn convert_path_to_abs(mut wd: &str, path: &str) -> Result<String, & 'static str> {
-    // assume wd is an absolute path
+use super::Context;
+use crate::fs::canonical_path;
 
-    if path.starts_with("/") {
-        // already been abs path
-        return Ok(String::from(path));
-    }
+/// Where the home directories of users are.
+pub const HOME_DIR: &str = "/home";
 
-    // split working dir and path dir
-    if wd.ends_with('/') {
-        wd = &wd[..wd.len()-1];
-    }
-    let mut wd_vec: Vec<&str> = wd.split('/').collect();
-    wd_vec.drain(0..1);
-    let mut path_vec: Vec<&str> = path.split('/').collect();
+/// Return the home directory of user `uid`.
+pub fn home_dir(uid: u8) -> String {
+    format!("{HOME_DIR}/{uid}")
+}
 
-    loop {
-        // get the first char
-        let tmp = match path_vec.get(0) {
-            Some(s) => *s,
-            None => break
-        };
-        // handle "." or ".."
-        if tmp == "." {
-            path_vec.drain(0..1);
-            continue
-        } else if tmp == ".." {
-            match wd_vec.pop() {
-                Some(_) => (),
-                None => return Err("Invalid path!")
+// convert path typed by the user to canonical absolute path
+// "~" stands for the user's home, "~<uid>" for another user's
+pub fn abs_path(ctx: &Context, path: &str) -> Result<String, & 'static str> {
+    let path = match path.strip_prefix('~') {
+        Some(rest) => {
+            let (user, rest) = rest.split_once('/').unwrap_or((rest, ""));
+            let uid = match user {
+                "" => ctx.uid,
+                u => match u.parse::<u8>() {
+                    Ok(uid) => uid,
+                    Err(_) => return Err("No such user")
+                }
             };
-            path_vec.drain(0..1);
-        } else {
-            break
-        }
-    }
-
-    let a = wd_vec.join("/");
-    let b = path_vec.join("/");
-
-    // regroup
-    let mut str = String::from("/") + &a;
+            format!("{}/{rest}", home_dir(uid))
+        },
+        None => String::from(path)
+    };
+    Ok(canonicalize(&ctx.wd, &path))
+}
 
-    if b.len() > 0 {
-        if !str.ends_with('/') {
-            str += "/";
-        }
-        str += &b;
+// resolve path against wd (absolute) into canonical absolute path:
+// ".", "..", repeated and trailing '/' are resolved, ".." stays at the root
+pub fn canonicalize(wd: &str, path: &str) -> String {
+    match path.starts_with('/') {
+        true => canonical_path(path),
+        false => canonical_path(&format!("{wd}/{path}"))
     }
-
-    Ok(str)
 }
 
-// split parent path and sub path
+// split canonical absolute path into parent path and name
 pub fn split_path(path: &str) -> (&str, &str) {
-    // split at the last '/' or '\'
-    if let Some(index) = path.rfind('/') {
-        let (parent_path, sub_path) = path.split_at(index);
-        (&parent_path[..index], &sub_path[1..])
-    } else if let Some(index) = path.rfind('\\') {
-        let (parent_path, sub_path) = path.split_at(index);
-        (&parent_path[..index], &sub_path[1..])
+    match path.rfind('/') {
+        Some(0) => ("/", &path[1..]),
+        Some(index) => (&path[..index], &path[index + 1..]),
+        None => ("/", path)
+    }
+}
+
+// format bytes as "512", "1.5K", "128M"...
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if size < 10.0 {
+        format!("{:.1}{}", size, UNITS[unit])
     } else {
-        ("./\n", &path)
+        format!("{:.0}{}", size, UNITS[unit])
     }
-}
\ No newline at end of file
+}
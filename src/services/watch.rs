@@ -0,0 +1,80 @@
+ /*
+ * watch path, and everything below it with -r
+ * loop:
+ *     wait for an event, at most HEARTBEAT
+ *     send "KIND path" of the event to the client
+ *     every HEARTBEAT, send nothing to see if the client is still there
+ *     stop once the client is gone
+ */
+use getopts::Options;
+use std::time::{Duration, Instant};
+use super::{Context, utils, permission};
+use crate::fs::{metadata, watch as watch_path, FsError};
+
+const USAGE: &str = "Usage: watch [-r] <path>\n       \
+    Events are shown until Enter is pressed.\n";
+const PERMISSION: (bool, bool, bool) = (true, false, false);
+// how often to check the client is still there
+const HEARTBEAT: Duration = Duration::from_secs(1);
+
+/// `emit`: send a part of the output to the client now. Return `false` once
+/// the client is gone.
+pub fn watch(mut ctx: Context, args: Vec<&str>, emit: &mut dyn FnMut(&str) -> bool) -> (Context, String) {
+    // define params
+    let mut opts = Options::new();
+    opts.optflag("h", "", "Help");
+    opts.optflag("r", "", "Watch everything below the directory too");
+
+    // parse args
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            return (ctx, f.to_string());
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() != 1 {
+        return (ctx, String::from(USAGE));
+    }
+
+    let path = &matches.free[0];
+    let abs_path = match utils::abs_path(&ctx, path) {
+        Ok(p) => p,
+        Err(_) => return (ctx, format!("watch: Cannot convert '{path}' to absolute path\n")),
+    };
+
+    // check permission
+    match metadata(&mut ctx.tx, &abs_path) {
+        Ok(m) => {
+            if !permission::check_permission(ctx.uid, &m, PERMISSION) {
+                return (ctx, format!("watch: Permission denied: '{path}'\n"));
+            }
+        },
+        Err(_) => return (ctx, format!("watch: No such file or directory: '{path}'\n")),
+    }
+
+    let watcher = match watch_path(&mut ctx.tx, &abs_path, matches.opt_present("r")) {
+        Ok(w) => w,
+        Err(FsError::NotFound) => return (ctx, format!("watch: No such file or directory: '{path}'\n")),
+        Err(_) => return (ctx, format!("watch: Cannot watch '{path}'\n")),
+    };
+
+    if !emit(&format!("watch: Watching '{abs_path}'\n")) {
+        return (ctx, String::new());
+    }
+    let mut checked = Instant::now();
+    loop {
+        let mut sent = match watcher.wait(HEARTBEAT) {
+            Some(ev) => emit(&format!("{ev}\n")),
+            None => true
+        };
+        if sent && checked.elapsed() >= HEARTBEAT {
+            sent = emit("");
+            checked = Instant::now();
+        }
+        if !sent {
+            break;
+        }
+    }
+    (ctx, String::new())
+}